@@ -0,0 +1,385 @@
+//! Decoding of contract call return values
+
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::bail;
+use failure::Error;
+use num256::Uint256;
+
+/// Types that can be decoded from the raw output of a contract call
+pub trait AbiDecode: Sized {
+    fn abi_decode(output: &[u8]) -> Result<Self, Error>;
+}
+
+/// Decodes `output` of a call to the function `call` as `T`, failing with
+/// `TokenBridgeError::MalformedOutput` for that call
+pub fn decode_output<T: AbiDecode>(call: &str, output: &[u8]) -> Result<T, Error> {
+    T::abi_decode(output).map_err(|_| malformed_output(call, output))
+}
+
+/// The `TokenBridgeError::MalformedOutput` of `output` from a call to `call`
+fn malformed_output(call: &str, output: &[u8]) -> Error {
+    TokenBridgeError::MalformedOutput {
+        call: call.to_string(),
+        raw: output.to_vec(),
+    }
+    .into()
+}
+
+/// A Solidity return type for `decode_values`. Every `uintN` and `intN` decodes as `Uint256`,
+/// signed values are left in two's complement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbiType {
+    Uint256,
+    Address,
+    Bool,
+    Bytes,
+}
+
+/// A return value decoded by `decode_values`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiValue {
+    Uint256(Uint256),
+    Address(Address),
+    Bool(bool),
+    Bytes(Vec<u8>),
+}
+
+impl AbiValue {
+    pub fn into_uint256(self) -> Result<Uint256, Error> {
+        match self {
+            AbiValue::Uint256(value) => Ok(value),
+            other => bail!("Expected a uint256, got {:?}", other),
+        }
+    }
+
+    pub fn into_address(self) -> Result<Address, Error> {
+        match self {
+            AbiValue::Address(value) => Ok(value),
+            other => bail!("Expected an address, got {:?}", other),
+        }
+    }
+
+    pub fn into_bool(self) -> Result<bool, Error> {
+        match self {
+            AbiValue::Bool(value) => Ok(value),
+            other => bail!("Expected a bool, got {:?}", other),
+        }
+    }
+
+    pub fn into_bytes(self) -> Result<Vec<u8>, Error> {
+        match self {
+            AbiValue::Bytes(value) => Ok(value),
+            other => bail!("Expected bytes, got {:?}", other),
+        }
+    }
+}
+
+/// Decodes `output` of a call to the function `call` as the return values `types`, in order.
+/// Fails with `TokenBridgeError::MalformedOutput` for that call if any of them doesn't decode.
+pub fn decode_values(call: &str, types: &[AbiType], output: &[u8]) -> Result<Vec<AbiValue>, Error> {
+    types
+        .iter()
+        .enumerate()
+        .map(|(index, kind)| decode_value(*kind, output, index))
+        .collect::<Result<Vec<_>, Error>>()
+        .map_err(|_| malformed_output(call, output))
+}
+
+/// The return value of type `kind` whose head is the `index`th word of `output`
+fn decode_value(kind: AbiType, output: &[u8], index: usize) -> Result<AbiValue, Error> {
+    let word = nth_word(output, index)?;
+    Ok(match kind {
+        AbiType::Uint256 => AbiValue::Uint256(Uint256::from_bytes_be(word)),
+        AbiType::Address => AbiValue::Address(decode_address(word)?),
+        AbiType::Bool => AbiValue::Bool(decode_bool(word)?),
+        AbiType::Bytes => AbiValue::Bytes(decode_bytes_at(output, word_to_usize(word)?)?),
+    })
+}
+
+/// Returns the `index`th 32 byte word of `output`
+fn nth_word(output: &[u8], index: usize) -> Result<&[u8], Error> {
+    let start = index.saturating_mul(32);
+    match output.get(start..start.saturating_add(32)) {
+        Some(val) => Ok(val),
+        None => bail!(
+            "Expected at least {} 32 byte words, got {:?}",
+            index + 1,
+            output
+        ),
+    }
+}
+
+/// `address` left padded to a 32 byte word, the way it appears in call data, outputs and
+/// indexed event topics
+pub fn address_word(address: Address) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[12..].copy_from_slice(address.as_bytes());
+    word
+}
+
+/// The address padded into `word`, failing if `word` isn't 32 bytes or has anything in the
+/// padding. The inverse of `address_word`.
+pub fn decode_address(word: &[u8]) -> Result<Address, Error> {
+    if word.len() != 32 {
+        bail!("Word {:?} is not 32 bytes", word);
+    }
+    if word[0..12].iter().any(|b| *b != 0) {
+        bail!("Word {:?} is not a padded address", word);
+    }
+    Address::from_slice(&word[12..32])
+}
+
+fn decode_bool(word: &[u8]) -> Result<bool, Error> {
+    if word[0..31].iter().any(|b| *b != 0) || word[31] > 1 {
+        bail!("Word {:?} is not a bool", word);
+    }
+    Ok(word[31] == 1)
+}
+
+impl AbiDecode for Uint256 {
+    fn abi_decode(output: &[u8]) -> Result<Self, Error> {
+        Ok(Uint256::from_bytes_be(nth_word(output, 0)?))
+    }
+}
+
+impl AbiDecode for Address {
+    fn abi_decode(output: &[u8]) -> Result<Self, Error> {
+        decode_address(nth_word(output, 0)?)
+    }
+}
+
+impl AbiDecode for bool {
+    fn abi_decode(output: &[u8]) -> Result<Self, Error> {
+        decode_bool(nth_word(output, 0)?)
+    }
+}
+
+/// The undecoded output, for callers that want to handle it themselves
+impl AbiDecode for Vec<u8> {
+    fn abi_decode(output: &[u8]) -> Result<Self, Error> {
+        Ok(output.to_vec())
+    }
+}
+
+/// The elements of a single dynamic array return value, one 32 byte word each
+fn array_words(output: &[u8]) -> Result<&[u8], Error> {
+    let offset = word_to_usize(nth_word(output, 0)?)?;
+    let start = offset.saturating_add(32);
+    let length = match output.get(offset..start) {
+        Some(word) => word_to_usize(word)?,
+        None => bail!("Array offset {} out of range in {:?}", offset, output),
+    };
+    match output.get(start..start.saturating_add(length.saturating_mul(32))) {
+        Some(words) => Ok(words),
+        None => bail!("Array of length {} out of range in {:?}", length, output),
+    }
+}
+
+/// A single dynamic `uint256[]` return value
+impl AbiDecode for Vec<Uint256> {
+    fn abi_decode(output: &[u8]) -> Result<Self, Error> {
+        Ok(array_words(output)?
+            .chunks(32)
+            .map(Uint256::from_bytes_be)
+            .collect())
+    }
+}
+
+/// A single dynamic `address[]` return value
+impl AbiDecode for Vec<Address> {
+    fn abi_decode(output: &[u8]) -> Result<Self, Error> {
+        array_words(output)?
+            .chunks(32)
+            .map(decode_address)
+            .collect()
+    }
+}
+
+/// Decodes a single dynamic `bytes` return value
+pub fn decode_bytes(output: &[u8]) -> Result<Vec<u8>, Error> {
+    decode_bytes_at(output, word_to_usize(nth_word(output, 0)?)?)
+}
+
+/// The dynamic `bytes` whose length word is at `offset` in `output`
+fn decode_bytes_at(output: &[u8], offset: usize) -> Result<Vec<u8>, Error> {
+    let start = offset.saturating_add(32);
+    let length = match output.get(offset..start) {
+        Some(word) => word_to_usize(word)?,
+        None => bail!("Bytes offset {} out of range in {:?}", offset, output),
+    };
+    match output.get(start..start.saturating_add(length)) {
+        Some(bytes) => Ok(bytes.to_vec()),
+        None => bail!("Bytes of length {} out of range in {:?}", length, output),
+    }
+}
+
+fn word_to_usize(word: &[u8]) -> Result<usize, Error> {
+    if word[0..24].iter().any(|b| *b != 0) {
+        bail!("Word {:?} is too large for an offset or length", word);
+    }
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&word[24..32]);
+    Ok(u64::from_be_bytes(value) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_decode_words() {
+        let mut word = [0u8; 32];
+        word[31] = 1;
+        assert_eq!(Uint256::abi_decode(&word).unwrap(), 1u32.into());
+        assert!(bool::abi_decode(&word).unwrap());
+
+        word[31] = 2;
+        assert!(bool::abi_decode(&word).is_err());
+
+        word[0] = 1;
+        assert!(Address::abi_decode(&word).is_err());
+
+        assert!(Uint256::abi_decode(&word[0..31]).is_err());
+    }
+
+    #[test]
+    fn test_decode_output() {
+        let mut word = [0u8; 32];
+        word[31] = 7;
+        let amount: Uint256 = decode_output("balanceOf(address)", &word).unwrap();
+        assert_eq!(amount, 7u32.into());
+
+        let err = decode_output::<Uint256>("allowance(address,address)", &word[1..]).unwrap_err();
+        match err.downcast_ref::<TokenBridgeError>() {
+            Some(TokenBridgeError::MalformedOutput { call, raw }) => {
+                assert_eq!(call, "allowance(address,address)");
+                assert_eq!(raw.len(), 31);
+            }
+            _ => panic!("Unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_decode_values() {
+        // (uint112, address, bool, bytes) with the bytes after the heads
+        let mut output = vec![0u8; 192];
+        output[31] = 5;
+        output[63] = 0xaa;
+        output[95] = 1;
+        output[127] = 128;
+        output[159] = 2;
+        output[160..162].copy_from_slice(&[3, 4]);
+        let types = [
+            AbiType::Uint256,
+            AbiType::Address,
+            AbiType::Bool,
+            AbiType::Bytes,
+        ];
+        let mut values = decode_values("test()", &types, &output)
+            .unwrap()
+            .into_iter();
+        assert_eq!(values.next().unwrap().into_uint256().unwrap(), 5u32.into());
+        let address = values.next().unwrap().into_address().unwrap();
+        assert_eq!(address.as_bytes()[19], 0xaa);
+        assert!(values.next().unwrap().into_bool().unwrap());
+        assert_eq!(values.next().unwrap().into_bytes().unwrap(), vec![3, 4]);
+        assert!(decode_values("test()", &[AbiType::Uint256], &output)
+            .unwrap()
+            .remove(0)
+            .into_bool()
+            .is_err());
+
+        // a missing head or bytes past the end
+        assert!(decode_values("test()", &types, &output[..96]).is_err());
+        output[159] = 40;
+        assert!(decode_values("test()", &types, &output).is_err());
+    }
+
+    #[test]
+    fn test_decode_bytes() {
+        let mut output = vec![0u8; 96];
+        output[31] = 32;
+        output[63] = 3;
+        output[64..67].copy_from_slice(&[1, 2, 3]);
+        assert_eq!(decode_bytes(&output).unwrap(), vec![1, 2, 3]);
+
+        output[63] = 40;
+        assert!(decode_bytes(&output).is_err());
+    }
+
+    #[test]
+    fn test_decode_uint_array() {
+        let mut output = vec![0u8; 128];
+        output[31] = 32;
+        output[63] = 2;
+        output[95] = 7;
+        output[127] = 9;
+        assert_eq!(
+            Vec::<Uint256>::abi_decode(&output).unwrap(),
+            vec![7u32.into(), 9u32.into()]
+        );
+
+        output[63] = 3;
+        assert!(Vec::<Uint256>::abi_decode(&output).is_err());
+    }
+
+    #[test]
+    fn test_decode_address_array() {
+        let validator = Address::from_slice(&[0xa1; 20]).unwrap();
+        let mut output = vec![0u8; 64];
+        output[31] = 32;
+        output[63] = 1;
+        output.extend_from_slice(&address_word(validator));
+        assert_eq!(
+            Vec::<Address>::abi_decode(&output).unwrap(),
+            vec![validator]
+        );
+
+        output[64] = 1;
+        assert!(Vec::<Address>::abi_decode(&output).is_err());
+    }
+
+    /// `bytes` encoded as the single dynamic `bytes` return value
+    fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+        let mut output = vec![0u8; 64];
+        output[31] = 32;
+        output[56..64].copy_from_slice(&(bytes.len() as u64).to_be_bytes());
+        output.extend_from_slice(bytes);
+        output.resize(output.len() + (32 - bytes.len() % 32) % 32, 0);
+        output
+    }
+
+    proptest! {
+        #[test]
+        fn prop_address_round_trip(bytes: [u8; 20]) {
+            let address = Address::from_slice(&bytes).unwrap();
+            let word = address_word(address);
+            prop_assert_eq!(decode_address(&word).unwrap(), address);
+            prop_assert_eq!(Address::abi_decode(&word).unwrap(), address);
+        }
+
+        #[test]
+        fn prop_uint_round_trip(bytes: [u8; 32]) {
+            let value = Uint256::from_bytes_be(&bytes);
+            let word: [u8; 32] = value.clone().into();
+            prop_assert_eq!(Uint256::abi_decode(&word).unwrap(), value);
+        }
+
+        #[test]
+        fn prop_bytes_round_trip(bytes in vec(any::<u8>(), 0..100)) {
+            prop_assert_eq!(decode_bytes(&encode_bytes(&bytes)).unwrap(), bytes);
+        }
+
+        #[test]
+        fn prop_decoders_dont_panic(output in vec(any::<u8>(), 0..200)) {
+            let types = [AbiType::Bytes, AbiType::Address, AbiType::Bool, AbiType::Uint256];
+            let _ = decode_values("test()", &types, &output);
+            let _ = Vec::<Uint256>::abi_decode(&output);
+            let _ = decode_bytes(&output);
+            let _ = decode_address(&output);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Several accounts sharing one `TokenBridge`, so a service converting for a fleet of routers
+//! can hold a single set of full node connections. Every account gets its own nonce sequence,
+//! which also lets transactions from the same account be sent without waiting for each other.
+
+use crate::signer::Signer;
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::Error;
+use num256::Uint256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The signers registered on a bridge and the next nonce of each of their accounts. Shared by
+/// all clones of the bridge.
+#[derive(Default)]
+pub struct Accounts {
+    signers: RwLock<HashMap<Address, Arc<dyn Signer>>>,
+    /// The nonce after the last one handed out, per chain and account
+    nonces: Mutex<HashMap<(Chain, Address), Uint256>>,
+}
+
+impl Accounts {
+    pub fn add(&self, signer: Arc<dyn Signer>) -> Address {
+        let address = signer.address();
+        self.signers.write().unwrap().insert(address, signer);
+        address
+    }
+
+    pub fn remove(&self, address: Address) -> Option<Arc<dyn Signer>> {
+        self.nonces
+            .lock()
+            .unwrap()
+            .retain(|(_, account), _| *account != address);
+        self.signers.write().unwrap().remove(&address)
+    }
+
+    pub fn get(&self, address: Address) -> Option<Arc<dyn Signer>> {
+        self.signers.read().unwrap().get(&address).cloned()
+    }
+
+    pub fn addresses(&self) -> Vec<Address> {
+        self.signers.read().unwrap().keys().cloned().collect()
+    }
+
+    /// The nonce to use for the next transaction of `address` on `chain`, given the
+    /// transaction count the full node reports. Nonces handed out before are skipped even if
+    /// the node doesn't know about their transactions yet.
+    pub(crate) fn next_nonce(
+        &self,
+        chain: Chain,
+        address: Address,
+        node_nonce: Uint256,
+    ) -> Uint256 {
+        let mut nonces = self.nonces.lock().unwrap();
+        let next = nonces
+            .get(&(chain, address))
+            .filter(|tracked| **tracked > node_nonce)
+            .cloned()
+            .unwrap_or(node_nonce);
+        nonces.insert((chain, address), next.clone() + 1u32.into());
+        next
+    }
+
+    /// Stops skipping nonces for `address` on `chain`, for when a transaction using a handed
+    /// out nonce was never accepted and the nonce would otherwise be left as a gap
+    pub(crate) fn reset_nonce(&self, chain: Chain, address: Address) {
+        self.nonces.lock().unwrap().remove(&(chain, address));
+    }
+}
+
+impl TokenBridge {
+    /// Registers `signer` so that its account can be used with `for_account`. Returns its
+    /// address.
+    pub fn add_account(&self, signer: Arc<dyn Signer>) -> Address {
+        self.accounts.add(signer)
+    }
+
+    /// A copy of this bridge that pays from and receives to `address`, which has to have been
+    /// registered with `add_account`. The copy shares the full node connections and accounts of
+    /// this bridge. Fails with `TokenBridgeError::UnknownAccount` otherwise.
+    pub fn for_account(&self, address: Address) -> Result<TokenBridge, Error> {
+        let signer = match self.accounts.get(address) {
+            Some(signer) => signer,
+            None => return Err(TokenBridgeError::UnknownAccount { address }.into()),
+        };
+        let mut bridge = self.clone();
+        bridge.own_address = address;
+        bridge.signer = Some(signer);
+        Ok(bridge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_nonce() {
+        let accounts = Accounts::default();
+        let (a, b) = (Address::default(), Address::from_slice(&[1u8; 20]).unwrap());
+        assert_eq!(accounts.next_nonce(Chain::Eth, a, 5u32.into()), 5u32.into());
+        // the node hasn't seen the first transaction yet
+        assert_eq!(accounts.next_nonce(Chain::Eth, a, 5u32.into()), 6u32.into());
+        // other accounts and chains are counted separately
+        assert_eq!(
+            accounts.next_nonce(Chain::Xdai, a, 0u32.into()),
+            0u32.into()
+        );
+        assert_eq!(accounts.next_nonce(Chain::Eth, b, 2u32.into()), 2u32.into());
+        // transactions sent from elsewhere are picked up from the node
+        assert_eq!(accounts.next_nonce(Chain::Eth, a, 9u32.into()), 9u32.into());
+        accounts.reset_nonce(Chain::Eth, a);
+        assert_eq!(accounts.next_nonce(Chain::Eth, a, 9u32.into()), 9u32.into());
+    }
+}
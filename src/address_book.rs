@@ -0,0 +1,97 @@
+//! Names for the addresses an operator deals with, such as exchange hot wallets, the bridge and
+//! their own routers, so exported history, receipts and log lines read as more than hex.
+
+use crate::network::Network;
+use crate::TokenBridge;
+use clarity::Address;
+use std::collections::HashMap;
+
+/// Labels by address. Labelling an address again replaces its label.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddressBook {
+    labels: HashMap<Address, String>,
+}
+
+impl AddressBook {
+    pub fn new() -> AddressBook {
+        AddressBook::default()
+    }
+
+    /// A book with the bridge, Dai, Uniswap and WETH contracts of `network` already labelled
+    pub fn for_network(network: &Network) -> AddressBook {
+        let addresses = network.addresses();
+        let mut book = AddressBook::new();
+        book.insert(addresses.xdai_foreign_bridge_address, "xDai foreign bridge");
+        book.insert(addresses.xdai_home_bridge_address, "xDai home bridge");
+        book.insert(addresses.foreign_dai_contract_address, "Dai");
+        book.insert(addresses.uniswap_address, "Uniswap Dai exchange");
+        book.insert(addresses.uniswap_factory_address, "Uniswap factory");
+        book.insert(addresses.weth_address, "WETH");
+        book
+    }
+
+    /// Labels `address`, returning its previous label if it had one
+    pub fn insert<S: Into<String>>(&mut self, address: Address, label: S) -> Option<String> {
+        self.labels.insert(address, label.into())
+    }
+
+    pub fn remove(&mut self, address: Address) -> Option<String> {
+        self.labels.remove(&address)
+    }
+
+    pub fn label(&self, address: Address) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    /// `address` with its label in front if it has one, for log lines
+    pub fn describe(&self, address: Address) -> String {
+        match self.label(address) {
+            Some(label) => format!("{} ({})", label, address),
+            None => address.to_string(),
+        }
+    }
+}
+
+impl TokenBridge {
+    /// The label of `address` in `address_book`, if one is set and has it
+    pub fn address_label(&self, address: Address) -> Option<String> {
+        self.address_book
+            .as_ref()
+            .and_then(|book| book.label(address))
+            .map(str::to_string)
+    }
+
+    /// `address` described by `address_book`, or just the address without one
+    pub fn describe_address(&self, address: Address) -> String {
+        match self.address_book {
+            Some(ref book) => book.describe(address),
+            None => address.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_book() {
+        let mut book = AddressBook::for_network(&Network::Mainnet);
+        let bridge = Network::Mainnet.addresses().xdai_foreign_bridge_address;
+        assert_eq!(book.label(bridge), Some("xDai foreign bridge"));
+
+        let hot_wallet = Address::from_slice(&[7u8; 20]).unwrap();
+        assert_eq!(book.label(hot_wallet), None);
+        assert_eq!(book.describe(hot_wallet), hot_wallet.to_string());
+        assert_eq!(book.insert(hot_wallet, "Exchange hot wallet"), None);
+        assert_eq!(
+            book.describe(hot_wallet),
+            format!("Exchange hot wallet ({})", hot_wallet)
+        );
+        assert_eq!(
+            book.insert(hot_wallet, "Router 1").as_deref(),
+            Some("Exchange hot wallet")
+        );
+        assert_eq!(book.remove(hot_wallet).as_deref(), Some("Router 1"));
+    }
+}
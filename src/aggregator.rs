@@ -0,0 +1,468 @@
+//! Quotes from DEX aggregators such as 0x and 1inch. Uniswap V1 prices are often several percent
+//! off the market for larger amounts, `eth_to_dai_best_execution` and
+//! `dai_to_eth_best_execution` swap through whichever of Uniswap and the aggregator gives more.
+
+use crate::logs::ERC20_TRANSFER;
+use crate::units::{Dai, Eth};
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::utils::hex_str_to_bytes;
+use clarity::Address;
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use serde_json::Value;
+use std::str::FromStr;
+use std::time::Duration;
+use web30::types::SendTxOption;
+
+/// How aggregators refer to ETH in place of a token address
+pub const ETH_PSEUDO_TOKEN: &str = "0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE";
+
+pub fn eth_pseudo_token() -> Address {
+    Address::from_str(ETH_PSEUDO_TOKEN).unwrap()
+}
+
+/// A swap to be quoted, the taker sends `sell_amount` and receives the output
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteRequest {
+    pub sell_token: Address,
+    pub buy_token: Address,
+    pub sell_amount: Uint256,
+    pub taker: Address,
+    /// The swap transaction reverts if it would give this much less than quoted
+    pub slippage_bps: u32,
+}
+
+/// A ready to send swap transaction from an aggregator
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregatorQuote {
+    /// The output expected before slippage
+    pub buy_amount: Uint256,
+    pub to: Address,
+    pub data: Vec<u8>,
+    pub value: Uint256,
+    pub gas: Option<Uint256>,
+    /// The contract that has to be approved to spend the sold token, if it isn't ETH
+    pub allowance_target: Address,
+}
+
+/// Something that quotes swaps, see `HttpAggregator` for the 0x and 1inch APIs
+pub trait AggregatorApi: Send + Sync {
+    /// Used in logs and in `SwapVenue::Aggregator`
+    fn name(&self) -> String;
+
+    fn quote(
+        &self,
+        request: &QuoteRequest,
+    ) -> Box<dyn Future<Item = AggregatorQuote, Error = Error>>;
+}
+
+/// The aggregator APIs whose quote format is understood
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregatorKind {
+    /// The 0x swap API, with a base url like `https://api.0x.org`
+    ZeroEx,
+    /// The 1inch swap API, with a base url like `https://api.1inch.exchange/v4.0/1`
+    OneInch,
+}
+
+fn slippage_percent(slippage_bps: u32) -> String {
+    format!("{}.{:02}", slippage_bps / 100, slippage_bps % 100)
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a Value, Error> {
+    value
+        .get(name)
+        .ok_or_else(|| format_err!("Aggregator quote has no {} {}", name, value))
+}
+
+fn uint_field(value: &Value, name: &str) -> Result<Uint256, Error> {
+    match field(value, name)? {
+        Value::String(number) => Ok(Uint256::from_str(number)
+            .map_err(|_| format_err!("Aggregator quote {} is not a number {}", name, number))?),
+        Value::Number(number) => Ok(Uint256::from_str(&number.to_string())
+            .map_err(|_| format_err!("Aggregator quote {} is not a number {}", name, number))?),
+        other => Err(format_err!(
+            "Aggregator quote {} is not a number {}",
+            name,
+            other
+        )),
+    }
+}
+
+fn str_field<'a>(value: &'a Value, name: &str) -> Result<&'a str, Error> {
+    field(value, name)?
+        .as_str()
+        .ok_or_else(|| format_err!("Aggregator quote {} is not a string", name))
+}
+
+impl AggregatorKind {
+    /// The url of a quote for `request`, `base_url` without a trailing slash
+    pub fn quote_url(self, base_url: &str, request: &QuoteRequest) -> String {
+        match self {
+            AggregatorKind::ZeroEx => format!(
+                "{}/swap/v1/quote?sellToken={}&buyToken={}&sellAmount={}&takerAddress={}&slippagePercentage={}",
+                base_url,
+                request.sell_token,
+                request.buy_token,
+                request.sell_amount,
+                request.taker,
+                // 0x takes a fraction rather than a percentage
+                f64::from(request.slippage_bps) / 10_000.0,
+            ),
+            AggregatorKind::OneInch => format!(
+                "{}/swap?fromTokenAddress={}&toTokenAddress={}&amount={}&fromAddress={}&slippage={}",
+                base_url,
+                request.sell_token,
+                request.buy_token,
+                request.sell_amount,
+                request.taker,
+                slippage_percent(request.slippage_bps),
+            ),
+        }
+    }
+
+    /// Decodes the response body of a quote request
+    pub fn parse_quote(self, body: &str) -> Result<AggregatorQuote, Error> {
+        let value: Value = serde_json::from_str(body)?;
+        let (buy_amount, tx) = match self {
+            AggregatorKind::ZeroEx => (uint_field(&value, "buyAmount")?, &value),
+            AggregatorKind::OneInch => (uint_field(&value, "toTokenAmount")?, field(&value, "tx")?),
+        };
+        let to: Address = str_field(tx, "to")?.parse()?;
+        let allowance_target = match self {
+            AggregatorKind::ZeroEx => str_field(&value, "allowanceTarget")?.parse()?,
+            // 1inch pulls the tokens with the router it sends the swap to
+            AggregatorKind::OneInch => to,
+        };
+        Ok(AggregatorQuote {
+            buy_amount,
+            to,
+            data: hex_str_to_bytes(str_field(tx, "data")?)?,
+            value: uint_field(tx, "value")?,
+            gas: uint_field(tx, "gas").ok(),
+            allowance_target,
+        })
+    }
+}
+
+/// Quotes from the HTTP API of an aggregator
+#[cfg(feature = "aggregator")]
+pub struct HttpAggregator {
+    pub kind: AggregatorKind,
+    pub base_url: String,
+    client: reqwest::r#async::Client,
+}
+
+#[cfg(feature = "aggregator")]
+impl HttpAggregator {
+    pub fn new(
+        kind: AggregatorKind,
+        base_url: &str,
+        timeout: Duration,
+    ) -> Result<HttpAggregator, Error> {
+        Ok(HttpAggregator {
+            kind,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: reqwest::r#async::Client::builder()
+                .timeout(timeout)
+                .build()?,
+        })
+    }
+}
+
+#[cfg(feature = "aggregator")]
+impl AggregatorApi for HttpAggregator {
+    fn name(&self) -> String {
+        format!("{:?}", self.kind)
+    }
+
+    fn quote(
+        &self,
+        request: &QuoteRequest,
+    ) -> Box<dyn Future<Item = AggregatorQuote, Error = Error>> {
+        let kind = self.kind;
+        Box::new(
+            self.client
+                .get(&kind.quote_url(&self.base_url, request))
+                .send()
+                .and_then(|response| response.error_for_status())
+                .and_then(|mut response| response.text())
+                .from_err()
+                .and_then(move |body| kind.parse_quote(&body)),
+        )
+    }
+}
+
+/// Where a swap was or would be executed
+#[derive(Debug, Clone, PartialEq)]
+pub enum SwapVenue {
+    Uniswap,
+    /// The aggregator by its `AggregatorApi::name`
+    Aggregator(String),
+}
+
+/// The better of the Uniswap quote and the aggregator quote
+#[derive(Debug, Clone, PartialEq)]
+pub struct BestQuote {
+    pub venue: SwapVenue,
+    /// The output expected before slippage
+    pub expected_output: Uint256,
+    /// The transaction to send if the aggregator won
+    pub aggregator_quote: Option<AggregatorQuote>,
+}
+
+/// Picks the aggregator quote only if it gives strictly more than Uniswap
+fn best_of(uniswap: Uint256, aggregator: Option<(String, AggregatorQuote)>) -> BestQuote {
+    match aggregator {
+        Some((name, quote)) if quote.buy_amount > uniswap => BestQuote {
+            venue: SwapVenue::Aggregator(name),
+            expected_output: quote.buy_amount.clone(),
+            aggregator_quote: Some(quote),
+        },
+        _ => BestQuote {
+            venue: SwapVenue::Uniswap,
+            expected_output: uniswap,
+            aggregator_quote: None,
+        },
+    }
+}
+
+impl TokenBridge {
+    /// The aggregator's quote, or `None` if there is no aggregator or it fails. A failing
+    /// aggregator is not a reason to not swap on Uniswap.
+    pub(crate) fn aggregator_quote(
+        &self,
+        request: QuoteRequest,
+    ) -> Box<dyn Future<Item = Option<(String, AggregatorQuote)>, Error = Error>> {
+        let aggregator = match self.aggregator {
+            Some(ref aggregator) => aggregator.clone(),
+            None => return Box::new(futures::future::ok(None)),
+        };
+        let name = aggregator.name();
+        Box::new(aggregator.quote(&request).then(move |quote| match quote {
+            Ok(quote) => Ok(Some((name, quote))),
+            Err(e) => {
+                warn!("Quote from {} failed with {:?}, using Uniswap", name, e);
+                Ok(None)
+            }
+        }))
+    }
+
+    /// Compares the Uniswap quote for selling `eth_amount` with the aggregator's
+    pub fn best_eth_to_dai_quote(
+        &self,
+        eth_amount: Eth,
+    ) -> Box<dyn Future<Item = BestQuote, Error = Error>> {
+        let request = QuoteRequest {
+            sell_token: eth_pseudo_token(),
+            buy_token: self.foreign_dai_contract_address,
+            sell_amount: eth_amount.wei().clone(),
+            taker: self.own_address,
+            slippage_bps: self.slippage_bps,
+        };
+        Box::new(
+            self.eth_to_dai_price(eth_amount)
+                .join(self.aggregator_quote(request))
+                .map(|(uniswap, aggregator)| best_of(uniswap.into_wei(), aggregator)),
+        )
+    }
+
+    /// Compares the Uniswap quote for selling `dai_amount` with the aggregator's
+    pub fn best_dai_to_eth_quote(
+        &self,
+        dai_amount: Dai,
+    ) -> Box<dyn Future<Item = BestQuote, Error = Error>> {
+        let request = QuoteRequest {
+            sell_token: self.foreign_dai_contract_address,
+            buy_token: eth_pseudo_token(),
+            sell_amount: dai_amount.wei().clone(),
+            taker: self.own_address,
+            slippage_bps: self.slippage_bps,
+        };
+        Box::new(
+            self.dai_to_eth_price(dai_amount)
+                .join(self.aggregator_quote(request))
+                .map(|(uniswap, aggregator)| best_of(uniswap.into_wei(), aggregator)),
+        )
+    }
+
+    /// Sells `eth_amount` for Dai through Uniswap or the aggregator, whichever quotes more. For
+    /// Uniswap this is `eth_to_dai_swap` and resolves to the Dai received, for the aggregator it
+    /// resolves to the quoted amount once the swap is mined, the swap reverts if it would give
+    /// more than `slippage_bps` less.
+    pub fn eth_to_dai_best_execution(
+        &self,
+        eth_amount: Eth,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = (SwapVenue, Dai), Error = Error>> {
+        let salf = self.clone();
+        Box::new(
+            self.best_eth_to_dai_quote(eth_amount.clone())
+                .and_then(
+                    move |BestQuote {
+                              venue,
+                              aggregator_quote,
+                              ..
+                          }| match aggregator_quote {
+                        Some(quote) => salf.in_account_order(move |salf| {
+                            salf.send_aggregator_swap(venue, quote, timeout)
+                        }),
+                        None => Box::new(
+                            salf.eth_to_dai_swap(eth_amount, timeout)
+                                .map(|dai| (SwapVenue::Uniswap, dai.into_wei())),
+                        ),
+                    },
+                )
+                .map(|(venue, dai)| (venue, Dai::from_wei(dai))),
+        )
+    }
+
+    /// Sells `dai_amount` for ETH through Uniswap or the aggregator, see
+    /// `eth_to_dai_best_execution`. The aggregator is approved to spend the Dai first if needed.
+    pub fn dai_to_eth_best_execution(
+        &self,
+        dai_amount: Dai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = (SwapVenue, Eth), Error = Error>> {
+        let salf = self.clone();
+        let dai = self.foreign_dai_contract_address;
+        Box::new(
+            self.best_dai_to_eth_quote(dai_amount.clone())
+                .and_then(
+                    move |BestQuote {
+                              venue,
+                              aggregator_quote,
+                              ..
+                          }| match aggregator_quote {
+                        Some(quote) => salf.in_account_order(move |salf| {
+                            Box::new(
+                                salf.ensure_token_approved(
+                                    dai,
+                                    quote.allowance_target,
+                                    dai_amount.wei().clone(),
+                                    Duration::from_secs(timeout),
+                                )
+                                .and_then(move |_| salf.spending_token(dai, dai_amount.wei()))
+                                .and_then(move |sender| {
+                                    sender.send_aggregator_swap(venue, quote, timeout)
+                                }),
+                            )
+                        }),
+                        None => Box::new(
+                            salf.dai_to_eth_swap(dai_amount, timeout)
+                                .map(|eth| (SwapVenue::Uniswap, eth.into_wei())),
+                        ),
+                    },
+                )
+                .map(|(venue, eth)| (venue, Eth::from_wei(eth))),
+        )
+    }
+
+    /// Sends the aggregator's swap and waits up to `timeout` seconds for it to be mined. Every
+    /// swap between ETH and Dai moves Dai, a mined swap without a Dai transfer reverted.
+    pub(crate) fn send_aggregator_swap(
+        &self,
+        venue: SwapVenue,
+        quote: AggregatorQuote,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = (SwapVenue, Uint256), Error = Error>> {
+        let dai = self.foreign_dai_contract_address;
+        let salf = self.clone();
+        let options = match quote.gas {
+            Some(gas) => vec![SendTxOption::GasLimit(gas)],
+            None => vec![],
+        };
+        let expected_output = quote.buy_amount;
+
+        Box::new(
+            self.send_swap_transaction(Chain::Eth, quote.to, quote.data, quote.value, options)
+                .and_then(move |tx_hash| {
+                    let confirmed = salf.confirm_transaction(
+                        Chain::Eth,
+                        tx_hash.clone(),
+                        dai,
+                        ERC20_TRANSFER,
+                        "value",
+                    );
+                    salf.wait_or_reconcile(
+                        Chain::Eth,
+                        tx_hash,
+                        confirmed,
+                        Duration::from_secs(timeout),
+                        dai,
+                        ERC20_TRANSFER,
+                        "value",
+                    )
+                })
+                .map(move |_| (venue, expected_output)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> QuoteRequest {
+        QuoteRequest {
+            sell_token: eth_pseudo_token(),
+            buy_token: Address::default(),
+            sell_amount: 1_000u32.into(),
+            taker: Address::default(),
+            slippage_bps: 50,
+        }
+    }
+
+    #[test]
+    fn test_quote_url() {
+        let url = AggregatorKind::ZeroEx.quote_url("https://api.0x.org", &request());
+        assert!(url.starts_with("https://api.0x.org/swap/v1/quote?"));
+        assert!(url.contains("&sellAmount=1000&"));
+        assert!(url.ends_with("&slippagePercentage=0.005"));
+        let url =
+            AggregatorKind::OneInch.quote_url("https://api.1inch.exchange/v4.0/1", &request());
+        assert!(url.ends_with("&slippage=0.50"));
+    }
+
+    #[test]
+    fn test_parse_quote() {
+        let zero_ex = r#"{"buyAmount":"2500","to":"0xdef1c0ded9bec7f1a1670819833240f027b25eff",
+            "data":"0x0102","value":"1000","gas":"150000",
+            "allowanceTarget":"0xdef1c0ded9bec7f1a1670819833240f027b25eff"}"#;
+        let quote = AggregatorKind::ZeroEx.parse_quote(zero_ex).unwrap();
+        assert_eq!(quote.buy_amount, 2500u32.into());
+        assert_eq!(quote.data, vec![1, 2]);
+        assert_eq!(quote.gas, Some(150_000u32.into()));
+
+        let one_inch = r#"{"toTokenAmount":"2400","tx":{"to":"0x1111111254fb6c44bac0bed2854e76f90643097d",
+            "data":"0x03","value":"1000","gas":140000}}"#;
+        let quote = AggregatorKind::OneInch.parse_quote(one_inch).unwrap();
+        assert_eq!(quote.allowance_target, quote.to);
+        assert_eq!(quote.gas, Some(140_000u32.into()));
+
+        assert!(AggregatorKind::OneInch.parse_quote(zero_ex).is_err());
+    }
+
+    #[test]
+    fn test_best_of() {
+        let quote = AggregatorKind::ZeroEx
+            .parse_quote(
+                r#"{"buyAmount":"2500","to":"0x0000000000000000000000000000000000000001",
+                "data":"0x","value":"0","allowanceTarget":"0x0000000000000000000000000000000000000001"}"#,
+            )
+            .unwrap();
+        let aggregator = Some(("ZeroEx".to_string(), quote));
+        assert_eq!(
+            best_of(2400u32.into(), aggregator.clone()).venue,
+            SwapVenue::Aggregator("ZeroEx".to_string())
+        );
+        assert_eq!(
+            best_of(2500u32.into(), aggregator).venue,
+            SwapVenue::Uniswap
+        );
+        assert_eq!(best_of(2400u32.into(), None).venue, SwapVenue::Uniswap);
+    }
+}
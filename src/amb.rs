@@ -0,0 +1,184 @@
+//! Client for the xDai Arbitrary Message Bridge (AMB). Newer bridge deployments move tokens by
+//! passing messages over the AMB rather than through the dedicated erc-to-native bridge.
+//!
+//! A message is sent by calling `requireToPassMessage` on the AMB of the source chain, which
+//! emits an event carrying the message id. Once the validators have executed the message on the
+//! other chain its AMB emits `AffirmationCompleted` (on xDai) or `RelayedMessage` (on Eth) with
+//! the same id and whether the call succeeded.
+
+use crate::encoding;
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::abi::derive_signature;
+use clarity::Address;
+use failure::bail;
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use futures_timer::FutureExt;
+use num256::Uint256;
+use std::str::FromStr;
+use std::time::Duration;
+use web30::types::NewFilter;
+
+pub use crate::contracts::{MAINNET_FOREIGN_AMB, MAINNET_HOME_AMB};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmbContracts {
+    /// The AMB on Eth
+    pub foreign_amb_address: Address,
+    /// The AMB on xDai
+    pub home_amb_address: Address,
+}
+
+impl AmbContracts {
+    pub fn mainnet() -> AmbContracts {
+        AmbContracts {
+            foreign_amb_address: Address::from_str(MAINNET_FOREIGN_AMB).unwrap(),
+            home_amb_address: Address::from_str(MAINNET_HOME_AMB).unwrap(),
+        }
+    }
+
+    /// The AMB on `chain`
+    pub fn address(&self, chain: Chain) -> Address {
+        match chain {
+            Chain::Eth => self.foreign_amb_address,
+            Chain::Xdai => self.home_amb_address,
+        }
+    }
+}
+
+/// The event emitted when a message leaves `chain`
+fn request_event(chain: Chain) -> &'static str {
+    match chain {
+        Chain::Eth => "UserRequestForAffirmation(bytes32,bytes)",
+        Chain::Xdai => "UserRequestForSignature(bytes32,bytes)",
+    }
+}
+
+/// The event emitted when a message has been executed on `chain`
+fn completed_event(chain: Chain) -> &'static str {
+    match chain {
+        Chain::Eth => "RelayedMessage(address,address,bytes32,bool)",
+        Chain::Xdai => "AffirmationCompleted(address,address,bytes32,bool)",
+    }
+}
+
+fn other_chain(chain: Chain) -> Chain {
+    match chain {
+        Chain::Eth => Chain::Xdai,
+        Chain::Xdai => Chain::Eth,
+    }
+}
+
+/// Whether the message execution reported by the data of a completed event succeeded
+pub fn completed_event_status(data: &[u8]) -> Result<bool, Error> {
+    match data.get(0..32) {
+        Some(word) => Ok(word[31] == 1),
+        None => bail!("Malformed AMB completion event data {:?}", data),
+    }
+}
+
+impl TokenBridge {
+    fn amb_contracts(&self) -> Result<AmbContracts, Error> {
+        match self.amb {
+            Some(ref amb) => Ok(amb.clone()),
+            None => Err(format_err!("No AMB contracts set")),
+        }
+    }
+
+    /// Asks the AMB on `from` to call `contract` on the other chain with `data`, giving the call
+    /// `gas` gas. Waits up to `timeout` for the request to be mined and returns the message id.
+    pub fn amb_send_message(
+        &self,
+        from: Chain,
+        contract: Address,
+        data: Vec<u8>,
+        gas: Uint256,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = [u8; 32], Error = Error>> {
+        let amb = try_future!(self.amb_contracts()).address(from);
+        let web3 = self.web3(from);
+        let payload = encoding::amb_require_to_pass_message(contract, data, gas);
+
+        Box::new(
+            self.in_account_order(move |salf| {
+                salf.send_transaction(from, amb, payload, 0u32.into(), Vec::new())
+            })
+            .and_then(move |tx_hash| {
+                web3.wait_for_transaction(tx_hash.into())
+                    .timeout(timeout)
+                    .and_then(move |tx| {
+                        let block = match tx.block_number {
+                            Some(block) => format!("0x{}", block.to_str_radix(16)),
+                            None => bail!("AMB request {:?} not in a block", tx.hash),
+                        };
+                        Ok((tx.hash, block))
+                    })
+                    .and_then(move |(tx_hash, block)| {
+                        web3.eth_get_logs(NewFilter {
+                            from_block: Some(block.clone()),
+                            to_block: Some(block),
+                            address: vec![amb],
+                            topics: None,
+                        })
+                        .and_then(move |logs| {
+                            let event = derive_signature(request_event(from));
+                            let log = logs.into_iter().find(|log| {
+                                log.transaction_hash.as_ref() == Some(&tx_hash)
+                                    && log.topics.first().map(|t| &t[..]) == Some(&event[..])
+                            });
+                            // the message id is the first indexed topic
+                            match log.as_ref().and_then(|log| log.topics.get(1)) {
+                                Some(id) if id.len() == 32 => {
+                                    let mut message_id = [0u8; 32];
+                                    message_id.copy_from_slice(id);
+                                    Ok(message_id)
+                                }
+                                _ => bail!("No AMB request event in {:?}", tx_hash),
+                            }
+                        })
+                    })
+            }),
+        )
+    }
+
+    /// Waits until the message `message_id` sent from `from` has been executed on the other
+    /// chain, returns whether the call it carried succeeded.
+    pub fn amb_wait_for_message(
+        &self,
+        from: Chain,
+        message_id: [u8; 32],
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = bool, Error = Error>> {
+        let to = other_chain(from);
+        let amb = try_future!(self.amb_contracts()).address(to);
+
+        Box::new(
+            self.wait_for_event(
+                to,
+                amb,
+                completed_event(to),
+                None,
+                None,
+                Some(vec![message_id]),
+            )
+            .timeout(timeout)
+            .and_then(|log| completed_event_status(&log.data)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completed_event_status() {
+        assert!(completed_event_status(&[]).is_err());
+        let mut word = [0u8; 32];
+        assert!(!completed_event_status(&word).unwrap());
+        word[31] = 1;
+        assert!(completed_event_status(&word).unwrap());
+    }
+}
@@ -0,0 +1,178 @@
+//! Converting between decimal strings like "0.01" and integer token amounts. Everything is done
+//! on the decimal digits so nothing is lost to floating point.
+
+use failure::bail;
+use failure::Error;
+use num256::Uint256;
+use std::str::FromStr;
+
+/// Decimals of ETH, Dai and xDai
+pub const ETH_DECIMALS: u32 = 18;
+
+/// Parses a decimal string into the integer amount of a token with `decimals` decimals. Errors
+/// rather than rounding if the string has more fractional digits than the token.
+pub fn parse_amount(amount: &str, decimals: u32) -> Result<Uint256, Error> {
+    let amount = amount.trim();
+    let (whole, fraction) = match amount.find('.') {
+        Some(i) => (&amount[..i], &amount[i + 1..]),
+        None => (amount, ""),
+    };
+    if whole.is_empty() && fraction.is_empty() {
+        bail!("Can't parse an empty amount");
+    }
+    if !whole
+        .chars()
+        .chain(fraction.chars())
+        .all(|c| c.is_ascii_digit())
+    {
+        bail!("Amount {} is not a decimal number", amount);
+    }
+    if fraction.len() > decimals as usize {
+        bail!(
+            "Amount {} has more than {} decimal places",
+            amount,
+            decimals
+        );
+    }
+
+    let mut digits = String::with_capacity(whole.len() + decimals as usize);
+    digits.push_str(whole);
+    digits.push_str(fraction);
+    for _ in fraction.len()..decimals as usize {
+        digits.push('0');
+    }
+    // an all zero or empty string is still zero
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(0u32.into());
+    }
+    match Uint256::from_str(digits) {
+        Ok(value) => Ok(value),
+        Err(e) => bail!("Amount {} is out of range: {:?}", amount, e),
+    }
+}
+
+/// Formats the integer amount of a token with `decimals` decimals as a decimal string with at
+/// most `precision` fractional digits. Digits past `precision` are dropped, not rounded, and
+/// trailing zeros are left out.
+pub fn format_amount(amount: &Uint256, decimals: u32, precision: u32) -> String {
+    let digits = amount.to_string();
+    let decimals = decimals as usize;
+    let (whole, fraction) = if digits.len() > decimals {
+        let split = digits.len() - decimals;
+        (digits[..split].to_string(), digits[split..].to_string())
+    } else {
+        (
+            "0".to_string(),
+            format!("{:0>width$}", digits, width = decimals),
+        )
+    };
+    let shown = &fraction[..fraction.len().min(precision as usize)];
+    let shown = shown.trim_end_matches('0');
+    if shown.is_empty() {
+        whole
+    } else {
+        format!("{}.{}", whole, shown)
+    }
+}
+
+/// Converts an amount of a token with `from` decimals into the same value with `to` decimals.
+/// Digits that don't fit into `to` decimals are dropped, so scaling down rounds down.
+pub fn scale_decimals(amount: Uint256, from: u32, to: u32) -> Uint256 {
+    let ten: Uint256 = 10u32.into();
+    if to >= from {
+        (0..to - from).fold(amount, |amount, _| amount * ten.clone())
+    } else {
+        (0..from - to).fold(amount, |amount, _| amount / ten.clone())
+    }
+}
+
+/// Parses an amount of ETH, or of Dai or xDai which have the same 18 decimals, into wei
+pub fn eth_to_wei(eth: &str) -> Result<Uint256, Error> {
+    parse_amount(eth, ETH_DECIMALS)
+}
+
+/// Formats an amount in wei as ETH, Dai or xDai with at most `precision` decimal places
+pub fn wei_to_eth(wei: &Uint256, precision: u32) -> String {
+    format_amount(wei, ETH_DECIMALS, precision)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(
+            eth_to_wei("0.01").unwrap(),
+            10_000_000_000_000_000u64.into()
+        );
+        assert_eq!(
+            eth_to_wei("2").unwrap(),
+            2_000_000_000_000_000_000u64.into()
+        );
+        assert_eq!(parse_amount("1.5", 6).unwrap(), 1_500_000u32.into());
+        assert_eq!(parse_amount(".5", 1).unwrap(), 5u32.into());
+        assert_eq!(parse_amount("0", 6).unwrap(), 0u32.into());
+        assert!(parse_amount("1.1234567", 6).is_err());
+        assert!(parse_amount("1e5", 6).is_err());
+        assert!(parse_amount("-1", 6).is_err());
+        assert!(parse_amount(".", 6).is_err());
+    }
+
+    #[test]
+    fn test_format_amount() {
+        let wei: Uint256 = 1_234_500_000_000_000_000u64.into();
+        assert_eq!(wei_to_eth(&wei, 2), "1.23");
+        assert_eq!(wei_to_eth(&wei, 18), "1.2345");
+        assert_eq!(wei_to_eth(&wei, 0), "1");
+        assert_eq!(format_amount(&5u32.into(), 6, 6), "0.000005");
+        assert_eq!(format_amount(&5u32.into(), 6, 2), "0");
+        assert_eq!(format_amount(&1_500_000u32.into(), 6, 6), "1.5");
+        assert_eq!(format_amount(&0u32.into(), 0, 2), "0");
+    }
+
+    #[test]
+    fn test_scale_decimals() {
+        let usdc: Uint256 = 1_500_000u32.into();
+        let wei = scale_decimals(usdc.clone(), 6, 18);
+        assert_eq!(wei, 1_500_000_000_000_000_000u64.into());
+        assert_eq!(scale_decimals(wei, 18, 6), usdc);
+        // a dust amount below one USDC base unit is lost
+        assert_eq!(
+            scale_decimals(999_999_999_999u64.into(), 18, 6),
+            0u32.into()
+        );
+        assert_eq!(scale_decimals(7u32.into(), 6, 6), 7u32.into());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_format_parse_round_trip(amount: u128, decimals in 0u32..40) {
+            let amount = Uint256::from(amount);
+            let formatted = format_amount(&amount, decimals, decimals);
+            prop_assert_eq!(parse_amount(&formatted, decimals).unwrap(), amount);
+        }
+
+        #[test]
+        fn prop_parse_amount_doesnt_panic(amount in "[0-9.e -]{0,40}", decimals in 0u32..40) {
+            let _ = parse_amount(&amount, decimals);
+        }
+
+        #[test]
+        fn prop_scale_decimals(amount: u128, from in 0u32..40, to in 0u32..40) {
+            let amount = Uint256::from(amount);
+            let scaled = scale_decimals(amount.clone(), from, to);
+            let back = scale_decimals(scaled, to, from);
+            if to >= from {
+                prop_assert_eq!(back, amount);
+            } else {
+                // scaling down drops less than one unit of the smaller precision
+                prop_assert!(back <= amount);
+                let unit = scale_decimals(1u32.into(), to, from);
+                prop_assert!(amount - back < unit);
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! The balances, prices and conversions of a `TokenBridge` as a trait, so that logic built on
+//! top of the bridge, like a rebalancer, can be run against `SimulatedTokenBridge` in tests.
+
+use crate::block_tag::BlockTag;
+use crate::fee::BridgeTransfer;
+use crate::units::{Dai, Eth, XDai};
+use crate::TokenBridge;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+
+/// What `TokenBridge` offers callers that convert between ETH, Dai and xDai. Every method does
+/// the same as the `TokenBridge` method of the same name.
+pub trait TokenBridgeApi: Send + Sync {
+    /// The address conversions are sent from and paid out to
+    fn own_address(&self) -> Address;
+
+    fn get_eth_balance(&self, address: Address) -> Box<dyn Future<Item = Eth, Error = Error>>;
+
+    fn get_dai_balance(&self, address: Address) -> Box<dyn Future<Item = Dai, Error = Error>>;
+
+    fn get_xdai_balance(&self, address: Address) -> Box<dyn Future<Item = XDai, Error = Error>>;
+
+    fn eth_to_dai_price(&self, amount: Eth) -> Box<dyn Future<Item = Dai, Error = Error>>;
+
+    fn dai_to_eth_price(&self, amount: Dai) -> Box<dyn Future<Item = Eth, Error = Error>>;
+
+    fn eth_cost_of_dai(&self, amount: Dai) -> Box<dyn Future<Item = Eth, Error = Error>>;
+
+    fn eth_to_dai_swap(
+        &self,
+        amount: Eth,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Dai, Error = Error>>;
+
+    fn dai_to_eth_swap(
+        &self,
+        amount: Dai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>>;
+
+    fn dai_to_xdai_bridge(
+        &self,
+        amount: Dai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>>;
+
+    fn xdai_to_dai_bridge(
+        &self,
+        amount: XDai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>>;
+
+    fn eth_to_xdai(&self, amount: Eth, timeout: u64)
+        -> Box<dyn Future<Item = XDai, Error = Error>>;
+
+    fn xdai_to_eth(&self, amount: XDai, timeout: u64)
+        -> Box<dyn Future<Item = Eth, Error = Error>>;
+}
+
+impl TokenBridgeApi for TokenBridge {
+    fn own_address(&self) -> Address {
+        self.own_address
+    }
+
+    fn get_eth_balance(&self, address: Address) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        self.get_eth_balance_at(address, BlockTag::Latest)
+    }
+
+    fn get_dai_balance(&self, address: Address) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        TokenBridge::get_dai_balance(self, address)
+    }
+
+    fn get_xdai_balance(&self, address: Address) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        self.get_xdai_balance_at(address, BlockTag::Latest)
+    }
+
+    fn eth_to_dai_price(&self, amount: Eth) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        TokenBridge::eth_to_dai_price(self, amount)
+    }
+
+    fn dai_to_eth_price(&self, amount: Dai) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        TokenBridge::dai_to_eth_price(self, amount)
+    }
+
+    fn eth_cost_of_dai(&self, amount: Dai) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        TokenBridge::eth_cost_of_dai(self, amount)
+    }
+
+    fn eth_to_dai_swap(
+        &self,
+        amount: Eth,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        TokenBridge::eth_to_dai_swap(self, amount, timeout)
+    }
+
+    fn dai_to_eth_swap(
+        &self,
+        amount: Dai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        TokenBridge::dai_to_eth_swap(self, amount, timeout)
+    }
+
+    fn dai_to_xdai_bridge(
+        &self,
+        amount: Dai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        TokenBridge::dai_to_xdai_bridge(self, amount, timeout)
+    }
+
+    fn xdai_to_dai_bridge(
+        &self,
+        amount: XDai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        TokenBridge::xdai_to_dai_bridge(self, amount, timeout)
+    }
+
+    fn eth_to_xdai(
+        &self,
+        amount: Eth,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        TokenBridge::eth_to_xdai(self, amount, timeout)
+    }
+
+    fn xdai_to_eth(
+        &self,
+        amount: XDai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        TokenBridge::xdai_to_eth(self, amount, timeout)
+    }
+}
@@ -0,0 +1,193 @@
+//! An append-only record of every transaction the bridge sends and what became of it, so that
+//! wallet history can be reconciled and disputes about where funds went can be settled from
+//! our own side.
+
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::utils::bytes_to_hex_str;
+use clarity::Address;
+use failure::format_err;
+use failure::Error;
+use num256::Uint256;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What finally happened to a sent transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxStatus {
+    /// Included in a block and did what it was sent to do
+    Executed,
+    /// Reverted, or a swap that can no longer execute because its deadline passed
+    NotExecuted,
+    /// Not seen in a block when we stopped waiting, it may still be mined
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AuditEntry {
+    /// A transaction was signed and handed to the full node. `tx_hash` is `None` and `error`
+    /// says why if the node didn't accept it.
+    Sent {
+        /// Unix time in seconds
+        timestamp: u64,
+        chain: Chain,
+        to: Address,
+        value: Uint256,
+        /// The calldata, hex encoded
+        data: String,
+        nonce: Uint256,
+        gas_price: Uint256,
+        gas_limit: Uint256,
+        tx_hash: Option<Uint256>,
+        error: Option<String>,
+    },
+    /// The outcome of a transaction recorded by an earlier `Sent` entry
+    Settled {
+        timestamp: u64,
+        chain: Chain,
+        tx_hash: Uint256,
+        status: TxStatus,
+    },
+}
+
+/// Somewhere to record `AuditEntry`s. Entries are only ever added, never changed.
+pub trait AuditSink: Send + Sync {
+    fn record(&self, entry: &AuditEntry) -> Result<(), Error>;
+}
+
+/// Appends each entry to a file as one line of JSON
+pub struct JsonLinesAuditSink {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl JsonLinesAuditSink {
+    pub fn new<P: Into<PathBuf>>(path: P) -> JsonLinesAuditSink {
+        JsonLinesAuditSink {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl AuditSink for JsonLinesAuditSink {
+    fn record(&self, entry: &AuditEntry) -> Result<(), Error> {
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| format_err!("Audit sink lock poisoned"))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // a single write per line so concurrent appenders can't interleave within it
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0)
+}
+
+/// The fields of a transaction sent by `send_transaction`
+pub(crate) struct SentTx {
+    pub chain: Chain,
+    pub to: Address,
+    pub value: Uint256,
+    pub data: Vec<u8>,
+    pub nonce: Uint256,
+    pub gas_price: Uint256,
+    pub gas_limit: Uint256,
+}
+
+impl TokenBridge {
+    fn audit(&self, entry: AuditEntry) {
+        if let Some(ref sink) = self.audit_sink {
+            // losing an audit entry must not fail a transaction that was already sent
+            if let Err(e) = sink.record(&entry) {
+                error!("Failed to record audit entry {:?}: {:?}", entry, e);
+            }
+        }
+    }
+
+    /// Records that `tx` was sent, with the node's answer
+    pub(crate) fn audit_sent(&self, tx: SentTx, result: Result<&Uint256, &Error>) {
+        let (tx_hash, error) = match result {
+            Ok(tx_hash) => (Some(tx_hash.clone()), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        self.audit(AuditEntry::Sent {
+            timestamp: now(),
+            chain: tx.chain,
+            to: tx.to,
+            value: tx.value,
+            data: bytes_to_hex_str(&tx.data),
+            nonce: tx.nonce,
+            gas_price: tx.gas_price,
+            gas_limit: tx.gas_limit,
+            tx_hash,
+            error,
+        });
+    }
+
+    pub(crate) fn audit_settled(&self, chain: Chain, tx_hash: Uint256, status: TxStatus) {
+        self.audit(AuditEntry::Settled {
+            timestamp: now(),
+            chain,
+            tx_hash,
+            status,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_json_lines_audit_sink() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", rand::random::<u64>()));
+        let sink = JsonLinesAuditSink::new(path.clone());
+        let sent = AuditEntry::Sent {
+            timestamp: 1,
+            chain: Chain::Eth,
+            to: Address::default(),
+            value: 5u32.into(),
+            data: "a9059cbb".to_string(),
+            nonce: 0u32.into(),
+            gas_price: 1u32.into(),
+            gas_limit: 21_000u32.into(),
+            tx_hash: Some(7u32.into()),
+            error: None,
+        };
+        let settled = AuditEntry::Settled {
+            timestamp: 2,
+            chain: Chain::Eth,
+            tx_hash: 7u32.into(),
+            status: TxStatus::Executed,
+        };
+        sink.record(&sent).unwrap();
+        JsonLinesAuditSink::new(path.clone())
+            .record(&settled)
+            .unwrap();
+
+        let entries: Vec<AuditEntry> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries, vec![sent, settled]);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+//! Manual bridge operations from the command line, built with the `cli` feature.
+//!
+//! Settings are read from the TOML file given with `--config` or `AUTO_BRIDGE_CONFIG`, in the
+//! format of `TokenBridgeConfig`, and any of these environment variables override it. Addresses
+//! may be ENS names.
+//!
+//! * `AUTO_BRIDGE_OWN_ADDRESS`
+//! * `AUTO_BRIDGE_ETH_NODE_URL`
+//! * `AUTO_BRIDGE_XDAI_NODE_URL`
+//! * `AUTO_BRIDGE_PRIVATE_KEY`, only needed for commands that send transactions and never
+//!   read from the config file
+//! * `AUTO_BRIDGE_KEYSTORE` and `AUTO_BRIDGE_KEYSTORE_PASSPHRASE`, a keystore file and its
+//!   passphrase to use instead of `AUTO_BRIDGE_PRIVATE_KEY`
+
+use auto_bridge::amounts::{format_amount, parse_amount, ETH_DECIMALS};
+use auto_bridge::units::{Dai, Eth, XDai};
+use auto_bridge::{read_keystore, TokenBridge, TokenBridgeConfig};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use clarity::{Address, PrivateKey};
+use failure::{bail, format_err, Error};
+use futures::Future;
+use std::env;
+use std::fs;
+use std::process;
+
+/// Decimals shown for amounts, all of ETH, Dai and xDai have 18
+const PRECISION: u32 = 6;
+
+fn main() {
+    let matches = App::new("auto-bridge")
+        .about("Swaps ETH and Dai on Uniswap and moves Dai over the xDai bridge")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .env("AUTO_BRIDGE_CONFIG")
+                .help("TOML file with the bridge settings"),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .default_value("600")
+                .help("Seconds to wait for transactions"),
+        )
+        .subcommand(
+            SubCommand::with_name("price")
+                .about("Dai received for selling an amount of ETH on Uniswap")
+                .arg(Arg::with_name("amount").required(true).help("ETH to sell")),
+        )
+        .subcommand(
+            SubCommand::with_name("swap")
+                .about("Swaps on Uniswap")
+                .arg(direction_arg(&["eth->dai", "dai->eth"]))
+                .arg(Arg::with_name("amount").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("bridge")
+                .about("Moves Dai over the xDai bridge")
+                .arg(direction_arg(&["dai->xdai", "xdai->dai"]))
+                .arg(Arg::with_name("amount").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("balances").about("ETH, Dai and xDai balances of our address"),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn direction_arg(directions: &'static [&'static str]) -> Arg<'static, 'static> {
+    Arg::with_name("direction")
+        .required(true)
+        .possible_values(directions)
+        .help("Quote it, the > is a redirect to the shell")
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let config = load_config(matches.value_of("config"))?;
+    let timeout: u64 = matches
+        .value_of("timeout")
+        .unwrap_or_default()
+        .parse()
+        .map_err(|_| format_err!("--timeout must be a number of seconds"))?;
+
+    match matches.subcommand() {
+        ("price", Some(args)) => {
+            let eth = Eth::from_wei(amount(args)?);
+            let bridge = TokenBridge::read_only_from_config(&config)?;
+            let dai = block_on(bridge.eth_to_dai_price(eth.clone()))?;
+            println!("{} ETH sells for {} Dai", show(eth.wei()), show(dai.wei()));
+        }
+        ("balances", _) => {
+            let bridge = TokenBridge::read_only_from_config(&config)?;
+            let own_address = bridge.own_address;
+            let balances = bridge.eth_web3.eth_get_balance(own_address).join3(
+                bridge.get_dai_balance(own_address),
+                bridge.xdai_web3.eth_get_balance(own_address),
+            );
+            let (eth, dai, xdai) = block_on(balances)?;
+            println!("{}", own_address);
+            println!("ETH  {}", show(&eth));
+            println!("Dai  {}", show(dai.wei()));
+            println!("xDai {}", show(&xdai));
+        }
+        ("swap", Some(args)) => {
+            let bridge = signing_bridge(&config)?;
+            let amount = amount(args)?;
+            match args.value_of("direction") {
+                Some("eth->dai") => {
+                    let dai = block_on(bridge.eth_to_dai_swap(Eth::from_wei(amount), timeout))?;
+                    println!("Received {} Dai", show(dai.wei()));
+                }
+                _ => {
+                    let eth = block_on(bridge.dai_to_eth_swap(Dai::from_wei(amount), timeout))?;
+                    println!("Received {} ETH", show(eth.wei()));
+                }
+            }
+        }
+        ("bridge", Some(args)) => {
+            let bridge = signing_bridge(&config)?;
+            let amount = amount(args)?;
+            let transfer = match args.value_of("direction") {
+                Some("dai->xdai") => {
+                    block_on(bridge.dai_to_xdai_bridge(Dai::from_wei(amount), timeout))?
+                }
+                _ => block_on(bridge.xdai_to_dai_bridge(XDai::from_wei(amount), timeout))?,
+            };
+            println!(
+                "Sent {} in transaction {:#066x}, the bridge keeps about {}",
+                show(&transfer.amount),
+                transfer.tx_hash,
+                show(&transfer.expected_fee)
+            );
+        }
+        _ => unreachable!("clap requires a known subcommand"),
+    }
+    Ok(())
+}
+
+fn load_config(path: Option<&str>) -> Result<TokenBridgeConfig, Error> {
+    let mut config: TokenBridgeConfig = match path {
+        Some(path) => toml::from_str(&fs::read_to_string(path)?)?,
+        None => TokenBridgeConfig::default(),
+    };
+    if let Ok(address) = env::var("AUTO_BRIDGE_OWN_ADDRESS") {
+        config.own_address = address
+            .parse()
+            .map_err(|_| format_err!("AUTO_BRIDGE_OWN_ADDRESS is not an address or name"))?;
+    }
+    if let Ok(url) = env::var("AUTO_BRIDGE_ETH_NODE_URL") {
+        config.eth_full_node_url = url;
+    }
+    if let Ok(url) = env::var("AUTO_BRIDGE_XDAI_NODE_URL") {
+        config.xdai_full_node_url = url;
+    }
+    if config.eth_full_node_url.is_empty() || config.xdai_full_node_url.is_empty() {
+        bail!("Both full node urls have to be set in the config file or environment");
+    }
+    block_on(config.resolve_names())
+}
+
+/// The key from `AUTO_BRIDGE_KEYSTORE` if it is set, otherwise `AUTO_BRIDGE_PRIVATE_KEY`
+fn secret() -> Result<PrivateKey, Error> {
+    if let Ok(path) = env::var("AUTO_BRIDGE_KEYSTORE") {
+        let passphrase = env::var("AUTO_BRIDGE_KEYSTORE_PASSPHRASE")
+            .map_err(|_| format_err!("AUTO_BRIDGE_KEYSTORE_PASSPHRASE is needed to unlock it"))?;
+        return read_keystore(path, &passphrase);
+    }
+    env::var("AUTO_BRIDGE_PRIVATE_KEY")
+        .map_err(|_| {
+            format_err!(
+                "AUTO_BRIDGE_PRIVATE_KEY or AUTO_BRIDGE_KEYSTORE is needed to send transactions"
+            )
+        })?
+        .parse()
+        .map_err(|_| format_err!("AUTO_BRIDGE_PRIVATE_KEY is not a private key"))
+}
+
+/// A bridge with the key from `secret`, whose address is ours
+fn signing_bridge(config: &TokenBridgeConfig) -> Result<TokenBridge, Error> {
+    let secret = secret()?;
+    let address = secret.to_public_key()?;
+    let configured = config.own_address.address()?;
+    if configured != Address::default() && configured != address {
+        bail!(
+            "The private key is for {} but the configured address is {}",
+            address,
+            configured
+        );
+    }
+    let config = TokenBridgeConfig {
+        own_address: address.into(),
+        ..config.clone()
+    };
+    TokenBridge::from_config(&config, secret)
+}
+
+fn amount(args: &ArgMatches) -> Result<num256::Uint256, Error> {
+    parse_amount(args.value_of("amount").unwrap_or_default(), ETH_DECIMALS)
+}
+
+fn show(amount: &num256::Uint256) -> String {
+    format_amount(amount, ETH_DECIMALS, PRECISION)
+}
+
+fn block_on<F: Future<Error = Error>>(future: F) -> Result<F::Item, Error> {
+    actix::System::new("auto-bridge").block_on(future)
+}
@@ -0,0 +1,516 @@
+//! Contract calls and balances read at a chosen block, so reconciliation can ask what a
+//! balance was at block N and a swap's quote and deadline can come from the same block.
+//!
+//! web30's `eth_call` and `eth_getBalance` always read the latest block and it can't read
+//! receipts, reading any other block or a receipt goes through a `BlockReader`, see
+//! `HttpBlockReader`.
+
+use crate::abi::{decode_output, AbiDecode};
+use crate::gas::GasReceipt;
+use crate::units::{Dai, Eth, XDai};
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::abi::{encode_call, Token};
+use clarity::utils::bytes_to_hex_str;
+use clarity::Address;
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use serde_json::{json, Value};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use web30::types::Block;
+
+/// The block state is read at
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum BlockTag {
+    #[default]
+    Latest,
+    /// The latest block with the transactions in the node's mempool applied on top
+    Pending,
+    Number(Uint256),
+}
+
+impl BlockTag {
+    /// The block parameter of a JSON-RPC request
+    pub fn to_rpc_param(&self) -> String {
+        match self {
+            BlockTag::Latest => "latest".to_string(),
+            BlockTag::Pending => "pending".to_string(),
+            BlockTag::Number(number) => format!("0x{}", number.to_str_radix(16)),
+        }
+    }
+}
+
+impl fmt::Display for BlockTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockTag::Number(number) => write!(f, "{}", number),
+            other => write!(f, "{}", other.to_rpc_param()),
+        }
+    }
+}
+
+/// The JSON-RPC reads web30 doesn't do, state at blocks other than the latest and receipts
+pub trait BlockReader: Send + Sync {
+    /// `eth_call` of `data` on `to` from `from`, returning the raw output
+    fn eth_call_at(
+        &self,
+        chain: Chain,
+        from: Address,
+        to: Address,
+        data: Vec<u8>,
+        block: &BlockTag,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>>;
+
+    fn eth_get_balance_at(
+        &self,
+        chain: Chain,
+        address: Address,
+        block: &BlockTag,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>>;
+
+    /// The gas used by `tx_hash`, `None` if it isn't mined yet
+    fn eth_get_transaction_receipt(
+        &self,
+        chain: Chain,
+        tx_hash: Uint256,
+    ) -> Box<dyn Future<Item = Option<GasReceipt>, Error = Error>>;
+}
+
+fn hex(bytes: &[u8]) -> String {
+    format!("0x{}", bytes_to_hex_str(bytes))
+}
+
+/// The JSON-RPC request calling `method` with `params`, for `BlockReader`s over other transports
+pub fn rpc_request(method: &str, params: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    })
+}
+
+/// The `eth_call` request of `BlockReader::eth_call_at`
+pub fn eth_call_request(from: Address, to: Address, data: &[u8], block: &BlockTag) -> Value {
+    rpc_request(
+        "eth_call",
+        json!([
+            {"from": hex(from.as_bytes()), "to": hex(to.as_bytes()), "data": hex(data)},
+            block.to_rpc_param(),
+        ]),
+    )
+}
+
+/// The `eth_getBalance` request of `BlockReader::eth_get_balance_at`
+pub fn get_balance_request(address: Address, block: &BlockTag) -> Value {
+    rpc_request(
+        "eth_getBalance",
+        json!([hex(address.as_bytes()), block.to_rpc_param()]),
+    )
+}
+
+/// The `eth_getTransactionReceipt` request of `BlockReader::eth_get_transaction_receipt`
+pub fn get_receipt_request(tx_hash: &Uint256) -> Value {
+    let hash: [u8; 32] = tx_hash.clone().into();
+    rpc_request("eth_getTransactionReceipt", json!([hex(&hash)]))
+}
+
+/// The `result` of a JSON-RPC response, errors for an `error` response. Nodes that have pruned
+/// the state of an old block answer with an error here.
+pub fn rpc_result(body: &str) -> Result<Value, Error> {
+    let value: Value = serde_json::from_str(body)?;
+    if let Some(error) = value.get("error") {
+        return Err(format_err!("JSON-RPC request failed {}", error));
+    }
+    match value.get("result") {
+        Some(result) => Ok(result.clone()),
+        None => Err(format_err!("JSON-RPC response without a result {}", body)),
+    }
+}
+
+fn hex_quantity(value: &Value) -> Result<Uint256, Error> {
+    match value.as_str() {
+        Some(hex) => Ok(Uint256::from_str(hex)?),
+        None => Err(format_err!("Expected a hex quantity, got {}", value)),
+    }
+}
+
+/// The gas of the receipt returned by `eth_getTransactionReceipt`, `None` for the `null` of a
+/// transaction that isn't mined
+pub fn parse_gas_receipt(result: &Value) -> Result<Option<GasReceipt>, Error> {
+    if result.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(GasReceipt {
+        gas_used: hex_quantity(&result["gasUsed"])?,
+        effective_gas_price: match result.get("effectiveGasPrice") {
+            Some(price) => Some(hex_quantity(price)?),
+            None => None,
+        },
+    }))
+}
+
+/// A `BlockReader` posting JSON-RPC requests to the full nodes itself. Reading blocks older
+/// than the last 128 or so needs archive nodes.
+#[cfg(feature = "block-tags")]
+pub struct HttpBlockReader {
+    pub eth_url: String,
+    pub xdai_url: String,
+    client: reqwest::r#async::Client,
+}
+
+#[cfg(feature = "block-tags")]
+impl HttpBlockReader {
+    pub fn new(
+        eth_url: &str,
+        xdai_url: &str,
+        timeout: std::time::Duration,
+    ) -> Result<HttpBlockReader, Error> {
+        Ok(HttpBlockReader {
+            eth_url: eth_url.to_string(),
+            xdai_url: xdai_url.to_string(),
+            client: reqwest::r#async::Client::builder()
+                .timeout(timeout)
+                .build()?,
+        })
+    }
+
+    fn post(&self, chain: Chain, request: Value) -> Box<dyn Future<Item = Value, Error = Error>> {
+        let url = match chain {
+            Chain::Eth => &self.eth_url,
+            Chain::Xdai => &self.xdai_url,
+        };
+        Box::new(
+            self.client
+                .post(url)
+                .json(&request)
+                .send()
+                .and_then(|response| response.error_for_status())
+                .and_then(|mut response| response.text())
+                .from_err()
+                .and_then(|body| rpc_result(&body)),
+        )
+    }
+}
+
+#[cfg(feature = "block-tags")]
+impl BlockReader for HttpBlockReader {
+    fn eth_call_at(
+        &self,
+        chain: Chain,
+        from: Address,
+        to: Address,
+        data: Vec<u8>,
+        block: &BlockTag,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        Box::new(
+            self.post(chain, eth_call_request(from, to, &data, block))
+                .and_then(|output| match output.as_str() {
+                    Some(output) => clarity::utils::hex_str_to_bytes(output),
+                    None => Err(format_err!("Expected hex data, got {}", output)),
+                }),
+        )
+    }
+
+    fn eth_get_balance_at(
+        &self,
+        chain: Chain,
+        address: Address,
+        block: &BlockTag,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        Box::new(
+            self.post(chain, get_balance_request(address, block))
+                .and_then(|balance| hex_quantity(&balance)),
+        )
+    }
+
+    fn eth_get_transaction_receipt(
+        &self,
+        chain: Chain,
+        tx_hash: Uint256,
+    ) -> Box<dyn Future<Item = Option<GasReceipt>, Error = Error>> {
+        Box::new(
+            self.post(chain, get_receipt_request(&tx_hash))
+                .and_then(|receipt| parse_gas_receipt(&receipt)),
+        )
+    }
+}
+
+impl TokenBridge {
+    fn block_reader_for(&self, block: &BlockTag) -> Result<Arc<dyn BlockReader>, Error> {
+        match self.block_reader {
+            Some(ref reader) => Ok(reader.clone()),
+            None => Err(format_err!(
+                "Reading at block {} needs a block_reader",
+                block
+            )),
+        }
+    }
+
+    /// `call_view` at `block`, which needs `block_reader` for anything but `BlockTag::Latest`
+    pub fn call_view_at<T: AbiDecode + 'static>(
+        &self,
+        chain: Chain,
+        address: Address,
+        signature: &str,
+        args: &[Token],
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = T, Error = Error>> {
+        if block == BlockTag::Latest {
+            return self.call_view(chain, address, signature, args);
+        }
+        let reader = try_future!(self.block_reader_for(&block));
+        let own_address = self.own_address;
+        let data = encode_call(signature, args);
+        let call = signature.to_string();
+
+        self.with_retry(chain, signature, move || {
+            let call = call.clone();
+            Box::new(
+                reader
+                    .eth_call_at(chain, own_address, address, data.clone(), &block)
+                    .and_then(move |output| decode_output(&call, &output)),
+            )
+        })
+    }
+
+    fn balance_at(
+        &self,
+        chain: Chain,
+        address: Address,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        if block == BlockTag::Latest {
+            let web3 = self.web3(chain);
+            return self.with_retry(chain, "eth_getBalance", move || {
+                web3.eth_get_balance(address)
+            });
+        }
+        let reader = try_future!(self.block_reader_for(&block));
+        self.with_retry(chain, "eth_getBalance", move || {
+            reader.eth_get_balance_at(chain, address, &block)
+        })
+    }
+
+    /// The ETH balance of `address` at `block`
+    pub fn get_eth_balance_at(
+        &self,
+        address: Address,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        Box::new(
+            self.balance_at(Chain::Eth, address, block)
+                .map(Eth::from_wei),
+        )
+    }
+
+    /// The xDai balance of `address` at `block` of the xDai chain
+    pub fn get_xdai_balance_at(
+        &self,
+        address: Address,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        Box::new(
+            self.balance_at(Chain::Xdai, address, block)
+                .map(XDai::from_wei),
+        )
+    }
+
+    /// The Dai balance of `address` at `block`
+    pub fn get_dai_balance_at(
+        &self,
+        address: Address,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        Box::new(
+            self.call_view_at(
+                Chain::Eth,
+                self.foreign_dai_contract_address,
+                "balanceOf(address)",
+                &[address.into()],
+                block,
+            )
+            .map(Dai::from_wei),
+        )
+    }
+
+    /// `eth_to_dai_price` at `block`. Only `BlockTag::Latest` goes through the price cache.
+    pub fn eth_to_dai_price_at(
+        &self,
+        amount: Eth,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        if block == BlockTag::Latest {
+            return self.eth_to_dai_price(amount);
+        }
+        self.fresh_eth_to_dai_price_at(amount, block)
+    }
+
+    /// `eth_to_dai_price_at` read from the node even at `BlockTag::Latest`, for swaps whose
+    /// minimum output must not come from a cached quote
+    pub(crate) fn fresh_eth_to_dai_price_at(
+        &self,
+        amount: Eth,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        let quote = match self.swap_backend.router() {
+            Some(_) => {
+                let market = try_future!(self.dai_market());
+                self.router_amount_out_at(
+                    Chain::Eth,
+                    market.router,
+                    amount.into_wei(),
+                    vec![market.wrapped_native, market.token],
+                    block,
+                )
+            }
+            None => self.call_view_at(
+                Chain::Eth,
+                self.uniswap_address,
+                "getEthToTokenInputPrice(uint256)",
+                &[amount.into_wei().into()],
+                block,
+            ),
+        };
+        Box::new(quote.map(Dai::from_wei))
+    }
+
+    /// `dai_to_eth_price` at `block`. Only `BlockTag::Latest` goes through the price cache.
+    pub fn dai_to_eth_price_at(
+        &self,
+        amount: Dai,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        if block == BlockTag::Latest {
+            return self.dai_to_eth_price(amount);
+        }
+        self.fresh_dai_to_eth_price_at(amount, block)
+    }
+
+    /// `dai_to_eth_price_at` read from the node even at `BlockTag::Latest`, see
+    /// `fresh_eth_to_dai_price_at`
+    pub(crate) fn fresh_dai_to_eth_price_at(
+        &self,
+        amount: Dai,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        let quote = match self.swap_backend.router() {
+            Some(_) => {
+                let market = try_future!(self.dai_market());
+                self.router_amount_out_at(
+                    Chain::Eth,
+                    market.router,
+                    amount.into_wei(),
+                    vec![market.token, market.wrapped_native],
+                    block,
+                )
+            }
+            None => self.call_view_at(
+                Chain::Eth,
+                self.uniswap_address,
+                "getTokenToEthInputPrice(uint256)",
+                &[amount.into_wei().into()],
+                block,
+            ),
+        };
+        Box::new(quote.map(Eth::from_wei))
+    }
+
+    /// The latest block of `chain`, then `read` run against that block, for quoting a swap whose
+    /// deadline is computed from the block's timestamp. Reading the block first means the quote
+    /// is never from an older state than the deadline. With a `block_reader` the read is pinned
+    /// to the block's number, otherwise it reads `latest`, which is that block unless another
+    /// was mined in between.
+    pub(crate) fn at_latest_block<T, F>(
+        &self,
+        chain: Chain,
+        read: F,
+    ) -> Box<dyn Future<Item = (Block, T), Error = Error>>
+    where
+        T: 'static,
+        F: FnOnce(BlockTag) -> Box<dyn Future<Item = T, Error = Error>> + 'static,
+    {
+        let pinned = self.block_reader.is_some();
+        Box::new(
+            self.web3(chain)
+                .eth_get_latest_block()
+                .and_then(move |block| {
+                    let tag = if pinned {
+                        BlockTag::Number(block.number.clone())
+                    } else {
+                        BlockTag::Latest
+                    };
+                    read(tag).map(move |value| (block, value))
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_tag() {
+        assert_eq!(BlockTag::default().to_rpc_param(), "latest");
+        assert_eq!(BlockTag::Pending.to_rpc_param(), "pending");
+        let number = BlockTag::Number(255u32.into());
+        assert_eq!(number.to_rpc_param(), "0xff");
+        assert_eq!(number.to_string(), "255");
+    }
+
+    #[test]
+    fn test_rpc_messages() {
+        let request = get_balance_request(Address::default(), &BlockTag::Number(16u32.into()));
+        assert_eq!(request["method"], "eth_getBalance");
+        assert_eq!(request["params"][1], "0x10");
+
+        let request = eth_call_request(
+            Address::default(),
+            Address::default(),
+            &[0xab],
+            &BlockTag::Pending,
+        );
+        assert_eq!(request["params"][0]["data"], "0xab");
+        assert_eq!(request["params"][1], "pending");
+
+        assert_eq!(
+            rpc_result(r#"{"jsonrpc":"2.0","id":1,"result":"0x01"}"#).unwrap(),
+            "0x01"
+        );
+        assert_eq!(
+            get_receipt_request(&1u32.into())["params"][0],
+            format!("0x{:064x}", 1)
+        );
+        assert!(rpc_result(
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"missing trie node"}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_gas_receipt() {
+        assert_eq!(parse_gas_receipt(&Value::Null).unwrap(), None);
+        let receipt = json!({"gasUsed": "0x5208", "effectiveGasPrice": "0x3b9aca00"});
+        assert_eq!(
+            parse_gas_receipt(&receipt).unwrap(),
+            Some(GasReceipt {
+                gas_used: 21_000u32.into(),
+                effective_gas_price: Some(1_000_000_000u32.into()),
+            })
+        );
+        let legacy = json!({"gasUsed": "0x5208"});
+        assert_eq!(
+            parse_gas_receipt(&legacy)
+                .unwrap()
+                .unwrap()
+                .effective_gas_price,
+            None
+        );
+        assert!(parse_gas_receipt(&json!({"gasUsed": 1})).is_err());
+    }
+}
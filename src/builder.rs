@@ -0,0 +1,292 @@
+//! Builder for `TokenBridge` that fills in the well known contract addresses
+
+use crate::keystore::read_keystore;
+use crate::mnemonic::derive_key;
+use crate::network::Network;
+use crate::pool::Web3Pool;
+use crate::signer::{LocalSigner, Signer};
+use crate::timeouts::Timeouts;
+use crate::GasStrategy;
+use crate::TokenBridge;
+use crate::DEFAULT_RPC_TIMEOUT;
+use clarity::{Address, PrivateKey};
+use failure::format_err;
+use failure::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use crate::contracts::{
+    MAINNET_DAI, MAINNET_UNISWAP_DAI_EXCHANGE, MAINNET_XDAI_FOREIGN_BRIDGE,
+    MAINNET_XDAI_HOME_BRIDGE,
+};
+
+#[derive(Clone, Default)]
+pub struct TokenBridgeBuilder {
+    uniswap_address: Option<Address>,
+    xdai_home_bridge_address: Option<Address>,
+    xdai_foreign_bridge_address: Option<Address>,
+    foreign_dai_contract_address: Option<Address>,
+    own_address: Option<Address>,
+    secret: Option<PrivateKey>,
+    signer: Option<Arc<dyn Signer>>,
+    eth_full_node_url: Option<String>,
+    xdai_full_node_url: Option<String>,
+    eth_rpc_timeout: Option<Duration>,
+    xdai_rpc_timeout: Option<Duration>,
+    eth_chain_id: Option<u64>,
+    xdai_chain_id: Option<u64>,
+    eth_gas_strategy: Option<GasStrategy>,
+    xdai_gas_strategy: Option<GasStrategy>,
+    web3_pool: Option<Web3Pool>,
+    weth_address: Option<Address>,
+    private_relay_url: Option<String>,
+    timeouts: Option<Timeouts>,
+}
+
+impl TokenBridgeBuilder {
+    /// A builder with nothing set, every field has to be provided
+    pub fn new() -> TokenBridgeBuilder {
+        TokenBridgeBuilder::default()
+    }
+
+    /// A builder with the Dai, xDai bridge and Uniswap addresses for mainnet already set, only
+    /// the key, own address and full node urls are left to provide
+    pub fn mainnet() -> TokenBridgeBuilder {
+        TokenBridgeBuilder::new().network(&Network::Mainnet)
+    }
+
+    /// Sets all the contract addresses and chain ids from a `Network` preset
+    pub fn network(mut self, network: &Network) -> Self {
+        let addresses = network.addresses();
+        self.eth_chain_id = Some(addresses.eth_chain_id);
+        self.xdai_chain_id = Some(addresses.xdai_chain_id);
+        self.uniswap_address = Some(addresses.uniswap_address);
+        self.xdai_home_bridge_address = Some(addresses.xdai_home_bridge_address);
+        self.xdai_foreign_bridge_address = Some(addresses.xdai_foreign_bridge_address);
+        self.foreign_dai_contract_address = Some(addresses.foreign_dai_contract_address);
+        self.weth_address = Some(addresses.weth_address);
+        self
+    }
+
+    /// The WETH contract, mainnet WETH by default
+    pub fn weth_address(mut self, address: Address) -> Self {
+        self.weth_address = Some(address);
+        self
+    }
+
+    /// Sends swaps on Eth through a private relay, see `TokenBridge::private_relay_url`
+    pub fn private_relay_url(mut self, url: String) -> Self {
+        self.private_relay_url = Some(url);
+        self
+    }
+
+    pub fn uniswap_address(mut self, address: Address) -> Self {
+        self.uniswap_address = Some(address);
+        self
+    }
+
+    pub fn xdai_home_bridge_address(mut self, address: Address) -> Self {
+        self.xdai_home_bridge_address = Some(address);
+        self
+    }
+
+    pub fn xdai_foreign_bridge_address(mut self, address: Address) -> Self {
+        self.xdai_foreign_bridge_address = Some(address);
+        self
+    }
+
+    pub fn foreign_dai_contract_address(mut self, address: Address) -> Self {
+        self.foreign_dai_contract_address = Some(address);
+        self
+    }
+
+    pub fn own_address(mut self, address: Address) -> Self {
+        self.own_address = Some(address);
+        self
+    }
+
+    pub fn secret(mut self, secret: PrivateKey) -> Self {
+        self.secret = Some(secret);
+        self
+    }
+
+    /// Signs with the key in the keystore file at `path`, decrypted with `passphrase` right
+    /// away, see `keystore`
+    pub fn keystore<P: AsRef<Path>>(self, path: P, passphrase: &str) -> Result<Self, Error> {
+        Ok(self.secret(read_keystore(path, passphrase)?))
+    }
+
+    /// Signs with the key at `path` of the BIP-39 mnemonic `phrase` without a passphrase, such
+    /// as `eth_account_path(n)` for the `n`th router of a fleet. Own address defaults to its
+    /// address.
+    pub fn mnemonic(self, phrase: &str, path: &str) -> Result<Self, Error> {
+        let key = derive_key(phrase, "", path)?;
+        let own_address = self.own_address.unwrap_or(key.address);
+        Ok(self.secret(key.secret).own_address(own_address))
+    }
+
+    /// Signs transactions with `signer` instead of a local key, own address defaults to the
+    /// signer's address
+    pub fn signer(mut self, signer: Arc<dyn Signer>) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    pub fn eth_full_node_url(mut self, url: String) -> Self {
+        self.eth_full_node_url = Some(url);
+        self
+    }
+
+    pub fn xdai_full_node_url(mut self, url: String) -> Self {
+        self.xdai_full_node_url = Some(url);
+        self
+    }
+
+    /// How long a single RPC request to the Eth full node may take, 10 seconds by default
+    pub fn eth_rpc_timeout(mut self, timeout: Duration) -> Self {
+        self.eth_rpc_timeout = Some(timeout);
+        self
+    }
+
+    /// How long a single RPC request to the xDai full node may take, 10 seconds by default
+    pub fn xdai_rpc_timeout(mut self, timeout: Duration) -> Self {
+        self.xdai_rpc_timeout = Some(timeout);
+        self
+    }
+
+    pub fn eth_chain_id(mut self, chain_id: u64) -> Self {
+        self.eth_chain_id = Some(chain_id);
+        self
+    }
+
+    pub fn xdai_chain_id(mut self, chain_id: u64) -> Self {
+        self.xdai_chain_id = Some(chain_id);
+        self
+    }
+
+    /// How Eth gas prices are chosen, the full node's suggestion by default
+    pub fn eth_gas_strategy(mut self, strategy: GasStrategy) -> Self {
+        self.eth_gas_strategy = Some(strategy);
+        self
+    }
+
+    /// How xDai gas prices are chosen, a fixed 10 gwei by default
+    pub fn xdai_gas_strategy(mut self, strategy: GasStrategy) -> Self {
+        self.xdai_gas_strategy = Some(strategy);
+        self
+    }
+
+    /// Limits on transaction submission, confirmation, event waits and whole conversions, none
+    /// by default
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = Some(timeouts);
+        self
+    }
+
+    /// Shares Web3 handles with every other bridge built over `pool`, by default bridges use
+    /// `Web3Pool::global()`
+    pub fn web3_pool(mut self, pool: Web3Pool) -> Self {
+        self.web3_pool = Some(pool);
+        self
+    }
+
+    /// Builds the `TokenBridge`, erroring if anything without a default was not provided
+    pub fn build(self) -> Result<TokenBridge, Error> {
+        if self.secret.is_none() && self.signer.is_none() {
+            return Err(format_err!(
+                "TokenBridgeBuilder is missing secret or signer"
+            ));
+        }
+        self.build_read_only()
+    }
+
+    /// Builds the `TokenBridge` without requiring a key, if no key was provided the result is
+    /// read only, see `TokenBridge::read_only`
+    pub fn build_read_only(self) -> Result<TokenBridge, Error> {
+        fn required<T>(value: Option<T>, name: &str) -> Result<T, Error> {
+            value.ok_or_else(|| format_err!("TokenBridgeBuilder is missing {}", name))
+        }
+
+        let signer = match (self.signer, self.secret) {
+            (Some(signer), _) => Some(signer),
+            (None, Some(secret)) => match self.own_address {
+                Some(own_address) => {
+                    Some(Arc::new(LocalSigner::with_address(secret, own_address)) as Arc<dyn Signer>)
+                }
+                None => Some(Arc::new(LocalSigner::new(secret)?) as Arc<dyn Signer>),
+            },
+            (None, None) => None,
+        };
+        let own_address = match (self.own_address, &signer) {
+            (Some(own_address), _) => Some(own_address),
+            (None, Some(signer)) => Some(signer.address()),
+            (None, None) => None,
+        };
+
+        let mut bridge = TokenBridge::read_only(
+            required(self.uniswap_address, "uniswap_address")?,
+            required(self.xdai_home_bridge_address, "xdai_home_bridge_address")?,
+            required(
+                self.xdai_foreign_bridge_address,
+                "xdai_foreign_bridge_address",
+            )?,
+            required(
+                self.foreign_dai_contract_address,
+                "foreign_dai_contract_address",
+            )?,
+            required(own_address, "own_address")?,
+            required(self.eth_full_node_url, "eth_full_node_url")?,
+            required(self.xdai_full_node_url, "xdai_full_node_url")?,
+        );
+        if let Some(ref signer) = signer {
+            bridge.accounts.add(signer.clone());
+        }
+        bridge.signer = signer;
+        if let Some(pool) = self.web3_pool {
+            bridge.web3_pool = pool;
+        }
+        bridge.set_rpc_timeouts(
+            self.eth_rpc_timeout.unwrap_or(DEFAULT_RPC_TIMEOUT),
+            self.xdai_rpc_timeout.unwrap_or(DEFAULT_RPC_TIMEOUT),
+        );
+        if self.eth_chain_id.is_some() {
+            bridge.eth_chain_id = self.eth_chain_id;
+        }
+        if self.xdai_chain_id.is_some() {
+            bridge.xdai_chain_id = self.xdai_chain_id;
+        }
+        if let Some(timeouts) = self.timeouts {
+            bridge.timeouts = timeouts;
+        }
+        if let Some(weth_address) = self.weth_address {
+            bridge.weth_address = weth_address;
+        }
+        bridge.private_relay_url = self.private_relay_url;
+        if let Some(strategy) = self.eth_gas_strategy {
+            bridge.eth_gas_strategy = strategy;
+        }
+        if let Some(strategy) = self.xdai_gas_strategy {
+            bridge.xdai_gas_strategy = strategy;
+        }
+        Ok(bridge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_mainnet_requires_key_and_urls() {
+        assert!(TokenBridgeBuilder::mainnet().build().is_err());
+        assert!(TokenBridgeBuilder::mainnet()
+            .own_address(Address::default())
+            .secret(PrivateKey::from_str(&"01".repeat(32)).unwrap())
+            .eth_full_node_url("https://eth.althea.org".into())
+            .xdai_full_node_url("https://dai.althea.org".into())
+            .build()
+            .is_ok());
+    }
+}
@@ -0,0 +1,93 @@
+//! Low level contract access for contracts this crate doesn't wrap itself
+
+use crate::abi::{decode_output, decode_values, AbiDecode, AbiType, AbiValue};
+use crate::events::BridgeEvent;
+use crate::timeouts::Timeouts;
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::abi::{encode_call, Token};
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use futures_timer::FutureExt;
+use num256::Uint256;
+use std::time::Duration;
+use web30::types::SendTxOption;
+
+impl TokenBridge {
+    /// Calls the read only function `signature` on `address` with `args` and decodes the result
+    /// as `T`.
+    pub fn call_view<T: AbiDecode + 'static>(
+        &self,
+        chain: Chain,
+        address: Address,
+        signature: &str,
+        args: &[Token],
+    ) -> Box<dyn Future<Item = T, Error = Error>> {
+        let web3 = self.web3(chain);
+        let own_address = self.own_address;
+        let function = signature.to_string();
+        let args = args.to_vec();
+
+        self.with_retry(chain, signature, move || {
+            let call = function.clone();
+            Box::new(
+                web3.contract_call(address, &function, &args, own_address)
+                    .and_then(move |output| decode_output(&call, &output)),
+            )
+        })
+    }
+
+    /// `call_view` for functions returning several values, decoded as `types` in order
+    pub fn call_view_values(
+        &self,
+        chain: Chain,
+        address: Address,
+        signature: &str,
+        args: &[Token],
+        types: &[AbiType],
+    ) -> Box<dyn Future<Item = Vec<AbiValue>, Error = Error>> {
+        let types = types.to_vec();
+        let call = signature.to_string();
+        Box::new(
+            self.call_view::<Vec<u8>>(chain, address, signature, args)
+                .and_then(move |output| decode_values(&call, &types, &output)),
+        )
+    }
+
+    /// Sends a transaction calling `signature` on `address` with `args` and `value` attached,
+    /// then waits up to `timeout` for it to be included in a block. `options` override the
+    /// defaults for `chain`. Returns the tx hash.
+    #[allow(clippy::too_many_arguments)]
+    pub fn send_call(
+        &self,
+        chain: Chain,
+        address: Address,
+        signature: &str,
+        args: &[Token],
+        value: Uint256,
+        options: Vec<SendTxOption>,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let web3 = self.web3(chain);
+        let payload = encode_call(signature, args);
+        let timeout = Timeouts::cap(timeout, self.timeouts.confirmation);
+
+        self.in_account_order(move |salf| {
+            Box::new(
+                salf.send_transaction(chain, address, payload, value, options)
+                    .and_then(move |tx_hash| {
+                        web3.wait_for_transaction(tx_hash.clone().into())
+                            .timeout(timeout)
+                            .map(move |_| {
+                                salf.emit(BridgeEvent::TxConfirmed {
+                                    chain,
+                                    tx_hash: tx_hash.clone(),
+                                });
+                                tx_hash
+                            })
+                    }),
+            )
+        })
+    }
+}
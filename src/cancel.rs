@@ -0,0 +1,71 @@
+//! Stopping long running conversions from the outside, for example when the process is asked
+//! to shut down while a conversion waits on the bridge.
+
+use failure::Error;
+use futures::future::{loop_fn, Loop};
+use futures::Future;
+use futures_timer::Delay;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How quickly a running conversion notices it was cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Shared between a conversion and whoever may want to stop it, clones cancel together.
+///
+/// Cancelling stops the conversion at the step it is in, nothing further is sent afterwards.
+/// A transaction sent by that step may still be mined, the operation stays checkpointed at the
+/// step so that `resume_pending` finds out whether it was and finishes the conversion.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Stops every conversion run with this token or one of its clones
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the token is cancelled
+    pub(crate) fn cancelled(&self) -> Box<dyn Future<Item = (), Error = Error>> {
+        let token = self.clone();
+        Box::new(loop_fn((), move |_| {
+            if token.is_cancelled() {
+                Box::new(futures::future::ok(Loop::Break(())))
+                    as Box<dyn Future<Item = _, Error = Error>>
+            } else {
+                Box::new(
+                    Delay::new(CANCEL_POLL_INTERVAL)
+                        .from_err()
+                        .map(|_| Loop::Continue(())),
+                )
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_token() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(!token.is_cancelled());
+        clone.cancel();
+        assert!(token.is_cancelled());
+        token.cancelled().wait().unwrap();
+        assert!(!CancelToken::new().is_cancelled());
+    }
+}
@@ -0,0 +1,162 @@
+//! Serializable configuration for building a `TokenBridge` from a settings file
+
+use crate::ens::AddressOrName;
+use crate::health::ETH_MAX_BLOCK_AGE;
+use crate::network::Network;
+use crate::GasStrategy;
+use crate::TokenBridge;
+use crate::DEFAULT_RPC_TIMEOUT;
+use crate::DEFAULT_SLIPPAGE_BPS;
+use crate::DEFAULT_XDAI_GAS_PRICE;
+use clarity::{Address, PrivateKey};
+use failure::Error;
+use futures::Future;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use web30::client::Web3;
+
+/// Everything needed to set up a `TokenBridge` except the private key, which is passed to
+/// `TokenBridge::from_config` separately so that it doesn't have to live in the same file.
+/// Fields missing when deserializing take their mainnet defaults. The addresses may be given
+/// as ENS names, which `resolve_names` looks up before the config can be used.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TokenBridgeConfig {
+    pub uniswap_address: AddressOrName,
+    /// This is the address of the xDai bridge on Eth
+    pub xdai_foreign_bridge_address: AddressOrName,
+    /// This is the address of the xDai bridge on xDai
+    pub xdai_home_bridge_address: AddressOrName,
+    /// This is the address of the Dai token contract on Eth
+    pub foreign_dai_contract_address: AddressOrName,
+    pub own_address: AddressOrName,
+    pub eth_full_node_url: String,
+    pub xdai_full_node_url: String,
+    /// How long a single RPC request to the Eth full node may take, in seconds
+    pub eth_rpc_timeout_secs: u64,
+    /// How long a single RPC request to the xDai full node may take, in seconds
+    pub xdai_rpc_timeout_secs: u64,
+    /// Chain id to sign Eth transactions with
+    pub eth_chain_id: Option<u64>,
+    /// Chain id to sign xDai transactions with
+    pub xdai_chain_id: Option<u64>,
+    pub eth_gas_strategy: GasStrategy,
+    pub xdai_gas_strategy: GasStrategy,
+    /// How far below the quoted price a swap may execute, in basis points
+    pub slippage_bps: u32,
+    /// Swaps with a larger price impact than this, in basis points, are refused
+    pub max_price_impact_bps: Option<u32>,
+    /// Swaps are refused if the Eth node's latest block is older than this, in seconds
+    pub max_eth_node_lag_secs: Option<u64>,
+    /// Swaps on Eth are sent to this endpoint instead of the full node, such as Flashbots
+    /// Protect
+    pub private_relay_url: Option<String>,
+}
+
+impl Default for TokenBridgeConfig {
+    fn default() -> TokenBridgeConfig {
+        let mainnet = Network::Mainnet.addresses();
+        TokenBridgeConfig {
+            uniswap_address: mainnet.uniswap_address.into(),
+            xdai_foreign_bridge_address: mainnet.xdai_foreign_bridge_address.into(),
+            xdai_home_bridge_address: mainnet.xdai_home_bridge_address.into(),
+            foreign_dai_contract_address: mainnet.foreign_dai_contract_address.into(),
+            own_address: Address::default().into(),
+            eth_full_node_url: String::new(),
+            xdai_full_node_url: String::new(),
+            eth_rpc_timeout_secs: DEFAULT_RPC_TIMEOUT.as_secs(),
+            xdai_rpc_timeout_secs: DEFAULT_RPC_TIMEOUT.as_secs(),
+            eth_chain_id: Some(mainnet.eth_chain_id),
+            xdai_chain_id: Some(mainnet.xdai_chain_id),
+            eth_gas_strategy: GasStrategy::Node,
+            xdai_gas_strategy: GasStrategy::Fixed(DEFAULT_XDAI_GAS_PRICE.into()),
+            slippage_bps: DEFAULT_SLIPPAGE_BPS,
+            max_price_impact_bps: None,
+            max_eth_node_lag_secs: Some(ETH_MAX_BLOCK_AGE.as_secs()),
+            private_relay_url: None,
+        }
+    }
+}
+
+impl TokenBridgeConfig {
+    /// Returns this config with every ENS name replaced by the address it resolves to on the
+    /// Eth full node. Fails with `TokenBridgeError::EnsResolution` if any of them doesn't.
+    pub fn resolve_names(&self) -> Box<dyn Future<Item = TokenBridgeConfig, Error = Error>> {
+        let web3 = Web3::new(
+            &self.eth_full_node_url,
+            Duration::from_secs(self.eth_rpc_timeout_secs),
+        );
+        let config = self.clone();
+        Box::new(
+            self.uniswap_address
+                .resolve(&web3)
+                .join5(
+                    self.xdai_foreign_bridge_address.resolve(&web3),
+                    self.xdai_home_bridge_address.resolve(&web3),
+                    self.foreign_dai_contract_address.resolve(&web3),
+                    self.own_address.resolve(&web3),
+                )
+                .map(
+                    move |(uniswap, foreign_bridge, home_bridge, dai, own)| TokenBridgeConfig {
+                        uniswap_address: uniswap.into(),
+                        xdai_foreign_bridge_address: foreign_bridge.into(),
+                        xdai_home_bridge_address: home_bridge.into(),
+                        foreign_dai_contract_address: dai.into(),
+                        own_address: own.into(),
+                        ..config
+                    },
+                ),
+        )
+    }
+}
+
+impl TokenBridge {
+    /// Fails with `TokenBridgeError::EnsResolution` if `config` still has ENS names in it, see
+    /// `TokenBridgeConfig::resolve_names`
+    pub fn from_config(
+        config: &TokenBridgeConfig,
+        secret: PrivateKey,
+    ) -> Result<TokenBridge, Error> {
+        Ok(TokenBridge::new(
+            config.uniswap_address.address()?,
+            config.xdai_home_bridge_address.address()?,
+            config.xdai_foreign_bridge_address.address()?,
+            config.foreign_dai_contract_address.address()?,
+            config.own_address.address()?,
+            secret,
+            config.eth_full_node_url.clone(),
+            config.xdai_full_node_url.clone(),
+        )
+        .configured(config))
+    }
+
+    /// Like `from_config` but without a key, see `read_only`
+    pub fn read_only_from_config(config: &TokenBridgeConfig) -> Result<TokenBridge, Error> {
+        Ok(TokenBridge::read_only(
+            config.uniswap_address.address()?,
+            config.xdai_home_bridge_address.address()?,
+            config.xdai_foreign_bridge_address.address()?,
+            config.foreign_dai_contract_address.address()?,
+            config.own_address.address()?,
+            config.eth_full_node_url.clone(),
+            config.xdai_full_node_url.clone(),
+        )
+        .configured(config))
+    }
+
+    fn configured(mut self, config: &TokenBridgeConfig) -> TokenBridge {
+        self.set_rpc_timeouts(
+            Duration::from_secs(config.eth_rpc_timeout_secs),
+            Duration::from_secs(config.xdai_rpc_timeout_secs),
+        );
+        self.eth_chain_id = config.eth_chain_id;
+        self.xdai_chain_id = config.xdai_chain_id;
+        self.eth_gas_strategy = config.eth_gas_strategy.clone();
+        self.xdai_gas_strategy = config.xdai_gas_strategy.clone();
+        self.slippage_bps = config.slippage_bps;
+        self.max_price_impact_bps = config.max_price_impact_bps;
+        self.max_eth_node_lag = config.max_eth_node_lag_secs.map(Duration::from_secs);
+        self.private_relay_url = config.private_relay_url.clone();
+        self
+    }
+}
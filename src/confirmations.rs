@@ -0,0 +1,114 @@
+//! Waiting until an event is buried under enough blocks that a reorg is unlikely to undo it,
+//! then checking that it is still there.
+
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::utils::bytes_to_hex_str;
+use failure::format_err;
+use failure::Error;
+use futures::future::{loop_fn, Loop};
+use futures::Future;
+use futures_timer::Delay;
+use num256::Uint256;
+use web30::types::{Log, NewFilter};
+
+/// Whether a log mined in `log_block` has at least `confirmations` blocks on top of it at
+/// `latest_block`
+pub fn is_confirmed(log_block: &Uint256, latest_block: &Uint256, confirmations: u32) -> bool {
+    *latest_block >= log_block.clone() + confirmations.into()
+}
+
+/// Whether `a` and `b` are the same log, as opposed to one re-mined differently
+fn same_log(a: &Log, b: &Log) -> bool {
+    a.transaction_hash == b.transaction_hash
+        && a.log_index == b.log_index
+        && a.topics == b.topics
+        && a.data == b.data
+        && b.removed != Some(true)
+}
+
+impl TokenBridge {
+    /// Waits until `log` has `confirmations` blocks on top of it on `chain`, polling the block
+    /// number as configured by `event_polling`, then fetches the logs of its block again and
+    /// fails with `TokenBridgeError::EventReorged` if it is gone. Resolves right away when
+    /// `confirmations` is zero.
+    pub fn wait_for_confirmations(
+        &self,
+        chain: Chain,
+        log: Log,
+        confirmations: u32,
+    ) -> Box<dyn Future<Item = Log, Error = Error>> {
+        if confirmations == 0 {
+            return Box::new(futures::future::ok(log));
+        }
+        let log_block = match log.block_number {
+            Some(ref block) => block.clone(),
+            None => {
+                return Box::new(futures::future::err(format_err!(
+                    "Can't wait for confirmations of a pending log"
+                )))
+            }
+        };
+        let web3 = self.web3(chain);
+        let polling = self.event_polling;
+
+        let confirmed = {
+            let web3 = web3.clone();
+            let log_block = log_block.clone();
+            loop_fn(1u32, move |attempt| {
+                let log_block = log_block.clone();
+                web3.eth_block_number().and_then(move |latest| {
+                    if is_confirmed(&log_block, &latest, confirmations) {
+                        Box::new(futures::future::ok(Loop::Break(())))
+                            as Box<dyn Future<Item = _, Error = Error>>
+                    } else {
+                        trace!(
+                            "log in block {} waiting for {} confirmations at {}",
+                            log_block,
+                            confirmations,
+                            latest
+                        );
+                        Box::new(
+                            Delay::new(polling.delay(attempt))
+                                .from_err()
+                                .map(move |_| Loop::Continue(attempt + 1)),
+                        )
+                    }
+                })
+            })
+        };
+
+        let block = format!("0x{}", log_block.to_str_radix(16));
+        let filter = NewFilter {
+            from_block: Some(block.clone()),
+            to_block: Some(block),
+            address: vec![log.address],
+            topics: Some(vec![log
+                .topics
+                .first()
+                .map(|topic| vec![Some(format!("0x{}", bytes_to_hex_str(topic)))])]),
+        };
+        Box::new(confirmed.and_then(move |_| {
+            web3.eth_get_logs(filter).and_then(move |logs| {
+                if logs.iter().any(|found| same_log(&log, found)) {
+                    Ok(log)
+                } else {
+                    Err(TokenBridgeError::EventReorged { block: log_block }.into())
+                }
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_confirmed() {
+        assert!(is_confirmed(&10u32.into(), &10u32.into(), 0));
+        assert!(!is_confirmed(&10u32.into(), &11u32.into(), 2));
+        assert!(is_confirmed(&10u32.into(), &12u32.into(), 2));
+    }
+}
@@ -0,0 +1,314 @@
+//! Every contract deployment this crate knows about, with the chain it is on and the selectors
+//! of the calls we send. The address constants re-exported by other modules and the `Network`
+//! presets are all defined here, so an address only ever has to be checked in one place.
+
+use clarity::Address;
+use std::str::FromStr;
+
+/// Chain id of Eth mainnet
+pub const ETH_MAINNET_CHAIN_ID: u64 = 1;
+/// Chain id of the xDai chain, now Gnosis Chain
+pub const XDAI_CHAIN_ID: u64 = 100;
+
+/// The Dai token on Eth mainnet
+pub const MAINNET_DAI: &str = "0x6B175474E89094C44Da98b954EedeAC495271d0F";
+/// Circle's USDC on Eth mainnet, 6 decimals
+pub const MAINNET_USDC: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48";
+/// USDC bridged to the xDai chain by the OmniBridge
+pub const XDAI_BRIDGED_USDC: &str = "0xDDAfbb505ad214D7b80b1f830fcCc89B60fb7A83";
+/// The legacy single collateral Dai token on Eth mainnet, what the bridge used before Dai
+pub const MAINNET_SAI: &str = "0x89d24A6b4CcB1B6fAA2625fE562bDD9a23260359";
+/// MakerDAO's `ScdMcdMigration`, swaps SAI for Dai one to one
+pub const MAINNET_SAI_MIGRATION: &str = "0xc73e0383F3Aff3215E6f04B0331D58CeCf0Ab849";
+/// The xDai bridge on Eth mainnet
+pub const MAINNET_XDAI_FOREIGN_BRIDGE: &str = "0x4aa42145Aa6Ebf72e164C9bBC74fbD3788045016";
+/// The xDai bridge on the xDai chain
+pub const MAINNET_XDAI_HOME_BRIDGE: &str = "0x7301CFA0e1756B71869E93d4e4Dca5c7d0eb0AA6";
+/// The OmniBridge token mediator on Eth mainnet
+pub const MAINNET_OMNIBRIDGE_FOREIGN: &str = "0x88ad09518695c6c3712AC10a214bE5109a655671";
+/// The OmniBridge token mediator on the xDai chain
+pub const MAINNET_OMNIBRIDGE_HOME: &str = "0xf6A78083ca3e2a662D6dd1703c939c8aCE2e268d";
+/// The AMB on Eth mainnet
+pub const MAINNET_FOREIGN_AMB: &str = "0x4C36d2919e407f0Cc2Ee3c993ccF8ac26d9CE64e";
+/// The AMB on the xDai chain
+pub const MAINNET_HOME_AMB: &str = "0x75Df5AF045d91108662D8080fD1FEFAd6aA0bb59";
+/// The Uniswap V1 factory on Eth mainnet
+pub const MAINNET_UNISWAP_FACTORY: &str = "0xc0a47dFe034B400B47bDaD5FecDa2621de6c4d95";
+/// The Uniswap V1 exchange for Dai on Eth mainnet
+pub const MAINNET_UNISWAP_DAI_EXCHANGE: &str = "0x2a1530C4C41db0B0b2bB646CB5Eb1A67b7158667";
+/// The Uniswap V1 exchange for SAI on Eth mainnet
+pub const MAINNET_UNISWAP_SAI_EXCHANGE: &str = "0x09cabEC1eAd1c0Ba254B09efb3EE13841712bE14";
+/// The Uniswap V2 router on Eth mainnet
+pub const MAINNET_UNISWAP_V2_ROUTER: &str = "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D";
+/// The Uniswap V3 `SwapRouter` on Eth mainnet
+pub const MAINNET_UNISWAP_V3_ROUTER: &str = "0xE592427A0AEce92De3Edee1F18E0157C05861564";
+/// The SushiSwap router on Eth mainnet
+pub const MAINNET_SUSHISWAP_ROUTER: &str = "0xd9e1cE17f2641f24aE83637ab66a2cca9C378B9F";
+/// The WETH9 contract on Eth mainnet
+pub const MAINNET_WETH: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+/// MakerDAO's Multicall on Eth mainnet
+pub const MAINNET_MULTICALL: &str = "0xeefBa1e63905eF1D7ACbA5a8513c70307C1cE441";
+/// The Chainlink ETH/USD aggregator proxy on Eth mainnet
+pub const MAINNET_ETH_USD_AGGREGATOR: &str = "0x5f4eC3Df9cbd43714FE2740f5E3616155c5b8419";
+/// The ENS registry, at the same address on mainnet and the testnets
+pub const ENS_REGISTRY: &str = "0x00000000000C2E074eC69A0dFb2997BA6C7d2e1e";
+/// The Honeyswap router on xDai
+pub const XDAI_HONEYSWAP_ROUTER: &str = "0x1C232F01118CB8B424793ae03F870aa7D0ac7f77";
+/// Wrapped xDai, which the router trades in place of xDai
+pub const XDAI_WXDAI: &str = "0xe91D153E0b41518A2Ce8Dd3D7944Fa863463a97d";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContractKind {
+    Dai,
+    Usdc,
+    BridgedUsdc,
+    Sai,
+    SaiMigration,
+    /// The xDai bridge, upgraded in place from SAI (v1) to Dai (v2) so both versions share
+    /// their addresses
+    XdaiForeignBridge,
+    XdaiHomeBridge,
+    OmniBridgeForeign,
+    OmniBridgeHome,
+    ForeignAmb,
+    HomeAmb,
+    UniswapV1Factory,
+    UniswapV1DaiExchange,
+    UniswapV1SaiExchange,
+    UniswapV2Router,
+    UniswapV3Router,
+    SushiSwapRouter,
+    Weth,
+    Multicall,
+    EthUsdAggregator,
+    EnsRegistry,
+    HoneyswapRouter,
+    Wxdai,
+}
+
+/// One deployment of a contract
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KnownContract {
+    pub kind: ContractKind,
+    pub chain_id: u64,
+    pub address: &'static str,
+}
+
+impl KnownContract {
+    pub fn address(&self) -> Address {
+        Address::from_str(self.address).unwrap()
+    }
+}
+
+const fn eth(kind: ContractKind, address: &'static str) -> KnownContract {
+    KnownContract {
+        kind,
+        chain_id: ETH_MAINNET_CHAIN_ID,
+        address,
+    }
+}
+
+const fn xdai(kind: ContractKind, address: &'static str) -> KnownContract {
+    KnownContract {
+        kind,
+        chain_id: XDAI_CHAIN_ID,
+        address,
+    }
+}
+
+pub const KNOWN_CONTRACTS: &[KnownContract] = &[
+    eth(ContractKind::Dai, MAINNET_DAI),
+    eth(ContractKind::Usdc, MAINNET_USDC),
+    xdai(ContractKind::BridgedUsdc, XDAI_BRIDGED_USDC),
+    eth(ContractKind::Sai, MAINNET_SAI),
+    eth(ContractKind::SaiMigration, MAINNET_SAI_MIGRATION),
+    eth(ContractKind::XdaiForeignBridge, MAINNET_XDAI_FOREIGN_BRIDGE),
+    xdai(ContractKind::XdaiHomeBridge, MAINNET_XDAI_HOME_BRIDGE),
+    eth(ContractKind::OmniBridgeForeign, MAINNET_OMNIBRIDGE_FOREIGN),
+    xdai(ContractKind::OmniBridgeHome, MAINNET_OMNIBRIDGE_HOME),
+    eth(ContractKind::ForeignAmb, MAINNET_FOREIGN_AMB),
+    xdai(ContractKind::HomeAmb, MAINNET_HOME_AMB),
+    eth(ContractKind::UniswapV1Factory, MAINNET_UNISWAP_FACTORY),
+    eth(
+        ContractKind::UniswapV1DaiExchange,
+        MAINNET_UNISWAP_DAI_EXCHANGE,
+    ),
+    eth(
+        ContractKind::UniswapV1SaiExchange,
+        MAINNET_UNISWAP_SAI_EXCHANGE,
+    ),
+    eth(ContractKind::UniswapV2Router, MAINNET_UNISWAP_V2_ROUTER),
+    eth(ContractKind::UniswapV3Router, MAINNET_UNISWAP_V3_ROUTER),
+    eth(ContractKind::SushiSwapRouter, MAINNET_SUSHISWAP_ROUTER),
+    eth(ContractKind::Weth, MAINNET_WETH),
+    eth(ContractKind::Multicall, MAINNET_MULTICALL),
+    eth(ContractKind::EthUsdAggregator, MAINNET_ETH_USD_AGGREGATOR),
+    eth(ContractKind::EnsRegistry, ENS_REGISTRY),
+    xdai(ContractKind::HoneyswapRouter, XDAI_HONEYSWAP_ROUTER),
+    xdai(ContractKind::Wxdai, XDAI_WXDAI),
+];
+
+/// The deployment of `kind` on `chain_id`, if we know of one
+pub fn known_contract(kind: ContractKind, chain_id: u64) -> Option<&'static KnownContract> {
+    KNOWN_CONTRACTS
+        .iter()
+        .find(|contract| contract.kind == kind && contract.chain_id == chain_id)
+}
+
+/// What the contract at `address` on `chain_id` is, if we know it
+pub fn identify(chain_id: u64, address: Address) -> Option<&'static KnownContract> {
+    KNOWN_CONTRACTS
+        .iter()
+        .find(|contract| contract.chain_id == chain_id && contract.address() == address)
+}
+
+/// A four byte function selector along with the signature it is derived from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selector {
+    pub signature: &'static str,
+    pub selector: [u8; 4],
+}
+
+pub const ERC20_TRANSFER_SELECTOR: Selector = Selector {
+    signature: "transfer(address,uint256)",
+    selector: [0xa9, 0x05, 0x9c, 0xbb],
+};
+pub const ERC20_APPROVE_SELECTOR: Selector = Selector {
+    signature: "approve(address,uint256)",
+    selector: [0x09, 0x5e, 0xa7, 0xb3],
+};
+pub const ERC20_TRANSFER_FROM_SELECTOR: Selector = Selector {
+    signature: "transferFrom(address,address,uint256)",
+    selector: [0x23, 0xb8, 0x72, 0xdd],
+};
+pub const ERC20_BALANCE_OF_SELECTOR: Selector = Selector {
+    signature: "balanceOf(address)",
+    selector: [0x70, 0xa0, 0x82, 0x31],
+};
+pub const ERC20_ALLOWANCE_SELECTOR: Selector = Selector {
+    signature: "allowance(address,address)",
+    selector: [0xdd, 0x62, 0xed, 0x3e],
+};
+pub const WETH_DEPOSIT_SELECTOR: Selector = Selector {
+    signature: "deposit()",
+    selector: [0xd0, 0xe3, 0x0d, 0xb0],
+};
+pub const WETH_WITHDRAW_SELECTOR: Selector = Selector {
+    signature: "withdraw(uint256)",
+    selector: [0x2e, 0x1a, 0x7d, 0x4d],
+};
+pub const FOREIGN_BRIDGE_RELAY_TOKENS_SELECTOR: Selector = Selector {
+    signature: "relayTokens(address,uint256)",
+    selector: [0x01, 0xe4, 0xf5, 0x3a],
+};
+pub const HOME_BRIDGE_RELAY_TOKENS_SELECTOR: Selector = Selector {
+    signature: "relayTokens(address)",
+    selector: [0x5d, 0x1e, 0x93, 0x07],
+};
+pub const OMNIBRIDGE_RELAY_TOKENS_SELECTOR: Selector = Selector {
+    signature: "relayTokens(address,address,uint256)",
+    selector: [0xad, 0x58, 0xbd, 0xd1],
+};
+pub const UNISWAP_ETH_TO_TOKEN_SWAP_SELECTOR: Selector = Selector {
+    signature: "ethToTokenSwapInput(uint256,uint256)",
+    selector: [0xf3, 0x9b, 0x5b, 0x9b],
+};
+pub const UNISWAP_ETH_TO_TOKEN_TRANSFER_SELECTOR: Selector = Selector {
+    signature: "ethToTokenTransferInput(uint256,uint256,address)",
+    selector: [0xad, 0x65, 0xd7, 0x6d],
+};
+pub const UNISWAP_TOKEN_TO_ETH_SWAP_SELECTOR: Selector = Selector {
+    signature: "tokenToEthSwapInput(uint256,uint256,uint256)",
+    selector: [0x95, 0xe3, 0xc5, 0x0b],
+};
+pub const UNISWAP_TOKEN_TO_ETH_TRANSFER_SELECTOR: Selector = Selector {
+    signature: "tokenToEthTransferInput(uint256,uint256,uint256,address)",
+    selector: [0x72, 0x37, 0xe0, 0x31],
+};
+pub const UNISWAP_TOKEN_TO_TOKEN_SWAP_SELECTOR: Selector = Selector {
+    signature: "tokenToTokenSwapInput(uint256,uint256,uint256,uint256,address)",
+    selector: [0xdd, 0xf7, 0xe1, 0xa7],
+};
+pub const ROUTER_SWAP_EXACT_ETH_FOR_TOKENS_SELECTOR: Selector = Selector {
+    signature: "swapExactETHForTokens(uint256,address[],address,uint256)",
+    selector: [0x7f, 0xf3, 0x6a, 0xb5],
+};
+pub const ROUTER_SWAP_EXACT_TOKENS_FOR_ETH_SELECTOR: Selector = Selector {
+    signature: "swapExactTokensForETH(uint256,uint256,address[],address,uint256)",
+    selector: [0x18, 0xcb, 0xaf, 0xe5],
+};
+pub const AMB_REQUIRE_TO_PASS_MESSAGE_SELECTOR: Selector = Selector {
+    signature: "requireToPassMessage(address,bytes,uint256)",
+    selector: [0xdc, 0x86, 0x01, 0xb3],
+};
+pub const SAI_MIGRATION_SWAP_SAI_TO_DAI_SELECTOR: Selector = Selector {
+    signature: "swapSaiToDai(uint256)",
+    selector: [0xfb, 0xab, 0xde, 0xbd],
+};
+pub const MULTICALL_AGGREGATE_SELECTOR: Selector = Selector {
+    signature: "aggregate((address,bytes)[])",
+    selector: [0x25, 0x2d, 0xba, 0x42],
+};
+
+pub const KNOWN_SELECTORS: &[Selector] = &[
+    ERC20_TRANSFER_SELECTOR,
+    ERC20_APPROVE_SELECTOR,
+    ERC20_TRANSFER_FROM_SELECTOR,
+    ERC20_BALANCE_OF_SELECTOR,
+    ERC20_ALLOWANCE_SELECTOR,
+    WETH_DEPOSIT_SELECTOR,
+    WETH_WITHDRAW_SELECTOR,
+    FOREIGN_BRIDGE_RELAY_TOKENS_SELECTOR,
+    HOME_BRIDGE_RELAY_TOKENS_SELECTOR,
+    OMNIBRIDGE_RELAY_TOKENS_SELECTOR,
+    UNISWAP_ETH_TO_TOKEN_SWAP_SELECTOR,
+    UNISWAP_ETH_TO_TOKEN_TRANSFER_SELECTOR,
+    UNISWAP_TOKEN_TO_ETH_SWAP_SELECTOR,
+    UNISWAP_TOKEN_TO_ETH_TRANSFER_SELECTOR,
+    UNISWAP_TOKEN_TO_TOKEN_SWAP_SELECTOR,
+    ROUTER_SWAP_EXACT_ETH_FOR_TOKENS_SELECTOR,
+    ROUTER_SWAP_EXACT_TOKENS_FOR_ETH_SELECTOR,
+    AMB_REQUIRE_TO_PASS_MESSAGE_SELECTOR,
+    SAI_MIGRATION_SWAP_SAI_TO_DAI_SELECTOR,
+    MULTICALL_AGGREGATE_SELECTOR,
+];
+
+/// The known call `data` starts with, for telling what a transaction does
+pub fn identify_call(data: &[u8]) -> Option<&'static Selector> {
+    KNOWN_SELECTORS
+        .iter()
+        .find(|known| data.len() >= 4 && data[..4] == known.selector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clarity::abi::derive_method_id;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_selectors_match_signatures() {
+        for known in KNOWN_SELECTORS {
+            assert_eq!(
+                derive_method_id(known.signature),
+                known.selector,
+                "{}",
+                known.signature
+            );
+        }
+        assert_eq!(
+            identify_call(&[0xa9, 0x05, 0x9c, 0xbb, 0x00]),
+            Some(&ERC20_TRANSFER_SELECTOR)
+        );
+        assert_eq!(identify_call(&[0xa9, 0x05]), None);
+    }
+
+    #[test]
+    fn test_known_contracts_unique() {
+        let mut seen = HashSet::new();
+        for contract in KNOWN_CONTRACTS {
+            assert!(seen.insert((contract.kind, contract.chain_id)));
+            assert_eq!(contract.address.len(), 42, "{:?}", contract.kind);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! What an ETH <-> xDai conversion of a given size costs right now, so that operators can tell
+//! whether a rebalance is worth it.
+
+use crate::fee::{bridge_fee_amount, BridgeDirection};
+use crate::operations::OperationKind;
+use crate::units::{Dai, Eth, XDai};
+use crate::Chain;
+use crate::TokenBridge;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+
+/// Gas limit of a Uniswap swap, as sent by `eth_to_dai_swap` and `dai_to_eth_swap`
+pub(crate) const SWAP_GAS: u64 = 80_000;
+/// Gas limit of the Dai transfer sent by `dai_to_xdai_bridge`
+pub(crate) const DAI_TRANSFER_GAS: u64 = 80_000;
+/// Rough gas use of a transfer to the home bridge on xDai
+pub(crate) const XDAI_BRIDGE_GAS: u64 = 100_000;
+/// The mid price is measured on a trade of this much ETH, 0.001, small enough that price impact
+/// doesn't matter
+pub(crate) const REFERENCE_ETH: u64 = 1_000_000_000_000_000;
+
+/// Breakdown of what a conversion costs. Gas is an estimate from the gas limits the
+/// transactions are sent with, so it is on the high side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionCost {
+    pub direction: OperationKind,
+    /// What is converted, ETH for `EthToXdai` and xDai for `XdaiToEth`
+    pub amount: Uint256,
+    /// Value lost on Uniswap compared to the mid price, the exchange fee plus price impact
+    pub swap_cost: Dai,
+    pub bridge_fee: Dai,
+    pub eth_gas: Eth,
+    pub xdai_gas: XDai,
+    /// Everything above in Dai, ETH is valued at the mid price
+    pub total: Dai,
+}
+
+pub(crate) fn saturating_sub(a: Uint256, b: Uint256) -> Uint256 {
+    if a > b {
+        a - b
+    } else {
+        0u32.into()
+    }
+}
+
+/// Puts the breakdown together. `mid_price` is the Dai value of `REFERENCE_ETH` and `swap_out`
+/// what Uniswap quotes for the swap step, Dai for `EthToXdai` and ETH for `XdaiToEth`.
+fn conversion_cost(
+    direction: OperationKind,
+    amount: Uint256,
+    mid_price: Uint256,
+    bridge_fee: Uint256,
+    swap_out: Uint256,
+    eth_gas_price: Uint256,
+    xdai_gas_price: Uint256,
+) -> ConversionCost {
+    let reference: Uint256 = REFERENCE_ETH.into();
+    let eth_in_dai = |eth: Uint256| eth * mid_price.clone() / reference.clone();
+
+    let (swap_cost, bridge_fee, eth_gas, xdai_gas) = match direction {
+        OperationKind::EthToXdai => {
+            let fair_dai = eth_in_dai(amount.clone());
+            (
+                saturating_sub(fair_dai, swap_out.clone()),
+                bridge_fee_amount(swap_out, bridge_fee),
+                eth_gas_price * (SWAP_GAS + DAI_TRANSFER_GAS).into(),
+                0u32.into(),
+            )
+        }
+        OperationKind::XdaiToEth => {
+            let fee = bridge_fee_amount(amount.clone(), bridge_fee);
+            let dai = saturating_sub(amount.clone(), fee.clone());
+            (
+                saturating_sub(dai, eth_in_dai(swap_out)),
+                fee,
+                eth_gas_price * SWAP_GAS.into(),
+                xdai_gas_price * XDAI_BRIDGE_GAS.into(),
+            )
+        }
+    };
+    let total =
+        swap_cost.clone() + bridge_fee.clone() + eth_in_dai(eth_gas.clone()) + xdai_gas.clone();
+
+    ConversionCost {
+        direction,
+        amount,
+        swap_cost: Dai::from_wei(swap_cost),
+        bridge_fee: Dai::from_wei(bridge_fee),
+        eth_gas: Eth::from_wei(eth_gas),
+        xdai_gas: XDai::from_wei(xdai_gas),
+        total: Dai::from_wei(total),
+    }
+}
+
+impl TokenBridge {
+    /// Estimates what converting `amount` in `direction` would cost at current prices.
+    /// `amount` is ETH for `EthToXdai` and xDai for `XdaiToEth`, like `Operation::amount`.
+    pub fn estimate_conversion_cost(
+        &self,
+        direction: OperationKind,
+        amount: Uint256,
+    ) -> Box<dyn Future<Item = ConversionCost, Error = Error>> {
+        let bridge_direction = match direction {
+            OperationKind::EthToXdai => BridgeDirection::DaiToXdai,
+            OperationKind::XdaiToEth => BridgeDirection::XdaiToDai,
+        };
+        let salf = self.clone();
+
+        Box::new(
+            self.get_price_quote(Eth::from_wei(REFERENCE_ETH.into()))
+                .join4(
+                    self.get_bridge_fee(bridge_direction),
+                    self.gas_price(Chain::Eth),
+                    self.gas_price(Chain::Xdai),
+                )
+                .and_then(move |(quote, fee, eth_gas_price, xdai_gas_price)| {
+                    let swap_out: Box<dyn Future<Item = Uint256, Error = Error>> = match direction {
+                        OperationKind::EthToXdai => Box::new(
+                            salf.eth_to_dai_price(Eth::from_wei(amount.clone()))
+                                .map(Dai::into_wei),
+                        ),
+                        OperationKind::XdaiToEth => {
+                            let fee_amount = bridge_fee_amount(amount.clone(), fee.clone());
+                            let dai = saturating_sub(amount.clone(), fee_amount);
+                            Box::new(salf.dai_to_eth_price(Dai::from_wei(dai)).map(Eth::into_wei))
+                        }
+                    };
+                    swap_out.map(move |swap_out| {
+                        conversion_cost(
+                            direction,
+                            amount,
+                            quote.mid_price.into_wei(),
+                            fee,
+                            swap_out,
+                            eth_gas_price,
+                            xdai_gas_price,
+                        )
+                    })
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversion_cost() {
+        // one ETH is 200 Dai, so REFERENCE_ETH is 0.2 Dai
+        let mid: Uint256 = 200_000_000_000_000_000u64.into();
+        let one_eth: Uint256 = 1_000_000_000_000_000_000u64.into();
+        let dai = |n: u64| -> Uint256 { Uint256::from(n) * one_eth.clone() };
+        let one_percent: Uint256 = 10_000_000_000_000_000u64.into();
+
+        let cost = conversion_cost(
+            OperationKind::EthToXdai,
+            one_eth.clone(),
+            mid.clone(),
+            one_percent.clone(),
+            dai(198),
+            1_000_000_000u64.into(),
+            1_000_000_000u64.into(),
+        );
+        assert_eq!(cost.swap_cost, Dai::from_wei(dai(2)));
+        assert_eq!(cost.bridge_fee, Dai::from_wei(dai(198) / 100u32.into()));
+        assert_eq!(cost.eth_gas, Eth::from_wei(160_000_000_000_000u64.into()));
+        assert_eq!(cost.xdai_gas, XDai::from_wei(0u32.into()));
+        // 2 + 1.98 + 0.032 Dai of gas
+        assert_eq!(
+            cost.total,
+            Dai::from_wei(4_012_000_000_000_000_000u64.into())
+        );
+
+        let cost = conversion_cost(
+            OperationKind::XdaiToEth,
+            dai(100),
+            mid,
+            one_percent,
+            490_000_000_000_000_000u64.into(),
+            0u32.into(),
+            1_000_000_000u64.into(),
+        );
+        assert_eq!(cost.bridge_fee, Dai::from_wei(dai(1)));
+        // 99 Dai swapped for 98 Dai worth of ETH
+        assert_eq!(cost.swap_cost, Dai::from_wei(dai(1)));
+        assert_eq!(cost.xdai_gas, XDai::from_wei(100_000_000_000_000u64.into()));
+    }
+}
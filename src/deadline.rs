@@ -0,0 +1,160 @@
+//! Swaps carry an on chain deadline, so whether one happened is only settled once a block past
+//! the deadline is mined. Until then a swap that hasn't shown up yet may still land, however
+//! long the RPC calls took.
+
+use crate::audit::TxStatus;
+use crate::logs::SwapEvent;
+use crate::reconcile::emitted_by;
+use crate::subscription::event_filter;
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use failure::Error;
+use futures::future::{loop_fn, Loop};
+use futures::Future;
+use futures_timer::Delay;
+use num256::Uint256;
+
+/// Calldata for a Uniswap swap together with what is needed to settle its outcome
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwapCall {
+    pub data: Vec<u8>,
+    /// The latest block when the call was built, the swap can't be mined before it
+    pub block: Uint256,
+    /// Unix time after which the swap reverts
+    pub deadline: Uint256,
+}
+
+/// How a swap ended, as far as the chain is concerned
+#[derive(Debug, Clone, PartialEq)]
+pub enum SwapOutcome {
+    /// The swap was mined and paid out `amount_out`
+    Executed { amount_out: Uint256 },
+    /// A block past `deadline` was mined without the swap, it can no longer happen
+    Expired { deadline: Uint256 },
+}
+
+impl SwapOutcome {
+    /// The amount received, or `TokenBridgeError::SwapExpired`
+    pub fn executed(self) -> Result<Uint256, Error> {
+        match self {
+            SwapOutcome::Executed { amount_out } => Ok(amount_out),
+            SwapOutcome::Expired { deadline } => {
+                Err(TokenBridgeError::SwapExpired { deadline }.into())
+            }
+        }
+    }
+}
+
+/// Whether a block with `timestamp` is past `deadline`, Uniswap accepts swaps up to and
+/// including the deadline itself
+pub fn deadline_passed(timestamp: &Uint256, deadline: &Uint256) -> bool {
+    timestamp > deadline
+}
+
+impl TokenBridge {
+    /// Records what became of the swap sent as `tx_hash`, see `audit_sink`
+    pub(crate) fn audit_swap_outcome(&self, tx_hash: Uint256, outcome: &SwapOutcome) {
+        let status = match outcome {
+            SwapOutcome::Executed { .. } => TxStatus::Executed,
+            SwapOutcome::Expired { .. } => TxStatus::NotExecuted,
+        };
+        self.audit_settled(Chain::Eth, tx_hash, status);
+    }
+
+    /// Waits for the swap on Uniswap sent as `tx_hash`, described by `swap_event`, to either
+    /// show up or become impossible because a block past `deadline` was mined. Only the event
+    /// emitted by `tx_hash` counts, the logs since `start_block` are searched for it after each
+    /// look at the latest block, so the result is definitive once the deadline has passed.
+    pub fn wait_for_swap_outcome(
+        &self,
+        swap_event: SwapEvent,
+        tx_hash: Uint256,
+        start_block: Uint256,
+        deadline: Uint256,
+    ) -> Box<dyn Future<Item = SwapOutcome, Error = Error>> {
+        let mut filter = event_filter(
+            self.uniswap_address,
+            swap_event.definition.signature,
+            Some(vec![self.own_address.into()]),
+            None,
+            None,
+        );
+        filter.from_block = Some(format!("0x{}", start_block.to_str_radix(16)));
+        filter.to_block = Some("latest".to_string());
+        let polling = self.event_polling;
+        let salf = self.clone();
+
+        let found = {
+            let salf = self.clone();
+            let deadline = deadline.clone();
+            loop_fn(1u32, move |attempt| {
+                let web3 = salf.eth_web3.clone();
+                let filter = filter.clone();
+                let deadline = deadline.clone();
+                let tx_hash = tx_hash.clone();
+                // the block first, logs read after it include everything up to it
+                salf.eth_web3
+                    .eth_get_latest_block()
+                    .and_then(move |block| {
+                        web3.eth_get_logs(filter).map(move |logs| {
+                            let log = logs
+                                .into_iter()
+                                .find(|log| log.removed != Some(true) && emitted_by(log, &tx_hash));
+                            (log, deadline_passed(&block.timestamp, &deadline))
+                        })
+                    })
+                    .and_then(move |(log, passed)| match (log, passed) {
+                        (Some(log), _) => Box::new(futures::future::ok(Loop::Break(Some(log))))
+                            as Box<dyn Future<Item = _, Error = Error>>,
+                        (None, true) => Box::new(futures::future::ok(Loop::Break(None))),
+                        (None, false) => Box::new(
+                            Delay::new(polling.delay(attempt))
+                                .from_err()
+                                .map(move |_| Loop::Continue(attempt + 1)),
+                        ),
+                    })
+            })
+        };
+
+        Box::new(found.and_then(move |log| {
+            match log {
+                Some(log) => Box::new(
+                    salf.wait_for_confirmations(Chain::Eth, log, salf.confirmations)
+                        .and_then(move |log| {
+                            let amount_out = swap_event
+                                .definition
+                                .decode(&log)?
+                                .uint(swap_event.amount_out)?;
+                            Ok(SwapOutcome::Executed { amount_out })
+                        }),
+                ) as Box<dyn Future<Item = _, Error = Error>>,
+                None => Box::new(futures::future::ok(SwapOutcome::Expired { deadline })),
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome() {
+        assert!(!deadline_passed(&100u32.into(), &100u32.into()));
+        assert!(deadline_passed(&101u32.into(), &100u32.into()));
+
+        let executed = SwapOutcome::Executed {
+            amount_out: 5u32.into(),
+        };
+        assert_eq!(executed.executed().unwrap(), 5u32.into());
+        let expired = SwapOutcome::Expired {
+            deadline: 100u32.into(),
+        };
+        assert!(expired
+            .executed()
+            .unwrap_err()
+            .downcast_ref::<TokenBridgeError>()
+            .is_some());
+    }
+}
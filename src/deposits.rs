@@ -0,0 +1,208 @@
+//! Watching an address on xDai for incoming funds. Bridge payouts are found by the home
+//! bridge's `AffirmationCompleted` events, anything else the balance went up by is reported as
+//! a plain transfer, since xDai transfers leave no log to find them by.
+
+use crate::cost::saturating_sub;
+use crate::logs::{EventDefinition, EventParam, ParamType};
+use crate::units::XDai;
+use crate::TokenBridge;
+use clarity::abi::derive_signature;
+use clarity::utils::bytes_to_hex_str;
+use clarity::Address;
+use failure::Error;
+use futures::{Future, Stream};
+use futures_timer::Delay;
+use num256::Uint256;
+use std::time::Duration;
+use web30::types::NewFilter;
+
+/// About one xDai block
+pub const INCOMING_XDAI_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Emitted by the home bridge when it pays out xDai for Dai sent to the foreign bridge
+pub const HOME_BRIDGE_AFFIRMATION_COMPLETED: EventDefinition = EventDefinition {
+    signature: "AffirmationCompleted(address,uint256,bytes32)",
+    params: &[
+        EventParam {
+            name: "recipient",
+            kind: ParamType::Address,
+            indexed: false,
+        },
+        EventParam {
+            name: "value",
+            kind: ParamType::Uint256,
+            indexed: false,
+        },
+        EventParam {
+            name: "transactionHash",
+            kind: ParamType::Bytes32,
+            indexed: false,
+        },
+    ],
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DepositSource {
+    /// Paid out by the bridge for the Dai transfer `eth_tx_hash` on Eth
+    Bridge { eth_tx_hash: [u8; 32] },
+    /// Any other increase of the balance
+    Transfer,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncomingXdai {
+    pub amount: XDai,
+    /// The block it was seen in, for transfers the latest block when the balance was read
+    pub block: Uint256,
+    pub source: DepositSource,
+}
+
+/// The deposits found by one poll, and the block and balance the next one continues from
+type PollResult = (Vec<IncomingXdai>, (Uint256, Uint256));
+
+/// What arrived between two polls, given the bridge payouts seen in between. Whatever the
+/// balance grew by beyond the payouts came in some other way.
+fn incoming_between(
+    bridged: Vec<IncomingXdai>,
+    last_balance: Uint256,
+    balance: Uint256,
+    block: Uint256,
+) -> Vec<IncomingXdai> {
+    let bridged_total = bridged.iter().fold(Uint256::from(0u32), |total, deposit| {
+        total + deposit.amount.wei().clone()
+    });
+    let transferred = saturating_sub(saturating_sub(balance, last_balance), bridged_total);
+    let mut incoming = bridged;
+    if transferred > 0u32.into() {
+        incoming.push(IncomingXdai {
+            amount: XDai::from_wei(transferred),
+            block,
+            source: DepositSource::Transfer,
+        });
+    }
+    incoming
+}
+
+impl TokenBridge {
+    /// A stream of xDai arriving at `address` from now on, polling every
+    /// `INCOMING_XDAI_POLL_INTERVAL`. Transfers are measured by the balance, so xDai spent from
+    /// `address` within the same poll hides as much of a transfer. Bridge payouts are always
+    /// reported in full. The stream ends on the first RPC error.
+    pub fn subscribe_incoming_xdai(
+        &self,
+        address: Address,
+    ) -> Box<dyn Stream<Item = IncomingXdai, Error = Error>> {
+        let salf = self.clone();
+        let start = self
+            .xdai_web3
+            .eth_block_number()
+            .join(self.xdai_web3.eth_get_balance(address));
+
+        Box::new(
+            start
+                .map(move |state| {
+                    futures::stream::unfold(state, move |(last_block, last_balance)| {
+                        let salf = salf.clone();
+                        Some(Delay::new(INCOMING_XDAI_POLL_INTERVAL).from_err().and_then(
+                            move |_| salf.poll_incoming_xdai(address, last_block, last_balance),
+                        ))
+                    })
+                })
+                .flatten_stream()
+                .map(futures::stream::iter_ok)
+                .flatten(),
+        )
+    }
+
+    /// Everything that arrived at `address` after `last_block`, along with the block and
+    /// balance to continue from
+    fn poll_incoming_xdai(
+        &self,
+        address: Address,
+        last_block: Uint256,
+        last_balance: Uint256,
+    ) -> Box<dyn Future<Item = PollResult, Error = Error>> {
+        let web3 = self.xdai_web3.clone();
+        let home_bridge = self.xdai_home_bridge_address;
+        let latencies = self.bridge_latencies.clone();
+
+        Box::new(
+            self.xdai_web3
+                .eth_block_number()
+                .join(self.xdai_web3.eth_get_balance(address))
+                .and_then(move |(block, balance)| {
+                    if block <= last_block {
+                        return Box::new(futures::future::ok((
+                            Vec::new(),
+                            (last_block, last_balance),
+                        )))
+                            as Box<dyn Future<Item = _, Error = Error>>;
+                    }
+                    let filter = NewFilter {
+                        from_block: Some(format!(
+                            "0x{}",
+                            (last_block + 1u32.into()).to_str_radix(16)
+                        )),
+                        to_block: Some(format!("0x{}", block.to_str_radix(16))),
+                        address: vec![home_bridge],
+                        topics: Some(vec![Some(vec![Some(format!(
+                            "0x{}",
+                            bytes_to_hex_str(&derive_signature(
+                                HOME_BRIDGE_AFFIRMATION_COMPLETED.signature
+                            ))
+                        ))])]),
+                    };
+                    Box::new(web3.eth_get_logs(filter).and_then(move |logs| {
+                        let mut bridged = Vec::new();
+                        for log in logs {
+                            let event = HOME_BRIDGE_AFFIRMATION_COMPLETED.decode(&log)?;
+                            if event.address("recipient")? != address {
+                                continue;
+                            }
+                            let eth_tx_hash = event.bytes32("transactionHash")?;
+                            latencies.arrived(&Uint256::from_bytes_be(&eth_tx_hash));
+                            bridged.push(IncomingXdai {
+                                amount: XDai::from_wei(event.uint("value")?),
+                                block: log.block_number.unwrap_or_else(|| block.clone()),
+                                source: DepositSource::Bridge { eth_tx_hash },
+                            });
+                        }
+                        let incoming =
+                            incoming_between(bridged, last_balance, balance.clone(), block.clone());
+                        Ok((incoming, (block, balance)))
+                    }))
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bridged(amount: u32) -> IncomingXdai {
+        IncomingXdai {
+            amount: XDai::from_wei(amount.into()),
+            block: 1u32.into(),
+            source: DepositSource::Bridge {
+                eth_tx_hash: [0u8; 32],
+            },
+        }
+    }
+
+    #[test]
+    fn test_incoming_between() {
+        // only the bridge payout
+        assert_eq!(
+            incoming_between(vec![bridged(10)], 5u32.into(), 15u32.into(), 2u32.into()),
+            vec![bridged(10)]
+        );
+        // a payout plus a transfer of 3
+        let incoming = incoming_between(vec![bridged(10)], 5u32.into(), 18u32.into(), 2u32.into());
+        assert_eq!(incoming.len(), 2);
+        assert_eq!(incoming[1].amount, XDai::from_wei(3u32.into()));
+        assert_eq!(incoming[1].source, DepositSource::Transfer);
+        // spending doesn't show up as anything
+        assert!(incoming_between(vec![], 5u32.into(), 2u32.into(), 2u32.into()).is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+//! An optional allowlist of where transactions may go, as defense in depth against a tampered
+//! config or an aggregator quote pointing funds somewhere they shouldn't. With it set every
+//! contract call has to go to one of the contracts the bridge is configured with or one of
+//! `extra_contracts`, and approvals may only be given to those. Anything else is refused with
+//! `TokenBridgeError::DestinationNotAllowed` before it is signed.
+//!
+//! Contracts passed in per call, such as the OmniBridge mediators of a `Stablecoin` or a
+//! token swapped on `xdai_router_address`, have to be listed in `extra_contracts`.
+
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::Error;
+
+/// Where transactions may be sent on top of the configured contracts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DestinationAllowlist {
+    /// Contracts that may be called and approved besides the ones the bridge is configured with
+    pub extra_contracts: Vec<Address>,
+    /// If set, plain ETH and xDai transfers may only be sent to these addresses
+    pub payment_recipients: Option<Vec<Address>>,
+}
+
+impl TokenBridge {
+    /// The contracts this bridge is configured with, which `destination_allowlist` always
+    /// allows. Addresses left unset are not included.
+    pub fn configured_contracts(&self) -> Vec<Address> {
+        let mut contracts = vec![
+            self.uniswap_address,
+            self.xdai_foreign_bridge_address,
+            self.xdai_home_bridge_address,
+            self.foreign_dai_contract_address,
+            self.weth_address,
+            self.sai_address,
+            self.sai_migration_address,
+            self.xdai_router_address,
+            self.wxdai_address,
+        ];
+        if let Some(ref amb) = self.amb {
+            contracts.push(amb.foreign_amb_address);
+            contracts.push(amb.home_amb_address);
+        }
+        contracts.retain(|contract| *contract != Address::default());
+        contracts
+    }
+
+    fn is_allowed_contract(&self, allowlist: &DestinationAllowlist, address: Address) -> bool {
+        allowlist.extra_contracts.contains(&address)
+            || self.configured_contracts().contains(&address)
+    }
+
+    /// Checks a transaction to `to` carrying `data` against `destination_allowlist`, a
+    /// transaction without data is a plain transfer
+    pub(crate) fn check_destination(&self, to: Address, data: &[u8]) -> Result<(), Error> {
+        let allowlist = match self.destination_allowlist {
+            Some(ref allowlist) => allowlist,
+            None => return Ok(()),
+        };
+        let allowed = if data.is_empty() {
+            match allowlist.payment_recipients {
+                Some(ref recipients) => recipients.contains(&to),
+                None => true,
+            }
+        } else {
+            self.is_allowed_contract(allowlist, to)
+        };
+        if allowed {
+            Ok(())
+        } else {
+            warn!("Refusing to send a transaction to {}", to);
+            Err(TokenBridgeError::DestinationNotAllowed { address: to }.into())
+        }
+    }
+
+    /// Checks that `spender` may be approved according to `destination_allowlist`
+    pub(crate) fn check_spender(&self, spender: Address) -> Result<(), Error> {
+        match self.destination_allowlist {
+            Some(ref allowlist) if !self.is_allowed_contract(allowlist, spender) => {
+                warn!("Refusing to approve {}", spender);
+                Err(TokenBridgeError::DestinationNotAllowed { address: spender }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_bridge, MockBlockReader};
+    use std::sync::Arc;
+
+    fn not_allowed(result: Result<(), Error>) -> bool {
+        match result {
+            Err(e) => matches!(
+                e.downcast_ref::<TokenBridgeError>(),
+                Some(TokenBridgeError::DestinationNotAllowed { .. })
+            ),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn test_destination_allowlist() {
+        let mut bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        let stranger = Address::from_slice(&[0x5e; 20]).unwrap();
+        let mediator = Address::from_slice(&[0x3d; 20]).unwrap();
+        let uniswap = bridge.uniswap_address;
+
+        // nothing is checked without an allowlist
+        assert!(bridge.check_destination(stranger, &[1]).is_ok());
+        assert!(bridge.check_spender(stranger).is_ok());
+
+        bridge.destination_allowlist = Some(DestinationAllowlist {
+            extra_contracts: vec![mediator],
+            payment_recipients: None,
+        });
+        assert!(bridge.check_destination(uniswap, &[1]).is_ok());
+        assert!(bridge.check_destination(mediator, &[1]).is_ok());
+        assert!(bridge.check_spender(uniswap).is_ok());
+        assert!(not_allowed(bridge.check_destination(stranger, &[1])));
+        assert!(not_allowed(bridge.check_spender(stranger)));
+        // payments go anywhere unless their recipients are restricted as well
+        assert!(bridge.check_destination(stranger, &[]).is_ok());
+
+        bridge.destination_allowlist = Some(DestinationAllowlist {
+            extra_contracts: Vec::new(),
+            payment_recipients: Some(vec![mediator]),
+        });
+        assert!(bridge.check_destination(mediator, &[]).is_ok());
+        assert!(not_allowed(bridge.check_destination(stranger, &[])));
+    }
+}
@@ -0,0 +1,238 @@
+//! The calldata of every contract call this crate sends, for integrators that build and
+//! broadcast transactions through their own infrastructure. Amounts are in wei and deadlines
+//! are unix timestamps, slippage has to be applied to the minimum outputs already, see
+//! `minimum_output`.
+
+use clarity::abi::{encode_call, Token};
+use clarity::Address;
+use num256::Uint256;
+
+/// Uniswap V1 `ethToTokenSwapInput`, sells the attached ETH for at least `min_tokens`
+pub fn uniswap_eth_to_token_swap(min_tokens: Uint256, deadline: Uint256) -> Vec<u8> {
+    encode_call(
+        "ethToTokenSwapInput(uint256,uint256)",
+        &[min_tokens.into(), deadline.into()],
+    )
+}
+
+/// Uniswap V1 `ethToTokenTransferInput`, like `uniswap_eth_to_token_swap` but the tokens go to
+/// `recipient`
+pub fn uniswap_eth_to_token_transfer(
+    min_tokens: Uint256,
+    deadline: Uint256,
+    recipient: Address,
+) -> Vec<u8> {
+    encode_call(
+        "ethToTokenTransferInput(uint256,uint256,address)",
+        &[min_tokens.into(), deadline.into(), recipient.into()],
+    )
+}
+
+/// Uniswap V1 `tokenToEthSwapInput`, sells `amount` tokens for at least `min_eth`
+pub fn uniswap_token_to_eth_swap(amount: Uint256, min_eth: Uint256, deadline: Uint256) -> Vec<u8> {
+    encode_call(
+        "tokenToEthSwapInput(uint256,uint256,uint256)",
+        &[amount.into(), min_eth.into(), deadline.into()],
+    )
+}
+
+/// Uniswap V1 `tokenToEthTransferInput`, like `uniswap_token_to_eth_swap` but the ETH goes to
+/// `recipient`
+pub fn uniswap_token_to_eth_transfer(
+    amount: Uint256,
+    min_eth: Uint256,
+    deadline: Uint256,
+    recipient: Address,
+) -> Vec<u8> {
+    encode_call(
+        "tokenToEthTransferInput(uint256,uint256,uint256,address)",
+        &[
+            amount.into(),
+            min_eth.into(),
+            deadline.into(),
+            recipient.into(),
+        ],
+    )
+}
+
+/// Uniswap V1 `tokenToTokenSwapInput`, sells `amount` tokens for at least `min_tokens` of
+/// `to_token` going through at least `min_eth`
+pub fn uniswap_token_to_token_swap(
+    amount: Uint256,
+    min_tokens: Uint256,
+    min_eth: Uint256,
+    deadline: Uint256,
+    to_token: Address,
+) -> Vec<u8> {
+    encode_call(
+        "tokenToTokenSwapInput(uint256,uint256,uint256,uint256,address)",
+        &[
+            amount.into(),
+            min_tokens.into(),
+            min_eth.into(),
+            deadline.into(),
+            to_token.into(),
+        ],
+    )
+}
+
+/// Uniswap V2 style router `swapExactETHForTokens`, sells the attached native coin along `path`
+pub fn router_swap_exact_eth_for_tokens(
+    min_out: Uint256,
+    path: Vec<Address>,
+    recipient: Address,
+    deadline: Uint256,
+) -> Vec<u8> {
+    encode_call(
+        "swapExactETHForTokens(uint256,address[],address,uint256)",
+        &[
+            min_out.into(),
+            path.into(),
+            recipient.into(),
+            deadline.into(),
+        ],
+    )
+}
+
+/// Uniswap V2 style router `swapExactTokensForETH`, sells `amount` of the first token of `path`
+pub fn router_swap_exact_tokens_for_eth(
+    amount: Uint256,
+    min_out: Uint256,
+    path: Vec<Address>,
+    recipient: Address,
+    deadline: Uint256,
+) -> Vec<u8> {
+    encode_call(
+        "swapExactTokensForETH(uint256,uint256,address[],address,uint256)",
+        &[
+            amount.into(),
+            min_out.into(),
+            path.into(),
+            recipient.into(),
+            deadline.into(),
+        ],
+    )
+}
+
+/// ERC20 `approve`
+pub fn erc20_approve(spender: Address, amount: Uint256) -> Vec<u8> {
+    encode_call("approve(address,uint256)", &[spender.into(), amount.into()])
+}
+
+/// ERC20 `transfer`. Sending Dai to the foreign bridge this way bridges it to the sender.
+pub fn erc20_transfer(to: Address, amount: Uint256) -> Vec<u8> {
+    encode_call("transfer(address,uint256)", &[to.into(), amount.into()])
+}
+
+/// ERC20 `transferFrom`, sends `amount` of `from`'s tokens to `to`. The sender has to be
+/// approved by `from`, Dai sent to the foreign bridge this way is bridged to `from`.
+pub fn erc20_transfer_from(from: Address, to: Address, amount: Uint256) -> Vec<u8> {
+    encode_call(
+        "transferFrom(address,address,uint256)",
+        &[from.into(), to.into(), amount.into()],
+    )
+}
+
+/// Dai's `permit`, approves `spender` to spend all of `holder`'s Dai or with `allowed` false
+/// revokes that, with the EIP-712 signature of `typed_data::dai_permit` by `holder`
+#[allow(clippy::too_many_arguments)]
+pub fn dai_permit(
+    holder: Address,
+    spender: Address,
+    nonce: Uint256,
+    expiry: Uint256,
+    allowed: bool,
+    v: u8,
+    r: &[u8],
+    s: &[u8],
+) -> Vec<u8> {
+    encode_call(
+        "permit(address,address,uint256,uint256,bool,uint8,bytes32,bytes32)",
+        &[
+            holder.into(),
+            spender.into(),
+            nonce.into(),
+            expiry.into(),
+            allowed.into(),
+            Uint256::from(v).into(),
+            // a bytes32 argument encodes the same as a uint256 one
+            Uint256::from_bytes_be(r).into(),
+            Uint256::from_bytes_be(s).into(),
+        ],
+    )
+}
+
+/// The foreign bridge's `relayTokens`, bridges `amount` Dai to `recipient` on xDai. The bridge
+/// has to be approved to spend the Dai.
+pub fn foreign_bridge_relay_tokens(recipient: Address, amount: Uint256) -> Vec<u8> {
+    encode_call(
+        "relayTokens(address,uint256)",
+        &[recipient.into(), amount.into()],
+    )
+}
+
+/// The home bridge's `relayTokens`, bridges the attached xDai to `recipient` on Eth. Sending
+/// xDai to the home bridge without calldata bridges it to the sender.
+pub fn home_bridge_relay_tokens(recipient: Address) -> Vec<u8> {
+    encode_call("relayTokens(address)", &[recipient.into()])
+}
+
+/// The OmniBridge mediator's `relayTokens` on either chain, bridges `amount` of `token` to
+/// `recipient` on the other one. The mediator has to be approved to spend the tokens.
+pub fn omnibridge_relay_tokens(token: Address, recipient: Address, amount: Uint256) -> Vec<u8> {
+    encode_call(
+        "relayTokens(address,address,uint256)",
+        &[token.into(), recipient.into(), amount.into()],
+    )
+}
+
+/// WETH `deposit`, wraps the attached ETH
+pub fn weth_deposit() -> Vec<u8> {
+    encode_call("deposit()", &[])
+}
+
+/// WETH `withdraw`, unwraps `amount`
+pub fn weth_withdraw(amount: Uint256) -> Vec<u8> {
+    encode_call("withdraw(uint256)", &[amount.into()])
+}
+
+/// MakerDAO's migration contract `swapSaiToDai`, swaps `amount` SAI for as much Dai. The
+/// migration contract has to be approved to spend the SAI.
+pub fn sai_migration_swap_sai_to_dai(amount: Uint256) -> Vec<u8> {
+    encode_call("swapSaiToDai(uint256)", &[amount.into()])
+}
+
+/// The Arbitrary Message Bridge's `requireToPassMessage`, calls `contract` with `data` on the
+/// other chain with `gas` gas
+pub fn amb_require_to_pass_message(contract: Address, data: Vec<u8>, gas: Uint256) -> Vec<u8> {
+    encode_call(
+        "requireToPassMessage(address,bytes,uint256)",
+        &[contract.into(), Token::UnboundedBytes(data), gas.into()],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::{
+        ERC20_APPROVE_SELECTOR, ERC20_TRANSFER_SELECTOR, WETH_DEPOSIT_SELECTOR,
+    };
+
+    #[test]
+    fn test_selectors() {
+        let selector = |data: Vec<u8>| data[..4].to_vec();
+        assert_eq!(
+            selector(erc20_approve(Address::default(), 1u32.into())),
+            ERC20_APPROVE_SELECTOR.selector
+        );
+        assert_eq!(
+            selector(erc20_transfer(Address::default(), 1u32.into())),
+            ERC20_TRANSFER_SELECTOR.selector
+        );
+        assert_eq!(weth_deposit(), WETH_DEPOSIT_SELECTOR.selector);
+        assert_eq!(
+            erc20_approve(Address::default(), 1u32.into()).len(),
+            4 + 2 * 32
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! ENS name resolution, so that configured contract and account addresses can be given as
+//! stable names like `dai.tokens.ethereum.eth` instead of raw hex.
+
+use crate::abi::decode_output;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha3::{Digest, Keccak256};
+use std::fmt;
+use std::str::FromStr;
+use web30::client::Web3;
+
+pub use crate::contracts::ENS_REGISTRY;
+
+pub fn ens_registry() -> Address {
+    ENS_REGISTRY.parse().unwrap()
+}
+
+/// The ENS node of `name` as defined by EIP-137
+pub fn namehash(name: &str) -> [u8; 32] {
+    let mut node = [0u8; 32];
+    if name.is_empty() {
+        return node;
+    }
+    for label in name.rsplit('.') {
+        let mut hasher = Keccak256::new();
+        hasher.input(node);
+        hasher.input(Keccak256::digest(label.as_bytes()));
+        node.copy_from_slice(&hasher.result());
+    }
+    node
+}
+
+/// An address, or an ENS name that has to be resolved to one before use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressOrName {
+    Address(Address),
+    Name(String),
+}
+
+impl AddressOrName {
+    /// The address, or an error if this is a name that has not been resolved
+    pub fn address(&self) -> Result<Address, Error> {
+        match self {
+            AddressOrName::Address(address) => Ok(*address),
+            AddressOrName::Name(name) => Err(TokenBridgeError::EnsResolution {
+                name: name.clone(),
+                reason: "not resolved yet".to_string(),
+            }
+            .into()),
+        }
+    }
+
+    /// Resolves a name through `web3`, addresses are returned as they are
+    pub fn resolve(&self, web3: &Web3) -> Box<dyn Future<Item = Address, Error = Error>> {
+        match self {
+            AddressOrName::Address(address) => Box::new(futures::future::ok(*address)),
+            AddressOrName::Name(name) => resolve_name(web3, name.clone()),
+        }
+    }
+}
+
+impl From<Address> for AddressOrName {
+    fn from(address: Address) -> AddressOrName {
+        AddressOrName::Address(address)
+    }
+}
+
+/// Anything starting with 0x has to be a valid address, everything else is taken as a name
+impl FromStr for AddressOrName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<AddressOrName, Error> {
+        if s.starts_with("0x") {
+            Ok(AddressOrName::Address(s.parse()?))
+        } else {
+            Ok(AddressOrName::Name(s.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for AddressOrName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressOrName::Address(address) => write!(f, "{}", address),
+            AddressOrName::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Serialize for AddressOrName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for AddressOrName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<AddressOrName, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Looks up the address `name` points to, failing with `TokenBridgeError::EnsResolution` if
+/// it has no resolver or no address
+pub fn resolve_name(web3: &Web3, name: String) -> Box<dyn Future<Item = Address, Error = Error>> {
+    let node = Uint256::from_bytes_be(&namehash(&name));
+    let web3 = web3.clone();
+    let failed = |name: &str, reason: &str| -> Error {
+        TokenBridgeError::EnsResolution {
+            name: name.to_string(),
+            reason: reason.to_string(),
+        }
+        .into()
+    };
+
+    Box::new(
+        web3.contract_call(
+            ens_registry(),
+            "resolver(bytes32)",
+            &[node.clone().into()],
+            Address::default(),
+        )
+        .and_then(move |resolver| {
+            let resolver: Address = decode_output("resolver(bytes32)", &resolver)?;
+            if resolver == Address::default() {
+                return Err(failed(&name, "no resolver set"));
+            }
+            Ok((resolver, name))
+        })
+        .and_then(move |(resolver, name)| {
+            web3.contract_call(
+                resolver,
+                "addr(bytes32)",
+                &[node.into()],
+                Address::default(),
+            )
+            .and_then(move |address| {
+                let address: Address = decode_output("addr(bytes32)", &address)?;
+                if address == Address::default() {
+                    return Err(failed(&name, "resolver has no address for it"));
+                }
+                trace!("resolved {} to {}", name, address);
+                Ok(address)
+            })
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clarity::utils::bytes_to_hex_str;
+
+    #[test]
+    fn test_namehash() {
+        assert_eq!(namehash(""), [0u8; 32]);
+        assert_eq!(
+            bytes_to_hex_str(&namehash("eth")),
+            "93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae"
+        );
+        assert_eq!(
+            bytes_to_hex_str(&namehash("foo.eth")),
+            "de9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f"
+        );
+    }
+
+    #[test]
+    fn test_address_or_name() {
+        let name: AddressOrName = "dai.tokens.ethereum.eth".parse().unwrap();
+        assert_eq!(
+            name,
+            AddressOrName::Name("dai.tokens.ethereum.eth".to_string())
+        );
+        assert!(name.address().is_err());
+        let address: AddressOrName = ENS_REGISTRY.parse().unwrap();
+        assert_eq!(address.address().unwrap(), ens_registry());
+        assert!("0x1234".parse::<AddressOrName>().is_err());
+    }
+}
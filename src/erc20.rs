@@ -0,0 +1,226 @@
+//! Generic ERC20 helpers used for tokens other than the configured Dai, on either chain
+
+use crate::abi::decode_output;
+use crate::encoding;
+use crate::events::BridgeEvent;
+use crate::logs::ERC20_APPROVAL;
+use crate::units::TokenAmount;
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::Address;
+use failure::bail;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::time::Duration;
+
+impl TokenBridge {
+    /// Balance of `token` held by `address` on Eth
+    pub fn get_token_balance(
+        &self,
+        token: Address,
+        address: Address,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.get_token_balance_on(Chain::Eth, token, address)
+    }
+
+    /// `get_token_balance` for a token on `chain`
+    pub fn get_token_balance_on(
+        &self,
+        chain: Chain,
+        token: Address,
+        address: Address,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let web3 = self.web3(chain);
+        let own_address = self.own_address;
+
+        self.with_retry(chain, "balanceOf(address)", move || {
+            Box::new(
+                web3.contract_call(token, "balanceOf(address)", &[address.into()], own_address)
+                    .and_then(|balance| decode_output("balanceOf(address)", &balance)),
+            )
+        })
+    }
+
+    /// The number of decimals `token` on `chain` reports
+    pub fn get_token_decimals_on(
+        &self,
+        chain: Chain,
+        token: Address,
+    ) -> Box<dyn Future<Item = u32, Error = Error>> {
+        Box::new(
+            self.call_view::<Uint256>(chain, token, "decimals()", &[])
+                .and_then(move |decimals| {
+                    // decimals is a uint8, anything larger is not a real token
+                    if decimals > 255u32.into() {
+                        bail!("{} reports {} decimals", token, decimals);
+                    }
+                    Ok(decimals.to_string().parse()?)
+                }),
+        )
+    }
+
+    /// `get_token_balance_on` along with the token's decimals
+    pub fn get_token_amount_on(
+        &self,
+        chain: Chain,
+        token: Address,
+        address: Address,
+    ) -> Box<dyn Future<Item = TokenAmount, Error = Error>> {
+        Box::new(
+            self.get_token_balance_on(chain, token, address)
+                .join(self.get_token_decimals_on(chain, token))
+                .map(|(value, decimals)| TokenAmount::new(value, decimals)),
+        )
+    }
+
+    /// How much of our `token` `spender` is currently allowed to transfer on Eth
+    pub fn get_token_allowance(
+        &self,
+        token: Address,
+        spender: Address,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.get_token_allowance_on(Chain::Eth, token, spender)
+    }
+
+    /// `get_token_allowance` for a token on `chain`
+    pub fn get_token_allowance_on(
+        &self,
+        chain: Chain,
+        token: Address,
+        spender: Address,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let web3 = self.web3(chain);
+        let own_address = self.own_address;
+
+        self.with_retry(chain, "allowance(address,address)", move || {
+            Box::new(
+                web3.contract_call(
+                    token,
+                    "allowance(address,address)",
+                    &[own_address.into(), spender.into()],
+                    own_address,
+                )
+                .and_then(|allowance| decode_output("allowance(address,address)", &allowance)),
+            )
+        })
+    }
+
+    /// Approves `spender` to transfer `amount` of our `token`, this future will not resolve
+    /// until the Approval event is seen or the timeout finishes. On timeout the error is
+    /// `TokenBridgeError::TimedOut` saying whether the approval went through. Fails without
+    /// sending if `spender` is not allowed by `destination_allowlist`.
+    pub fn approve_token_transfers(
+        &self,
+        token: Address,
+        spender: Address,
+        amount: Uint256,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        self.approve_token_transfers_on(Chain::Eth, token, spender, amount, timeout)
+    }
+
+    /// `approve_token_transfers` for a token on `chain`
+    pub fn approve_token_transfers_on(
+        &self,
+        chain: Chain,
+        token: Address,
+        spender: Address,
+        amount: Uint256,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        self.in_account_order(move |salf| {
+            salf.send_approval(chain, token, spender, amount, timeout)
+        })
+    }
+
+    fn send_approval(
+        &self,
+        chain: Chain,
+        token: Address,
+        spender: Address,
+        amount: Uint256,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let own_address = self.own_address;
+        let salf = self.clone();
+
+        try_future!(self.check_spender(spender));
+        let payload = encoding::erc20_approve(spender, amount);
+
+        Box::new(
+            self.send_transaction(chain, token, payload, 0u32.into(), vec![])
+                .and_then(move |tx_hash| {
+                    let approval = salf.wait_for_event(
+                        chain,
+                        token,
+                        ERC20_APPROVAL.signature,
+                        Some(vec![own_address.into()]),
+                        Some(vec![spender.into()]),
+                        None,
+                    );
+                    salf.wait_or_reconcile(
+                        chain,
+                        tx_hash,
+                        approval,
+                        timeout,
+                        token,
+                        ERC20_APPROVAL,
+                        "value",
+                    )
+                    .map(move |_| salf)
+                })
+                .and_then(move |salf| {
+                    salf.emit(BridgeEvent::EventObserved {
+                        chain,
+                        contract: token,
+                        event: "Approval(address,address,uint256)".to_string(),
+                    });
+                    Ok(())
+                }),
+        )
+    }
+
+    /// Approves `spender` for an unlimited amount of `token` unless the current allowance
+    /// already covers `amount`
+    pub fn ensure_token_approved(
+        &self,
+        token: Address,
+        spender: Address,
+        amount: Uint256,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        self.ensure_token_approved_on(Chain::Eth, token, spender, amount, timeout)
+    }
+
+    /// `ensure_token_approved` for a token on `chain`
+    pub fn ensure_token_approved_on(
+        &self,
+        chain: Chain,
+        token: Address,
+        spender: Address,
+        amount: Uint256,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        // the allowance is read in our turn so a concurrent spend can't use it up in between
+        self.in_account_order(move |salf| {
+            Box::new(salf.get_token_allowance_on(chain, token, spender).and_then(
+                move |allowance| {
+                    trace!("{} allowance for {} is {}", token, spender, allowance);
+                    if allowance >= amount {
+                        Box::new(futures::future::ok(()))
+                            as Box<dyn Future<Item = (), Error = Error>>
+                    } else {
+                        salf.send_approval(
+                            chain,
+                            token,
+                            spender,
+                            num::Bounded::max_value(),
+                            timeout,
+                        )
+                    }
+                },
+            ))
+        })
+    }
+}
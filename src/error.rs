@@ -0,0 +1,126 @@
+use crate::operations::Operation;
+use crate::pending::ConversionKind;
+use crate::spending::SpendAsset;
+use clarity::Address;
+use failure::Fail;
+use num256::Uint256;
+use std::time::Duration;
+
+/// What happened to a transaction whose event didn't show up in time
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeoutOutcome {
+    /// The transaction was mined but reverted
+    DefinitelyNotExecuted,
+    /// The transaction was mined after the timeout and moved this amount
+    ExecutedButLate(Uint256),
+    /// The transaction with this hash is not mined yet and may still be
+    Unknown(Uint256),
+}
+
+/// Errors for conditions that callers are expected to handle themselves, as opposed to RPC or
+/// encoding failures. These are returned wrapped in `failure::Error`, use `downcast_ref` to
+/// match on them.
+#[derive(Debug, Fail)]
+pub enum TokenBridgeError {
+    #[fail(
+        display = "Refusing to transfer to {} because it is a contract and not allowlisted",
+        address
+    )]
+    RecipientIsContract { address: Address },
+    #[fail(
+        display = "Price impact of {} basis points exceeds the limit of {}",
+        impact_bps, max_bps
+    )]
+    PriceImpactTooHigh { impact_bps: u32, max_bps: u32 },
+    #[fail(
+        display = "Uniswap price {} is too far from the oracle price {}",
+        uniswap_price, oracle_price
+    )]
+    OracleDivergence {
+        oracle_price: Uint256,
+        uniswap_price: Uint256,
+    },
+    #[fail(display = "Oracle price is stale, last updated {:?} ago", age)]
+    OracleStale { age: Duration },
+    #[fail(display = "This TokenBridge has no key and can not send transactions")]
+    ReadOnly,
+    #[fail(display = "Bridge unavailable: {}", reason)]
+    BridgeUnavailable { reason: String },
+    #[fail(display = "Transaction would fail: {}", reason)]
+    SimulationReverted { reason: String },
+    #[fail(display = "Event in block {} was removed by a reorg", block)]
+    EventReorged { block: Uint256 },
+    #[fail(display = "Swap was not mined before its deadline {}", deadline)]
+    SwapExpired { deadline: Uint256 },
+    #[fail(display = "Timed out waiting for the transaction, {:?}", outcome)]
+    TimedOut { outcome: TimeoutOutcome },
+    #[fail(display = "Could not resolve ENS name {}: {}", name, reason)]
+    EnsResolution { name: String, reason: String },
+    #[fail(display = "No account {} has been added to this bridge", address)]
+    UnknownAccount { address: Address },
+    #[fail(
+        display = "Eth node is {:?} behind, its prices can not be trusted",
+        lag
+    )]
+    StaleNode { lag: Duration },
+    #[fail(display = "Transaction {} was mined but reverted", tx_hash)]
+    TransactionReverted { tx_hash: Uint256 },
+    /// `operation` was stopped with a `CancelToken`, its stage is how far it got. Anything
+    /// before that stage was executed, the step of the stage itself may have been sent.
+    #[fail(display = "Cancelled, {:?}", operation)]
+    Cancelled { operation: Operation },
+    /// The home bridge keeps xDai sent below its `minPerTx` without paying out any Dai, so such
+    /// transfers are refused instead of sent
+    #[fail(display = "Amount is below the bridge minimum of {}", min)]
+    BelowBridgeMinimum { min: Uint256 },
+    /// A contract returned something that doesn't decode as the return value of `call`, the
+    /// signature of the function that was called. Usually the address isn't the contract it is
+    /// configured as.
+    #[fail(display = "Malformed output from {} call {:?}", call, raw)]
+    MalformedOutput { call: String, raw: Vec<u8> },
+    /// An identical conversion is still running for `account`, see `PendingRegistry`
+    #[fail(
+        display = "A {:?} conversion of this amount is already pending for {}",
+        kind, account
+    )]
+    DuplicateConversion {
+        kind: ConversionKind,
+        account: Address,
+    },
+    /// `amount` is above the `large_transfers` threshold and the conversion was declined by
+    /// the confirmer, or there is none, see `with_confirmed_transfer`
+    #[fail(display = "{:?} of {} was not confirmed", kind, amount)]
+    LargeTransferNotConfirmed {
+        kind: ConversionKind,
+        amount: Uint256,
+    },
+    /// Sending `amount` of `asset` would go over a limit of the `SpendingPolicy`, `allowed` is
+    /// the most that could be sent right now
+    #[fail(
+        display = "Sending {} of {:?} exceeds the spending limit, {} is allowed",
+        amount, asset, allowed
+    )]
+    SpendingLimitExceeded {
+        asset: SpendAsset,
+        amount: Uint256,
+        allowed: Uint256,
+    },
+    /// `address` is not allowed by `destination_allowlist`, nothing was sent
+    #[fail(
+        display = "Refusing to send to {}, it is not in the destination allowlist",
+        address
+    )]
+    DestinationNotAllowed { address: Address },
+    /// The passphrase a keystore was decrypted with is not the one it was encrypted with
+    #[fail(display = "Wrong keystore passphrase")]
+    WrongPassphrase,
+    /// A relayer refused a `SignedRelayRequest`, see `RelayVerifier::verify`
+    #[fail(display = "Invalid relay request: {}", reason)]
+    InvalidRelayRequest { reason: String },
+    /// There is too little ETH to swap for more and no faucet to ask, see `ensure_gas`
+    #[fail(
+        display = "ETH balance {} can't pay for gas, {} is needed to swap for more",
+        balance, needed
+    )]
+    OutOfGas { balance: Uint256, needed: Uint256 },
+}
@@ -0,0 +1,165 @@
+//! How long the bridge has recently taken to pay out, measured from sending a transfer to
+//! seeing the funds arrive. Dai to xDai payouts are seen by `subscribe_incoming_xdai`, xDai to
+//! Dai payouts by `xdai_to_eth`, so only transfers sent and watched by the same process are
+//! measured.
+
+use crate::fee::BridgeDirection;
+use crate::TokenBridge;
+use num256::Uint256;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many recent payouts estimates are based on, per direction
+pub const BRIDGE_LATENCY_WINDOW: usize = 20;
+
+/// Transfers nobody saw arrive after this long are forgotten
+const MAX_TRACKED_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What to expect from the bridge, based on the payouts in the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BridgeEta {
+    /// The median payout time
+    pub typical: Duration,
+    /// The 90th percentile, a transfer taking longer than this is unusually slow
+    pub slow: Duration,
+    /// How many payouts the estimate is based on
+    pub samples: usize,
+}
+
+impl BridgeEta {
+    /// Whether a transfer sent `elapsed` ago is taking longer than the bridge usually does
+    pub fn is_overdue(&self, elapsed: Duration) -> bool {
+        elapsed > self.slow
+    }
+
+    fn from_samples(samples: &VecDeque<Duration>) -> Option<BridgeEta> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = samples.iter().cloned().collect();
+        sorted.sort();
+        let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+        Some(BridgeEta {
+            typical: percentile(50),
+            slow: percentile(90),
+            samples: sorted.len(),
+        })
+    }
+}
+
+/// Transfers sent to the bridge that haven't arrived yet and the latest payout times, shared
+/// by all clones of a bridge
+#[derive(Debug, Default)]
+pub struct BridgeLatencies {
+    /// When each transfer still waiting for its payout was sent, by tx hash
+    sent: Mutex<HashMap<Uint256, (BridgeDirection, Instant)>>,
+    samples: Mutex<HashMap<BridgeDirection, VecDeque<Duration>>>,
+}
+
+impl BridgeLatencies {
+    /// Adds a payout that took `latency` to the window of `direction`
+    pub fn record(&self, direction: BridgeDirection, latency: Duration) {
+        let mut samples = self.samples.lock().unwrap();
+        let window = samples.entry(direction).or_default();
+        if window.len() == BRIDGE_LATENCY_WINDOW {
+            window.pop_front();
+        }
+        window.push_back(latency);
+    }
+
+    pub fn estimate(&self, direction: BridgeDirection) -> Option<BridgeEta> {
+        self.samples
+            .lock()
+            .unwrap()
+            .get(&direction)
+            .and_then(BridgeEta::from_samples)
+    }
+
+    /// Starts timing the transfer `tx_hash` sent to the bridge just now
+    pub(crate) fn sent(&self, direction: BridgeDirection, tx_hash: Uint256) {
+        let now = Instant::now();
+        let mut sent = self.sent.lock().unwrap();
+        sent.retain(|_, (_, sent_at)| now.duration_since(*sent_at) < MAX_TRACKED_AGE);
+        sent.insert(tx_hash, (direction, now));
+    }
+
+    /// Records the payout for the transfer `tx_hash` if it was timed by `sent`
+    pub(crate) fn arrived(&self, tx_hash: &Uint256) {
+        let sent = self.sent.lock().unwrap().remove(tx_hash);
+        if let Some((direction, sent_at)) = sent {
+            let latency = sent_at.elapsed();
+            info!(
+                "{:?} payout of {:#x} took {:?}",
+                direction, tx_hash, latency
+            );
+            self.record(direction, latency);
+        }
+    }
+}
+
+impl TokenBridge {
+    /// How long a transfer in `direction` can be expected to take, based on the last
+    /// `BRIDGE_LATENCY_WINDOW` payouts seen by this bridge and its clones. `None` until one has
+    /// been seen.
+    pub fn estimate_bridge_eta(&self, direction: BridgeDirection) -> Option<BridgeEta> {
+        self.bridge_latencies.estimate(direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let latencies = BridgeLatencies::default();
+        assert_eq!(latencies.estimate(BridgeDirection::DaiToXdai), None);
+        for minutes in 1..=10 {
+            latencies.record(
+                BridgeDirection::DaiToXdai,
+                Duration::from_secs(minutes * 60),
+            );
+        }
+        let eta = latencies.estimate(BridgeDirection::DaiToXdai).unwrap();
+        assert_eq!(eta.samples, 10);
+        assert_eq!(eta.typical, Duration::from_secs(5 * 60));
+        assert_eq!(eta.slow, Duration::from_secs(9 * 60));
+        assert!(eta.is_overdue(Duration::from_secs(10 * 60)));
+        assert_eq!(latencies.estimate(BridgeDirection::XdaiToDai), None);
+
+        // old payouts fall out of the window
+        for _ in 0..BRIDGE_LATENCY_WINDOW {
+            latencies.record(BridgeDirection::DaiToXdai, Duration::from_secs(30));
+        }
+        let eta = latencies.estimate(BridgeDirection::DaiToXdai).unwrap();
+        assert_eq!(eta.samples, BRIDGE_LATENCY_WINDOW);
+        assert_eq!(eta.slow, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_sent_and_arrived() {
+        let latencies = BridgeLatencies::default();
+        latencies.sent(BridgeDirection::XdaiToDai, 7u32.into());
+        // payouts of transfers we didn't time are ignored
+        latencies.arrived(&8u32.into());
+        assert_eq!(latencies.estimate(BridgeDirection::XdaiToDai), None);
+        latencies.arrived(&7u32.into());
+        assert_eq!(
+            latencies
+                .estimate(BridgeDirection::XdaiToDai)
+                .unwrap()
+                .samples,
+            1
+        );
+        // each transfer is counted once
+        latencies.arrived(&7u32.into());
+        assert_eq!(
+            latencies
+                .estimate(BridgeDirection::XdaiToDai)
+                .unwrap()
+                .samples,
+            1
+        );
+    }
+}
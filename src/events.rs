@@ -0,0 +1,48 @@
+//! Progress updates for multi-step operations, so callers can show what a long running swap or
+//! bridge is doing before its future resolves
+
+use crate::instrument;
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::Address;
+use futures::sync::mpsc::{unbounded, UnboundedReceiver};
+use num256::Uint256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BridgeEvent {
+    /// A transaction was accepted by the full node
+    TxSubmitted { chain: Chain, tx_hash: Uint256 },
+    /// A transaction we are waiting on was included in a block
+    TxConfirmed { chain: Chain, tx_hash: Uint256 },
+    /// A contract event we were waiting for showed up, `event` is its signature
+    EventObserved {
+        chain: Chain,
+        contract: Address,
+        event: String,
+    },
+    /// The output of an operation is in our account on `chain`
+    FundsArrived { chain: Chain, amount: Uint256 },
+    /// A failed step is being tried again
+    Retrying { attempt: u32, reason: String },
+}
+
+impl TokenBridge {
+    /// Returns a stream of `BridgeEvent`s for every operation started on this bridge or its
+    /// clones from now on. Only the most recently returned stream receives events.
+    pub fn progress_events(&self) -> UnboundedReceiver<BridgeEvent> {
+        let (sender, receiver) = unbounded();
+        *self.progress.lock().unwrap() = Some(sender);
+        receiver
+    }
+
+    /// Sends `event` to the progress stream if there is one
+    pub(crate) fn emit(&self, event: BridgeEvent) {
+        instrument::progress(&event);
+        let progress = self.progress.lock().unwrap();
+        if let Some(ref progress) = *progress {
+            trace!("progress {:?}", event);
+            // the receiver being dropped just means nobody is listening anymore
+            let _ = progress.unbounded_send(event);
+        }
+    }
+}
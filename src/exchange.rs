@@ -0,0 +1,89 @@
+//! Finding Uniswap V1 exchanges through the factory contract
+
+use crate::abi::decode_output;
+use crate::TokenBridge;
+use clarity::{Address, PrivateKey};
+use failure::bail;
+use failure::Error;
+use futures::Future;
+
+pub use crate::contracts::MAINNET_UNISWAP_FACTORY;
+
+impl TokenBridge {
+    /// Looks up the Uniswap V1 exchange for `token` in `factory`, checking that the exchange
+    /// it returns actually trades `token`.
+    pub fn discover_exchange(
+        &self,
+        factory: Address,
+        token: Address,
+    ) -> Box<dyn Future<Item = Address, Error = Error>> {
+        let web3 = self.eth_web3.clone();
+        let own_address = self.own_address;
+
+        Box::new(
+            web3.contract_call(
+                factory,
+                "getExchange(address)",
+                &[token.into()],
+                own_address,
+            )
+            .and_then(move |exchange| {
+                let exchange = decode_output("getExchange(address)", &exchange)?;
+                if exchange == Address::default() {
+                    bail!("Factory {} has no exchange for token {}", factory, token);
+                }
+                Ok(exchange)
+            })
+            .and_then(move |exchange| {
+                web3.contract_call(exchange, "tokenAddress()", &[], own_address)
+                    .and_then(move |exchange_token| {
+                        let exchange_token: Address =
+                            decode_output("tokenAddress()", &exchange_token)?;
+                        if exchange_token != token {
+                            bail!(
+                                "Exchange {} trades {} but {} was expected",
+                                exchange,
+                                exchange_token,
+                                token
+                            );
+                        }
+                        Ok(exchange)
+                    })
+            }),
+        )
+    }
+
+    /// Like `new`, but looks up the Dai exchange in the Uniswap V1 `factory` instead of
+    /// taking its address as a parameter.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_factory(
+        factory: Address,
+        xdai_home_bridge_address: Address,
+        xdai_foreign_bridge_address: Address,
+        foreign_dai_contract_address: Address,
+        own_address: Address,
+        secret: PrivateKey,
+        eth_full_node_url: String,
+        xdai_full_node_url: String,
+    ) -> Box<dyn Future<Item = TokenBridge, Error = Error>> {
+        let bridge = TokenBridge::new(
+            Address::default(),
+            xdai_home_bridge_address,
+            xdai_foreign_bridge_address,
+            foreign_dai_contract_address,
+            own_address,
+            secret,
+            eth_full_node_url,
+            xdai_full_node_url,
+        );
+
+        Box::new(
+            bridge
+                .discover_exchange(factory, foreign_dai_contract_address)
+                .map(move |uniswap_address| TokenBridge {
+                    uniswap_address,
+                    ..bridge
+                }),
+        )
+    }
+}
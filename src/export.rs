@@ -0,0 +1,250 @@
+//! Bridge and swap history as CSV or JSON for accounting, one row per transfer or swap with its
+//! date, fees and gas, built on `get_bridge_history`.
+
+use crate::amounts::wei_to_eth;
+use crate::amounts::ETH_DECIMALS;
+use crate::fee::{bridge_fee_amount, BridgeDirection};
+use crate::history::{HistoryEntry, HistoryKind};
+use crate::receipt::csv_field;
+use crate::TokenBridge;
+use clarity::utils::bytes_to_hex_str;
+use clarity::Address;
+use failure::Error;
+use futures::future::join_all;
+use futures::Future;
+use num::ToPrimitive;
+use num256::Uint256;
+use serde::Serialize;
+
+/// The columns of `history_to_csv`
+pub const HISTORY_CSV_HEADER: &str =
+    "date,direction,token,gross_amount,fee,gas,tx_hash,counterparty";
+
+/// Uniswap V1 keeps 0.3% of what is sold
+const UNISWAP_V1_FEE_PER_MILLE: u32 = 3;
+
+/// One transfer or swap, amounts are decimal strings of whole tokens
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExportRow {
+    /// UTC time of the block, as `YYYY-MM-DDTHH:MM:SSZ`
+    pub date: String,
+    /// One of `DaiToXdai`, `XdaiToDai`, `EthToDai` and `DaiToEth`
+    pub direction: String,
+    /// What `gross_amount` and `fee` are in, `ETH` or `DAI`
+    pub token: String,
+    /// The amount bridged, or sold in a swap, before fees
+    pub gross_amount: String,
+    /// Kept by the bridge or Uniswap
+    pub fee: String,
+    /// The most the transaction could have paid for gas, in ETH. Zero for transactions sent by
+    /// someone else, such as the bridge paying out a withdrawal.
+    pub gas: String,
+    pub tx_hash: String,
+    /// The bridge or exchange, by its label in the `address_book` if it has one
+    pub counterparty: String,
+}
+
+/// The days since 1970-01-01 as a (year, month, day) date, from Howard Hinnant's
+/// `civil_from_days`
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// `timestamp` in Unix seconds as an ISO 8601 UTC date and time
+fn format_date(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days(timestamp / 86400);
+    let seconds = timestamp % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The row for `entry` mined at `timestamp`. `bridge_fee` is the fee of the bridge in the
+/// direction of the entry, `gas` what its transaction paid in wei.
+fn export_row(
+    entry: &HistoryEntry,
+    timestamp: u64,
+    bridge_fee: Uint256,
+    gas: Uint256,
+) -> ExportRow {
+    let uniswap_fee =
+        |sold: &Uint256| sold.clone() * UNISWAP_V1_FEE_PER_MILLE.into() / 1000u32.into();
+    let (direction, token, gross, fee) = match entry.kind {
+        HistoryKind::DaiToXdai { ref amount } => (
+            "DaiToXdai",
+            "DAI",
+            amount.clone(),
+            bridge_fee_amount(amount.clone(), bridge_fee),
+        ),
+        HistoryKind::XdaiToDai { ref amount } => (
+            "XdaiToDai",
+            "DAI",
+            amount.clone(),
+            bridge_fee_amount(amount.clone(), bridge_fee),
+        ),
+        HistoryKind::EthToDai { ref eth_sold, .. } => {
+            ("EthToDai", "ETH", eth_sold.clone(), uniswap_fee(eth_sold))
+        }
+        HistoryKind::DaiToEth { ref dai_sold, .. } => {
+            ("DaiToEth", "DAI", dai_sold.clone(), uniswap_fee(dai_sold))
+        }
+    };
+    let tx_hash: [u8; 32] = entry.tx_hash.clone().into();
+    ExportRow {
+        date: format_date(timestamp),
+        direction: direction.to_string(),
+        token: token.to_string(),
+        gross_amount: wei_to_eth(&gross, ETH_DECIMALS),
+        fee: wei_to_eth(&fee, ETH_DECIMALS),
+        gas: wei_to_eth(&gas, ETH_DECIMALS),
+        tx_hash: format!("0x{}", bytes_to_hex_str(&tx_hash)),
+        counterparty: match entry.counterparty_label {
+            Some(ref label) => label.clone(),
+            None => entry.counterparty.to_string(),
+        },
+    }
+}
+
+/// `rows` as a CSV document with a `HISTORY_CSV_HEADER` header row
+pub fn history_to_csv(rows: &[ExportRow]) -> String {
+    let mut csv = String::from(HISTORY_CSV_HEADER);
+    csv.push('\n');
+    for row in rows {
+        let fields = [
+            &row.date,
+            &row.direction,
+            &row.token,
+            &row.gross_amount,
+            &row.fee,
+            &row.gas,
+            &row.tx_hash,
+            &row.counterparty,
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// `rows` as a JSON array of objects with the fields of `ExportRow`
+pub fn history_to_json(rows: &[ExportRow]) -> Result<String, Error> {
+    Ok(serde_json::to_string_pretty(rows)?)
+}
+
+impl TokenBridge {
+    /// The history of `address` between `from_block` and `to_block`, see `get_bridge_history`,
+    /// with the date, fees and gas of each entry for `history_to_csv` or `history_to_json`.
+    ///
+    /// Bridge fees are worked out at the fee the bridge charges now, web30 can't read receipts
+    /// so gas is the gas limit of each transaction times its gas price.
+    pub fn export_history(
+        &self,
+        address: Address,
+        from_block: Uint256,
+        to_block: Uint256,
+    ) -> Box<dyn Future<Item = Vec<ExportRow>, Error = Error>> {
+        let salf = self.clone();
+        Box::new(
+            self.get_bridge_history(address, from_block, to_block)
+                .join3(
+                    self.get_bridge_fee(BridgeDirection::DaiToXdai),
+                    self.get_bridge_fee(BridgeDirection::XdaiToDai),
+                )
+                .and_then(move |(entries, deposit_fee, withdrawal_fee)| {
+                    join_all(entries.into_iter().map(move |entry| {
+                        let bridge_fee = match entry.kind {
+                            HistoryKind::XdaiToDai { .. } => withdrawal_fee.clone(),
+                            _ => deposit_fee.clone(),
+                        };
+                        salf.eth_web3
+                            .eth_get_block_by_number(entry.block.clone())
+                            .join(
+                                salf.eth_web3
+                                    .eth_get_transaction_by_hash(entry.tx_hash.clone()),
+                            )
+                            .map(move |(block, tx)| {
+                                let gas = match tx {
+                                    Some(ref tx) if tx.from == address => {
+                                        tx.gas.clone() * tx.gas_price.clone()
+                                    }
+                                    _ => 0u32.into(),
+                                };
+                                let timestamp = block.timestamp.to_u64().unwrap_or(0);
+                                export_row(&entry, timestamp, bridge_fee, gas)
+                            })
+                    }))
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_date(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_date(1_600_000_000), "2020-09-13T12:26:40Z");
+    }
+
+    #[test]
+    fn test_history_csv() {
+        let one_percent: Uint256 = 10_000_000_000_000_000u64.into();
+        let deposit = HistoryEntry {
+            block: 1u32.into(),
+            tx_hash: 255u32.into(),
+            log_index: 0u32.into(),
+            kind: HistoryKind::DaiToXdai {
+                amount: 2_000_000_000_000_000_000u64.into(),
+            },
+            counterparty: Address::default(),
+            counterparty_label: Some("xDai bridge".to_string()),
+        };
+        let swap = HistoryEntry {
+            kind: HistoryKind::EthToDai {
+                eth_sold: 1_000_000_000_000_000_000u64.into(),
+                dai_bought: 1u32.into(),
+            },
+            counterparty_label: None,
+            ..deposit.clone()
+        };
+        let rows = vec![
+            export_row(&deposit, 0, one_percent.clone(), 21_000u32.into()),
+            export_row(&swap, 0, one_percent, 0u32.into()),
+        ];
+        let csv = history_to_csv(&rows);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], HISTORY_CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            format!(
+                "1970-01-01T00:00:00Z,DaiToXdai,DAI,2,0.02,0.000000000000021,0x{:064x},xDai bridge",
+                255
+            )
+        );
+        assert!(lines[2].starts_with("1970-01-01T00:00:00Z,EthToDai,ETH,1,0.003,0,"));
+        assert!(lines[2].ends_with(&Address::default().to_string()));
+        assert!(history_to_json(&rows)
+            .unwrap()
+            .contains("\"fee\": \"0.003\""));
+    }
+}
@@ -0,0 +1,305 @@
+//! Failure injection for chaos testing. A `FaultInjector` set as `fault_injector` drops,
+//! delays or fails the read only RPC calls and transaction submissions its rules match, so an
+//! operator can watch retries, `resume_pending` and reconciliation deal with a flaky node before
+//! trusting them with funds.
+//!
+//! Read only calls are named by the contract function they call, like `balanceOf(address)`, or
+//! by their JSON-RPC method, like `eth_getBalance`. Faults apply per attempt, so a rule failing
+//! a call twice is retried through with the default `RetryPolicy`.
+
+use crate::gas::GasPurpose;
+use crate::Chain;
+use crate::TokenBridge;
+use failure::Error;
+use futures::Future;
+use futures_timer::Delay;
+use num256::Uint256;
+use sha3::{Digest, Keccak256};
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// What happens to a request a rule matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// The request is lost. Reads fail as if the node never answered, transactions are
+    /// reported as sent with their hash but never reach the node, like a transaction dropped
+    /// from the mempool.
+    Drop,
+    /// The request is sent after this long
+    Delay(Duration),
+    /// The request fails with this message without being sent. Retries treat it like any
+    /// other transport error.
+    Error(String),
+}
+
+/// Which requests a rule applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaultTarget {
+    /// Read only calls whose name matches this pattern, where `*` matches any run of
+    /// characters, so `get*Price(uint256)` covers every Uniswap quote
+    Call(String),
+    /// Transactions sent for this purpose, or every transaction if `None`
+    Transaction(Option<GasPurpose>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaultRule {
+    /// The chain the rule applies to, both if `None`
+    pub chain: Option<Chain>,
+    pub target: FaultTarget,
+    pub fault: Fault,
+    /// How many matching requests to affect before the rule is used up, every one if `None`
+    pub times: Option<u32>,
+}
+
+/// Whether `name` matches `pattern`, in which `*` matches any run of characters
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // there is always a first part, the empty string if the pattern starts with `*`
+    let first = parts.next().unwrap_or("");
+    if !name.starts_with(first) {
+        return false;
+    }
+    let mut rest = &name[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        // no `*` at all, the whole name has to be the pattern
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
+
+/// Rules checked in the order they were added, the first matching rule that isn't used up
+/// decides what happens to a request
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    rules: Mutex<Vec<FaultRule>>,
+}
+
+impl FaultInjector {
+    pub fn new() -> FaultInjector {
+        FaultInjector::default()
+    }
+
+    pub fn add(&self, rule: FaultRule) {
+        self.rules.lock().unwrap().push(rule);
+    }
+
+    /// Removes every rule, letting all requests through again
+    pub fn clear(&self) {
+        self.rules.lock().unwrap().clear();
+    }
+
+    /// The fault for the read only call `name` on `chain`, if a rule has one
+    pub fn call_fault(&self, chain: Chain, name: &str) -> Option<Fault> {
+        self.take(chain, |target| match target {
+            FaultTarget::Call(pattern) => matches_pattern(pattern, name),
+            FaultTarget::Transaction(_) => false,
+        })
+    }
+
+    /// The fault for a transaction sent on `chain` for `purpose`, if a rule has one
+    pub fn transaction_fault(&self, chain: Chain, purpose: GasPurpose) -> Option<Fault> {
+        self.take(chain, |target| match target {
+            FaultTarget::Transaction(None) => true,
+            FaultTarget::Transaction(Some(matched)) => *matched == purpose,
+            FaultTarget::Call(_) => false,
+        })
+    }
+
+    fn take<F: Fn(&FaultTarget) -> bool>(&self, chain: Chain, matches: F) -> Option<Fault> {
+        let mut rules = self.rules.lock().unwrap();
+        let rule = rules.iter_mut().find(|rule| {
+            (rule.chain.is_none() || rule.chain == Some(chain))
+                && rule.times != Some(0)
+                && matches(&rule.target)
+        })?;
+        if let Some(ref mut times) = rule.times {
+            *times -= 1;
+        }
+        Some(rule.fault.clone())
+    }
+}
+
+/// The transport error a faulted read fails with, so that it is retried like a real one
+fn injected(kind: io::ErrorKind, message: String) -> Error {
+    io::Error::new(kind, format!("Injected fault: {}", message)).into()
+}
+
+impl TokenBridge {
+    /// Runs the read only call `name` on `chain` made by `request`, or what `fault_injector`
+    /// does to it instead
+    pub(crate) fn inject_call_fault<T, F>(
+        &self,
+        chain: Chain,
+        name: &str,
+        request: F,
+    ) -> Box<dyn Future<Item = T, Error = Error>>
+    where
+        T: 'static,
+        F: FnOnce() -> Box<dyn Future<Item = T, Error = Error>> + 'static,
+    {
+        let fault = match self.fault_injector {
+            Some(ref injector) => injector.call_fault(chain, name),
+            None => None,
+        };
+        match fault {
+            None => request(),
+            Some(fault) => {
+                warn!("Injecting {:?} into {} on {:?}", fault, name, chain);
+                match fault {
+                    Fault::Drop => Box::new(futures::future::err(injected(
+                        io::ErrorKind::TimedOut,
+                        format!("{} was dropped", name),
+                    ))),
+                    Fault::Delay(delay) => {
+                        Box::new(Delay::new(delay).from_err().and_then(move |_| request()))
+                    }
+                    Fault::Error(message) => Box::new(futures::future::err(injected(
+                        io::ErrorKind::Other,
+                        message,
+                    ))),
+                }
+            }
+        }
+    }
+
+    /// Broadcasts the signed transaction `tx` sent on `chain` for `purpose` with `broadcast`,
+    /// or does what `fault_injector` does to it instead. Returns the tx hash.
+    pub(crate) fn inject_transaction_fault<F>(
+        &self,
+        chain: Chain,
+        purpose: GasPurpose,
+        tx: Vec<u8>,
+        broadcast: F,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>>
+    where
+        F: FnOnce(Vec<u8>) -> Box<dyn Future<Item = Uint256, Error = Error>> + 'static,
+    {
+        let fault = match self.fault_injector {
+            Some(ref injector) => injector.transaction_fault(chain, purpose),
+            None => None,
+        };
+        match fault {
+            None => broadcast(tx),
+            Some(fault) => {
+                warn!(
+                    "Injecting {:?} into a {:?} transaction on {:?}",
+                    fault, purpose, chain
+                );
+                match fault {
+                    // the hash of a signed legacy transaction is the hash of its encoding
+                    Fault::Drop => Box::new(futures::future::ok(Uint256::from_bytes_be(
+                        &Keccak256::digest(&tx),
+                    ))),
+                    Fault::Delay(delay) => Box::new(
+                        Delay::new(delay)
+                            .from_err()
+                            .and_then(move |_| broadcast(tx)),
+                    ),
+                    Fault::Error(message) => Box::new(futures::future::err(injected(
+                        io::ErrorKind::Other,
+                        message,
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_tag::BlockTag;
+    use crate::mock::{mock_bridge, MockBlockReader};
+    use crate::retry::RetryPolicy;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("eth_getBalance", "eth_getBalance"));
+        assert!(!matches_pattern("eth_getBalance", "eth_getBalances"));
+        assert!(matches_pattern("*", "balanceOf(address)"));
+        assert!(matches_pattern(
+            "get*Price(uint256)",
+            "getEthToTokenInputPrice(uint256)"
+        ));
+        assert!(!matches_pattern("get*Price(uint256)", "getReserves()"));
+        assert!(matches_pattern("eth_*", "eth_getLogs"));
+        assert!(matches_pattern("*(address)", "balanceOf(address)"));
+        assert!(!matches_pattern("a*ab", "ab"));
+    }
+
+    #[test]
+    fn test_fault_rules() {
+        let injector = FaultInjector::new();
+        injector.add(FaultRule {
+            chain: Some(Chain::Eth),
+            target: FaultTarget::Call("balanceOf(*)".to_string()),
+            fault: Fault::Error("node unreachable".to_string()),
+            times: Some(2),
+        });
+        injector.add(FaultRule {
+            chain: None,
+            target: FaultTarget::Transaction(Some(GasPurpose::Bridging)),
+            fault: Fault::Drop,
+            times: None,
+        });
+
+        assert_eq!(injector.call_fault(Chain::Xdai, "balanceOf(address)"), None);
+        for _ in 0..2 {
+            assert!(injector
+                .call_fault(Chain::Eth, "balanceOf(address)")
+                .is_some());
+        }
+        assert_eq!(injector.call_fault(Chain::Eth, "balanceOf(address)"), None);
+
+        assert_eq!(
+            injector.transaction_fault(Chain::Xdai, GasPurpose::Bridging),
+            Some(Fault::Drop)
+        );
+        assert_eq!(
+            injector.transaction_fault(Chain::Eth, GasPurpose::Routing),
+            None
+        );
+        injector.clear();
+        assert_eq!(
+            injector.transaction_fault(Chain::Xdai, GasPurpose::Bridging),
+            None
+        );
+    }
+
+    #[test]
+    fn test_injected_read_fault() {
+        let reader = Arc::new(MockBlockReader::default());
+        let mut bridge = mock_bridge(reader.clone());
+        bridge.retry_policy = RetryPolicy::never();
+        reader.set_balance(Chain::Eth, bridge.own_address, 5u32.into());
+        let injector = Arc::new(FaultInjector::new());
+        injector.add(FaultRule {
+            chain: None,
+            target: FaultTarget::Call("eth_get*".to_string()),
+            fault: Fault::Drop,
+            times: Some(1),
+        });
+        bridge.fault_injector = Some(injector);
+
+        let block = BlockTag::Number(100u32.into());
+        let balance = || {
+            bridge
+                .get_eth_balance_at(bridge.own_address, block.clone())
+                .wait()
+        };
+        assert!(balance().is_err());
+        assert_eq!(balance().unwrap().into_wei(), 5u32.into());
+    }
+}
@@ -0,0 +1,123 @@
+//! Fees charged by the bridge itself. Newer deployments of the xDai bridge have a fee manager
+//! that keeps a percentage of every transfer, read through the home bridge on the xDai chain.
+
+use crate::Chain;
+use crate::TokenBridge;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+
+/// Bridge fees are fractions scaled by this, 10^16 is a 1% fee
+pub const FEE_PRECISION: u64 = 1_000_000_000_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BridgeDirection {
+    /// Dai on Eth to xDai
+    DaiToXdai,
+    /// xDai to Dai on Eth
+    XdaiToDai,
+}
+
+/// The part of `amount` kept by a bridge charging `fee`, see `FEE_PRECISION`
+pub fn bridge_fee_amount(amount: Uint256, fee: Uint256) -> Uint256 {
+    amount * fee / FEE_PRECISION.into()
+}
+
+/// A transfer sent to the bridge
+#[derive(Debug, Clone, PartialEq)]
+pub struct BridgeTransfer {
+    pub tx_hash: Uint256,
+    /// The amount sent to the bridge
+    pub amount: Uint256,
+    /// What the bridge is expected to keep, at the fee it charged when the transfer was sent
+    pub expected_fee: Uint256,
+}
+
+impl BridgeTransfer {
+    /// What should arrive on the other side
+    pub fn expected_net_amount(&self) -> Uint256 {
+        amount_after_fee(self.amount.clone(), self.expected_fee.clone())
+    }
+}
+
+fn amount_after_fee(amount: Uint256, fee: Uint256) -> Uint256 {
+    if fee > amount {
+        0u32.into()
+    } else {
+        amount - fee
+    }
+}
+
+impl TokenBridge {
+    /// The fee the bridge currently charges in `direction`, scaled by `FEE_PRECISION`. Bridges
+    /// without a fee manager charge nothing.
+    pub fn get_bridge_fee(
+        &self,
+        direction: BridgeDirection,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let home_bridge = self.xdai_home_bridge_address;
+        let salf = self.clone();
+        // deposits are charged the "foreign" fee and withdrawals the "home" fee
+        let getter = match direction {
+            BridgeDirection::DaiToXdai => "getForeignFee()",
+            BridgeDirection::XdaiToDai => "getHomeFee()",
+        };
+
+        Box::new(
+            self.call_view::<Vec<u8>>(Chain::Xdai, home_bridge, "feeManagerContract()", &[])
+                .and_then(move |fee_manager| {
+                    // older bridges don't have the function at all and return nothing
+                    let has_fee_manager =
+                        fee_manager.len() >= 32 && fee_manager[12..32].iter().any(|b| *b != 0);
+                    if has_fee_manager {
+                        salf.call_view(Chain::Xdai, home_bridge, getter, &[])
+                    } else {
+                        Box::new(futures::future::ok(0u32.into()))
+                    }
+                }),
+        )
+    }
+
+    /// The smallest transfer the bridge accepts in `direction`, from the `minPerTx` of the
+    /// bridge receiving it
+    pub fn get_bridge_minimum(
+        &self,
+        direction: BridgeDirection,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let (chain, bridge) = match direction {
+            BridgeDirection::DaiToXdai => (Chain::Eth, self.xdai_foreign_bridge_address),
+            BridgeDirection::XdaiToDai => (Chain::Xdai, self.xdai_home_bridge_address),
+        };
+        self.call_view(chain, bridge, "minPerTx()", &[])
+    }
+
+    /// How much of `amount` arrives on the other side of the bridge after its fee
+    pub fn net_bridged_amount(
+        &self,
+        direction: BridgeDirection,
+        amount: Uint256,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        Box::new(
+            self.get_bridge_fee(direction)
+                .map(move |fee| amount_after_fee(amount.clone(), bridge_fee_amount(amount, fee))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bridge_fee() {
+        let one_percent: Uint256 = 10_000_000_000_000_000u64.into();
+        let transfer = BridgeTransfer {
+            tx_hash: 0u32.into(),
+            amount: 500u32.into(),
+            expected_fee: bridge_fee_amount(500u32.into(), one_percent),
+        };
+        assert_eq!(transfer.expected_fee, 5u32.into());
+        assert_eq!(transfer.expected_net_amount(), 495u32.into());
+        assert_eq!(bridge_fee_amount(500u32.into(), 0u32.into()), 0u32.into());
+    }
+}
@@ -0,0 +1,308 @@
+//! What the transactions we send spend on gas, so operators can tell the cost of bridging apart
+//! from the cost of routing.
+//!
+//! Every transaction is recorded when it is sent, with its gas limit standing in for the gas
+//! used. web30 can't read receipts, `settle_gas_usage` replaces the estimates with the gas used
+//! from the receipts through `block_reader`.
+
+use crate::units::{Eth, XDai};
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::Address;
+use failure::Error;
+use futures::future::join_all;
+use futures::Future;
+use num256::Uint256;
+use std::sync::Mutex;
+
+/// The `approve(address,uint256)` selector
+const APPROVE_SELECTOR: [u8; 4] = [0x09, 0x5e, 0xa7, 0xb3];
+/// The `transfer(address,uint256)` selector
+const TRANSFER_SELECTOR: [u8; 4] = [0xa9, 0x05, 0x9c, 0xbb];
+
+/// What a transaction was sent for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GasPurpose {
+    /// Moving Dai or xDai over the xDai bridge
+    Bridging,
+    /// Swaps, wrapping and the approvals they need
+    Routing,
+    /// Anything else, such as plain transfers
+    Other,
+}
+
+/// The gas used by the transaction `tx_hash`, as returned by `eth_getTransactionReceipt`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GasReceipt {
+    pub gas_used: Uint256,
+    /// Missing from the receipts of nodes older than the London fork
+    pub effective_gas_price: Option<Uint256>,
+}
+
+/// The gas of one transaction we sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GasUsage {
+    pub chain: Chain,
+    pub tx_hash: Uint256,
+    pub purpose: GasPurpose,
+    /// The `Operation` the transaction was sent for, if any
+    pub operation_id: Option<String>,
+    /// The gas limit of the transaction until it is `settled`
+    pub gas_used: Uint256,
+    pub effective_gas_price: Uint256,
+    /// Whether `gas_used` and `effective_gas_price` are from the receipt
+    pub settled: bool,
+}
+
+impl GasUsage {
+    /// What the transaction paid for gas, in wei of the native coin of `chain`
+    pub fn cost(&self) -> Uint256 {
+        self.gas_used.clone() * self.effective_gas_price.clone()
+    }
+}
+
+/// Gas paid on each chain
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GasSpent {
+    pub eth: Eth,
+    pub xdai: XDai,
+}
+
+/// Every transaction sent by a bridge and its clones, see `GasUsage`
+#[derive(Debug, Default)]
+pub struct GasLedger {
+    usage: Mutex<Vec<GasUsage>>,
+}
+
+impl GasLedger {
+    pub(crate) fn record(&self, usage: GasUsage) {
+        self.usage.lock().unwrap().push(usage);
+    }
+
+    /// Replaces the estimate for `tx_hash` on `chain` with its receipt
+    pub(crate) fn settle(&self, chain: Chain, tx_hash: &Uint256, receipt: GasReceipt) {
+        let mut usage = self.usage.lock().unwrap();
+        for entry in usage.iter_mut() {
+            if entry.chain == chain && entry.tx_hash == *tx_hash {
+                entry.gas_used = receipt.gas_used.clone();
+                if let Some(ref price) = receipt.effective_gas_price {
+                    entry.effective_gas_price = price.clone();
+                }
+                entry.settled = true;
+            }
+        }
+    }
+
+    /// Every transaction recorded so far, oldest first
+    pub fn usage(&self) -> Vec<GasUsage> {
+        self.usage.lock().unwrap().clone()
+    }
+
+    /// The gas paid by the transactions `include` picks
+    pub fn spent<F: Fn(&GasUsage) -> bool>(&self, include: F) -> GasSpent {
+        let mut eth: Uint256 = 0u32.into();
+        let mut xdai: Uint256 = 0u32.into();
+        for entry in self
+            .usage
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| include(entry))
+        {
+            match entry.chain {
+                Chain::Eth => eth = eth + entry.cost(),
+                Chain::Xdai => xdai = xdai + entry.cost(),
+            }
+        }
+        GasSpent {
+            eth: Eth::from_wei(eth),
+            xdai: XDai::from_wei(xdai),
+        }
+    }
+}
+
+/// The recipient of a `transfer(address,uint256)` call
+fn transfer_recipient(data: &[u8]) -> Option<Address> {
+    if data.get(0..4)? != TRANSFER_SELECTOR {
+        return None;
+    }
+    Address::from_slice(data.get(16..36)?).ok()
+}
+
+impl TokenBridge {
+    /// A copy of this bridge that records the gas of its transactions under `operation_id`
+    pub(crate) fn for_operation(&self, operation_id: &str) -> TokenBridge {
+        let mut bridge = self.clone();
+        bridge.operation_id = Some(operation_id.to_string());
+        bridge
+    }
+
+    /// What a transaction to `to` with `data` is for, `swap` if it was sent as a swap
+    pub(crate) fn gas_purpose(&self, to: Address, data: &[u8], swap: bool) -> GasPurpose {
+        let bridge_transfer = to == self.foreign_dai_contract_address
+            && transfer_recipient(data) == Some(self.xdai_foreign_bridge_address);
+        if bridge_transfer
+            || to == self.xdai_home_bridge_address
+            || to == self.xdai_foreign_bridge_address
+        {
+            GasPurpose::Bridging
+        } else if swap || to == self.weth_address || data.get(0..4) == Some(&APPROVE_SELECTOR) {
+            GasPurpose::Routing
+        } else {
+            GasPurpose::Other
+        }
+    }
+
+    /// Records the transaction `tx_hash` sent with `gas_limit` at `gas_price`
+    pub(crate) fn record_gas(
+        &self,
+        chain: Chain,
+        tx_hash: Uint256,
+        purpose: GasPurpose,
+        gas_limit: Uint256,
+        gas_price: Uint256,
+    ) {
+        self.gas_ledger.record(GasUsage {
+            chain,
+            tx_hash,
+            purpose,
+            operation_id: self.operation_id.clone(),
+            gas_used: gas_limit,
+            effective_gas_price: gas_price,
+            settled: false,
+        });
+    }
+
+    /// The gas paid by every transaction sent by this bridge and its clones so far
+    pub fn total_gas_spent(&self) -> GasSpent {
+        self.gas_ledger.spent(|_| true)
+    }
+
+    /// `total_gas_spent` of the transactions sent for `purpose`
+    pub fn gas_spent_on(&self, purpose: GasPurpose) -> GasSpent {
+        self.gas_ledger.spent(|entry| entry.purpose == purpose)
+    }
+
+    /// Every transaction sent for the operation with `operation_id`
+    pub fn operation_gas_usage(&self, operation_id: &str) -> Vec<GasUsage> {
+        self.gas_ledger
+            .usage()
+            .into_iter()
+            .filter(|entry| entry.operation_id.as_deref() == Some(operation_id))
+            .collect()
+    }
+
+    /// Reads the receipts of the transactions recorded with their gas limit and replaces the
+    /// estimate with the gas they used. Transactions that aren't mined yet stay estimates.
+    /// Does nothing without a `block_reader`. Resolves to `total_gas_spent` afterwards.
+    pub fn settle_gas_usage(&self) -> Box<dyn Future<Item = GasSpent, Error = Error>> {
+        let reader = match self.block_reader {
+            Some(ref reader) => reader.clone(),
+            None => return Box::new(futures::future::ok(self.total_gas_spent())),
+        };
+        let ledger = self.gas_ledger.clone();
+        let receipts = self
+            .gas_ledger
+            .usage()
+            .into_iter()
+            .filter(|entry| !entry.settled)
+            .map(|entry| {
+                let ledger = ledger.clone();
+                reader
+                    .eth_get_transaction_receipt(entry.chain, entry.tx_hash.clone())
+                    .map(move |receipt| {
+                        if let Some(receipt) = receipt {
+                            ledger.settle(entry.chain, &entry.tx_hash, receipt);
+                        }
+                    })
+            })
+            .collect::<Vec<_>>();
+        Box::new(join_all(receipts).map(move |_| ledger.spent(|_| true)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_bridge, MockBlockReader};
+    use std::sync::Arc;
+
+    fn usage(chain: Chain, purpose: GasPurpose, gas_used: u32) -> GasUsage {
+        GasUsage {
+            chain,
+            tx_hash: gas_used.into(),
+            purpose,
+            operation_id: None,
+            gas_used: gas_used.into(),
+            effective_gas_price: 10u32.into(),
+            settled: false,
+        }
+    }
+
+    #[test]
+    fn test_gas_ledger() {
+        let ledger = GasLedger::default();
+        ledger.record(usage(Chain::Eth, GasPurpose::Routing, 100_000));
+        ledger.record(usage(Chain::Eth, GasPurpose::Bridging, 80_000));
+        ledger.record(usage(Chain::Xdai, GasPurpose::Bridging, 50_000));
+
+        let total = ledger.spent(|_| true);
+        assert_eq!(total.eth, Eth::from_wei(1_800_000u32.into()));
+        assert_eq!(total.xdai, XDai::from_wei(500_000u32.into()));
+
+        ledger.settle(
+            Chain::Eth,
+            &80_000u32.into(),
+            GasReceipt {
+                gas_used: 60_000u32.into(),
+                effective_gas_price: None,
+            },
+        );
+        let bridging = ledger.spent(|entry| entry.purpose == GasPurpose::Bridging);
+        assert_eq!(bridging.eth, Eth::from_wei(600_000u32.into()));
+        assert!(ledger.usage()[1].settled);
+        assert!(!ledger.usage()[0].settled);
+    }
+
+    #[test]
+    fn test_transfer_recipient() {
+        let mut data = TRANSFER_SELECTOR.to_vec();
+        data.extend_from_slice(&[0u8; 31]);
+        data.push(1);
+        data.extend_from_slice(&[0u8; 32]);
+        let recipient = transfer_recipient(&data).unwrap();
+        assert_eq!(recipient.as_bytes()[19], 1);
+        assert_eq!(transfer_recipient(&APPROVE_SELECTOR), None);
+    }
+
+    #[test]
+    fn test_settle_gas_usage() {
+        let reader = Arc::new(MockBlockReader::default());
+        let bridge = mock_bridge(reader.clone());
+        let gas_price: Uint256 = 10u32.into();
+        for tx_hash in 1u32..3 {
+            bridge.record_gas(
+                Chain::Eth,
+                tx_hash.into(),
+                GasPurpose::Other,
+                100_000u32.into(),
+                gas_price.clone(),
+            );
+        }
+        reader.set_receipt(
+            Chain::Eth,
+            1u32.into(),
+            GasReceipt {
+                gas_used: 21_000u32.into(),
+                effective_gas_price: Some(5u32.into()),
+            },
+        );
+
+        // the second transaction isn't mined and keeps its estimate
+        let spent = bridge.settle_gas_usage().wait().unwrap();
+        assert_eq!(spent.eth, Eth::from_wei(1_105_000u32.into()));
+        let usage = bridge.gas_ledger.usage();
+        assert!(usage[0].settled);
+        assert!(!usage[1].settled);
+    }
+}
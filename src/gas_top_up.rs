@@ -0,0 +1,238 @@
+//! Keeps enough ETH around to pay for gas. A router that converts everything it holds on Eth
+//! can end up with Dai but no ETH to pay for the swap that would get it ETH. With a `GasTopUp`
+//! policy set every conversion sending transactions on Eth, other than gasless ones whose
+//! relayer pays, first checks the ETH balance and, when it is below `min_balance`, sells a
+//! little Dai for ETH while there is still gas for that, or asks the `GasFaucet` for ETH once
+//! there isn't.
+//!
+//! A failed top-up doesn't stop the conversion, it fails by itself if the gas really runs out.
+//! Call `ensure_gas` to top up on its own and see errors.
+
+use crate::cost::SWAP_GAS;
+use crate::pending::ConversionKind;
+use crate::units::{Dai, Eth};
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use futures_timer::FutureExt;
+use num256::Uint256;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Gas limit of the Dai approval Uniswap may need before the top-up swap
+const APPROVE_GAS: u64 = 50_000;
+
+/// Swap deadline and faucet wait of the top-ups made before conversions, in seconds
+pub const GAS_TOP_UP_TIMEOUT: u64 = 600;
+
+/// Something that sends ETH for gas to accounts that ran out, such as a faucet or a relayer
+pub trait GasFaucet: Send + Sync {
+    /// Sends `amount` ETH to `address`, resolves to the hash of the transaction
+    fn request_eth(
+        &self,
+        address: Address,
+        amount: &Uint256,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>>;
+}
+
+/// When and how to top up the ETH balance, amounts in wei
+#[derive(Clone)]
+pub struct GasTopUp {
+    /// Top up once the ETH balance is below this, enough for the next few conversions
+    pub min_balance: Uint256,
+    /// The Dai sold for ETH by a top-up
+    pub dai_to_sell: Uint256,
+    /// Asked for ETH when there isn't enough left to swap for it
+    pub faucet: Option<Arc<dyn GasFaucet>>,
+    /// The ETH asked of `faucet`
+    pub faucet_amount: Uint256,
+}
+
+/// What `ensure_gas` did
+#[derive(Debug, Clone, PartialEq)]
+pub enum GasTopUpOutcome {
+    /// The balance was at `min_balance` or above
+    NotNeeded,
+    /// `dai_to_sell` was swapped for this much ETH
+    Swapped(Eth),
+    /// The faucet sent ETH in this transaction
+    Faucet { tx_hash: Uint256 },
+}
+
+/// How a top-up would be made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TopUpStep {
+    None,
+    Swap,
+    Faucet,
+    /// Neither is possible
+    Stuck,
+}
+
+/// Picks the step for an account with `balance` ETH and `dai` Dai when the swap costs
+/// `swap_cost` in gas
+fn plan_top_up(
+    policy: &GasTopUp,
+    balance: &Uint256,
+    dai: &Uint256,
+    swap_cost: &Uint256,
+) -> TopUpStep {
+    if *balance >= policy.min_balance {
+        TopUpStep::None
+    } else if balance >= swap_cost && *dai >= policy.dai_to_sell {
+        TopUpStep::Swap
+    } else if policy.faucet.is_some() {
+        TopUpStep::Faucet
+    } else {
+        TopUpStep::Stuck
+    }
+}
+
+impl TokenBridge {
+    /// Tops up our ETH balance according to `gas_top_up` if it is below `min_balance`. Fails
+    /// with `TokenBridgeError::OutOfGas` if there is too little ETH to swap for more and no
+    /// faucet, `timeout` is the swap deadline or how long to wait for the faucet.
+    pub fn ensure_gas(
+        &self,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = GasTopUpOutcome, Error = Error>> {
+        let policy = match self.gas_top_up {
+            Some(ref policy) => policy.clone(),
+            None => return Box::new(futures::future::ok(GasTopUpOutcome::NotNeeded)),
+        };
+        let own_address = self.own_address;
+        let eth_web3 = self.eth_web3.clone();
+        // the top-up swap is a conversion itself, it must not try to top up again nor wait
+        // behind the pending conversion it tops up for, nor be taken for the transaction of the
+        // operation step it runs in
+        let mut salf = self.clone();
+        salf.gas_top_up = None;
+        salf.pending_registry = None;
+        salf.operation_id = None;
+
+        Box::new(
+            self.eth_web3
+                .eth_get_balance(own_address)
+                .join3(
+                    self.get_dai_balance(own_address),
+                    self.gas_price(Chain::Eth),
+                )
+                .and_then(move |(balance, dai, gas_price)| {
+                    let swap_cost = gas_price * (SWAP_GAS + APPROVE_GAS).into();
+                    let step = plan_top_up(&policy, &balance, dai.wei(), &swap_cost);
+                    let outcome: Box<dyn Future<Item = GasTopUpOutcome, Error = Error>> =
+                        match (step, policy.faucet) {
+                            (TopUpStep::None, _) => {
+                                Box::new(futures::future::ok(GasTopUpOutcome::NotNeeded))
+                            }
+                            (TopUpStep::Swap, _) => {
+                                info!(
+                                    "ETH balance {} is low, selling {} Dai for gas",
+                                    balance, policy.dai_to_sell
+                                );
+                                Box::new(
+                                    salf.dai_to_eth_swap(
+                                        Dai::from_wei(policy.dai_to_sell),
+                                        timeout,
+                                    )
+                                    .map(GasTopUpOutcome::Swapped),
+                                )
+                            }
+                            (TopUpStep::Faucet, Some(faucet)) => {
+                                info!(
+                                    "ETH balance {} is too low to swap for gas, asking the faucet",
+                                    balance
+                                );
+                                Box::new(
+                                    faucet
+                                        .request_eth(own_address, &policy.faucet_amount)
+                                        .and_then(move |tx_hash| {
+                                            eth_web3
+                                                .wait_for_transaction(tx_hash.clone().into())
+                                                .timeout(Duration::from_secs(timeout))
+                                                .map(move |_| GasTopUpOutcome::Faucet { tx_hash })
+                                        }),
+                                )
+                            }
+                            _ => Box::new(futures::future::err(
+                                TokenBridgeError::OutOfGas {
+                                    balance,
+                                    needed: swap_cost,
+                                }
+                                .into(),
+                            )),
+                        };
+                    outcome
+                }),
+        )
+    }
+
+    /// Tops up gas before a conversion of `kind` that sends transactions on Eth, once it holds
+    /// its pending claim, never fails
+    pub(crate) fn top_up_gas_before(
+        &self,
+        kind: ConversionKind,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let on_eth = match kind {
+            ConversionKind::XdaiToDai
+            | ConversionKind::XdaiToToken(_)
+            | ConversionKind::TokenToXdai(_) => false,
+            ConversionKind::BridgeToken { from, .. } => from == Chain::Eth,
+            _ => true,
+        };
+        if self.gas_top_up.is_none() || !on_eth {
+            return Box::new(futures::future::ok(()));
+        }
+        Box::new(self.ensure_gas(GAS_TOP_UP_TIMEOUT).then(|outcome| {
+            match outcome {
+                Ok(GasTopUpOutcome::NotNeeded) => {}
+                Ok(outcome) => info!("Topped up gas with {:?}", outcome),
+                Err(e) => warn!("Could not top up gas, trying anyway: {:?}", e),
+            }
+            Ok(())
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoFaucet;
+
+    impl GasFaucet for NoFaucet {
+        fn request_eth(
+            &self,
+            _address: Address,
+            _amount: &Uint256,
+        ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+            Box::new(futures::future::err(failure::format_err!("dry")))
+        }
+    }
+
+    #[test]
+    fn test_plan_top_up() {
+        let mut policy = GasTopUp {
+            min_balance: 1_000u32.into(),
+            dai_to_sell: 500u32.into(),
+            faucet: None,
+            faucet_amount: 2_000u32.into(),
+        };
+        let plan = |policy: &GasTopUp, balance: u32, dai: u32| {
+            plan_top_up(policy, &balance.into(), &dai.into(), &100u32.into())
+        };
+
+        assert_eq!(plan(&policy, 1_000, 0), TopUpStep::None);
+        assert_eq!(plan(&policy, 999, 500), TopUpStep::Swap);
+        // no gas for the swap, or no Dai to sell
+        assert_eq!(plan(&policy, 99, 500), TopUpStep::Stuck);
+        assert_eq!(plan(&policy, 999, 499), TopUpStep::Stuck);
+
+        policy.faucet = Some(Arc::new(NoFaucet));
+        assert_eq!(plan(&policy, 99, 500), TopUpStep::Faucet);
+        assert_eq!(plan(&policy, 999, 500), TopUpStep::Swap);
+    }
+}
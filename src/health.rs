@@ -0,0 +1,165 @@
+//! Health of the full nodes behind a bridge, so that a node that is down or lagging behind the
+//! chain can be told apart from a problem with the bridge itself
+
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use failure::Error;
+use futures::Future;
+use num::ToPrimitive;
+use num256::Uint256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Eth blocks come every ~13 seconds, a latest block older than this means the node is behind
+pub const ETH_MAX_BLOCK_AGE: Duration = Duration::from_secs(180);
+/// xDai blocks come every 5 seconds
+pub const XDAI_MAX_BLOCK_AGE: Duration = Duration::from_secs(60);
+
+/// What a full node reported about itself, see `check_health`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainHealth {
+    pub chain: Chain,
+    /// Why the node could not be queried, `None` if it responded
+    pub error: Option<String>,
+    pub latest_block: Option<Uint256>,
+    /// How long ago the latest block was produced according to its timestamp
+    pub block_age: Option<Duration>,
+    /// Whether the latest block is recent enough for the node to be following the chain
+    pub synced: bool,
+    /// The network id reported by the node
+    pub chain_id: Option<u64>,
+    /// The chain id transactions are signed for, if one is configured
+    pub expected_chain_id: Option<u64>,
+}
+
+impl ChainHealth {
+    fn unreachable(chain: Chain, expected_chain_id: Option<u64>, error: Error) -> ChainHealth {
+        ChainHealth {
+            chain,
+            error: Some(error.to_string()),
+            latest_block: None,
+            block_age: None,
+            synced: false,
+            chain_id: None,
+            expected_chain_id,
+        }
+    }
+
+    /// True if the node responded, is synced and is on the expected chain
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none() && self.synced && self.chain_id_matches()
+    }
+
+    /// False only if the node is known to be on a different chain than configured
+    pub fn chain_id_matches(&self) -> bool {
+        match (self.chain_id, self.expected_chain_id) {
+            (Some(found), Some(expected)) => found == expected,
+            _ => true,
+        }
+    }
+}
+
+/// The age of a block with `timestamp` at `now`, both in seconds since the epoch. Blocks from
+/// the future because of clock skew have an age of zero.
+pub fn block_age(timestamp: &Uint256, now: u64) -> Duration {
+    let timestamp = timestamp.to_u64().unwrap_or(u64::MAX);
+    Duration::from_secs(now.saturating_sub(timestamp))
+}
+
+/// Errors with `TokenBridgeError::StaleNode` if the block with `timestamp` is older than
+/// `max_lag`, quotes and swap deadlines based on it would be off
+pub fn ensure_fresh(timestamp: &Uint256, max_lag: Duration) -> Result<(), Error> {
+    let lag = block_age(timestamp, now());
+    if lag > max_lag {
+        return Err(TokenBridgeError::StaleNode { lag }.into());
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0)
+}
+
+impl TokenBridge {
+    /// Queries the full node of each chain for its latest block and network id. This never
+    /// fails, a node that does not respond is reported with `error` set.
+    pub fn check_health(
+        &self,
+    ) -> Box<dyn Future<Item = (ChainHealth, ChainHealth), Error = Error>> {
+        Box::new(
+            self.check_chain_health(Chain::Eth)
+                .join(self.check_chain_health(Chain::Xdai)),
+        )
+    }
+
+    /// Queries the full node of `chain`, see `check_health`
+    pub fn check_chain_health(
+        &self,
+        chain: Chain,
+    ) -> Box<dyn Future<Item = ChainHealth, Error = Error>> {
+        let (expected_chain_id, max_age) = match chain {
+            Chain::Eth => (self.eth_chain_id, ETH_MAX_BLOCK_AGE),
+            Chain::Xdai => (self.xdai_chain_id, XDAI_MAX_BLOCK_AGE),
+        };
+        let web3 = self.web3(chain);
+        Box::new(
+            web3.eth_get_latest_block()
+                .join(web3.net_version())
+                .then(move |result| {
+                    Ok(match result {
+                        Ok((block, net_version)) => {
+                            let age = block_age(&block.timestamp, now());
+                            ChainHealth {
+                                chain,
+                                error: None,
+                                latest_block: Some(block.number),
+                                block_age: Some(age),
+                                synced: age <= max_age,
+                                chain_id: net_version.trim().parse().ok(),
+                                expected_chain_id,
+                            }
+                        }
+                        Err(e) => ChainHealth::unreachable(chain, expected_chain_id, e),
+                    })
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::format_err;
+
+    #[test]
+    fn test_block_age() {
+        assert_eq!(block_age(&100u32.into(), 130), Duration::from_secs(30));
+        assert_eq!(block_age(&130u32.into(), 100), Duration::from_secs(0));
+
+        let mut health = ChainHealth::unreachable(Chain::Xdai, Some(100), format_err!("down"));
+        assert!(!health.is_healthy());
+        health.error = None;
+        health.synced = true;
+        health.chain_id = Some(100);
+        assert!(health.is_healthy());
+        health.chain_id = Some(77);
+        assert!(!health.is_healthy());
+    }
+
+    #[test]
+    fn test_ensure_fresh() {
+        let max_lag = Duration::from_secs(60);
+        assert!(ensure_fresh(&now().into(), max_lag).is_ok());
+        let hour_ago: Uint256 = (now() - 3600).into();
+        match ensure_fresh(&hour_ago, max_lag)
+            .unwrap_err()
+            .downcast_ref::<TokenBridgeError>()
+        {
+            Some(TokenBridgeError::StaleNode { lag }) => assert!(*lag >= Duration::from_secs(3600)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Past bridge transfers and Uniswap swaps of an address, read back from the Eth event logs so
+//! that accounting doesn't need an indexer of its own.
+//!
+//! Everything is found on the Eth side. Bridging Dai to xDai shows up as a Dai transfer to the
+//! foreign bridge and bridging back as a Dai transfer from it, the xDai side is a plain value
+//! transfer which leaves no log.
+
+use crate::abi::address_word;
+use crate::block_tag::BlockTag;
+use crate::logs::{DecodedEvent, EventDefinition, SwapEvent, ERC20_TRANSFER};
+use crate::logs::{UNISWAP_V1_ETH_PURCHASE, UNISWAP_V1_TOKEN_PURCHASE};
+use crate::TokenBridge;
+use clarity::utils::bytes_to_hex_str;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use web30::types::{Log, NewFilter};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistoryKind {
+    /// Dai sent to the foreign bridge, paid out as xDai
+    DaiToXdai { amount: Uint256 },
+    /// Dai paid out by the foreign bridge for xDai
+    XdaiToDai { amount: Uint256 },
+    EthToDai {
+        eth_sold: Uint256,
+        dai_bought: Uint256,
+    },
+    DaiToEth {
+        dai_sold: Uint256,
+        eth_bought: Uint256,
+    },
+}
+
+/// One past conversion step, found by the log it emitted on Eth
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub block: Uint256,
+    pub tx_hash: Uint256,
+    pub log_index: Uint256,
+    pub kind: HistoryKind,
+    /// The bridge for transfers, the exchange for swaps
+    pub counterparty: Address,
+    /// The label of `counterparty` in the bridge's `address_book`
+    pub counterparty_label: Option<String>,
+}
+
+fn topic(bytes: &[u8]) -> Option<String> {
+    Some(format!("0x{}", bytes_to_hex_str(bytes)))
+}
+
+fn address_topic(address: Address) -> Option<String> {
+    topic(&address_word(address))
+}
+
+fn or_zero(value: &Option<Uint256>) -> Uint256 {
+    value.clone().unwrap_or_else(|| 0u32.into())
+}
+
+fn entry(log: &Log, kind: HistoryKind, counterparty: Address) -> HistoryEntry {
+    HistoryEntry {
+        block: or_zero(&log.block_number),
+        tx_hash: match log.transaction_hash {
+            Some(ref hash) => Uint256::from_bytes_be(hash),
+            None => 0u32.into(),
+        },
+        log_index: or_zero(&log.log_index),
+        kind,
+        counterparty,
+        counterparty_label: None,
+    }
+}
+
+/// Decodes the logs of one query with `counterparty` into entries, leaving out logs removed by
+/// a reorg
+fn decode_logs<F>(
+    logs: &[Log],
+    event: EventDefinition,
+    counterparty: Address,
+    kind: F,
+) -> Result<Vec<HistoryEntry>, Error>
+where
+    F: Fn(&DecodedEvent) -> Result<HistoryKind, Error>,
+{
+    let mut entries = Vec::new();
+    for log in logs {
+        if log.removed == Some(true) {
+            continue;
+        }
+        let decoded = event.decode(log)?;
+        entries.push(entry(log, kind(&decoded)?, counterparty));
+    }
+    Ok(entries)
+}
+
+/// Oldest first, in the order the logs were emitted
+fn sort_history(mut entries: Vec<HistoryEntry>) -> Vec<HistoryEntry> {
+    entries.sort_by(|a, b| (&a.block, &a.log_index).cmp(&(&b.block, &b.log_index)));
+    entries
+}
+
+impl TokenBridge {
+    /// The bridge transfers and Uniswap swaps of `address` mined between `from_block` and
+    /// `to_block` inclusive, oldest first. Swaps are only found on the Uniswap V1 exchange at
+    /// `uniswap_address`, router swaps emit no event naming the trader.
+    pub fn get_bridge_history(
+        &self,
+        address: Address,
+        from_block: Uint256,
+        to_block: Uint256,
+    ) -> Box<dyn Future<Item = Vec<HistoryEntry>, Error = Error>> {
+        let dai = self.foreign_dai_contract_address;
+        let bridge = self.xdai_foreign_bridge_address;
+        let uniswap = self.uniswap_address;
+        let transfer = topic(&ERC20_TRANSFER.topic0());
+
+        let deposits = self
+            .history_logs(
+                dai,
+                vec![
+                    transfer.clone(),
+                    address_topic(address),
+                    address_topic(bridge),
+                ],
+                &from_block,
+                &to_block,
+            )
+            .and_then(move |logs| {
+                decode_logs(&logs, ERC20_TRANSFER, bridge, |event| {
+                    Ok(HistoryKind::DaiToXdai {
+                        amount: event.uint("value")?,
+                    })
+                })
+            });
+        let withdrawals = self
+            .history_logs(
+                dai,
+                vec![transfer, address_topic(bridge), address_topic(address)],
+                &from_block,
+                &to_block,
+            )
+            .and_then(move |logs| {
+                decode_logs(&logs, ERC20_TRANSFER, bridge, |event| {
+                    Ok(HistoryKind::XdaiToDai {
+                        amount: event.uint("value")?,
+                    })
+                })
+            });
+        let eth_to_dai = self
+            .swap_history(UNISWAP_V1_TOKEN_PURCHASE, address, &from_block, &to_block)
+            .and_then(move |logs| {
+                decode_logs(
+                    &logs,
+                    UNISWAP_V1_TOKEN_PURCHASE.definition,
+                    uniswap,
+                    |event| {
+                        Ok(HistoryKind::EthToDai {
+                            eth_sold: event.uint("eth_sold")?,
+                            dai_bought: event.uint("tokens_bought")?,
+                        })
+                    },
+                )
+            });
+        let dai_to_eth = self
+            .swap_history(UNISWAP_V1_ETH_PURCHASE, address, &from_block, &to_block)
+            .and_then(move |logs| {
+                decode_logs(
+                    &logs,
+                    UNISWAP_V1_ETH_PURCHASE.definition,
+                    uniswap,
+                    |event| {
+                        Ok(HistoryKind::DaiToEth {
+                            dai_sold: event.uint("tokens_sold")?,
+                            eth_bought: event.uint("eth_bought")?,
+                        })
+                    },
+                )
+            });
+
+        let salf = self.clone();
+        Box::new(deposits.join4(withdrawals, eth_to_dai, dai_to_eth).map(
+            move |(mut deposits, withdrawals, eth_to_dai, dai_to_eth)| {
+                deposits.extend(withdrawals);
+                deposits.extend(eth_to_dai);
+                deposits.extend(dai_to_eth);
+                for entry in deposits.iter_mut() {
+                    entry.counterparty_label = salf.address_label(entry.counterparty);
+                }
+                sort_history(deposits)
+            },
+        ))
+    }
+
+    fn swap_history(
+        &self,
+        event: SwapEvent,
+        buyer: Address,
+        from_block: &Uint256,
+        to_block: &Uint256,
+    ) -> Box<dyn Future<Item = Vec<Log>, Error = Error>> {
+        self.history_logs(
+            self.uniswap_address,
+            vec![topic(&event.definition.topic0()), address_topic(buyer)],
+            from_block,
+            to_block,
+        )
+    }
+
+    fn history_logs(
+        &self,
+        contract: Address,
+        topics: Vec<Option<String>>,
+        from_block: &Uint256,
+        to_block: &Uint256,
+    ) -> Box<dyn Future<Item = Vec<Log>, Error = Error>> {
+        Box::new(self.eth_web3.eth_get_logs(NewFilter {
+            from_block: Some(BlockTag::Number(from_block.clone()).to_rpc_param()),
+            to_block: Some(BlockTag::Number(to_block.clone()).to_rpc_param()),
+            address: vec![contract],
+            topics: Some(topics.into_iter().map(|topic| Some(vec![topic])).collect()),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry_at(block: u32, log_index: u32) -> HistoryEntry {
+        HistoryEntry {
+            block: block.into(),
+            tx_hash: 0u32.into(),
+            log_index: log_index.into(),
+            kind: HistoryKind::DaiToXdai {
+                amount: 1u32.into(),
+            },
+            counterparty: Address::default(),
+            counterparty_label: None,
+        }
+    }
+
+    #[test]
+    fn test_sort_history() {
+        let sorted = sort_history(vec![entry_at(2, 0), entry_at(1, 5), entry_at(1, 2)]);
+        assert_eq!(sorted, vec![entry_at(1, 2), entry_at(1, 5), entry_at(2, 0)]);
+    }
+
+    #[test]
+    fn test_address_topic() {
+        let topic = address_topic(Address::default()).unwrap();
+        assert_eq!(topic, format!("0x{}", "0".repeat(64)));
+    }
+}
@@ -0,0 +1,130 @@
+//! Swaps between xDai and bridged tokens on the xDai chain through Honeyswap, or any other
+//! Uniswap V2 style router there, so that a conversion doesn't have to pay mainnet gas when the
+//! xDai side has the liquidity.
+
+use crate::pending::ConversionKind;
+use crate::router::RouterMarket;
+use crate::units::XDai;
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub use crate::contracts::{XDAI_HONEYSWAP_ROUTER, XDAI_WXDAI};
+
+pub fn honeyswap_router() -> Address {
+    Address::from_str(XDAI_HONEYSWAP_ROUTER).unwrap()
+}
+
+pub fn wxdai() -> Address {
+    Address::from_str(XDAI_WXDAI).unwrap()
+}
+
+impl TokenBridge {
+    fn xdai_market(&self, token: Address) -> RouterMarket {
+        RouterMarket {
+            chain: Chain::Xdai,
+            router: self.xdai_router_address,
+            wrapped_native: self.wxdai_address,
+            token,
+        }
+    }
+
+    /// How much of `token` selling `amount` xDai on the xDai router gives
+    pub fn xdai_to_token_price(
+        &self,
+        token: Address,
+        amount: XDai,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.router_amount_out(
+            Chain::Xdai,
+            self.xdai_router_address,
+            amount.into_wei(),
+            vec![self.wxdai_address, token],
+        )
+    }
+
+    /// How much xDai selling `amount` of `token` on the xDai router gives
+    pub fn token_to_xdai_price(
+        &self,
+        token: Address,
+        amount: Uint256,
+    ) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        Box::new(
+            self.router_amount_out(
+                Chain::Xdai,
+                self.xdai_router_address,
+                amount,
+                vec![token, self.wxdai_address],
+            )
+            .map(XDai::from_wei),
+        )
+    }
+
+    /// Sells `amount` xDai for `token` on the xDai router. Resolves to the amount of `token`
+    /// received once the swap is mined, waiting at most `timeout` seconds.
+    pub fn xdai_to_token_swap(
+        &self,
+        token: Address,
+        amount: XDai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.send_conversion(
+            ConversionKind::XdaiToToken(token),
+            amount.wei().clone(),
+            move |salf| {
+                let market = salf.xdai_market(token);
+                let own_address = salf.own_address;
+                salf.router_swap_native_for_tokens(market, amount.into_wei(), own_address, timeout)
+            },
+        )
+    }
+
+    /// Sells `amount` of `token` for xDai on the xDai router, approving the router first if
+    /// needed and `auto_approve` is on. Resolves to the xDai received once the swap is mined.
+    pub fn token_to_xdai_swap(
+        &self,
+        token: Address,
+        amount: Uint256,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        self.send_conversion(
+            ConversionKind::TokenToXdai(token),
+            amount.clone(),
+            move |salf| salf.run_token_to_xdai_swap(token, amount, timeout),
+        )
+    }
+
+    fn run_token_to_xdai_swap(
+        &self,
+        token: Address,
+        amount: Uint256,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        let market = self.xdai_market(token);
+        let ensure_approved = if self.auto_approve {
+            self.ensure_token_approved_on(
+                Chain::Xdai,
+                token,
+                market.router,
+                amount.clone(),
+                Duration::from_secs(timeout),
+            )
+        } else {
+            Box::new(futures::future::ok(()))
+        };
+        let salf = self.clone();
+        let own_address = self.own_address;
+        Box::new(
+            ensure_approved
+                .and_then(move |_| {
+                    salf.router_swap_tokens_for_native(market, amount, own_address, timeout)
+                })
+                .map(XDai::from_wei),
+        )
+    }
+}
@@ -0,0 +1,117 @@
+//! `tracing` spans and events around RPC calls, transaction submission and operation stage
+//! changes, so the crate's actions can be lined up with what happened on chain. Only with the
+//! `structured-logging` feature, without it all of this compiles to nothing and the `log`
+//! output is all there is.
+
+use crate::events::BridgeEvent;
+use crate::operations::Operation;
+use crate::Chain;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::fmt::Display;
+#[cfg(feature = "structured-logging")]
+use std::time::Instant;
+
+/// A `tracing::Span`, or nothing when the feature is off
+#[derive(Clone)]
+pub(crate) struct Span {
+    #[cfg(feature = "structured-logging")]
+    inner: tracing::Span,
+}
+
+impl Span {
+    /// One attempt at a read only RPC call
+    #[cfg_attr(not(feature = "structured-logging"), allow(unused_variables))]
+    pub(crate) fn rpc(chain: Chain, attempt: u32) -> Span {
+        Span {
+            #[cfg(feature = "structured-logging")]
+            inner: tracing::debug_span!("rpc", ?chain, attempt),
+        }
+    }
+
+    /// Sending a transaction, the rest of its parameters are recorded once they are known
+    #[cfg_attr(not(feature = "structured-logging"), allow(unused_variables))]
+    pub(crate) fn transaction(chain: Chain, to: Address, value: &Uint256) -> Span {
+        Span {
+            #[cfg(feature = "structured-logging")]
+            inner: tracing::info_span!(
+                "send_transaction",
+                ?chain,
+                %to,
+                %value,
+                nonce = tracing::field::Empty,
+                gas_price = tracing::field::Empty,
+                gas_limit = tracing::field::Empty,
+                tx_hash = tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// Waiting for `event` from `contract`
+    #[cfg_attr(not(feature = "structured-logging"), allow(unused_variables))]
+    pub(crate) fn wait_for_event(chain: Chain, contract: Address, event: &'static str) -> Span {
+        Span {
+            #[cfg(feature = "structured-logging")]
+            inner: tracing::info_span!("wait_for_event", ?chain, %contract, event),
+        }
+    }
+
+    /// Sets `field`, which has to be one the span was created with
+    #[cfg_attr(not(feature = "structured-logging"), allow(unused_variables))]
+    pub(crate) fn record<V: Display>(&self, field: &'static str, value: V) {
+        #[cfg(feature = "structured-logging")]
+        self.inner.record(field, tracing::field::display(value));
+    }
+
+    /// Runs `future` inside this span and records how long it took and how it ended
+    pub(crate) fn instrument<T: 'static>(
+        self,
+        future: Box<dyn Future<Item = T, Error = Error>>,
+    ) -> Box<dyn Future<Item = T, Error = Error>> {
+        #[cfg(feature = "structured-logging")]
+        {
+            use tracing_futures::Instrument;
+
+            let start = Instant::now();
+            let span = self.inner.clone();
+            Box::new(
+                future
+                    .then(move |res| {
+                        let elapsed_ms = start.elapsed().as_millis() as u64;
+                        match res {
+                            Ok(_) => tracing::debug!(parent: &span, elapsed_ms, "completed"),
+                            Err(ref e) => {
+                                tracing::warn!(parent: &span, elapsed_ms, error = %e, "failed")
+                            }
+                        }
+                        res
+                    })
+                    .instrument(self.inner),
+            )
+        }
+        #[cfg(not(feature = "structured-logging"))]
+        future
+    }
+}
+
+/// Records a progress update, see `TokenBridge::progress_events`
+#[cfg_attr(not(feature = "structured-logging"), allow(unused_variables))]
+pub(crate) fn progress(event: &BridgeEvent) {
+    #[cfg(feature = "structured-logging")]
+    tracing::info!(?event, "progress");
+}
+
+/// Records `operation` moving to its current stage
+#[cfg_attr(not(feature = "structured-logging"), allow(unused_variables))]
+pub(crate) fn stage_changed(operation: &Operation) {
+    #[cfg(feature = "structured-logging")]
+    tracing::info!(
+        operation = %operation.id,
+        kind = ?operation.kind,
+        amount = %operation.amount,
+        stage = ?operation.stage,
+        "operation stage changed"
+    );
+}
@@ -0,0 +1,322 @@
+//! Ethereum JSON keystore files (Web3 Secret Storage, version 3), so that the key a bridge
+//! signs with can be kept encrypted at rest and unlocked with a passphrase instead of being
+//! stored in plain text. Files written by geth, parity, MyCrypto and the like are accepted,
+//! with either an scrypt or a PBKDF2 key derivation.
+//!
+//! Decrypting a file with the default scrypt parameters takes a second or so and a few hundred
+//! MB of memory, it is meant to be done once on startup.
+
+use crate::TokenBridgeError;
+use aes_ctr::cipher::{NewStreamCipher, SyncStreamCipher};
+use aes_ctr::Aes128Ctr;
+use clarity::utils::{bytes_to_hex_str, hex_str_to_bytes};
+use clarity::{Address, PrivateKey};
+use failure::bail;
+use failure::format_err;
+use failure::Error;
+use hmac::Hmac;
+use scrypt::ScryptParams;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The only keystore version there is in use
+pub const KEYSTORE_VERSION: u32 = 3;
+
+/// The scrypt parameters `Keystore::encrypt` uses, the same as geth's standard ones
+const SCRYPT_LOG_N: u8 = 18;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// The length of the key derived from the passphrase, the first half is the AES key and the
+/// second half goes into the MAC
+const DERIVED_KEY_LEN: usize = 32;
+
+/// A keystore file as stored on disk, see `decrypt` to get the key out of it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    pub id: String,
+    /// The address of the key as hex without `0x`, some tools leave it out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Older geth versions write this as `Crypto`
+    #[serde(alias = "Crypto")]
+    pub crypto: KeystoreCrypto,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeystoreCrypto {
+    /// Always `aes-128-ctr`
+    pub cipher: String,
+    pub cipherparams: CipherParams,
+    pub ciphertext: String,
+    #[serde(flatten)]
+    pub kdf: Kdf,
+    pub mac: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CipherParams {
+    pub iv: String,
+}
+
+/// How the encryption key is derived from the passphrase
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kdf", content = "kdfparams", rename_all = "lowercase")]
+pub enum Kdf {
+    Scrypt {
+        dklen: usize,
+        n: u64,
+        r: u32,
+        p: u32,
+        salt: String,
+    },
+    Pbkdf2 {
+        c: u32,
+        dklen: usize,
+        /// Always `hmac-sha256`
+        prf: String,
+        salt: String,
+    },
+}
+
+impl Kdf {
+    fn derive_key(&self, passphrase: &str) -> Result<Vec<u8>, Error> {
+        match self {
+            Kdf::Scrypt {
+                dklen,
+                n,
+                r,
+                p,
+                salt,
+            } => {
+                if *dklen < DERIVED_KEY_LEN || !n.is_power_of_two() {
+                    bail!("Unsupported scrypt parameters dklen {} n {}", dklen, n);
+                }
+                let params = ScryptParams::new(n.trailing_zeros() as u8, *r, *p).map_err(|_| {
+                    format_err!("Invalid scrypt parameters n {} r {} p {}", n, r, p)
+                })?;
+                let mut key = vec![0u8; *dklen];
+                scrypt::scrypt(
+                    passphrase.as_bytes(),
+                    &decode_hex("salt", salt)?,
+                    &params,
+                    &mut key,
+                )
+                .map_err(|_| format_err!("Invalid scrypt key length {}", dklen))?;
+                Ok(key)
+            }
+            Kdf::Pbkdf2 {
+                c,
+                dklen,
+                prf,
+                salt,
+            } => {
+                if prf != "hmac-sha256" {
+                    bail!("Unsupported PBKDF2 function {}", prf);
+                }
+                if *dklen < DERIVED_KEY_LEN {
+                    bail!("Unsupported PBKDF2 key length {}", dklen);
+                }
+                let mut key = vec![0u8; *dklen];
+                pbkdf2::pbkdf2::<Hmac<Sha256>>(
+                    passphrase.as_bytes(),
+                    &decode_hex("salt", salt)?,
+                    *c,
+                    &mut key,
+                );
+                Ok(key)
+            }
+        }
+    }
+}
+
+impl Keystore {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Keystore, Error> {
+        let contents = fs::read_to_string(path.as_ref()).map_err(|e| {
+            format_err!("Could not read keystore {}: {}", path.as_ref().display(), e)
+        })?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The key stored in this keystore, fails with `TokenBridgeError::WrongPassphrase` if
+    /// `passphrase` is not the one it was encrypted with
+    pub fn decrypt(&self, passphrase: &str) -> Result<PrivateKey, Error> {
+        if self.version != KEYSTORE_VERSION {
+            bail!("Unsupported keystore version {}", self.version);
+        }
+        let crypto = &self.crypto;
+        if crypto.cipher != "aes-128-ctr" {
+            bail!("Unsupported keystore cipher {}", crypto.cipher);
+        }
+        let mut secret = decode_hex("ciphertext", &crypto.ciphertext)?;
+        let derived_key = crypto.kdf.derive_key(passphrase)?;
+        if mac(&derived_key, &secret) != decode_hex("mac", &crypto.mac)? {
+            return Err(TokenBridgeError::WrongPassphrase.into());
+        }
+
+        let iv = decode_hex("iv", &crypto.cipherparams.iv)?;
+        Aes128Ctr::new_var(&derived_key[..16], &iv)
+            .map_err(|_| format_err!("Invalid keystore iv length {}", iv.len()))?
+            .apply_keystream(&mut secret);
+        if secret.len() != 32 {
+            bail!("Keystore holds a {} byte key", secret.len());
+        }
+        let key = PrivateKey::from_slice(&secret)?;
+
+        if let Some(ref address) = self.address {
+            let expected = Address::from_str(&format!("0x{}", address.trim_start_matches("0x")))
+                .map_err(|_| format_err!("Invalid keystore address {}", address))?;
+            if key.to_public_key()? != expected {
+                bail!("Keystore key does not belong to its address {}", address);
+            }
+        }
+        Ok(key)
+    }
+
+    /// Encrypts `key` with `passphrase` using scrypt with geth's standard parameters
+    pub fn encrypt(key: &PrivateKey, passphrase: &str) -> Result<Keystore, Error> {
+        Keystore::encrypt_with(
+            key,
+            passphrase,
+            Kdf::Scrypt {
+                dklen: DERIVED_KEY_LEN,
+                n: 1 << SCRYPT_LOG_N,
+                r: SCRYPT_R,
+                p: SCRYPT_P,
+                salt: bytes_to_hex_str(&rand::random::<[u8; 32]>()),
+            },
+        )
+    }
+
+    fn encrypt_with(key: &PrivateKey, passphrase: &str, kdf: Kdf) -> Result<Keystore, Error> {
+        let derived_key = kdf.derive_key(passphrase)?;
+        let iv = rand::random::<[u8; 16]>();
+        let mut ciphertext = key.to_bytes().to_vec();
+        Aes128Ctr::new_var(&derived_key[..16], &iv)
+            .map_err(|_| format_err!("Invalid keystore iv length {}", iv.len()))?
+            .apply_keystream(&mut ciphertext);
+
+        Ok(Keystore {
+            version: KEYSTORE_VERSION,
+            id: random_uuid(),
+            address: Some(bytes_to_hex_str(key.to_public_key()?.as_bytes())),
+            crypto: KeystoreCrypto {
+                cipher: "aes-128-ctr".to_string(),
+                cipherparams: CipherParams {
+                    iv: bytes_to_hex_str(&iv),
+                },
+                mac: bytes_to_hex_str(&mac(&derived_key, &ciphertext)),
+                ciphertext: bytes_to_hex_str(&ciphertext),
+                kdf,
+            },
+        })
+    }
+}
+
+/// Reads the keystore file at `path` and decrypts the key in it with `passphrase`
+pub fn read_keystore<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<PrivateKey, Error> {
+    Keystore::read(path)?.decrypt(passphrase)
+}
+
+fn mac(derived_key: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+    let mut hasher = Keccak256::new();
+    hasher.input(&derived_key[16..32]);
+    hasher.input(ciphertext);
+    hasher.result().to_vec()
+}
+
+fn decode_hex(field: &str, hex: &str) -> Result<Vec<u8>, Error> {
+    hex_str_to_bytes(hex).map_err(|_| format_err!("Keystore {} is not hex", field))
+}
+
+/// A random version 4 UUID for the keystore id
+fn random_uuid() -> String {
+    let mut bytes = rand::random::<[u8; 16]>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes_to_hex_str(&bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The PBKDF2 test vector of the Web3 Secret Storage definition
+    const PBKDF2_KEYSTORE: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "6087dab2f9fdbbfaddc31a909735c1e6" },
+            "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+            "kdf": "pbkdf2",
+            "kdfparams": {
+                "c": 262144,
+                "dklen": 32,
+                "prf": "hmac-sha256",
+                "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+            },
+            "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    #[test]
+    fn test_pbkdf2_keystore() {
+        let keystore: Keystore = serde_json::from_str(PBKDF2_KEYSTORE).unwrap();
+        let key = keystore.decrypt("testpassword").unwrap();
+        assert_eq!(
+            bytes_to_hex_str(&key.to_bytes()),
+            "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d"
+        );
+        match keystore.decrypt("wrongpassword") {
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<TokenBridgeError>(),
+                Some(TokenBridgeError::WrongPassphrase)
+            )),
+            Ok(_) => panic!("decrypted with the wrong passphrase"),
+        }
+    }
+
+    #[test]
+    fn test_scrypt_round_trip() {
+        let key = PrivateKey::from_slice(&[7u8; 32]).unwrap();
+        // cheap parameters, the standard ones take too long for a test
+        let kdf = Kdf::Scrypt {
+            dklen: 32,
+            n: 1 << 10,
+            r: 8,
+            p: 1,
+            salt: bytes_to_hex_str(&[9u8; 32]),
+        };
+        let keystore = Keystore::encrypt_with(&key, "correct horse", kdf).unwrap();
+
+        let path = std::env::temp_dir().join(format!("keystore-{}.json", rand::random::<u64>()));
+        keystore.write(&path).unwrap();
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.contains(r#""kdf": "scrypt""#));
+        assert_eq!(
+            read_keystore(&path, "correct horse").unwrap().to_bytes(),
+            key.to_bytes()
+        );
+        assert!(read_keystore(&path, "battery staple").is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,307 @@
+//! A safety net for fleets that convert automatically: conversions above a configured amount
+//! are only sent once someone confirms them, so that a bug can't move a whole treasury through
+//! the bridge unnoticed.
+//!
+//! Confirmation is asked of `LargeTransferPolicy::confirmer`, which can prompt an operator or
+//! check with another service. Without one, large conversions fail with
+//! `TokenBridgeError::LargeTransferNotConfirmed`. To confirm one some other way, take its
+//! `confirmation_request`, have it confirmed and retry the conversion on the bridge returned by
+//! `with_confirmed_transfer(request.confirmed())`, which lets only that conversion through.
+
+use crate::pending::ConversionKind;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A conversion waiting to be confirmed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfirmationRequest {
+    /// The account the funds are sent from
+    pub account: Address,
+    pub kind: ConversionKind,
+    /// The ETH, Dai or xDai put in
+    pub amount: Uint256,
+}
+
+impl ConfirmationRequest {
+    /// Marks this request as confirmed, see `TokenBridge::with_confirmed_transfer`
+    pub fn confirmed(self) -> ConfirmedTransfer {
+        ConfirmedTransfer {
+            request: self,
+            used: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// A conversion confirmed outside of `LargeTransferPolicy::confirmer`. It lets through the
+/// conversion of the kind and amount of its request once, every other one is still asked about.
+#[derive(Debug, Clone)]
+pub struct ConfirmedTransfer {
+    request: ConfirmationRequest,
+    /// Shared by the clones so that the confirmation can't be used twice
+    used: Arc<AtomicBool>,
+}
+
+impl ConfirmedTransfer {
+    pub fn request(&self) -> &ConfirmationRequest {
+        &self.request
+    }
+
+    /// Whether this confirms `request`, using it up if it does
+    fn admits(&self, request: &ConfirmationRequest) -> bool {
+        self.request == *request && !self.used.swap(true, Ordering::SeqCst)
+    }
+}
+
+/// Decides whether a large conversion may go ahead
+pub trait TransferConfirmer: Send + Sync {
+    /// Resolves to true if the conversion in `request` may be sent
+    fn confirm(&self, request: &ConfirmationRequest)
+        -> Box<dyn Future<Item = bool, Error = Error>>;
+}
+
+/// Which conversions have to be confirmed, none by default
+#[derive(Clone, Default)]
+pub struct LargeTransferPolicy {
+    /// Conversions of more ETH than this need confirmation
+    pub eth_threshold: Option<Uint256>,
+    /// Conversions of more Dai or xDai than this need confirmation
+    pub dai_threshold: Option<Uint256>,
+    /// Conversions selling or bridging more of a token than its threshold here need
+    /// confirmation, in the token's own units
+    pub token_thresholds: HashMap<Address, Uint256>,
+    pub confirmer: Option<Arc<dyn TransferConfirmer>>,
+}
+
+impl fmt::Debug for LargeTransferPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LargeTransferPolicy")
+            .field("eth_threshold", &self.eth_threshold)
+            .field("dai_threshold", &self.dai_threshold)
+            .field("token_thresholds", &self.token_thresholds)
+            .field("confirmer", &self.confirmer.is_some())
+            .finish()
+    }
+}
+
+impl LargeTransferPolicy {
+    pub fn requires_confirmation(&self, kind: ConversionKind, amount: &Uint256) -> bool {
+        let threshold = match kind {
+            ConversionKind::EthToDai | ConversionKind::EthToXdai => self.eth_threshold.as_ref(),
+            ConversionKind::DaiToEth
+            | ConversionKind::DaiToXdai
+            | ConversionKind::XdaiToDai
+            | ConversionKind::XdaiToEth
+            | ConversionKind::XdaiToToken(_) => self.dai_threshold.as_ref(),
+            ConversionKind::TokenToToken(token)
+            | ConversionKind::TokenToXdai(token)
+            | ConversionKind::BridgeToken { token, .. } => self.token_thresholds.get(&token),
+        };
+        match threshold {
+            Some(threshold) => amount > threshold,
+            None => false,
+        }
+    }
+}
+
+impl TokenBridge {
+    /// What a conversion of `amount` of `kind` has to have confirmed, `None` if
+    /// `large_transfers` lets it through without confirmation
+    pub fn confirmation_request(
+        &self,
+        kind: ConversionKind,
+        amount: &Uint256,
+    ) -> Option<ConfirmationRequest> {
+        if !self.large_transfers.requires_confirmation(kind, amount) {
+            return None;
+        }
+        Some(ConfirmationRequest {
+            account: self.own_address,
+            kind,
+            amount: amount.clone(),
+        })
+    }
+
+    /// A copy of this bridge that sends the conversion `confirmed` was made for without asking
+    /// `large_transfers.confirmer`, once
+    pub fn with_confirmed_transfer(&self, confirmed: ConfirmedTransfer) -> TokenBridge {
+        let mut bridge = self.clone();
+        bridge.confirmed_transfer = Some(confirmed);
+        bridge
+    }
+
+    /// A copy of this bridge whose conversions are sent without asking for confirmation, for
+    /// the steps of a conversion that was confirmed as a whole
+    pub(crate) fn with_large_transfer_confirmed(&self) -> TokenBridge {
+        let mut bridge = self.clone();
+        bridge.large_transfer_confirmed = true;
+        bridge
+    }
+
+    /// Resolves to the bridge the conversion of `amount` may be sent with, once it is
+    /// confirmed if `large_transfers` requires it. Steps of the conversion sent on the returned
+    /// bridge aren't asked about again.
+    pub(crate) fn confirm_large_transfer(
+        &self,
+        kind: ConversionKind,
+        amount: Uint256,
+    ) -> Box<dyn Future<Item = TokenBridge, Error = Error>> {
+        if self.large_transfer_confirmed {
+            return Box::new(futures::future::ok(self.clone()));
+        }
+        let request = match self.confirmation_request(kind, &amount) {
+            Some(request) => request,
+            None => return Box::new(futures::future::ok(self.clone())),
+        };
+        if let Some(ref confirmed) = self.confirmed_transfer {
+            if confirmed.admits(&request) {
+                return Box::new(futures::future::ok(self.with_large_transfer_confirmed()));
+            }
+        }
+        let confirmer = match self.large_transfers.confirmer {
+            Some(ref confirmer) => confirmer.clone(),
+            None => {
+                return Box::new(futures::future::err(
+                    TokenBridgeError::LargeTransferNotConfirmed { kind, amount }.into(),
+                ))
+            }
+        };
+        info!("Asking for confirmation of {:?}", request);
+        let confirmed = self.with_large_transfer_confirmed();
+        Box::new(confirmer.confirm(&request).and_then(move |approved| {
+            if approved {
+                Ok(confirmed)
+            } else {
+                warn!("{:?} was not confirmed", request);
+                Err(TokenBridgeError::LargeTransferNotConfirmed {
+                    kind: request.kind,
+                    amount: request.amount,
+                }
+                .into())
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_bridge, MockBlockReader};
+    use std::sync::Mutex;
+
+    /// Approves what it is told to and remembers what it was asked
+    struct Operator {
+        approve: bool,
+        asked: Mutex<Vec<ConfirmationRequest>>,
+    }
+
+    impl TransferConfirmer for Operator {
+        fn confirm(
+            &self,
+            request: &ConfirmationRequest,
+        ) -> Box<dyn Future<Item = bool, Error = Error>> {
+            self.asked.lock().unwrap().push(request.clone());
+            Box::new(futures::future::ok(self.approve))
+        }
+    }
+
+    fn not_confirmed(result: Result<TokenBridge, Error>) -> bool {
+        match result {
+            Err(e) => matches!(
+                e.downcast_ref::<TokenBridgeError>(),
+                Some(TokenBridgeError::LargeTransferNotConfirmed { .. })
+            ),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn test_requires_confirmation() {
+        let mut policy = LargeTransferPolicy {
+            eth_threshold: Some(100u32.into()),
+            ..LargeTransferPolicy::default()
+        };
+        assert!(policy.requires_confirmation(ConversionKind::EthToXdai, &101u32.into()));
+        assert!(!policy.requires_confirmation(ConversionKind::EthToDai, &100u32.into()));
+        // no Dai threshold set
+        assert!(!policy.requires_confirmation(ConversionKind::DaiToEth, &1_000u32.into()));
+
+        let token = Address::from_slice(&[0x70; 20]).unwrap();
+        policy.token_thresholds.insert(token, 5u32.into());
+        assert!(policy.requires_confirmation(ConversionKind::TokenToToken(token), &6u32.into()));
+        let other = ConversionKind::TokenToToken(Address::default());
+        assert!(!policy.requires_confirmation(other, &6u32.into()));
+    }
+
+    #[test]
+    fn test_confirm_large_transfer() {
+        let mut bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        bridge.large_transfers.dai_threshold = Some(100u32.into());
+        let kind = ConversionKind::XdaiToEth;
+
+        assert!(bridge
+            .confirm_large_transfer(kind, 50u32.into())
+            .wait()
+            .is_ok());
+        assert!(not_confirmed(
+            bridge.confirm_large_transfer(kind, 500u32.into()).wait()
+        ));
+        // a confirmation taken some other way lets its own conversion through, once
+        let request = bridge.confirmation_request(kind, &500u32.into()).unwrap();
+        let confirmed = bridge.with_confirmed_transfer(request.confirmed());
+        assert!(not_confirmed(
+            confirmed.confirm_large_transfer(kind, 600u32.into()).wait()
+        ));
+        assert!(not_confirmed(
+            confirmed
+                .confirm_large_transfer(ConversionKind::XdaiToDai, 500u32.into())
+                .wait()
+        ));
+        assert!(confirmed
+            .confirm_large_transfer(kind, 500u32.into())
+            .wait()
+            .is_ok());
+        assert!(not_confirmed(
+            confirmed.confirm_large_transfer(kind, 500u32.into()).wait()
+        ));
+
+        let operator = Arc::new(Operator {
+            approve: false,
+            asked: Mutex::new(Vec::new()),
+        });
+        bridge.large_transfers.confirmer = Some(operator.clone());
+        assert!(not_confirmed(
+            bridge.confirm_large_transfer(kind, 500u32.into()).wait()
+        ));
+        assert_eq!(
+            operator.asked.lock().unwrap()[0],
+            ConfirmationRequest {
+                account: bridge.own_address,
+                kind,
+                amount: 500u32.into(),
+            }
+        );
+
+        bridge.large_transfers.confirmer = Some(Arc::new(Operator {
+            approve: true,
+            asked: Mutex::new(Vec::new()),
+        }));
+        let mut confirmed = bridge
+            .confirm_large_transfer(kind, 500u32.into())
+            .wait()
+            .unwrap();
+        // the steps of a confirmed conversion go through without asking again
+        confirmed.large_transfers.confirmer = None;
+        assert!(confirmed
+            .confirm_large_transfer(ConversionKind::XdaiToDai, 500u32.into())
+            .wait()
+            .is_ok());
+    }
+}
@@ -1,18 +1,258 @@
 #[macro_use]
 extern crate log;
 
-use clarity::abi::encode_call;
+/// Unwraps a `Result` in a function that returns a boxed future, returning a failed future
+/// on error
+macro_rules! try_future {
+    ($e:expr) => {
+        match $e {
+            Ok(val) => val,
+            Err(e) => return Box::new(futures::future::err(e.into())),
+        }
+    };
+}
+
+pub mod abi;
+pub mod accounts;
+pub mod address_book;
+pub mod aggregator;
+pub mod amb;
+pub mod amounts;
+pub mod api;
+pub mod audit;
+pub mod block_tag;
+pub mod builder;
+mod call;
+pub mod cancel;
+pub mod config;
+mod confirmations;
+pub mod contracts;
+pub mod cost;
+pub mod deadline;
+pub mod deposits;
+pub mod destinations;
+pub mod encoding;
+pub mod ens;
+mod erc20;
+mod error;
+pub mod eta;
+pub mod events;
+pub mod exchange;
+pub mod export;
+pub mod faults;
+pub mod fee;
+pub mod gas;
+pub mod gas_top_up;
+pub mod health;
+pub mod history;
+pub mod honeyswap;
+mod instrument;
+pub mod keystore;
+pub mod large_transfer;
+pub mod logs;
+pub mod message;
+mod metrics;
+pub mod mnemonic;
+#[cfg(test)]
+mod mock;
+pub mod network;
+pub mod operations;
+pub mod oracle;
+pub mod payment_uri;
+pub mod payments;
+pub mod pending;
+pub mod pool;
+pub mod preflight;
+pub mod price_cache;
+mod price_impact;
+pub mod queue;
+pub mod quote;
+pub mod rebalancer;
+pub mod receipt;
+mod reconcile;
+pub mod relay;
+pub mod reserves;
+pub mod retry;
+pub mod route;
+pub mod router;
+pub mod sai;
+pub mod signer;
+pub mod simulate;
+pub mod simulated;
+pub mod snapshot;
+pub mod spending;
+pub mod split;
+pub mod stablecoin;
+pub mod subscription;
+pub mod sweep;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod timeouts;
+mod token_swap;
+pub mod twap;
+mod tx;
+pub mod typed_data;
+pub mod units;
+pub mod validators;
+pub mod weth;
+pub mod withdrawal;
+#[cfg(feature = "ws")]
+pub mod ws;
+
+pub use crate::accounts::Accounts;
+pub use crate::address_book::AddressBook;
+pub use crate::aggregator::{AggregatorApi, SwapVenue};
+pub use crate::amb::AmbContracts;
+pub use crate::amounts::{format_amount, parse_amount, scale_decimals};
+pub use crate::api::TokenBridgeApi;
+pub use crate::audit::{AuditSink, JsonLinesAuditSink};
+pub use crate::block_tag::{BlockReader, BlockTag};
+pub use crate::builder::TokenBridgeBuilder;
+pub use crate::cancel::CancelToken;
+pub use crate::config::TokenBridgeConfig;
+pub use crate::contracts::{ContractKind, KnownContract};
+pub use crate::cost::ConversionCost;
+pub use crate::deadline::{SwapCall, SwapOutcome};
+pub use crate::deposits::{DepositSource, IncomingXdai};
+pub use crate::destinations::DestinationAllowlist;
+pub use crate::ens::AddressOrName;
+pub use crate::error::{TimeoutOutcome, TokenBridgeError};
+pub use crate::eta::{BridgeEta, BridgeLatencies};
+pub use crate::events::BridgeEvent;
+pub use crate::export::ExportRow;
+pub use crate::faults::{Fault, FaultInjector, FaultRule, FaultTarget};
+pub use crate::fee::{bridge_fee_amount, BridgeDirection, BridgeTransfer};
+pub use crate::gas::{GasLedger, GasPurpose, GasSpent, GasUsage};
+pub use crate::gas_top_up::{GasFaucet, GasTopUp, GasTopUpOutcome};
+pub use crate::health::ChainHealth;
+pub use crate::history::{HistoryEntry, HistoryKind};
+pub use crate::keystore::{read_keystore, Keystore};
+pub use crate::large_transfer::{
+    ConfirmationRequest, ConfirmedTransfer, LargeTransferPolicy, TransferConfirmer,
+};
+pub use crate::logs::{LogDecodeError, SwapBackend};
+use crate::logs::{ERC20_APPROVAL, ERC20_TRANSFER};
+pub use crate::message::{eip191_hash, verify_signature};
+pub use crate::mnemonic::{derive_key, eth_account_path, DerivedKey};
+pub use crate::network::{Network, NetworkAddresses};
+pub use crate::operations::{
+    JsonFileStore, Operation, OperationStatus, OperationStore, OperationTx,
+};
+pub use crate::oracle::PriceOracle;
+pub use crate::payment_uri::{PaymentAsset, PaymentRequest};
+pub use crate::pending::{ConversionKind, DuplicatePolicy, PendingKey, PendingRegistry};
+pub use crate::pool::Web3Pool;
+pub use crate::preflight::BridgePreflight;
+pub use crate::price_cache::{PriceCache, PriceDirection};
+pub use crate::price_impact::price_impact_bps;
+pub use crate::queue::OperationQueue;
+pub use crate::quote::PriceQuote;
+pub use crate::rebalancer::{Rebalancer, RebalancerConfig};
+pub use crate::receipt::{Receipt, SignedReceipt};
+pub use crate::relay::{
+    JsonRelayedStore, RelayQuote, RelayRequest, RelayVerifier, RelayedRequest, RelayedStore,
+    RelayerApi, SignedRelayRequest,
+};
+pub use crate::reserves::PoolReserves;
+pub use crate::retry::RetryPolicy;
+pub use crate::route::{Route, RoutePlanner};
+pub use crate::signer::{LocalSigner, Signer};
+pub use crate::simulate::Simulation;
+pub use crate::simulated::{SimulatedCall, SimulatedTokenBridge, SimulatedTransfer};
+pub use crate::snapshot::BridgeSnapshot;
+pub use crate::spending::{
+    JsonSpendingStore, Spend, SpendAsset, SpendingLimiter, SpendingLimits, SpendingPolicy,
+    SpendingStore,
+};
+pub use crate::split::{ExecutionPolicy, SwapResult};
+pub use crate::stablecoin::{Stablecoin, StablecoinBridge};
+pub use crate::subscription::{EventPolling, LogSubscriber};
+pub use crate::timeouts::Timeouts;
+pub use crate::twap::{PriceSample, TwapOracle};
+pub use crate::tx::{RawTxParams, TxParams, FLASHBOTS_PROTECT_RPC};
+pub use crate::typed_data::{
+    dai_permit, typed_data_hash, verify_typed_data, Eip712Domain, TypedStruct, TypedValue,
+};
+pub use crate::units::{Dai, Eth, TokenAmount, XDai};
+pub use crate::validators::{ValidatorActivity, ValidatorHealth};
+
+use crate::abi::decode_output;
+use crate::contracts::XDAI_CHAIN_ID;
+use crate::withdrawal::HOME_BRIDGE_USER_REQUEST_FOR_SIGNATURE;
 use clarity::{Address, PrivateKey};
-use failure::bail;
 use failure::Error;
+use futures::sync::mpsc::UnboundedSender;
 use futures::Future;
 use futures_timer::FutureExt;
 use num::Bounded;
 use num256::Uint256;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use web30::client::Web3;
 use web30::types::SendTxOption;
 
+/// Timeout for a single RPC request unless configured otherwise
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Slippage allowed on swaps unless configured otherwise, 2.5%
+pub const DEFAULT_SLIPPAGE_BPS: u32 = 250;
+
+/// The least output to accept for a swap quoted at `amount` when allowing `slippage_bps` basis
+/// points of slippage
+pub fn minimum_output(amount: Uint256, slippage_bps: u32) -> Uint256 {
+    let kept: Uint256 = (10_000 - slippage_bps.min(10_000)).into();
+    let whole: Uint256 = 10_000u32.into();
+    // the remainder is scaled on its own so amounts close to the top of the range can't
+    // overflow, this is the same as rounding down amount * kept / whole
+    amount.clone() / whole.clone() * kept.clone() + amount % whole.clone() * kept / whole
+}
+
+/// The gas price xDai transactions use unless configured otherwise, 10 gwei
+pub const DEFAULT_XDAI_GAS_PRICE: u64 = 10_000_000_000;
+
+/// How the gas price of transactions on a chain is chosen
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GasStrategy {
+    /// Use the gas price suggested by the full node
+    Node,
+    /// Always use this gas price, in wei
+    Fixed(Uint256),
+}
+
+impl GasStrategy {
+    fn to_option(&self) -> Option<SendTxOption> {
+        match self {
+            GasStrategy::Node => None,
+            GasStrategy::Fixed(price) => Some(SendTxOption::GasPrice(price.clone())),
+        }
+    }
+}
+
+/// The two chains a `TokenBridge` talks to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Chain {
+    Eth,
+    Xdai,
+}
+
+/// What to do when a plain value transfer is addressed to a contract. Sending xDai or ETH to a
+/// random contract (or the token contract itself) is a common mistake and can not be undone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContractRecipientCheck {
+    /// Don't look up the recipient at all
+    Off,
+    /// Log a warning but send anyway
+    Warn,
+    /// Refuse to send with `TokenBridgeError::RecipientIsContract`
+    Block,
+}
+
+/// Everything that changes after construction is behind a lock or an atomic, so a single
+/// `TokenBridge` can be put in an `Arc` and used from any number of threads or actors at once.
+/// Clones share nonces, caches and the progress stream, configuration fields are copied.
+/// The returned futures are not `Send`, they have to run on the thread that created them.
 #[derive(Clone)]
 pub struct TokenBridge {
     pub xdai_web3: Web3,
@@ -25,9 +265,148 @@ pub struct TokenBridge {
     /// This is the address of the Dai token contract on Eth
     pub foreign_dai_contract_address: Address,
     pub own_address: Address,
-    pub secret: PrivateKey,
+    /// Signs our transactions, `None` for a read only bridge
+    pub signer: Option<Arc<dyn Signer>>,
+    /// Other accounts this bridge can act for, see `for_account`, and the nonces handed out
+    /// for each account including our own
+    pub accounts: Arc<Accounts>,
+    /// How far below the quoted price a swap may execute, in basis points
+    pub slippage_bps: u32,
+    /// Preflight check applied to the recipient of plain transfers, off by default
+    pub contract_recipient_check: ContractRecipientCheck,
+    /// Contracts that may receive plain transfers regardless of `contract_recipient_check`
+    pub contract_recipient_allowlist: Vec<Address>,
+    /// Restricts which contracts are called or approved and optionally who is paid when set,
+    /// see `destinations`
+    pub destination_allowlist: Option<DestinationAllowlist>,
+    /// If set, swaps whose price impact exceeds this many basis points are refused with
+    /// `TokenBridgeError::PriceImpactTooHigh` instead of being sent
+    pub max_price_impact_bps: Option<u32>,
+    /// If set, swaps are refused with `TokenBridgeError::OracleDivergence` when the Uniswap
+    /// price is too far from this oracle's price
+    pub price_oracle: Option<PriceOracle>,
+    /// Chain id to sign Eth transactions with, `None` signs them without one
+    pub eth_chain_id: Option<u64>,
+    /// Chain id to sign xDai transactions with, `None` signs them without one
+    pub xdai_chain_id: Option<u64>,
+    pub eth_gas_strategy: GasStrategy,
+    pub xdai_gas_strategy: GasStrategy,
+    /// How many blocks have to be mined on top of an event before it is acted on, zero by
+    /// default. Timeouts of functions that wait for events include this wait.
+    pub confirmations: u32,
+    /// The exchange at `uniswap_address`, decides how swap events are decoded
+    pub swap_backend: SwapBackend,
+    /// If set, every transaction is run with `eth_call` first and is not sent if it would
+    /// revert, see `simulate_transaction`
+    pub simulate_before_send: bool,
+    /// If set, `eth_to_dai_price` and `dai_to_eth_price` are served from this cache while fresh
+    pub price_cache: Option<Arc<PriceCache>>,
+    /// If set, swaps on Eth take their minimum output from this average price instead of the
+    /// price of the latest block, see `run_twap_sampler`
+    pub twap: Option<Arc<TwapOracle>>,
+    /// Labels for the addresses in exported history, receipts and log lines when set
+    pub address_book: Option<Arc<AddressBook>>,
+    /// Multicall contract on Eth used by `snapshot`
+    pub multicall_address: Address,
+    /// WETH contract on Eth used by `wrap_eth` and `unwrap_weth`
+    pub weth_address: Address,
+    /// The legacy SAI token on Eth, see `get_sai_balance`
+    pub sai_address: Address,
+    /// MakerDAO's SAI to Dai migration contract on Eth used by `migrate_sai_to_dai`
+    pub sai_migration_address: Address,
+    /// Uniswap V2 style router on xDai used by `xdai_to_token_swap` and `token_to_xdai_swap`,
+    /// Honeyswap by default
+    pub xdai_router_address: Address,
+    /// Wrapped xDai as traded by `xdai_router_address`
+    pub wxdai_address: Address,
+    /// Quotes compared against Uniswap by `eth_to_dai_best_execution` and
+    /// `dai_to_eth_best_execution`
+    pub aggregator: Option<Arc<dyn AggregatorApi>>,
+    /// Sends and pays for the Eth transactions of `dai_to_xdai_gasless`
+    pub relayer: Option<Arc<dyn RelayerApi>>,
+    /// Reads contract state at blocks other than the latest, see `call_view_at`. When set,
+    /// swap quotes are also read at the block their deadline is computed from.
+    pub block_reader: Option<Arc<dyn BlockReader>>,
+    /// Pushes Eth events instead of polling for them when set
+    pub eth_log_subscriber: Option<Arc<dyn LogSubscriber>>,
+    /// Pushes xDai events instead of polling for them when set
+    pub xdai_log_subscriber: Option<Arc<dyn LogSubscriber>>,
+    /// How read only RPC calls are retried when they fail
+    pub retry_policy: RetryPolicy,
+    /// How often event waits poll the full node
+    pub event_polling: EventPolling,
+    /// How long each kind of wait may take, on top of the `timeout` arguments of functions
+    pub timeouts: Timeouts,
+    /// Approve Uniswap to spend our Dai as part of `dai_to_eth_swap` if the allowance doesn't
+    /// cover the swap, on by default. When off the caller has to approve beforehand.
+    pub auto_approve: bool,
+    /// Overrides for every transaction sent, empty by default, see `with_tx_params`
+    pub tx_params: TxParams,
+    /// Swaps on Eth are broadcast to this RPC endpoint instead of `eth_web3` when set, so that
+    /// they stay out of the public mempool where they could be sandwiched. Any endpoint taking
+    /// `eth_sendRawTransaction` works, such as `FLASHBOTS_PROTECT_RPC`.
+    pub private_relay_url: Option<String>,
+    /// Swaps fail with `TokenBridgeError::StaleNode` if the latest block of the Eth node is older
+    /// than this, `None` turns the check off
+    pub max_eth_node_lag: Option<Duration>,
+    /// Checks run on the bridge contracts before sending them funds
+    pub bridge_preflight: BridgePreflight,
+    /// The Arbitrary Message Bridge contracts, needed for the `amb_*` functions
+    pub amb: Option<AmbContracts>,
+    /// Checkpoints conversions started with `eth_to_xdai` and `xdai_to_eth` so that
+    /// `resume_pending` can finish them after a restart
+    pub operation_store: Option<Arc<dyn OperationStore>>,
+    /// Records every transaction sent and, where it is known, what became of it
+    pub audit_sink: Option<Arc<dyn AuditSink>>,
+    /// The gas of every transaction sent, see `total_gas_spent`
+    pub gas_ledger: Arc<GasLedger>,
+    /// Drops, delays or fails matching RPC calls and transactions for chaos testing when set,
+    /// see `faults`
+    pub fault_injector: Option<Arc<FaultInjector>>,
+    /// Rejects or queues a conversion started while an identical one is in flight when set,
+    /// see `pending`
+    pub pending_registry: Option<Arc<PendingRegistry>>,
+    /// Runs the swaps, bridge transfers and payments of each account one at a time, see `queue`
+    pub operation_queue: Arc<OperationQueue>,
+    /// Recent bridge payout times, see `estimate_bridge_eta`
+    pub bridge_latencies: Arc<BridgeLatencies>,
+    /// Conversions above these amounts are only sent once confirmed, see `large_transfer`
+    pub large_transfers: LargeTransferPolicy,
+    /// Limits how much each swap, transfer and payment may send and how much may be sent per
+    /// day when set, see `spending`
+    pub spending_limiter: Option<Arc<SpendingLimiter>>,
+    /// Keeps some ETH for gas around when set, see `gas_top_up`
+    pub gas_top_up: Option<GasTopUp>,
+    /// The operation transactions are sent for, see `for_operation`
+    operation_id: Option<String>,
+    /// Set on the bridge a confirmed conversion is sent with, see `confirm_large_transfer`
+    large_transfer_confirmed: bool,
+    /// A conversion confirmed by the caller, see `with_confirmed_transfer`
+    confirmed_transfer: Option<ConfirmedTransfer>,
+    /// Set on the bridge a queued call is made with, see `in_account_order`
+    holds_account_turn: bool,
+    /// The tokens the transactions sent with this bridge move, see `spending_token`
+    token_spend: Option<(Address, Uint256)>,
+    /// Receives progress updates, see `progress_events`
+    progress: Arc<Mutex<Option<UnboundedSender<BridgeEvent>>>>,
+    /// The operations being run right now by id, see `pending_operations`
+    running_operations: Arc<Mutex<HashMap<String, Operation>>>,
+    /// Where the Web3 handles come from, see `set_web3_pool`
+    web3_pool: Web3Pool,
+    /// Kept so that the Web3 handles can be recreated with a different timeout or pool
+    eth_full_node_url: String,
+    xdai_full_node_url: String,
+    eth_rpc_timeout: Duration,
+    xdai_rpc_timeout: Duration,
 }
 
+// sharing one bridge across threads is part of the API, this fails to compile if a field
+// breaks it
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<TokenBridge>();
+};
+
 impl TokenBridge {
     pub fn new(
         uniswap_address: Address,
@@ -39,384 +418,1068 @@ impl TokenBridge {
         eth_full_node_url: String,
         xdai_full_node_url: String,
     ) -> TokenBridge {
+        TokenBridge::new_inner(
+            uniswap_address,
+            xdai_home_bridge_address,
+            xdai_foreign_bridge_address,
+            foreign_dai_contract_address,
+            own_address,
+            Some(Arc::new(LocalSigner::with_address(secret, own_address))),
+            eth_full_node_url,
+            xdai_full_node_url,
+        )
+    }
+
+    /// A bridge that signs its transactions with `signer` instead of a local key, such as a
+    /// hardware wallet or a remote signing service. Our address is the signer's address.
+    pub fn with_signer(
+        uniswap_address: Address,
+        xdai_home_bridge_address: Address,
+        xdai_foreign_bridge_address: Address,
+        foreign_dai_contract_address: Address,
+        signer: Arc<dyn Signer>,
+        eth_full_node_url: String,
+        xdai_full_node_url: String,
+    ) -> TokenBridge {
+        TokenBridge::new_inner(
+            uniswap_address,
+            xdai_home_bridge_address,
+            xdai_foreign_bridge_address,
+            foreign_dai_contract_address,
+            signer.address(),
+            Some(signer),
+            eth_full_node_url,
+            xdai_full_node_url,
+        )
+    }
+
+    /// A bridge without a private key, for quotes and balance checks only. Methods that would
+    /// send a transaction fail with `TokenBridgeError::ReadOnly`.
+    pub fn read_only(
+        uniswap_address: Address,
+        xdai_home_bridge_address: Address,
+        xdai_foreign_bridge_address: Address,
+        foreign_dai_contract_address: Address,
+        own_address: Address,
+        eth_full_node_url: String,
+        xdai_full_node_url: String,
+    ) -> TokenBridge {
+        TokenBridge::new_inner(
+            uniswap_address,
+            xdai_home_bridge_address,
+            xdai_foreign_bridge_address,
+            foreign_dai_contract_address,
+            own_address,
+            None,
+            eth_full_node_url,
+            xdai_full_node_url,
+        )
+    }
+
+    fn new_inner(
+        uniswap_address: Address,
+        xdai_home_bridge_address: Address,
+        xdai_foreign_bridge_address: Address,
+        foreign_dai_contract_address: Address,
+        own_address: Address,
+        signer: Option<Arc<dyn Signer>>,
+        eth_full_node_url: String,
+        xdai_full_node_url: String,
+    ) -> TokenBridge {
+        let accounts = Arc::new(Accounts::default());
+        let web3_pool = Web3Pool::global();
+        if let Some(ref signer) = signer {
+            accounts.add(signer.clone());
+        }
         TokenBridge {
             uniswap_address,
             xdai_home_bridge_address,
             xdai_foreign_bridge_address,
             foreign_dai_contract_address,
             own_address,
-            secret,
-            xdai_web3: Web3::new(&xdai_full_node_url, Duration::from_secs(10)),
-            eth_web3: Web3::new(&eth_full_node_url, Duration::from_secs(10)),
+            signer,
+            accounts,
+            slippage_bps: DEFAULT_SLIPPAGE_BPS,
+            contract_recipient_check: ContractRecipientCheck::Off,
+            contract_recipient_allowlist: Vec::new(),
+            destination_allowlist: None,
+            max_price_impact_bps: None,
+            price_oracle: None,
+            eth_chain_id: None,
+            xdai_chain_id: Some(XDAI_CHAIN_ID),
+            eth_gas_strategy: GasStrategy::Node,
+            xdai_gas_strategy: GasStrategy::Fixed(DEFAULT_XDAI_GAS_PRICE.into()),
+            confirmations: 0,
+            swap_backend: SwapBackend::UniswapV1,
+            simulate_before_send: false,
+            price_cache: None,
+            twap: None,
+            address_book: None,
+            multicall_address: snapshot::mainnet_multicall(),
+            weth_address: weth::mainnet_weth(),
+            sai_address: sai::mainnet_sai(),
+            sai_migration_address: sai::mainnet_sai_migration(),
+            xdai_router_address: honeyswap::honeyswap_router(),
+            wxdai_address: honeyswap::wxdai(),
+            aggregator: None,
+            relayer: None,
+            block_reader: None,
+            eth_log_subscriber: None,
+            xdai_log_subscriber: None,
+            retry_policy: RetryPolicy::default(),
+            event_polling: EventPolling::default(),
+            timeouts: Timeouts::default(),
+            auto_approve: true,
+            tx_params: TxParams::default(),
+            private_relay_url: None,
+            max_eth_node_lag: Some(health::ETH_MAX_BLOCK_AGE),
+            bridge_preflight: BridgePreflight::default(),
+            amb: None,
+            operation_store: None,
+            audit_sink: None,
+            gas_ledger: Arc::new(GasLedger::default()),
+            fault_injector: None,
+            pending_registry: None,
+            operation_queue: Arc::new(OperationQueue::default()),
+            bridge_latencies: Arc::new(BridgeLatencies::default()),
+            large_transfers: LargeTransferPolicy::default(),
+            spending_limiter: None,
+            gas_top_up: None,
+            operation_id: None,
+            large_transfer_confirmed: false,
+            confirmed_transfer: None,
+            holds_account_turn: false,
+            token_spend: None,
+            progress: Arc::new(Mutex::new(None)),
+            running_operations: Arc::new(Mutex::new(HashMap::new())),
+            xdai_web3: web3_pool.get(&xdai_full_node_url, DEFAULT_RPC_TIMEOUT),
+            eth_web3: web3_pool.get(&eth_full_node_url, DEFAULT_RPC_TIMEOUT),
+            web3_pool,
+            eth_full_node_url,
+            xdai_full_node_url,
+            eth_rpc_timeout: DEFAULT_RPC_TIMEOUT,
+            xdai_rpc_timeout: DEFAULT_RPC_TIMEOUT,
+        }
+    }
+
+    /// The signer for our transactions, or `TokenBridgeError::ReadOnly` if there is none
+    pub fn signer(&self) -> Result<Arc<dyn Signer>, Error> {
+        match self.signer {
+            Some(ref signer) => Ok(signer.clone()),
+            None => Err(TokenBridgeError::ReadOnly.into()),
         }
     }
 
-    /// This just sends some Eth. Returns the tx hash.
+    /// Sets how long a single RPC request to each full node may take before it fails
+    pub fn set_rpc_timeouts(&mut self, eth_timeout: Duration, xdai_timeout: Duration) {
+        self.eth_rpc_timeout = eth_timeout;
+        self.xdai_rpc_timeout = xdai_timeout;
+        self.eth_web3 = self.web3_pool.get(&self.eth_full_node_url, eth_timeout);
+        self.xdai_web3 = self.web3_pool.get(&self.xdai_full_node_url, xdai_timeout);
+    }
+
+    /// Takes the Web3 handles of this bridge from `pool` instead of the process wide
+    /// `Web3Pool::global()`. Bridges over the same pool and node urls share their handles.
+    pub fn set_web3_pool(&mut self, pool: Web3Pool) {
+        self.web3_pool = pool;
+        self.set_rpc_timeouts(self.eth_rpc_timeout, self.xdai_rpc_timeout);
+    }
+
+    /// A copy of this bridge whose RPC requests to both full nodes use `timeout`, for
+    /// individual calls that need a longer or shorter timeout than usual
+    pub fn with_rpc_timeout(&self, timeout: Duration) -> TokenBridge {
+        let mut bridge = self.clone();
+        bridge.set_rpc_timeouts(timeout, timeout);
+        bridge
+    }
+
+    /// The Web3 handle for `chain`
+    pub fn web3(&self, chain: Chain) -> Web3 {
+        match chain {
+            Chain::Eth => self.eth_web3.clone(),
+            Chain::Xdai => self.xdai_web3.clone(),
+        }
+    }
+
+    /// Adds the defaults for transactions on `chain` to `options`, options that are already set
+    /// take precedence.
+    fn tx_options(&self, chain: Chain, mut options: Vec<SendTxOption>) -> Vec<SendTxOption> {
+        let (chain_id, gas_strategy) = match chain {
+            Chain::Eth => (self.eth_chain_id, &self.eth_gas_strategy),
+            Chain::Xdai => (self.xdai_chain_id, &self.xdai_gas_strategy),
+        };
+        let defaults = chain_id
+            .map(SendTxOption::NetworkId)
+            .into_iter()
+            .chain(gas_strategy.to_option());
+        for default in defaults {
+            let is_set = options
+                .iter()
+                .any(|option| std::mem::discriminant(option) == std::mem::discriminant(&default));
+            if !is_set {
+                options.push(default);
+            }
+        }
+        options
+    }
+
+    /// Looks up the code at `to` and warns or errors according to `contract_recipient_check`
+    /// if it is a contract that is not in `contract_recipient_allowlist`.
+    fn check_transfer_recipient(
+        &self,
+        web3: &Web3,
+        to: Address,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let check = self.contract_recipient_check;
+        let recipient = self.describe_address(to);
+        if check == ContractRecipientCheck::Off || self.contract_recipient_allowlist.contains(&to) {
+            return Box::new(futures::future::ok(()));
+        }
+
+        Box::new(web3.eth_get_code(to).and_then(move |code| {
+            if code.is_empty() {
+                return Ok(());
+            }
+            match check {
+                ContractRecipientCheck::Block => {
+                    Err(TokenBridgeError::RecipientIsContract { address: to }.into())
+                }
+                _ => {
+                    warn!("Sending a plain transfer to contract {}", recipient);
+                    Ok(())
+                }
+            }
+        }))
+    }
+
+    /// This just sends some Eth and waits up to `timeout` seconds for it to be mined
     pub fn eth_transfer(
         &self,
         to: Address,
-        amount: Uint256,
+        amount: Eth,
         timeout: u64,
     ) -> Box<dyn Future<Item = (), Error = Error>> {
         let web3 = self.eth_web3.clone();
-        let own_address = self.own_address.clone();
-        let secret = self.secret.clone();
+        let salf = self.clone();
+        let value = amount.into_wei();
+        let timeout = Timeouts::cap(Duration::from_secs(timeout), self.timeouts.confirmation);
 
-        Box::new(
-            web3.send_transaction(to, Vec::new(), amount, own_address, secret, vec![])
-                .and_then(move |tx_hash| {
-                    web3.wait_for_transaction(tx_hash.into())
-                        .timeout(Duration::from_secs(timeout));
-                    Ok(())
-                }),
-        )
+        Box::new(self.check_transfer_recipient(&web3, to).and_then(move |_| {
+            salf.in_account_order(move |salf| {
+                Box::new(
+                    salf.send_transaction(Chain::Eth, to, Vec::new(), value, vec![])
+                        .and_then(move |tx_hash| {
+                            web3.wait_for_transaction(tx_hash.into())
+                                .timeout(timeout)
+                                .map(|_| ())
+                        }),
+                )
+            })
+        }))
     }
 
     /// Price of ETH in Dai
-    pub fn eth_to_dai_price(
-        &self,
-        amount: Uint256,
-    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    pub fn eth_to_dai_price(&self, amount: Eth) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        if self.swap_backend.router().is_some() {
+            return Box::new(
+                self.router_eth_to_dai_price(amount.into_wei())
+                    .map(Dai::from_wei),
+            );
+        }
         let web3 = self.eth_web3.clone();
         let uniswap_address = self.uniswap_address.clone();
         let own_address = self.own_address.clone();
 
-        Box::new(
-            web3.contract_call(
-                uniswap_address,
-                "getEthToTokenInputPrice(uint256)",
-                &[amount.into()],
-                own_address,
+        let quote = self.cached_price(PriceDirection::EthToDai, amount.into_wei(), move |amount| {
+            self.with_retry(Chain::Eth, "getEthToTokenInputPrice(uint256)", move || {
+                Box::new(
+                    web3.contract_call(
+                        uniswap_address,
+                        "getEthToTokenInputPrice(uint256)",
+                        &[amount.clone().into()],
+                        own_address,
+                    )
+                    .and_then(|tokens_bought| {
+                        decode_output("getEthToTokenInputPrice(uint256)", &tokens_bought)
+                    }),
+                )
+            })
+        });
+        Box::new(quote.map(Dai::from_wei))
+    }
+
+    /// How much ETH has to be sold to buy exactly `dai_amount` Dai
+    pub fn eth_cost_of_dai(&self, dai_amount: Dai) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        if self.swap_backend.router().is_some() {
+            return Box::new(
+                self.router_eth_cost_of_dai(dai_amount.into_wei())
+                    .map(Eth::from_wei),
+            );
+        }
+        let web3 = self.eth_web3.clone();
+        let uniswap_address = self.uniswap_address;
+        let own_address = self.own_address;
+        let dai_amount = dai_amount.into_wei();
+
+        let cost = self.with_retry(Chain::Eth, "getEthToTokenOutputPrice(uint256)", move || {
+            Box::new(
+                web3.contract_call(
+                    uniswap_address,
+                    "getEthToTokenOutputPrice(uint256)",
+                    &[dai_amount.clone().into()],
+                    own_address,
+                )
+                .and_then(|eth_sold| decode_output("getEthToTokenOutputPrice(uint256)", &eth_sold)),
             )
-            .and_then(move |tokens_bought| {
-                Ok(Uint256::from_bytes_be(match tokens_bought.get(0..32) {
-                    Some(val) => val,
-                    None => bail!(
-                        "Malformed output from uniswap getEthToTokenInputPrice call {:?}",
-                        tokens_bought
-                    ),
-                }))
-            }),
-        )
+        });
+        Box::new(cost.map(Eth::from_wei))
     }
 
     /// Price of Dai in Eth
-    pub fn dai_to_eth_price(
-        &self,
-        amount: Uint256,
-    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    pub fn dai_to_eth_price(&self, amount: Dai) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        if self.swap_backend.router().is_some() {
+            return Box::new(
+                self.router_dai_to_eth_price(amount.into_wei())
+                    .map(Eth::from_wei),
+            );
+        }
         let web3 = self.eth_web3.clone();
         let uniswap_address = self.uniswap_address.clone();
         let own_address = self.own_address.clone();
 
+        let quote = self.cached_price(PriceDirection::DaiToEth, amount.into_wei(), move |amount| {
+            self.with_retry(Chain::Eth, "getTokenToEthInputPrice(uint256)", move || {
+                Box::new(
+                    web3.contract_call(
+                        uniswap_address,
+                        "getTokenToEthInputPrice(uint256)",
+                        &[amount.clone().into()],
+                        own_address,
+                    )
+                    .and_then(|eth_bought| {
+                        decode_output("getTokenToEthInputPrice(uint256)", &eth_bought)
+                    }),
+                )
+            })
+        });
+        Box::new(quote.map(Eth::from_wei))
+    }
+
+    /// Runs the optional price impact and oracle checks that guard a swap of `amount`, which is
+    /// ETH if `eth_to_dai` is set and Dai otherwise.
+    pub(crate) fn swap_checks(
+        &self,
+        amount: Uint256,
+        eth_to_dai: bool,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let impact_check = match (self.max_price_impact_bps, eth_to_dai) {
+            (Some(max_bps), true) => {
+                self.check_eth_to_dai_price_impact(Eth::from_wei(amount), max_bps)
+            }
+            (Some(max_bps), false) => {
+                self.check_dai_to_eth_price_impact(Dai::from_wei(amount), max_bps)
+            }
+            (None, _) => Box::new(futures::future::ok(())),
+        };
+
         Box::new(
-            web3.contract_call(
-                uniswap_address,
-                "getTokenToEthInputPrice(uint256)",
-                &[amount.into()],
-                own_address,
-            )
-            .and_then(move |eth_bought| {
-                Ok(Uint256::from_bytes_be(match eth_bought.get(0..32) {
-                    Some(val) => val,
-                    None => bail!(
-                        "Malformed output from uniswap getTokenToEthInputPrice call {:?}",
-                        eth_bought
-                    ),
-                }))
+            impact_check
+                .join(self.check_uniswap_against_oracle())
+                .map(|_| ()),
+        )
+    }
+
+    /// The call selling `eth_amount` ETH on Uniswap for at least the current quote minus our
+    /// slippage allowance, expiring `timeout` seconds after the latest block, the Dai goes to
+    /// `recipient`
+    fn eth_to_dai_swap_payload(
+        &self,
+        eth_amount: Eth,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = SwapCall, Error = Error>> {
+        let slippage_bps = self.slippage_bps;
+        let own_address = self.own_address;
+        let max_lag = self.max_eth_node_lag;
+        let salf = self.clone();
+        Box::new(
+            self.at_latest_block(Chain::Eth, move |block| {
+                let twap = salf.clone();
+                Box::new(
+                    salf.fresh_eth_to_dai_price_at(eth_amount, block)
+                        .and_then(move |quote| {
+                            twap.twap_adjusted_quote(PriceDirection::EthToDai, quote.into_wei())
+                        }),
+                )
+            })
+            .and_then(move |(block, expected_dai)| {
+                if let Some(max_lag) = max_lag {
+                    health::ensure_fresh(&block.timestamp, max_lag)?;
+                }
+                let expected_dai = minimum_output(expected_dai, slippage_bps);
+                let deadline = block.timestamp + timeout.into();
+                let data = if recipient == own_address {
+                    encoding::uniswap_eth_to_token_swap(expected_dai, deadline.clone())
+                } else {
+                    encoding::uniswap_eth_to_token_transfer(
+                        expected_dai,
+                        deadline.clone(),
+                        recipient,
+                    )
+                };
+                Ok(SwapCall {
+                    data,
+                    block: block.number,
+                    deadline,
+                })
             }),
         )
     }
 
-    /// Sell `eth_amount` ETH for Dai.
-    /// This function will error out if it takes longer than 'timeout' and the transaction is guaranteed not
-    /// to be accepted on the blockchain after this time.
+    /// Sends the conversion of `amount` made by `send` from our account, unless an identical
+    /// one is pending, it needs confirmation and isn't confirmed or it is over the spending
+    /// limits. `send` is given the bridge to send with once the calls of our account queued
+    /// before it are done.
+    fn send_conversion<T, F>(
+        &self,
+        kind: ConversionKind,
+        amount: Uint256,
+        send: F,
+    ) -> Box<dyn Future<Item = T, Error = Error>>
+    where
+        T: 'static,
+        F: FnOnce(TokenBridge) -> Box<dyn Future<Item = T, Error = Error>> + 'static,
+    {
+        let salf = self.clone();
+        let asset = self.conversion_asset(kind);
+        self.guard_conversion(kind, amount.clone(), move || {
+            let topped_up = salf.top_up_gas_before(kind);
+            let confirmed = amount.clone();
+            Box::new(
+                topped_up
+                    .and_then(move |_| salf.confirm_large_transfer(kind, confirmed))
+                    .and_then(move |salf| {
+                        // counted as the transaction moving it is sent
+                        salf.check_spending(asset, &amount)?;
+                        Ok(salf)
+                    })
+                    .and_then(move |salf| salf.in_account_order(send)),
+            )
+        })
+    }
+
+    /// Sell `eth_amount` ETH for Dai. The swap is only valid until `timeout` seconds after the
+    /// latest block's timestamp, this resolves once it is mined or fails with
+    /// `TokenBridgeError::SwapExpired` once a block past that deadline is mined without it.
     pub fn eth_to_dai_swap(
         &self,
-        eth_amount: Uint256,
+        eth_amount: Eth,
         timeout: u64,
-    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    ) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        self.eth_to_dai_swap_to(eth_amount, self.own_address, timeout)
+    }
+
+    /// Like `eth_to_dai_swap` but the Dai is sent to `recipient`, we still pay the ETH and gas
+    pub fn eth_to_dai_swap_to(
+        &self,
+        eth_amount: Eth,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        self.send_conversion(
+            ConversionKind::EthToDai,
+            eth_amount.wei().clone(),
+            move |salf| salf.run_eth_to_dai_swap(eth_amount, recipient, timeout),
+        )
+    }
+
+    fn run_eth_to_dai_swap(
+        &self,
+        eth_amount: Eth,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        let own_address = self.own_address;
         let uniswap_address = self.uniswap_address.clone();
-        let own_address = self.own_address.clone();
-        let secret = self.secret.clone();
-        let web3 = self.eth_web3.clone();
+        let swap_event = match self.swap_backend.eth_to_token_event() {
+            Some(swap_event) => swap_event,
+            None => return self.router_eth_to_dai_swap(eth_amount, recipient, timeout),
+        };
+        let salf = self.clone();
 
         Box::new(
-            web3.eth_get_latest_block()
-                .join(self.eth_to_dai_price(eth_amount.clone()))
-                .and_then(move |(block, expected_dai)| {
-                    // Equivalent to `amount * (1 - 0.025)` without using decimals
-                    let expected_dai = (expected_dai / 40u64.into()) * 39u64.into();
-                    let deadline = block.timestamp + timeout.into();
-                    let payload = encode_call(
-                        "ethToTokenSwapInput(uint256,uint256)",
-                        &[expected_dai.clone().into(), deadline.into()],
-                    );
-
-                    web3.send_transaction(
-                        uniswap_address,
-                        payload,
-                        eth_amount,
-                        own_address,
-                        secret,
-                        vec![SendTxOption::GasLimit(80_000u64.into())],
-                    )
-                    .join(
-                        web3.wait_for_event_alt(
-                            uniswap_address,
-                            "TokenPurchase(address,uint256,uint256)",
-                            Some(vec![own_address.into()]),
-                            None,
-                            None,
-                            |_| true,
-                        )
-                        .timeout(Duration::from_secs(timeout)),
-                    )
-                    .and_then(move |(_tx, response)| {
-                        let transfered_dai = Uint256::from_bytes_be(&response.topics[3]);
-                        Ok(transfered_dai)
-                    })
+            self.swap_checks(eth_amount.wei().clone(), true)
+                .and_then(move |_| {
+                    salf.eth_to_dai_swap_payload(eth_amount.clone(), recipient, timeout)
+                        .and_then(move |swap| {
+                            salf.send_swap_transaction(
+                                Chain::Eth,
+                                uniswap_address,
+                                swap.data,
+                                eth_amount.into_wei(),
+                                vec![SendTxOption::GasLimit(80_000u64.into())],
+                            )
+                            .and_then({
+                                let salf = salf.clone();
+                                let (block, deadline) = (swap.block, swap.deadline);
+                                move |tx_hash| {
+                                    salf.wait_for_swap_outcome(
+                                        swap_event,
+                                        tx_hash.clone(),
+                                        block,
+                                        deadline,
+                                    )
+                                    .map(move |outcome| (tx_hash, outcome))
+                                }
+                            })
+                            .and_then(move |(tx_hash, outcome)| {
+                                salf.audit_swap_outcome(tx_hash, &outcome);
+                                let transfered_dai = outcome.executed()?;
+                                metrics::swap_executed("eth_to_dai");
+                                salf.emit(BridgeEvent::EventObserved {
+                                    chain: Chain::Eth,
+                                    contract: uniswap_address,
+                                    event: swap_event.definition.signature.to_string(),
+                                });
+                                if recipient == own_address {
+                                    salf.emit(BridgeEvent::FundsArrived {
+                                        chain: Chain::Eth,
+                                        amount: transfered_dai.clone(),
+                                    });
+                                }
+                                Ok(Dai::from_wei(transfered_dai))
+                            })
+                        })
                 }),
         )
     }
 
-    /// Checks if the uniswap contract has been approved to spend dai from our account.
-    pub fn check_if_uniswap_dai_approved(&self) -> Box<dyn Future<Item = bool, Error = Error>> {
-        let web3 = self.eth_web3.clone();
-        let uniswap_address = self.uniswap_address.clone();
-        let dai_address = self.foreign_dai_contract_address.clone();
-        let own_address = self.own_address.clone();
+    /// How much of our Dai the uniswap contract is currently allowed to transfer
+    pub fn get_allowance(&self) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        Box::new(
+            self.get_token_allowance(self.foreign_dai_contract_address, self.uniswap_address)
+                .map(Dai::from_wei),
+        )
+    }
 
+    /// Checks if the uniswap contract is allowed to spend at least `dai_amount` of our Dai
+    pub fn allowance_sufficient_for(
+        &self,
+        dai_amount: Dai,
+    ) -> Box<dyn Future<Item = bool, Error = Error>> {
         Box::new(
-            web3.contract_call(
-                dai_address,
-                "allowance(address,address)",
-                &[own_address.into(), uniswap_address.into()],
-                own_address,
-            )
-            .and_then(move |allowance| {
-                let allowance = Uint256::from_bytes_be(match allowance.get(0..32) {
-                    Some(val) => val,
-                    None => bail!(
-                        "Malformed output from uniswap getTokenToEthInputPrice call {:?}",
-                        allowance
-                    ),
-                });
-
-                // Check if the allowance remaining is greater than half of a Uint256- it's as good
-                // a test as any.
-                Ok(allowance > (Uint256::max_value() / 2u32.into()))
-            }),
+            self.get_allowance()
+                .map(move |allowance| allowance.wei() >= dai_amount.wei()),
         )
     }
 
-    /// Sends transaction to the DAI contract to approve uniswap transactions, this future will not
-    /// resolve until the process is either successful for the timeout finishes
+    /// Checks if the uniswap contract has been approved to spend dai from our account, which
+    /// means an allowance of more than half of a Uint256 as given by
+    /// `approve_uniswap_dai_transfers`. Finite approvals count as not approved, use
+    /// `allowance_sufficient_for` to check against an amount.
+    pub fn check_if_uniswap_dai_approved(&self) -> Box<dyn Future<Item = bool, Error = Error>> {
+        let half_max = Uint256::max_value() / 2u32.into();
+        self.allowance_sufficient_for(Dai::from_wei(half_max + 1u32.into()))
+    }
+
+    /// Sends transaction to the DAI contract to approve uniswap transactions unless uniswap is
+    /// already approved, see `check_if_uniswap_dai_approved`. This future will not resolve until
+    /// the approval transaction is mined and has emitted its Approval event, if it reverted the
+    /// error is `TokenBridgeError::TransactionReverted`. On timeout the error is
+    /// `TokenBridgeError::TimedOut` saying whether the approval went through.
     pub fn approve_uniswap_dai_transfers(
         &self,
         timeout: Duration,
     ) -> Box<dyn Future<Item = (), Error = Error>> {
-        let dai_address = self.foreign_dai_contract_address.clone();
-        let own_address = self.own_address.clone();
-        let uniswap_address = self.uniswap_address.clone();
-        let secret = self.secret.clone();
-        let web3 = self.eth_web3.clone();
+        self.in_account_order(move |salf| salf.run_uniswap_dai_approval(timeout))
+    }
 
-        let payload = encode_call(
-            "approve(address,uint256)",
-            &[uniswap_address.into(), Uint256::max_value().into()],
-        );
+    fn run_uniswap_dai_approval(
+        &self,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let dai_address = self.foreign_dai_contract_address;
+        let uniswap_address = self.uniswap_address;
+        let salf = self.clone();
 
         Box::new(
-            web3.send_transaction(
-                dai_address,
-                payload,
-                0u32.into(),
-                own_address,
-                secret,
-                vec![],
-            )
-            .join(web3.wait_for_event_alt(
-                dai_address,
-                "Approval(address,address,uint256)",
-                Some(vec![own_address.into()]),
-                Some(vec![uniswap_address.into()]),
-                None,
-                |_| true,
-            ))
-            .timeout(timeout)
-            .and_then(move |_| Ok(())),
+            self.check_if_uniswap_dai_approved()
+                .and_then(move |is_approved| {
+                    if is_approved {
+                        trace!("uniswap already approved, not sending an approval");
+                        return Box::new(futures::future::ok(()))
+                            as Box<dyn Future<Item = (), Error = Error>>;
+                    }
+                    let payload = encoding::erc20_approve(uniswap_address, Uint256::max_value());
+                    Box::new(
+                        salf.send_transaction(
+                            Chain::Eth,
+                            dai_address,
+                            payload,
+                            0u32.into(),
+                            vec![],
+                        )
+                        .and_then(move |tx_hash| {
+                            let confirmed = salf.confirm_transaction(
+                                Chain::Eth,
+                                tx_hash.clone(),
+                                dai_address,
+                                ERC20_APPROVAL,
+                                "value",
+                            );
+                            salf.wait_or_reconcile(
+                                Chain::Eth,
+                                tx_hash,
+                                confirmed,
+                                timeout,
+                                dai_address,
+                                ERC20_APPROVAL,
+                                "value",
+                            )
+                            .map(move |_| {
+                                salf.emit(BridgeEvent::EventObserved {
+                                    chain: Chain::Eth,
+                                    contract: dai_address,
+                                    event: ERC20_APPROVAL.signature.to_string(),
+                                });
+                            })
+                        }),
+                    )
+                }),
         )
     }
 
-    /// Sell `dai_amount` Dai for ETH
-    /// This function will error out if it takes longer than 'timeout' and the transaction is guaranteed not
-    /// to be accepted on the blockchain after this time.
+    /// Approves Uniswap to spend an unlimited amount of our Dai unless the current allowance
+    /// already covers `dai_amount`, see `approve_uniswap_dai_transfers`
+    pub fn ensure_uniswap_dai_approved(
+        &self,
+        dai_amount: Dai,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        self.ensure_token_approved(
+            self.foreign_dai_contract_address,
+            self.uniswap_address,
+            dai_amount.into_wei(),
+            timeout,
+        )
+    }
+
+    /// The call selling `dai_amount` Dai on Uniswap for at least the current quote minus our
+    /// slippage allowance, expiring `timeout` seconds after the latest block, the ETH goes to
+    /// `recipient`
+    fn dai_to_eth_swap_payload(
+        &self,
+        dai_amount: Dai,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = SwapCall, Error = Error>> {
+        let slippage_bps = self.slippage_bps;
+        let own_address = self.own_address;
+        let max_lag = self.max_eth_node_lag;
+        let salf = self.clone();
+        let quote_amount = dai_amount.clone();
+        Box::new(
+            self.at_latest_block(Chain::Eth, move |block| {
+                let twap = salf.clone();
+                Box::new(
+                    salf.fresh_dai_to_eth_price_at(quote_amount, block)
+                        .and_then(move |quote| {
+                            twap.twap_adjusted_quote(PriceDirection::DaiToEth, quote.into_wei())
+                        }),
+                )
+            })
+            .and_then(move |(block, expected_eth)| {
+                if let Some(max_lag) = max_lag {
+                    health::ensure_fresh(&block.timestamp, max_lag)?;
+                }
+                let expected_eth = minimum_output(expected_eth, slippage_bps);
+                let deadline = block.timestamp + timeout.into();
+                let data = if recipient == own_address {
+                    encoding::uniswap_token_to_eth_swap(
+                        dai_amount.into_wei(),
+                        expected_eth,
+                        deadline.clone(),
+                    )
+                } else {
+                    encoding::uniswap_token_to_eth_transfer(
+                        dai_amount.into_wei(),
+                        expected_eth,
+                        deadline.clone(),
+                        recipient,
+                    )
+                };
+                Ok(SwapCall {
+                    data,
+                    block: block.number,
+                    deadline,
+                })
+            }),
+        )
+    }
+
+    /// Sell `dai_amount` Dai for ETH. The swap is only valid until `timeout` seconds after the
+    /// latest block's timestamp, this resolves once it is mined or fails with
+    /// `TokenBridgeError::SwapExpired` once a block past that deadline is mined without it.
+    /// Uniswap is approved to spend the Dai first if needed, see `auto_approve`.
     pub fn dai_to_eth_swap(
         &self,
-        dai_amount: Uint256,
+        dai_amount: Dai,
         timeout: u64,
-    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        self.dai_to_eth_swap_to(dai_amount, self.own_address, timeout)
+    }
+
+    /// Like `dai_to_eth_swap` but the ETH is sent to `recipient`, we still pay the Dai and gas
+    pub fn dai_to_eth_swap_to(
+        &self,
+        dai_amount: Dai,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        self.send_conversion(
+            ConversionKind::DaiToEth,
+            dai_amount.wei().clone(),
+            move |salf| salf.run_dai_to_eth_swap(dai_amount, recipient, timeout),
+        )
+    }
+
+    fn run_dai_to_eth_swap(
+        &self,
+        dai_amount: Dai,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        let own_address = self.own_address;
         let uniswap_address = self.uniswap_address.clone();
-        let own_address = self.own_address.clone();
-        let secret = self.secret.clone();
-        let web3 = self.eth_web3.clone();
+        let swap_event = match self.swap_backend.token_to_eth_event() {
+            Some(swap_event) => swap_event,
+            None => return self.router_dai_to_eth_swap(dai_amount, recipient, timeout),
+        };
         let salf = self.clone();
 
+        let ensure_approved = if self.auto_approve {
+            self.ensure_uniswap_dai_approved(
+                dai_amount.clone(),
+                Timeouts::cap(Duration::from_secs(timeout), self.timeouts.confirmation),
+            )
+        } else {
+            Box::new(futures::future::ok(()))
+        };
+
         Box::new(
-            self.check_if_uniswap_dai_approved()
+            ensure_approved
                 .and_then({
                     let salf = self.clone();
-                    move |is_approved| {
-                        trace!("uniswap approved {}", is_approved);
-                        if is_approved {
-                            Box::new(futures::future::ok(()))
-                                as Box<dyn Future<Item = (), Error = Error>>
-                        } else {
-                            salf.approve_uniswap_dai_transfers(Duration::from_secs(600))
-                        }
-                    }
+                    let dai_amount = dai_amount.clone();
+                    move |_| salf.swap_checks(dai_amount.into_wei(), false)
                 })
                 .and_then(move |_| {
-                    web3.eth_get_latest_block()
-                        .join(salf.dai_to_eth_price(dai_amount.clone()))
-                        .and_then(move |(block, expected_eth)| {
-                            // Equivalent to `amount * (1 - 0.025)` without using decimals
-                            let expected_eth = (expected_eth / 40u64.into()) * 39u64.into();
-                            let deadline = block.timestamp + timeout.into();
-                            let payload = encode_call(
-                                "tokenToEthSwapInput(uint256,uint256,uint256)",
-                                &[
-                                    dai_amount.into(),
-                                    expected_eth.clone().into(),
-                                    deadline.into(),
-                                ],
-                            );
-
-                            web3.send_transaction(
-                                uniswap_address,
-                                payload,
-                                0u32.into(),
-                                own_address,
-                                secret,
-                                vec![SendTxOption::GasLimit(80_000u64.into())],
-                            )
-                            .join(
-                                web3.wait_for_event_alt(
+                    let swap_amount = dai_amount.clone();
+                    salf.dai_to_eth_swap_payload(dai_amount, recipient, timeout)
+                        .and_then(move |swap| {
+                            let data = swap.data;
+                            futures::future::result(salf.spending_token(
+                                salf.foreign_dai_contract_address,
+                                swap_amount.wei(),
+                            ))
+                            .and_then(move |sender| {
+                                sender.send_swap_transaction(
+                                    Chain::Eth,
                                     uniswap_address,
-                                    "EthPurchase(address,uint256,uint256)",
-                                    Some(vec![own_address.into()]),
-                                    None,
-                                    None,
-                                    |_| true,
+                                    data,
+                                    0u32.into(),
+                                    vec![SendTxOption::GasLimit(80_000u64.into())],
                                 )
-                                .timeout(Duration::from_secs(timeout)),
-                            )
-                            .and_then(move |(_tx, response)| {
-                                let transfered_eth = Uint256::from_bytes_be(&response.topics[3]);
-                                Ok(transfered_eth)
+                            })
+                            .and_then({
+                                let salf = salf.clone();
+                                let (block, deadline) = (swap.block, swap.deadline);
+                                move |tx_hash| {
+                                    salf.wait_for_swap_outcome(
+                                        swap_event,
+                                        tx_hash.clone(),
+                                        block,
+                                        deadline,
+                                    )
+                                    .map(move |outcome| (tx_hash, outcome))
+                                }
+                            })
+                            .and_then(move |(tx_hash, outcome)| {
+                                salf.audit_swap_outcome(tx_hash, &outcome);
+                                let transfered_eth = outcome.executed()?;
+                                metrics::swap_executed("dai_to_eth");
+                                salf.emit(BridgeEvent::EventObserved {
+                                    chain: Chain::Eth,
+                                    contract: uniswap_address,
+                                    event: swap_event.definition.signature.to_string(),
+                                });
+                                if recipient == own_address {
+                                    salf.emit(BridgeEvent::FundsArrived {
+                                        chain: Chain::Eth,
+                                        amount: transfered_eth.clone(),
+                                    });
+                                }
+                                Ok(Eth::from_wei(transfered_eth))
                             })
                         })
                 }),
         )
     }
 
-    /// Bridge `dai_amount` dai to xdai
+    /// Bridge `dai_amount` dai to xdai. The result includes the fee the bridge is expected to
+    /// keep.
     pub fn dai_to_xdai_bridge(
         &self,
-        dai_amount: Uint256,
+        dai_amount: Dai,
         timeout: u64,
-    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
-        let eth_web3 = self.eth_web3.clone();
-        let foreign_dai_contract_address = self.foreign_dai_contract_address.clone();
-        let xdai_foreign_bridge_address = self.xdai_foreign_bridge_address.clone();
-        let own_address = self.own_address.clone();
-        let secret = self.secret.clone();
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        self.dai_to_xdai_bridge_to(dai_amount, self.own_address, timeout)
+    }
+
+    /// Like `dai_to_xdai_bridge` but the xDai is paid out to `recipient`. This goes through the
+    /// bridge's `relayTokens`, which needs an approval of the bridge first.
+    pub fn dai_to_xdai_bridge_to(
+        &self,
+        dai_amount: Dai,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        self.send_conversion(
+            ConversionKind::DaiToXdai,
+            dai_amount.wei().clone(),
+            move |salf| salf.run_dai_to_xdai_bridge(dai_amount, recipient, timeout),
+        )
+    }
+
+    fn run_dai_to_xdai_bridge(
+        &self,
+        dai_amount: Dai,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        let dai_amount = dai_amount.into_wei();
+        let dai = self.foreign_dai_contract_address;
+        let latencies = self.bridge_latencies.clone();
+        let salf = self.clone();
 
-        // You basically just send it some coins
         // We have no idea when this has succeeded since the events are not indexed
         Box::new(
-            eth_web3
-                .send_transaction(
-                    foreign_dai_contract_address,
-                    encode_call(
-                        "transfer(address,uint256)",
-                        &[
-                            xdai_foreign_bridge_address.into(),
-                            dai_amount.clone().into(),
-                        ],
-                    ),
+            self.bridge_preflight(BridgeDirection::DaiToXdai)
+                .and_then({
+                    let salf = self.clone();
+                    move |_| salf.get_bridge_fee(BridgeDirection::DaiToXdai)
+                })
+                .and_then({
+                    let salf = self.clone();
+                    move |fee| {
+                        salf.send_dai_to_bridge(dai_amount.clone(), recipient, timeout)
+                            .map(move |tx_hash| (tx_hash, dai_amount, fee))
+                    }
+                })
+                .and_then(move |(tx_hash, dai_amount, fee)| {
+                    metrics::bridge_deposit(BridgeDirection::DaiToXdai);
+                    latencies.sent(BridgeDirection::DaiToXdai, tx_hash.clone());
+                    let confirmed = salf.confirm_transaction(
+                        Chain::Eth,
+                        tx_hash.clone(),
+                        dai,
+                        ERC20_TRANSFER,
+                        "value",
+                    );
+                    salf.wait_or_reconcile(
+                        Chain::Eth,
+                        tx_hash.clone(),
+                        confirmed,
+                        Duration::from_secs(timeout),
+                        dai,
+                        ERC20_TRANSFER,
+                        "value",
+                    )
+                    .map(move |_| BridgeTransfer {
+                        tx_hash,
+                        expected_fee: bridge_fee_amount(dai_amount.clone(), fee),
+                        amount: dai_amount,
+                    })
+                }),
+        )
+    }
+
+    /// Sends `amount` Dai to the foreign bridge to be paid out to `recipient` on xDai, returns
+    /// the tx hash. Approving the bridge first, if needed, may take up to `timeout` seconds.
+    fn send_dai_to_bridge(
+        &self,
+        amount: Uint256,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let foreign_dai_contract_address = self.foreign_dai_contract_address;
+        let xdai_foreign_bridge_address = self.xdai_foreign_bridge_address;
+
+        // You basically just send it some coins, they are paid out to the sender
+        if recipient == self.own_address {
+            let sender = try_future!(self.spending_token(foreign_dai_contract_address, &amount));
+            return sender.send_transaction(
+                Chain::Eth,
+                foreign_dai_contract_address,
+                encoding::erc20_transfer(xdai_foreign_bridge_address, amount),
+                0u32.into(),
+                vec![SendTxOption::GasLimit(80_000u64.into())],
+            );
+        }
+
+        let salf = self.clone();
+        Box::new(
+            self.ensure_token_approved(
+                foreign_dai_contract_address,
+                xdai_foreign_bridge_address,
+                amount.clone(),
+                Duration::from_secs(timeout),
+            )
+            .and_then({
+                let amount = amount.clone();
+                move |_| salf.spending_token(foreign_dai_contract_address, &amount)
+            })
+            .and_then(move |sender| {
+                sender.send_transaction(
+                    Chain::Eth,
+                    xdai_foreign_bridge_address,
+                    encoding::foreign_bridge_relay_tokens(recipient, amount),
                     0u32.into(),
-                    own_address,
-                    secret,
-                    vec![SendTxOption::GasLimit(80_000u64.into())],
+                    vec![SendTxOption::GasLimit(150_000u64.into())],
                 )
-                .and_then(move |tx_hash| {
-                    eth_web3
-                        .wait_for_transaction(tx_hash.into())
-                        .timeout(Duration::from_secs(timeout));
-                    Ok(dai_amount)
-                }),
+            }),
         )
     }
 
-    /// Bridge `xdai_amount` xdai to dai
+    /// Bridge `xdai_amount` xdai to dai. The result includes the fee the bridge is expected to
+    /// keep. Amounts below the bridge minimum would be kept by the bridge without paying out
+    /// anything, they fail with `TokenBridgeError::BelowBridgeMinimum` without being sent.
+    /// Waits up to `timeout` seconds for the transfer to be mined on xDai, not for the Dai to
+    /// arrive.
     pub fn xdai_to_dai_bridge(
         &self,
-        xdai_amount: Uint256,
-    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
-        let xdai_web3 = self.xdai_web3.clone();
+        xdai_amount: XDai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        self.xdai_to_dai_bridge_to(xdai_amount, self.own_address, timeout)
+    }
 
-        let xdai_home_bridge_address = self.xdai_home_bridge_address.clone();
+    /// Like `xdai_to_dai_bridge` but the Dai is paid out to `recipient`, using the bridge's
+    /// `relayTokens`
+    pub fn xdai_to_dai_bridge_to(
+        &self,
+        xdai_amount: XDai,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        self.send_conversion(
+            ConversionKind::XdaiToDai,
+            xdai_amount.wei().clone(),
+            move |salf| salf.run_xdai_to_dai_bridge(xdai_amount, recipient, timeout),
+        )
+    }
 
-        let own_address = self.own_address.clone();
-        let secret = self.secret.clone();
+    fn run_xdai_to_dai_bridge(
+        &self,
+        xdai_amount: XDai,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        let xdai_amount = xdai_amount.into_wei();
+        let payload = if recipient == self.own_address {
+            Vec::new()
+        } else {
+            encoding::home_bridge_relay_tokens(recipient)
+        };
+        let xdai_home_bridge_address = self.xdai_home_bridge_address.clone();
+        let salf = self.clone();
 
         // You basically just send it some coins
-        Box::new(xdai_web3.send_transaction(
-            xdai_home_bridge_address,
-            Vec::new(),
-            xdai_amount,
-            own_address,
-            secret,
-            vec![
-                SendTxOption::GasPrice(10_000_000_000u128.into()),
-                SendTxOption::NetworkId(100u64),
-            ],
-        ))
+        Box::new(
+            self.bridge_preflight(BridgeDirection::XdaiToDai)
+                .and_then({
+                    let salf = self.clone();
+                    move |_| {
+                        salf.get_bridge_fee(BridgeDirection::XdaiToDai)
+                            .join(salf.get_bridge_minimum(BridgeDirection::XdaiToDai))
+                    }
+                })
+                .and_then({
+                    let xdai_amount = xdai_amount.clone();
+                    move |(fee, min)| {
+                        if xdai_amount < min {
+                            return Err(TokenBridgeError::BelowBridgeMinimum { min }.into());
+                        }
+                        Ok(fee)
+                    }
+                })
+                .and_then(move |fee| {
+                    salf.send_transaction(
+                        Chain::Xdai,
+                        xdai_home_bridge_address,
+                        payload,
+                        xdai_amount.clone(),
+                        vec![],
+                    )
+                    .and_then(move |tx_hash| {
+                        metrics::bridge_deposit(BridgeDirection::XdaiToDai);
+                        salf.bridge_latencies
+                            .sent(BridgeDirection::XdaiToDai, tx_hash.clone());
+                        let confirmed = salf.confirm_transaction(
+                            Chain::Xdai,
+                            tx_hash.clone(),
+                            xdai_home_bridge_address,
+                            HOME_BRIDGE_USER_REQUEST_FOR_SIGNATURE,
+                            "value",
+                        );
+                        salf.wait_or_reconcile(
+                            Chain::Xdai,
+                            tx_hash.clone(),
+                            confirmed,
+                            Duration::from_secs(timeout),
+                            xdai_home_bridge_address,
+                            HOME_BRIDGE_USER_REQUEST_FOR_SIGNATURE,
+                            "value",
+                        )
+                        .map(move |_| BridgeTransfer {
+                            tx_hash,
+                            expected_fee: bridge_fee_amount(xdai_amount.clone(), fee),
+                            amount: xdai_amount,
+                        })
+                    })
+                }),
+        )
     }
 
-    pub fn get_dai_balance(
-        &self,
-        address: Address,
-    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    pub fn get_dai_balance(&self, address: Address) -> Box<dyn Future<Item = Dai, Error = Error>> {
         let web3 = self.eth_web3.clone();
         let dai_address = self.foreign_dai_contract_address;
         let own_address = self.own_address;
-        Box::new(
-            web3.contract_call(
-                dai_address,
-                "balanceOf(address)",
-                &[address.into()],
-                own_address,
+        let balance = self.with_retry(Chain::Eth, "balanceOf(address)", move || {
+            Box::new(
+                web3.contract_call(
+                    dai_address,
+                    "balanceOf(address)",
+                    &[address.into()],
+                    own_address,
+                )
+                .and_then(|balance| decode_output("balanceOf(address)", &balance)),
             )
-            .and_then(|balance| {
-                Ok(Uint256::from_bytes_be(match balance.get(0..32) {
-                    Some(val) => val,
-                    None => bail!(
-                        "Got bad output for DAI balance from the full node {:?}",
-                        balance
-                    ),
-                }))
-            }),
-        )
+        });
+        Box::new(balance.map(Dai::from_wei))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use actix;
+    use crate::contracts::{
+        MAINNET_SAI, MAINNET_UNISWAP_SAI_EXCHANGE, MAINNET_XDAI_FOREIGN_BRIDGE,
+        MAINNET_XDAI_HOME_BRIDGE,
+    };
+    use crate::mock::{mock_bridge, word, MockBlockReader};
+    use futures::Stream;
+    use proptest::prelude::*;
     use std::str::FromStr;
 
     fn new_token_bridge() -> TokenBridge {
@@ -427,10 +1490,10 @@ mod tests {
         .unwrap();
 
         TokenBridge::new(
-            Address::from_str("0x09cabEC1eAd1c0Ba254B09efb3EE13841712bE14".into()).unwrap(),
-            Address::from_str("0x7301CFA0e1756B71869E93d4e4Dca5c7d0eb0AA6".into()).unwrap(),
-            Address::from_str("0x4aa42145Aa6Ebf72e164C9bBC74fbD3788045016".into()).unwrap(),
-            Address::from_str("0x89d24A6b4CcB1B6fAA2625fE562bDD9a23260359".into()).unwrap(),
+            Address::from_str(MAINNET_UNISWAP_SAI_EXCHANGE).unwrap(),
+            Address::from_str(MAINNET_XDAI_HOME_BRIDGE).unwrap(),
+            Address::from_str(MAINNET_XDAI_FOREIGN_BRIDGE).unwrap(),
+            Address::from_str(MAINNET_SAI).unwrap(),
             Address::from_str("0x79AE13432950bF5CDC3499f8d4Cf5963c3F0d42c".into()).unwrap(),
             pk,
             "https://eth.althea.org".into(),
@@ -438,135 +1501,95 @@ mod tests {
         )
     }
 
-    fn eth_to_wei(eth: f64) -> Uint256 {
-        let wei = (eth * 1000000000000000000f64) as u64;
-        wei.into()
-    }
-
     #[test]
-    fn test_is_approved() {
-        let pk = PrivateKey::from_str(&format!(
-            "FE1FC0A7A29503BAF72274A{}601D67309E8F3{}D22",
-            "AA3ECDE6DB3E20", "29F7AB4BA52"
-        ))
-        .unwrap();
-
-        let system = actix::System::new("test");
-
-        let token_bridge = new_token_bridge();
-
-        let unapproved_token_bridge = TokenBridge::new(
-            Address::from_str("0x09cabEC1eAd1c0Ba254B09efb3EE13841712bE14".into()).unwrap(),
-            Address::from_str("0x7301CFA0e1756B71869E93d4e4Dca5c7d0eb0AA6".into()).unwrap(),
-            Address::from_str("0x4aa42145Aa6Ebf72e164C9bBC74fbD3788045016".into()).unwrap(),
-            Address::from_str("0x89d24A6b4CcB1B6fAA2625fE562bDD9a23260359".into()).unwrap(),
-            Address::from_str("0x6d943740746934b2f5D9c9E6Cb1908758A42452f".into()).unwrap(),
-            pk,
-            "https://eth.althea.org".into(),
-            "https://dai.althea.org".into(),
-        );
-
-        actix::spawn(
-            token_bridge
-                .check_if_uniswap_dai_approved()
-                .and_then(move |is_approved| {
-                    assert!(is_approved);
-                    unapproved_token_bridge
-                        .check_if_uniswap_dai_approved()
-                        .and_then(move |is_approved| {
-                            assert!(!is_approved);
-                            Ok(())
-                        })
+    fn test_shared_across_threads() {
+        let bridge = Arc::new(new_token_bridge());
+        let events = bridge.progress_events();
+        let threads: Vec<_> = (0..4u32)
+            .map(|attempt| {
+                let bridge = bridge.clone();
+                std::thread::spawn(move || {
+                    bridge.emit(BridgeEvent::Retrying {
+                        attempt,
+                        reason: String::new(),
+                    })
                 })
-                .then(|res| {
-                    res.unwrap();
-                    actix::System::current().stop();
-                    Box::new(futures::future::ok(()))
-                }),
-        );
-        system.run();
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(events.take(4).collect().wait().unwrap().len(), 4);
     }
 
     #[test]
-    fn test_eth_to_dai_swap() {
-        let system = actix::System::new("test");
-
-        let token_bridge = new_token_bridge();
-
-        actix::spawn(
-            token_bridge
-                .dai_to_eth_price(eth_to_wei(0.01f64))
-                .and_then(move |one_cent_in_eth| {
-                    token_bridge.eth_to_dai_swap(one_cent_in_eth.clone(), 600)
-                })
-                .then(|res| {
-                    res.unwrap();
-                    actix::System::current().stop();
-                    Box::new(futures::future::ok(()))
-                }),
-        );
-
-        system.run();
+    fn test_minimum_output() {
+        assert_eq!(minimum_output(40u32.into(), 250), 39u32.into());
+        assert_eq!(minimum_output(40u32.into(), 0), 40u32.into());
+        assert_eq!(minimum_output(40u32.into(), 20_000), 0u32.into());
     }
 
-    #[test]
-    fn test_dai_to_eth_swap() {
-        let system = actix::System::new("test");
-        let token_bridge = new_token_bridge();
-
-        actix::spawn(
-            token_bridge
-                .approve_uniswap_dai_transfers(Duration::from_secs(600))
-                .and_then(move |_| token_bridge.dai_to_eth_swap(eth_to_wei(0.01f64), 600))
-                .then(|res| {
-                    res.unwrap();
-                    actix::System::current().stop();
-                    Box::new(futures::future::ok(()))
-                }),
-        );
+    proptest! {
+        #[test]
+        fn prop_minimum_output_rounds_down(amount: u128, slippage_bps in 0u32..=10_000) {
+            let kept = u128::from(10_000 - slippage_bps);
+            let exact = Uint256::from(amount) * kept.into() / 10_000u32.into();
+            prop_assert_eq!(minimum_output(amount.into(), slippage_bps), exact);
+        }
 
-        system.run();
+        #[test]
+        fn prop_minimum_output_bounded(bytes: [u8; 32], low in 0u32..20_000, high in 0u32..20_000) {
+            let amount = Uint256::from_bytes_be(&bytes);
+            let (low, high) = (low.min(high), low.max(high));
+            let most = minimum_output(amount.clone(), low);
+            prop_assert!(most <= amount);
+            prop_assert!(minimum_output(amount, high) <= most);
+        }
     }
 
     #[test]
-    fn test_dai_to_xdai_bridge() {
-        let system = actix::System::new("test");
-
-        let token_bridge = new_token_bridge();
-
-        actix::spawn(
-            token_bridge
-                // All we can really do here is test that it doesn't throw. Check your balances in
-                // 5-10 minutes to see if the money got transferred.
-                .dai_to_xdai_bridge(eth_to_wei(0.01f64), 600)
-                .then(|res| {
-                    res.unwrap();
-                    actix::System::current().stop();
-                    Box::new(futures::future::ok(()))
-                }),
-        );
+    fn test_mock_reads() {
+        let reader = Arc::new(MockBlockReader::default());
+        let bridge = mock_bridge(reader.clone());
+        let block = BlockTag::Number(100u32.into());
+        let one_eth: Uint256 = 1_000_000_000_000_000_000u64.into();
 
-        system.run();
-    }
-
-    #[test]
-    fn test_xdai_to_dai_bridge() {
-        let system = actix::System::new("test");
-
-        let token_bridge = new_token_bridge();
-
-        actix::spawn(
-            token_bridge
-                // All we can really do here is test that it doesn't throw. Check your balances in
-                // 5-10 minutes to see if the money got transferred.
-                .xdai_to_dai_bridge(eth_to_wei(0.01f64))
-                .then(|res| {
-                    res.unwrap();
-                    actix::System::current().stop();
-                    Box::new(futures::future::ok(()))
-                }),
+        reader.set_balance(Chain::Eth, bridge.own_address, one_eth.clone());
+        reader.set_call(
+            Chain::Eth,
+            bridge.foreign_dai_contract_address,
+            "balanceOf(address)",
+            &[bridge.own_address.into()],
+            word(5u32.into()),
+        );
+        reader.set_call(
+            Chain::Eth,
+            bridge.uniswap_address,
+            "getEthToTokenInputPrice(uint256)",
+            &[one_eth.clone().into()],
+            word(200u32.into()),
         );
 
-        system.run();
+        assert_eq!(
+            bridge
+                .get_eth_balance_at(bridge.own_address, block.clone())
+                .wait()
+                .unwrap(),
+            Eth::from_wei(one_eth.clone())
+        );
+        assert_eq!(
+            bridge
+                .get_dai_balance_at(bridge.own_address, block.clone())
+                .wait()
+                .unwrap(),
+            Dai::from_wei(5u32.into())
+        );
+        assert_eq!(
+            bridge
+                .eth_to_dai_price_at(Eth::from_wei(one_eth), block)
+                .wait()
+                .unwrap(),
+            Dai::from_wei(200u32.into())
+        );
     }
 }
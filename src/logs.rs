@@ -0,0 +1,392 @@
+//! ABI decoding of event logs against a definition of the event, rather than picking bytes out
+//! of the topics by position. Swap events are defined per `SwapBackend` since exchanges differ
+//! in which parameters they index.
+
+use crate::abi::decode_address;
+use clarity::abi::derive_signature;
+use clarity::Address;
+use failure::Error;
+use failure::Fail;
+use num256::Uint256;
+use web30::types::Log;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    Uint256,
+    Address,
+    Bytes32,
+}
+
+/// One parameter of an event, in declaration order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventParam {
+    pub name: &'static str,
+    pub kind: ParamType,
+    /// Indexed parameters are in the topics, the rest in the data
+    pub indexed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventDefinition {
+    /// The canonical signature, as used by `wait_for_event`
+    pub signature: &'static str,
+    pub params: &'static [EventParam],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventValue {
+    Uint256(Uint256),
+    Address(Address),
+    Bytes32([u8; 32]),
+}
+
+/// Why a log could not be decoded
+#[derive(Debug, Fail)]
+pub enum LogDecodeError {
+    #[fail(display = "Log is not a {} event", event)]
+    WrongEvent { event: &'static str },
+    #[fail(display = "{} log has {} topics, expected {}", event, found, expected)]
+    TopicCount {
+        event: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    #[fail(
+        display = "{} log has {} bytes of data, expected {}",
+        event, found, expected
+    )]
+    DataLength {
+        event: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    #[fail(display = "{} parameter {} is not a valid value", event, param)]
+    InvalidValue {
+        event: &'static str,
+        param: &'static str,
+    },
+    #[fail(display = "{} has no {} parameter of that type", event, param)]
+    NoSuchParam {
+        event: &'static str,
+        param: &'static str,
+    },
+}
+
+/// The parameters of a decoded log by name
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedEvent {
+    pub event: &'static str,
+    pub values: Vec<(&'static str, EventValue)>,
+}
+
+impl DecodedEvent {
+    pub fn uint(&self, name: &'static str) -> Result<Uint256, LogDecodeError> {
+        match self.values.iter().find(|(param, _)| *param == name) {
+            Some((_, EventValue::Uint256(value))) => Ok(value.clone()),
+            _ => Err(LogDecodeError::NoSuchParam {
+                event: self.event,
+                param: name,
+            }),
+        }
+    }
+
+    pub fn address(&self, name: &'static str) -> Result<Address, LogDecodeError> {
+        match self.values.iter().find(|(param, _)| *param == name) {
+            Some((_, EventValue::Address(value))) => Ok(*value),
+            _ => Err(LogDecodeError::NoSuchParam {
+                event: self.event,
+                param: name,
+            }),
+        }
+    }
+
+    pub fn bytes32(&self, name: &'static str) -> Result<[u8; 32], LogDecodeError> {
+        match self.values.iter().find(|(param, _)| *param == name) {
+            Some((_, EventValue::Bytes32(value))) => Ok(*value),
+            _ => Err(LogDecodeError::NoSuchParam {
+                event: self.event,
+                param: name,
+            }),
+        }
+    }
+}
+
+impl EventDefinition {
+    /// The first topic of logs of this event
+    pub fn topic0(&self) -> [u8; 32] {
+        derive_signature(self.signature)
+    }
+
+    pub fn decode(&self, log: &Log) -> Result<DecodedEvent, Error> {
+        let topics: Vec<&[u8]> = log.topics.iter().map(|topic| &topic[..]).collect();
+        Ok(self.decode_raw(&topics, &log.data)?)
+    }
+
+    /// Decodes a log given as its topics and data
+    pub fn decode_raw(
+        &self,
+        topics: &[&[u8]],
+        data: &[u8],
+    ) -> Result<DecodedEvent, LogDecodeError> {
+        let event = self.signature;
+        let indexed = self.params.iter().filter(|param| param.indexed).count();
+        if topics.first().copied() != Some(&self.topic0()[..]) {
+            return Err(LogDecodeError::WrongEvent { event });
+        }
+        if topics.len() != indexed + 1 {
+            return Err(LogDecodeError::TopicCount {
+                event,
+                expected: indexed + 1,
+                found: topics.len(),
+            });
+        }
+        let data_words = self.params.len() - indexed;
+        if data.len() != data_words * 32 {
+            return Err(LogDecodeError::DataLength {
+                event,
+                expected: data_words * 32,
+                found: data.len(),
+            });
+        }
+
+        let mut topics = topics[1..].iter().cloned();
+        let mut words = data.chunks(32);
+        let mut values = Vec::with_capacity(self.params.len());
+        for param in self.params {
+            let word = if param.indexed {
+                topics.next()
+            } else {
+                words.next()
+            };
+            let value = match word {
+                Some(word) if word.len() == 32 => decode_word(param.kind, word),
+                _ => None,
+            };
+            match value {
+                Some(value) => values.push((param.name, value)),
+                None => {
+                    return Err(LogDecodeError::InvalidValue {
+                        event,
+                        param: param.name,
+                    })
+                }
+            }
+        }
+        Ok(DecodedEvent { event, values })
+    }
+}
+
+fn decode_word(kind: ParamType, word: &[u8]) -> Option<EventValue> {
+    match kind {
+        ParamType::Uint256 => Some(EventValue::Uint256(Uint256::from_bytes_be(word))),
+        ParamType::Address => decode_address(word).ok().map(EventValue::Address),
+        ParamType::Bytes32 => {
+            let mut value = [0u8; 32];
+            value.copy_from_slice(word);
+            Some(EventValue::Bytes32(value))
+        }
+    }
+}
+
+pub const ERC20_APPROVAL: EventDefinition = EventDefinition {
+    signature: "Approval(address,address,uint256)",
+    params: &[
+        EventParam {
+            name: "owner",
+            kind: ParamType::Address,
+            indexed: true,
+        },
+        EventParam {
+            name: "spender",
+            kind: ParamType::Address,
+            indexed: true,
+        },
+        EventParam {
+            name: "value",
+            kind: ParamType::Uint256,
+            indexed: false,
+        },
+    ],
+};
+
+pub const ERC20_TRANSFER: EventDefinition = EventDefinition {
+    signature: "Transfer(address,address,uint256)",
+    params: &[
+        EventParam {
+            name: "from",
+            kind: ParamType::Address,
+            indexed: true,
+        },
+        EventParam {
+            name: "to",
+            kind: ParamType::Address,
+            indexed: true,
+        },
+        EventParam {
+            name: "value",
+            kind: ParamType::Uint256,
+            indexed: false,
+        },
+    ],
+};
+
+/// A swap event and which of its parameters holds the amount received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapEvent {
+    pub definition: EventDefinition,
+    pub amount_out: &'static str,
+}
+
+pub const UNISWAP_V1_TOKEN_PURCHASE: SwapEvent = SwapEvent {
+    definition: EventDefinition {
+        signature: "TokenPurchase(address,uint256,uint256)",
+        params: &[
+            EventParam {
+                name: "buyer",
+                kind: ParamType::Address,
+                indexed: true,
+            },
+            EventParam {
+                name: "eth_sold",
+                kind: ParamType::Uint256,
+                indexed: true,
+            },
+            EventParam {
+                name: "tokens_bought",
+                kind: ParamType::Uint256,
+                indexed: true,
+            },
+        ],
+    },
+    amount_out: "tokens_bought",
+};
+
+pub const UNISWAP_V1_ETH_PURCHASE: SwapEvent = SwapEvent {
+    definition: EventDefinition {
+        signature: "EthPurchase(address,uint256,uint256)",
+        params: &[
+            EventParam {
+                name: "buyer",
+                kind: ParamType::Address,
+                indexed: true,
+            },
+            EventParam {
+                name: "tokens_sold",
+                kind: ParamType::Uint256,
+                indexed: true,
+            },
+            EventParam {
+                name: "eth_bought",
+                kind: ParamType::Uint256,
+                indexed: true,
+            },
+        ],
+    },
+    amount_out: "eth_bought",
+};
+
+/// The exchange swaps go through, which decides the shape of the events to wait for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapBackend {
+    /// The Uniswap V1 exchange at `uniswap_address`
+    UniswapV1,
+    /// A Uniswap V2 style router such as SushiSwap's, see `router::mainnet_sushiswap_router`.
+    /// Swaps go through WETH and their output is measured by the token transfers.
+    SushiSwap { router: Address },
+}
+
+impl SwapBackend {
+    /// Emitted by the exchange when ETH is sold for tokens, `None` for router backends
+    pub fn eth_to_token_event(self) -> Option<SwapEvent> {
+        match self {
+            SwapBackend::UniswapV1 => Some(UNISWAP_V1_TOKEN_PURCHASE),
+            SwapBackend::SushiSwap { .. } => None,
+        }
+    }
+
+    /// Emitted by the exchange when tokens are sold for ETH, `None` for router backends
+    pub fn token_to_eth_event(self) -> Option<SwapEvent> {
+        match self {
+            SwapBackend::UniswapV1 => Some(UNISWAP_V1_ETH_PURCHASE),
+            SwapBackend::SushiSwap { .. } => None,
+        }
+    }
+
+    /// The router swaps are sent to, `None` for Uniswap V1
+    pub fn router(self) -> Option<Address> {
+        match self {
+            SwapBackend::UniswapV1 => None,
+            SwapBackend::SushiSwap { router } => Some(router),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::address_word;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn word(value: u8) -> Vec<u8> {
+        let mut word = vec![0u8; 32];
+        word[31] = value;
+        word
+    }
+
+    #[test]
+    fn test_decode_token_purchase() {
+        let definition = UNISWAP_V1_TOKEN_PURCHASE.definition;
+        let topic0 = definition.topic0();
+        let (buyer, sold, bought) = (word(1), word(2), word(3));
+        let topics: Vec<&[u8]> = vec![&topic0, &buyer, &sold, &bought];
+
+        let decoded = definition.decode_raw(&topics, &[]).unwrap();
+        assert_eq!(decoded.uint("tokens_bought").unwrap(), 3u32.into());
+        assert_eq!(decoded.uint("eth_sold").unwrap(), 2u32.into());
+        assert!(decoded.address("buyer").is_ok());
+        assert!(decoded.uint("buyer").is_err());
+
+        // amounts in the data instead of the topics, as other exchanges do it
+        let moved = [word(2), word(3)].concat();
+        match definition.decode_raw(&topics[0..2], &moved) {
+            Err(LogDecodeError::TopicCount { found: 2, .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match UNISWAP_V1_ETH_PURCHASE.definition.decode_raw(&topics, &[]) {
+            Err(LogDecodeError::WrongEvent { .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_transfer_round_trip(from: [u8; 20], to: [u8; 20], value: [u8; 32]) {
+            let (from, to) = (Address::from_slice(&from).unwrap(), Address::from_slice(&to).unwrap());
+            let topic0 = ERC20_TRANSFER.topic0();
+            let (from_topic, to_topic) = (address_word(from), address_word(to));
+            let topics: Vec<&[u8]> = vec![&topic0, &from_topic, &to_topic];
+
+            let decoded = ERC20_TRANSFER.decode_raw(&topics, &value).unwrap();
+            prop_assert_eq!(decoded.address("from").unwrap(), from);
+            prop_assert_eq!(decoded.address("to").unwrap(), to);
+            prop_assert_eq!(decoded.uint("value").unwrap(), Uint256::from_bytes_be(&value));
+        }
+
+        #[test]
+        fn prop_decode_raw_doesnt_panic(
+            topics in vec(vec(any::<u8>(), 0..40), 0..5),
+            data in vec(any::<u8>(), 0..100),
+            swap_topic0: bool,
+        ) {
+            // a real first topic now and then so decoding gets past the event check
+            let topic0 = UNISWAP_V1_TOKEN_PURCHASE.definition.topic0();
+            let mut topics: Vec<&[u8]> = topics.iter().map(|topic| &topic[..]).collect();
+            if swap_topic0 && !topics.is_empty() {
+                topics[0] = &topic0;
+            }
+            let _ = UNISWAP_V1_TOKEN_PURCHASE.definition.decode_raw(&topics, &data);
+            let _ = ERC20_TRANSFER.decode_raw(&topics, &data);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Signed messages in the EIP-191 format wallets use for `personal_sign`, so that Althea peers
+//! can prove they control an address, such as the one funding a bridge operation, with the key
+//! the bridge already holds.
+//!
+//! The message is prefixed with `"\x19Ethereum Signed Message:\n"` and its length before being
+//! hashed, which keeps a signed message from ever being a valid transaction.
+
+use crate::withdrawal::split_signature;
+use crate::TokenBridge;
+use clarity::{Address, Signature};
+use failure::bail;
+use failure::Error;
+use futures::Future;
+use num::ToPrimitive;
+use num256::Uint256;
+use sha3::{Digest, Keccak256};
+
+const EIP191_PREFIX: &str = "\x19Ethereum Signed Message:\n";
+
+/// The hash of `message` that gets signed, as `eth_sign` and `personal_sign` compute it
+pub fn eip191_hash(message: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.input(format!("{}{}", EIP191_PREFIX, message.len()).as_bytes());
+    hasher.input(message);
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&hasher.result());
+    hash
+}
+
+/// `signature` as the 65 byte r ++ s ++ v wallets produce, with v as 27 or 28
+pub fn signature_to_bytes(signature: &Signature) -> Result<Vec<u8>, Error> {
+    let v = match signature.v.to_u8() {
+        Some(v) if v < 2 => v + 27,
+        Some(v) => v,
+        None => bail!("Invalid signature v {}", signature.v),
+    };
+    let r: [u8; 32] = signature.r.clone().into();
+    let s: [u8; 32] = signature.s.clone().into();
+    let mut bytes = Vec::with_capacity(65);
+    bytes.extend_from_slice(&r);
+    bytes.extend_from_slice(&s);
+    bytes.push(v);
+    Ok(bytes)
+}
+
+/// Whether the 65 byte r ++ s ++ v `signature` over `message` was made by `address`. Both 0/1
+/// and 27/28 are accepted for v, a signature that isn't 65 bytes long is an error.
+pub fn verify_signature(address: Address, message: &[u8], signature: &[u8]) -> Result<bool, Error> {
+    is_signed_by(address, &eip191_hash(message), signature)
+}
+
+/// Whether `signature` over `hash` was made by `address`, as `verify_signature` checks it
+pub(crate) fn is_signed_by(
+    address: Address,
+    hash: &[u8; 32],
+    signature: &[u8],
+) -> Result<bool, Error> {
+    let (v, r, s) = split_signature(signature)?;
+    let v = match v {
+        0 | 1 => v + 27,
+        27 | 28 => v,
+        _ => return Ok(false),
+    };
+    let signature = Signature::new(
+        v.into(),
+        Uint256::from_bytes_be(&r),
+        Uint256::from_bytes_be(&s),
+    );
+    Ok(match signature.recover(hash) {
+        Ok(signer) => signer == address,
+        Err(_) => false,
+    })
+}
+
+impl TokenBridge {
+    /// Signs `message` with our key, returning the 65 byte r ++ s ++ v signature that
+    /// `verify_signature` checks against `own_address`
+    pub fn sign_message(&self, message: &[u8]) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        let signer = try_future!(self.signer());
+        Box::new(
+            signer
+                .sign_message(message)
+                .and_then(|signature| signature_to_bytes(&signature)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_bridge, MockBlockReader};
+    use clarity::utils::bytes_to_hex_str;
+    use clarity::PrivateKey;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_eip191_hash() {
+        assert_eq!(
+            bytes_to_hex_str(&eip191_hash(b"Hello World")),
+            "a1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2"
+        );
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        let address = PrivateKey::from_slice(&[2u8; 32])
+            .unwrap()
+            .to_public_key()
+            .unwrap();
+        let message = b"I control this address";
+        let signature = bridge.sign_message(message).wait().unwrap();
+        assert_eq!(signature.len(), 65);
+        assert!(signature[64] == 27 || signature[64] == 28);
+
+        assert!(verify_signature(address, message, &signature).unwrap());
+        assert!(!verify_signature(address, b"Something else", &signature).unwrap());
+        let stranger = Address::from_slice(&[0x5e; 20]).unwrap();
+        assert!(!verify_signature(stranger, message, &signature).unwrap());
+        // v as 0 or 1 is just as good
+        let mut legacy = signature.clone();
+        legacy[64] -= 27;
+        assert!(verify_signature(address, message, &legacy).unwrap());
+        assert!(verify_signature(address, message, &signature[..64]).is_err());
+    }
+}
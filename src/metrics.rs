@@ -0,0 +1,145 @@
+//! Prometheus metrics for watching bridge health across many routers, only with the `metrics`
+//! feature. Everything is registered in the default registry, so `prometheus::gather()`
+//! includes it. Without the feature the functions here do nothing.
+//!
+//! * `auto_bridge_swaps_total{direction}` swaps that executed
+//! * `auto_bridge_bridge_deposits_total{direction}` transfers sent to the xDai bridge
+//! * `auto_bridge_rpc_errors_total{chain}` failed read only calls to the chain's full node,
+//!   every failed attempt counts
+//! * `auto_bridge_gas_wei_total{chain}` gas limit times gas price of every transaction sent,
+//!   which is the most they could have cost since gas used isn't looked up
+//! * `auto_bridge_conversion_seconds{direction}` time taken by `eth_to_xdai` and `xdai_to_eth`
+
+use crate::fee::BridgeDirection;
+use crate::operations::OperationKind;
+use crate::Chain;
+use num256::Uint256;
+use std::time::Instant;
+
+#[cfg(feature = "metrics")]
+mod registered {
+    use lazy_static::lazy_static;
+    use prometheus::core::Collector;
+    use prometheus::{CounterVec, HistogramOpts, HistogramVec, IntCounterVec, Opts};
+
+    fn register<C: Collector + Clone + 'static>(collector: C) -> C {
+        prometheus::register(Box::new(collector.clone())).unwrap();
+        collector
+    }
+
+    lazy_static! {
+        pub static ref SWAPS: IntCounterVec = register(
+            IntCounterVec::new(
+                Opts::new("auto_bridge_swaps_total", "Swaps that executed"),
+                &["direction"]
+            )
+            .unwrap()
+        );
+        pub static ref BRIDGE_DEPOSITS: IntCounterVec = register(
+            IntCounterVec::new(
+                Opts::new(
+                    "auto_bridge_bridge_deposits_total",
+                    "Transfers sent to the xDai bridge"
+                ),
+                &["direction"]
+            )
+            .unwrap()
+        );
+        pub static ref RPC_ERRORS: IntCounterVec = register(
+            IntCounterVec::new(
+                Opts::new(
+                    "auto_bridge_rpc_errors_total",
+                    "Failed read only calls to the full node"
+                ),
+                &["chain"]
+            )
+            .unwrap()
+        );
+        pub static ref GAS_WEI: CounterVec = register(
+            CounterVec::new(
+                Opts::new(
+                    "auto_bridge_gas_wei_total",
+                    "Gas limit times gas price of transactions sent"
+                ),
+                &["chain"]
+            )
+            .unwrap()
+        );
+        pub static ref CONVERSION_SECONDS: HistogramVec = register(
+            HistogramVec::new(
+                HistogramOpts::new(
+                    "auto_bridge_conversion_seconds",
+                    "Time taken by ETH <-> xDai conversions"
+                )
+                .buckets(vec![60.0, 300.0, 600.0, 1200.0, 1800.0, 3600.0, 7200.0]),
+                &["direction"]
+            )
+            .unwrap()
+        );
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn chain_label(chain: Chain) -> &'static str {
+    match chain {
+        Chain::Eth => "eth",
+        Chain::Xdai => "xdai",
+    }
+}
+
+/// A swap executed, `direction` is "eth_to_dai", "dai_to_eth" or "token_to_token"
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn swap_executed(direction: &'static str) {
+    #[cfg(feature = "metrics")]
+    registered::SWAPS.with_label_values(&[direction]).inc();
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn bridge_deposit(direction: BridgeDirection) {
+    #[cfg(feature = "metrics")]
+    {
+        let direction = match direction {
+            BridgeDirection::DaiToXdai => "dai_to_xdai",
+            BridgeDirection::XdaiToDai => "xdai_to_dai",
+        };
+        registered::BRIDGE_DEPOSITS
+            .with_label_values(&[direction])
+            .inc();
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn rpc_error(chain: Chain) {
+    #[cfg(feature = "metrics")]
+    registered::RPC_ERRORS
+        .with_label_values(&[chain_label(chain)])
+        .inc();
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn transaction_sent(chain: Chain, gas_price: &Uint256, gas_limit: &Uint256) {
+    #[cfg(feature = "metrics")]
+    {
+        use num::ToPrimitive;
+
+        let gas_wei = (gas_price.clone() * gas_limit.clone()).to_f64();
+        registered::GAS_WEI
+            .with_label_values(&[chain_label(chain)])
+            .inc_by(gas_wei.unwrap_or(0.0));
+    }
+}
+
+/// A conversion of `kind` that started at `started` completed
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn conversion_finished(kind: OperationKind, started: Instant) {
+    #[cfg(feature = "metrics")]
+    {
+        let direction = match kind {
+            OperationKind::EthToXdai => "eth_to_xdai",
+            OperationKind::XdaiToEth => "xdai_to_eth",
+        };
+        registered::CONVERSION_SECONDS
+            .with_label_values(&[direction])
+            .observe(started.elapsed().as_secs_f64());
+    }
+}
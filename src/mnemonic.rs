@@ -0,0 +1,157 @@
+//! Keys derived from a BIP-39 mnemonic along a BIP-32 path, so that a fleet can be provisioned
+//! from one master seed with every router getting its own deterministic account, the one at
+//! `eth_account_path(index)` as wallets such as MetaMask and Ledger Live derive them.
+//!
+//! Only English mnemonics are accepted. Anyone holding the mnemonic holds every account
+//! derived from it, it should be kept the way a private key would be.
+
+use bip39::{Language, Mnemonic, Seed};
+use clarity::{Address, PrivateKey};
+use failure::bail;
+use failure::format_err;
+use failure::Error;
+use hmac::{Hmac, Mac, NewMac};
+use secp256k1::{PublicKey, SecretKey};
+use sha2::Sha512;
+use sha3::{Digest, Keccak256};
+
+/// The BIP-44 path of Ethereum accounts, account `n` is at `m/44'/60'/0'/0/n`
+pub const ETH_DERIVATION_PATH: &str = "m/44'/60'/0'/0";
+
+/// Indexes from this one up derive hardened children, written with a trailing `'`
+const HARDENED: u32 = 1 << 31;
+
+/// The path of the `index`th Ethereum account of a mnemonic
+pub fn eth_account_path(index: u32) -> String {
+    format!("{}/{}", ETH_DERIVATION_PATH, index)
+}
+
+/// A key derived by `derive_key` and the address it signs for
+#[derive(Clone)]
+pub struct DerivedKey {
+    pub secret: PrivateKey,
+    pub address: Address,
+}
+
+/// Derives the key at `path`, such as `m/44'/60'/0'/0/0`, from the English `phrase` and the
+/// optional BIP-39 `passphrase`, which is empty for most wallets
+pub fn derive_key(phrase: &str, passphrase: &str, path: &str) -> Result<DerivedKey, Error> {
+    let indexes = parse_path(path)?;
+    let mnemonic = Mnemonic::from_phrase(phrase, Language::English)
+        .map_err(|e| format_err!("Invalid mnemonic: {}", e))?;
+    let seed = Seed::new(&mnemonic, passphrase);
+
+    let (mut key, mut chain_code) = hmac_sha512(b"Bitcoin seed", &[seed.as_bytes()])?;
+    for index in indexes {
+        let (child, child_chain_code) = derive_child(&key, &chain_code, index)?;
+        key = child;
+        chain_code = child_chain_code;
+    }
+
+    let public = PublicKey::from_secret_key(&key).serialize();
+    let hash = Keccak256::digest(&public[1..]);
+    Ok(DerivedKey {
+        secret: PrivateKey::from_slice(&key.serialize())?,
+        address: Address::from_slice(&hash[12..])?,
+    })
+}
+
+/// The child indexes along `path`, hardened ones with `HARDENED` added
+fn parse_path(path: &str) -> Result<Vec<u32>, Error> {
+    let mut parts = path.split('/');
+    if parts.next() != Some("m") {
+        bail!("Derivation path {} does not start with m", path);
+    }
+    parts
+        .map(|part| {
+            let (number, hardened) = match part.strip_suffix('\'') {
+                Some(number) => (number, true),
+                None => (part, false),
+            };
+            match number.parse::<u32>() {
+                Ok(index) if index < HARDENED => {
+                    Ok(if hardened { index + HARDENED } else { index })
+                }
+                _ => Err(format_err!(
+                    "Invalid index {} in derivation path {}",
+                    part,
+                    path
+                )),
+            }
+        })
+        .collect()
+}
+
+fn derive_child(
+    key: &SecretKey,
+    chain_code: &[u8],
+    index: u32,
+) -> Result<(SecretKey, Vec<u8>), Error> {
+    let (tweak, child_chain_code) = if index >= HARDENED {
+        hmac_sha512(chain_code, &[&[0], &key.serialize(), &index.to_be_bytes()])?
+    } else {
+        let public = PublicKey::from_secret_key(key).serialize_compressed();
+        hmac_sha512(chain_code, &[&public, &index.to_be_bytes()])?
+    };
+    let mut child = key.clone();
+    child
+        .tweak_add_assign(&tweak)
+        .map_err(|_| format_err!("Child key {} is invalid", index))?;
+    Ok((child, child_chain_code))
+}
+
+/// HMAC-SHA512 of `data` split into the key it encodes and the chain code
+fn hmac_sha512(key: &[u8], data: &[&[u8]]) -> Result<(SecretKey, Vec<u8>), Error> {
+    let mut mac = Hmac::<Sha512>::new_varkey(key).map_err(|_| format_err!("Invalid HMAC key"))?;
+    for part in data {
+        mac.update(part);
+    }
+    let output = mac.finalize().into_bytes();
+    // a key outside the curve order has a chance of about 1 in 2^127, BIP-32 skips the index
+    let key = SecretKey::parse_slice(&output[..32])
+        .map_err(|_| format_err!("Derived an invalid key, try the next index"))?;
+    Ok((key, output[32..].to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clarity::utils::bytes_to_hex_str;
+    use std::str::FromStr;
+
+    /// The mnemonic hardhat and anvil derive their test accounts from
+    const TEST_MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+    #[test]
+    fn test_derive_key() {
+        let first = derive_key(TEST_MNEMONIC, "", &eth_account_path(0)).unwrap();
+        assert_eq!(
+            bytes_to_hex_str(&first.secret.to_bytes()),
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        );
+        assert_eq!(
+            first.address,
+            Address::from_str("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266").unwrap()
+        );
+        let second = derive_key(TEST_MNEMONIC, "", "m/44'/60'/0'/0/1").unwrap();
+        assert_eq!(
+            second.address,
+            Address::from_str("0x70997970C51812dc3A010C7d01b50e0d17dc79C8").unwrap()
+        );
+        // the passphrase is part of the seed
+        let other = derive_key(TEST_MNEMONIC, "fleet", &eth_account_path(0)).unwrap();
+        assert_ne!(other.address, first.address);
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(derive_key("test test test", "", &eth_account_path(0)).is_err());
+        // right words, wrong checksum
+        let phrase = TEST_MNEMONIC.replace("junk", "test");
+        assert!(derive_key(&phrase, "", &eth_account_path(0)).is_err());
+        assert!(parse_path("44'/60'").is_err());
+        assert!(parse_path("m/44'/x").is_err());
+        assert!(parse_path("m/2147483648").is_err());
+        assert_eq!(parse_path("m/1'/2").unwrap(), vec![HARDENED + 1, 2]);
+    }
+}
@@ -0,0 +1,130 @@
+//! A `BlockReader` answering from state set up by the test, so unit tests can read balances,
+//! contract calls and receipts without a full node or real funds.
+
+use crate::block_tag::{BlockReader, BlockTag};
+use crate::contracts::{
+    MAINNET_DAI, MAINNET_UNISWAP_DAI_EXCHANGE, MAINNET_XDAI_FOREIGN_BRIDGE,
+    MAINNET_XDAI_HOME_BRIDGE,
+};
+use crate::gas::GasReceipt;
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::abi::{encode_call, Token};
+use clarity::{Address, PrivateKey};
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// The chain, contract and calldata of a mocked call
+type CallKey = (Chain, Address, Vec<u8>);
+
+/// Answers every block tag from the same state. Calls and balances that weren't set fail.
+#[derive(Debug, Default)]
+pub(crate) struct MockBlockReader {
+    calls: Mutex<HashMap<CallKey, Vec<u8>>>,
+    balances: Mutex<HashMap<(Chain, Address), Uint256>>,
+    receipts: Mutex<HashMap<(Chain, Uint256), GasReceipt>>,
+}
+
+impl MockBlockReader {
+    /// Makes calls of `signature` with `args` on `to` return `output`
+    pub fn set_call(
+        &self,
+        chain: Chain,
+        to: Address,
+        signature: &str,
+        args: &[Token],
+        output: Vec<u8>,
+    ) {
+        let data = encode_call(signature, args);
+        self.calls.lock().unwrap().insert((chain, to, data), output);
+    }
+
+    pub fn set_balance(&self, chain: Chain, address: Address, balance: Uint256) {
+        self.balances
+            .lock()
+            .unwrap()
+            .insert((chain, address), balance);
+    }
+
+    pub fn set_receipt(&self, chain: Chain, tx_hash: Uint256, receipt: GasReceipt) {
+        self.receipts
+            .lock()
+            .unwrap()
+            .insert((chain, tx_hash), receipt);
+    }
+}
+
+impl BlockReader for MockBlockReader {
+    fn eth_call_at(
+        &self,
+        chain: Chain,
+        _from: Address,
+        to: Address,
+        data: Vec<u8>,
+        _block: &BlockTag,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        let output = self.calls.lock().unwrap().get(&(chain, to, data)).cloned();
+        Box::new(futures::future::result(output.ok_or_else(|| {
+            format_err!("No mocked output for the call to {}", to)
+        })))
+    }
+
+    fn eth_get_balance_at(
+        &self,
+        chain: Chain,
+        address: Address,
+        _block: &BlockTag,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let balance = self
+            .balances
+            .lock()
+            .unwrap()
+            .get(&(chain, address))
+            .cloned();
+        Box::new(futures::future::result(
+            balance.ok_or_else(|| format_err!("No mocked balance for {}", address)),
+        ))
+    }
+
+    fn eth_get_transaction_receipt(
+        &self,
+        chain: Chain,
+        tx_hash: Uint256,
+    ) -> Box<dyn Future<Item = Option<GasReceipt>, Error = Error>> {
+        let receipt = self
+            .receipts
+            .lock()
+            .unwrap()
+            .get(&(chain, tx_hash))
+            .cloned();
+        Box::new(futures::future::ok(receipt))
+    }
+}
+
+/// A 32 byte ABI word holding `value`
+pub(crate) fn word(value: Uint256) -> Vec<u8> {
+    let word: [u8; 32] = value.into();
+    word.to_vec()
+}
+
+/// A mainnet bridge that reads through `reader`. Its full node urls don't resolve, so anything
+/// not going through `reader` fails instead of touching a real chain.
+pub(crate) fn mock_bridge(reader: Arc<MockBlockReader>) -> TokenBridge {
+    let mut bridge = TokenBridge::new(
+        Address::from_str(MAINNET_UNISWAP_DAI_EXCHANGE).unwrap(),
+        Address::from_str(MAINNET_XDAI_HOME_BRIDGE).unwrap(),
+        Address::from_str(MAINNET_XDAI_FOREIGN_BRIDGE).unwrap(),
+        Address::from_str(MAINNET_DAI).unwrap(),
+        Address::from_slice(&[1u8; 20]).unwrap(),
+        PrivateKey::from_slice(&[2u8; 32]).unwrap(),
+        "http://eth.invalid".into(),
+        "http://xdai.invalid".into(),
+    );
+    bridge.block_reader = Some(reader);
+    bridge
+}
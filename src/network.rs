@@ -0,0 +1,91 @@
+//! Named sets of contract addresses and chain ids for the environments the bridge runs in
+
+use crate::contracts::{known_contract, ContractKind, ETH_MAINNET_CHAIN_ID, XDAI_CHAIN_ID};
+use clarity::Address;
+
+/// Everything about a deployment of the xDai bridge and its Uniswap market that does not depend
+/// on who is using it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkAddresses {
+    /// Chain id of the Eth side
+    pub eth_chain_id: u64,
+    /// Chain id of the xDai side
+    pub xdai_chain_id: u64,
+    /// The Dai token contract on Eth
+    pub foreign_dai_contract_address: Address,
+    /// The xDai bridge on Eth
+    pub xdai_foreign_bridge_address: Address,
+    /// The xDai bridge on xDai
+    pub xdai_home_bridge_address: Address,
+    /// The Uniswap V1 exchange for Dai on Eth
+    pub uniswap_address: Address,
+    /// The Uniswap V1 factory on Eth
+    pub uniswap_factory_address: Address,
+    /// The WETH contract on Eth
+    pub weth_address: Address,
+}
+
+/// A contract from the registry, which has every contract the presets use
+fn registered(kind: ContractKind, chain_id: u64) -> Address {
+    known_contract(kind, chain_id)
+        .expect("preset contract missing from the registry")
+        .address()
+}
+
+/// The preset of a bridge between `eth_chain_id` and `xdai_chain_id`
+fn preset(eth_chain_id: u64, xdai_chain_id: u64) -> NetworkAddresses {
+    NetworkAddresses {
+        eth_chain_id,
+        xdai_chain_id,
+        foreign_dai_contract_address: registered(ContractKind::Dai, eth_chain_id),
+        xdai_foreign_bridge_address: registered(ContractKind::XdaiForeignBridge, eth_chain_id),
+        xdai_home_bridge_address: registered(ContractKind::XdaiHomeBridge, xdai_chain_id),
+        uniswap_address: registered(ContractKind::UniswapV1DaiExchange, eth_chain_id),
+        uniswap_factory_address: registered(ContractKind::UniswapV1Factory, eth_chain_id),
+        weth_address: registered(ContractKind::Weth, eth_chain_id),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Network {
+    /// Ethereum mainnet bridged to Gnosis Chain (xDai)
+    Mainnet,
+    /// Any other deployment, such as a testnet pair or a private fork. Testnet bridges are
+    /// redeployed as the testnets come and go, so there is no preset for them.
+    Custom(NetworkAddresses),
+}
+
+impl Network {
+    pub fn addresses(&self) -> NetworkAddresses {
+        match self {
+            Network::Mainnet => preset(ETH_MAINNET_CHAIN_ID, XDAI_CHAIN_ID),
+            Network::Custom(addresses) => addresses.clone(),
+        }
+    }
+
+    pub fn eth_chain_id(&self) -> u64 {
+        self.addresses().eth_chain_id
+    }
+
+    pub fn xdai_chain_id(&self) -> u64 {
+        self.addresses().xdai_chain_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::MAINNET_DAI;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_addresses() {
+        let mainnet = Network::Mainnet.addresses();
+        assert_eq!(mainnet.eth_chain_id, 1);
+        assert_eq!(mainnet.xdai_chain_id, 100);
+        assert_eq!(
+            mainnet.foreign_dai_contract_address,
+            Address::from_str(MAINNET_DAI).unwrap()
+        );
+    }
+}
@@ -0,0 +1,1075 @@
+//! Checkpointed ETH <-> xDai conversions that survive a restart. Every step of a conversion is
+//! saved to an `OperationStore` before it is started, `resume_pending` picks up whatever was in
+//! flight when the process stopped.
+//!
+//! The transaction a step sends its funds in is checkpointed with its nonce right after it is
+//! broadcast. After a restart the step is only sent again if that transaction reverted or its
+//! nonce was taken by another one, a transaction still waiting to be mined is waited for.
+//!
+//! Operations stored before transactions were checkpointed fall back to the balance saved
+//! right before each step, which other transfers from the same account while the conversion
+//! was interrupted can confuse.
+
+use crate::cancel::CancelToken;
+use crate::error::TimeoutOutcome;
+use crate::events::BridgeEvent;
+use crate::fee::BridgeDirection;
+use crate::instrument;
+use crate::logs::{EventDefinition, ERC20_TRANSFER};
+use crate::metrics;
+use crate::timeouts;
+use crate::units::{Dai, Eth, XDai};
+use crate::withdrawal::HOME_BRIDGE_USER_REQUEST_FOR_SIGNATURE;
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::format_err;
+use failure::Error;
+use futures::future::{loop_fn, Either, Loop};
+use futures::Future;
+use futures::Stream;
+use futures_timer::{Delay, FutureExt};
+use num256::Uint256;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often the Dai balance is checked while waiting for the bridge
+const BRIDGE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OperationKind {
+    /// ETH to Dai on Uniswap, then Dai to xDai over the bridge
+    EthToXdai,
+    /// xDai to Dai over the bridge, then Dai to ETH on Uniswap
+    XdaiToEth,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OperationStage {
+    /// Nothing has been sent yet
+    Pending,
+    /// The ETH to Dai swap may have been sent, `dai_before` is our Dai balance before it
+    SwappingEthToDai { dai_before: Uint256 },
+    /// The transfer of `dai` to the bridge may have been sent
+    BridgingDaiToXdai { dai: Uint256, dai_before: Uint256 },
+    /// The xDai transfer to the bridge may have been sent, once it has we wait for the Dai to
+    /// show up on Eth
+    BridgingXdaiToDai {
+        xdai_before: Uint256,
+        dai_before: Uint256,
+    },
+    /// The swap of `dai` to ETH may have been sent
+    SwappingDaiToEth { dai: Uint256, dai_before: Uint256 },
+    /// Done, `amount` is the xDai or ETH we ended up with if it is known
+    Complete { amount: Option<Uint256> },
+}
+
+/// The transaction a step of an operation sent its funds in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperationTx {
+    pub chain: Chain,
+    pub tx_hash: Uint256,
+    pub nonce: Uint256,
+}
+
+/// An ETH <-> xDai conversion and how far it got
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Operation {
+    pub id: String,
+    pub kind: OperationKind,
+    /// The ETH or xDai put in
+    pub amount: Uint256,
+    pub stage: OperationStage,
+    /// The key the caller started the operation with, see `eth_to_xdai_idempotent`
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// When the operation was created in seconds since the epoch, `None` for operations
+    /// stored before this was recorded
+    #[serde(default)]
+    pub started_at: Option<u64>,
+    /// The transaction the current stage sent its funds in, once it has been broadcast
+    #[serde(default)]
+    pub stage_tx: Option<OperationTx>,
+    /// Whether `stage_tx` is checkpointed, false for operations stored before it was
+    #[serde(default)]
+    pub records_stage_tx: bool,
+}
+
+impl Operation {
+    pub fn new(kind: OperationKind, amount: Uint256) -> Operation {
+        Operation {
+            id: format!("{:016x}", rand::random::<u64>()),
+            kind,
+            amount,
+            stage: OperationStage::Pending,
+            idempotency_key: None,
+            started_at: Some(now()),
+            stage_tx: None,
+            records_stage_tx: true,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        matches!(self.stage, OperationStage::Complete { .. })
+    }
+
+    /// The step the operation is at, counting from one up to `OPERATION_STEPS`, and what that
+    /// step is doing. Complete operations are past the last step.
+    pub fn step(&self) -> (u32, &'static str) {
+        match (self.kind, &self.stage) {
+            (_, OperationStage::Complete { .. }) => (OPERATION_STEPS + 1, "done"),
+            (OperationKind::EthToXdai, OperationStage::Pending) => {
+                (1, "checking the bridge before swapping")
+            }
+            (OperationKind::EthToXdai, OperationStage::SwappingEthToDai { .. }) => {
+                (2, "swapping ETH to Dai on Uniswap")
+            }
+            (OperationKind::EthToXdai, OperationStage::BridgingDaiToXdai { .. }) => {
+                (3, "sending Dai to the xDai bridge")
+            }
+            (OperationKind::XdaiToEth, OperationStage::Pending) => {
+                (1, "checking balances before bridging")
+            }
+            (OperationKind::XdaiToEth, OperationStage::BridgingXdaiToDai { .. }) => {
+                (2, "waiting for the bridge to pay out Dai on Eth")
+            }
+            (OperationKind::XdaiToEth, OperationStage::SwappingDaiToEth { .. }) => {
+                (3, "swapping Dai to ETH on Uniswap")
+            }
+            (_, _) => (0, "stuck at a stage that is not part of its kind"),
+        }
+    }
+}
+
+/// How many steps `Operation::step` counts, the same for both kinds
+pub const OPERATION_STEPS: u32 = 3;
+
+/// Where an unfinished operation is at, for showing to the user
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationStatus {
+    pub operation: Operation,
+    /// Whether this process is running it right now, otherwise it is checkpointed and waits
+    /// for `resume_pending`
+    pub running: bool,
+    /// See `Operation::step`
+    pub step: u32,
+    pub next_action: &'static str,
+    /// How long ago the operation was started, if that is known
+    pub age: Option<Duration>,
+    /// The transactions sent for the operation by this process so far, older ones are only in
+    /// the `audit_sink`
+    pub tx_hashes: Vec<Uint256>,
+}
+
+/// Somewhere to checkpoint operations so they can be resumed after a restart
+pub trait OperationStore: Send + Sync {
+    /// Inserts `operation` or replaces the stored operation with the same id
+    fn save(&self, operation: &Operation) -> Result<(), Error>;
+
+    fn load_all(&self) -> Result<Vec<Operation>, Error>;
+
+    /// Operations that have not completed yet
+    fn pending(&self) -> Result<Vec<Operation>, Error> {
+        Ok(self
+            .load_all()?
+            .into_iter()
+            .filter(|operation| !operation.is_complete())
+            .collect())
+    }
+
+    /// The operation started with the idempotency key `key`, if there is one
+    fn find_by_key(&self, key: &str) -> Result<Option<Operation>, Error> {
+        Ok(self
+            .load_all()?
+            .into_iter()
+            .find(|operation| operation.idempotency_key.as_deref() == Some(key)))
+    }
+}
+
+/// Keeps all operations in a single JSON file, rewritten on every save
+pub struct JsonFileStore {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl JsonFileStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> JsonFileStore {
+        JsonFileStore {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> Result<Vec<Operation>, Error> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let contents = fs::read_to_string(&self.path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+impl OperationStore for JsonFileStore {
+    fn save(&self, operation: &Operation) -> Result<(), Error> {
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| format_err!("Operation store lock poisoned"))?;
+        let mut operations = self.read()?;
+        match operations.iter_mut().find(|op| op.id == operation.id) {
+            Some(existing) => *existing = operation.clone(),
+            None => operations.push(operation.clone()),
+        }
+
+        // write then rename so a crash mid write can't leave a truncated file behind
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&operations)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn load_all(&self) -> Result<Vec<Operation>, Error> {
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| format_err!("Operation store lock poisoned"))?;
+        self.read()
+    }
+}
+
+impl TokenBridge {
+    /// Swaps `eth_amount` ETH to Dai and bridges it to xDai, checkpointing each step in
+    /// `operation_store` if one is set. Returns the xDai amount bridged.
+    pub fn eth_to_xdai(
+        &self,
+        eth_amount: Eth,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        self.eth_to_xdai_cancellable(eth_amount, timeout, &CancelToken::new())
+    }
+
+    /// `eth_to_xdai` stopping early with `TokenBridgeError::Cancelled` once `cancel` is
+    /// cancelled
+    pub fn eth_to_xdai_cancellable(
+        &self,
+        eth_amount: Eth,
+        timeout: u64,
+        cancel: &CancelToken,
+    ) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        let operation = Operation::new(OperationKind::EthToXdai, eth_amount.into_wei());
+        let started = Instant::now();
+        Box::new(
+            self.run_operation(operation, timeout, cancel.clone())
+                .inspect(move |operation| metrics::conversion_finished(operation.kind, started))
+                .and_then(|operation| operation_output(&operation))
+                .map(XDai::from_wei),
+        )
+    }
+
+    /// Bridges `xdai_amount` xDai to Dai, waits for it to arrive and swaps it to ETH,
+    /// checkpointing each step in `operation_store` if one is set. Returns the ETH received.
+    pub fn xdai_to_eth(
+        &self,
+        xdai_amount: XDai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        self.xdai_to_eth_cancellable(xdai_amount, timeout, &CancelToken::new())
+    }
+
+    /// `xdai_to_eth` stopping early with `TokenBridgeError::Cancelled` once `cancel` is
+    /// cancelled
+    pub fn xdai_to_eth_cancellable(
+        &self,
+        xdai_amount: XDai,
+        timeout: u64,
+        cancel: &CancelToken,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        let operation = Operation::new(OperationKind::XdaiToEth, xdai_amount.into_wei());
+        let started = Instant::now();
+        Box::new(
+            self.run_operation(operation, timeout, cancel.clone())
+                .inspect(move |operation| metrics::conversion_finished(operation.kind, started))
+                .and_then(|operation| operation_output(&operation))
+                .map(Eth::from_wei),
+        )
+    }
+
+    /// `eth_to_xdai` that converts at most once per `key`, for callers that may retry after a
+    /// crash. If `operation_store` already holds an operation started with `key` it is returned
+    /// as stored, at whatever stage it reached, instead of converting again. Interrupted
+    /// operations are finished by `resume_pending`. Otherwise a new conversion is run and
+    /// returned once complete. Needs an `operation_store`.
+    pub fn eth_to_xdai_idempotent(
+        &self,
+        eth_amount: Eth,
+        timeout: u64,
+        key: &str,
+    ) -> Box<dyn Future<Item = Operation, Error = Error>> {
+        self.run_idempotent(
+            OperationKind::EthToXdai,
+            eth_amount.into_wei(),
+            timeout,
+            key,
+        )
+    }
+
+    /// `xdai_to_eth` that converts at most once per `key`, see `eth_to_xdai_idempotent`
+    pub fn xdai_to_eth_idempotent(
+        &self,
+        xdai_amount: XDai,
+        timeout: u64,
+        key: &str,
+    ) -> Box<dyn Future<Item = Operation, Error = Error>> {
+        self.run_idempotent(
+            OperationKind::XdaiToEth,
+            xdai_amount.into_wei(),
+            timeout,
+            key,
+        )
+    }
+
+    fn run_idempotent(
+        &self,
+        kind: OperationKind,
+        amount: Uint256,
+        timeout: u64,
+        key: &str,
+    ) -> Box<dyn Future<Item = Operation, Error = Error>> {
+        let store = match self.operation_store {
+            Some(ref store) => store.clone(),
+            None => {
+                return Box::new(futures::future::err(format_err!(
+                    "Idempotency keys need an operation store"
+                )))
+            }
+        };
+        if let Some(existing) = try_future!(store.find_by_key(key)) {
+            if existing.kind != kind || existing.amount != amount {
+                return Box::new(futures::future::err(format_err!(
+                    "Idempotency key {} was used for a different operation {:?}",
+                    key,
+                    existing
+                )));
+            }
+            info!(
+                "Operation {} with key {} exists at {:?}, not converting again",
+                existing.id, key, existing.stage
+            );
+            return Box::new(futures::future::ok(existing));
+        }
+
+        let operation = Operation {
+            idempotency_key: Some(key.to_string()),
+            ..Operation::new(kind, amount)
+        };
+        let started = Instant::now();
+        Box::new(
+            self.run_operation(operation, timeout, CancelToken::new())
+                .inspect(move |operation| metrics::conversion_finished(operation.kind, started)),
+        )
+    }
+
+    /// Finishes every operation in `operation_store` that was interrupted before completing,
+    /// one at a time. Returns the completed operations.
+    pub fn resume_pending(
+        &self,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Vec<Operation>, Error = Error>> {
+        self.resume_pending_cancellable(timeout, &CancelToken::new())
+    }
+
+    /// `resume_pending` stopping early with `TokenBridgeError::Cancelled` once `cancel` is
+    /// cancelled. The operations not reached yet stay pending.
+    pub fn resume_pending_cancellable(
+        &self,
+        timeout: u64,
+        cancel: &CancelToken,
+    ) -> Box<dyn Future<Item = Vec<Operation>, Error = Error>> {
+        let store = match self.operation_store {
+            Some(ref store) => store.clone(),
+            None => return Box::new(futures::future::err(format_err!("No operation store set"))),
+        };
+        let pending = try_future!(store.pending());
+        info!("Resuming {} pending operations", pending.len());
+        let salf = self.clone();
+        let cancel = cancel.clone();
+
+        Box::new(
+            futures::stream::iter_ok(pending)
+                .and_then(move |operation| salf.run_operation(operation, timeout, cancel.clone()))
+                .collect(),
+        )
+    }
+
+    /// Every conversion that is running or checkpointed without having completed, running ones
+    /// first. Conversions that haven't sent anything yet are only listed while running.
+    pub fn pending_operations(&self) -> Result<Vec<OperationStatus>, Error> {
+        let mut operations: Vec<(Operation, bool)> = self
+            .running_operations
+            .lock()
+            .unwrap()
+            .values()
+            .map(|operation| (operation.clone(), true))
+            .collect();
+        operations.sort_by_key(|(operation, _)| operation.started_at);
+        if let Some(ref store) = self.operation_store {
+            for operation in store.pending()? {
+                if !operations
+                    .iter()
+                    .any(|(running, _)| running.id == operation.id)
+                {
+                    operations.push((operation, false));
+                }
+            }
+        }
+
+        let now = now();
+        Ok(operations
+            .into_iter()
+            .map(|(operation, running)| {
+                let (step, next_action) = operation.step();
+                OperationStatus {
+                    running,
+                    step,
+                    next_action,
+                    age: operation
+                        .started_at
+                        .map(|started_at| Duration::from_secs(now.saturating_sub(started_at))),
+                    tx_hashes: self
+                        .operation_gas_usage(&operation.id)
+                        .into_iter()
+                        .map(|usage| usage.tx_hash)
+                        .collect(),
+                    operation,
+                }
+            })
+            .collect())
+    }
+
+    fn checkpoint(&self, operation: &Operation) -> Result<(), Error> {
+        trace!("operation {} is now {:?}", operation.id, operation.stage);
+        instrument::stage_changed(operation);
+        if let Some(running) = self
+            .running_operations
+            .lock()
+            .unwrap()
+            .get_mut(&operation.id)
+        {
+            *running = operation.clone();
+        }
+        match self.operation_store {
+            Some(ref store) => store.save(operation),
+            None => Ok(()),
+        }
+    }
+
+    /// Checkpoints `tx_hash` as the transaction the stage of the operation this bridge runs
+    /// for sent its funds in, see `for_operation`. Called right after the broadcast, failing to
+    /// save is only logged as the transaction is out already.
+    pub(crate) fn checkpoint_stage_tx(&self, chain: Chain, tx_hash: Uint256, nonce: Uint256) {
+        let id = match self.operation_id {
+            Some(ref id) => id,
+            None => return,
+        };
+        let operation = match self.running_operations.lock().unwrap().get_mut(id) {
+            Some(operation) => {
+                operation.stage_tx = Some(OperationTx {
+                    chain,
+                    tx_hash,
+                    nonce,
+                });
+                operation.clone()
+            }
+            None => return,
+        };
+        trace!("operation {} sent {:?}", id, operation.stage_tx);
+        if let Some(ref store) = self.operation_store {
+            if let Err(e) = store.save(&operation) {
+                error!(
+                    "Failed to checkpoint the transaction of operation {}: {:?}",
+                    id, e
+                );
+            }
+        }
+    }
+
+    /// Whether the stage `operation` is at sent its funds before a restart, and the amount
+    /// `event` from `contract` reports if it did. A checkpointed transaction still waiting to
+    /// be mined is waited for up to `timeout` seconds. Operations stored before transactions
+    /// were checkpointed go by `by_balance`, what our balance says.
+    fn stage_sent(
+        &self,
+        operation: &Operation,
+        contract: Address,
+        event: EventDefinition,
+        timeout: u64,
+        by_balance: Option<Uint256>,
+    ) -> Box<dyn Future<Item = Option<Uint256>, Error = Error>> {
+        if !operation.records_stage_tx {
+            return Box::new(futures::future::ok(by_balance));
+        }
+        let OperationTx {
+            chain,
+            tx_hash,
+            nonce,
+        } = match operation.stage_tx {
+            Some(ref tx) => tx.clone(),
+            None => return Box::new(futures::future::ok(None)),
+        };
+        let salf = self.clone();
+
+        // the nonce is looked up first, if it is taken and our transaction still isn't mined
+        // after that another one took its place
+        Box::new(
+            self.web3(chain)
+                .eth_get_transaction_count(self.own_address)
+                .and_then(move |count| {
+                    salf.reconcile_transaction(chain, tx_hash.clone(), contract, event, "value")
+                        .and_then(move |outcome| match outcome {
+                            TimeoutOutcome::ExecutedButLate(amount) => {
+                                Box::new(futures::future::ok(Some(amount)))
+                                    as Box<dyn Future<Item = _, Error = Error>>
+                            }
+                            TimeoutOutcome::DefinitelyNotExecuted => {
+                                info!("Transaction {} of the operation reverted", tx_hash);
+                                Box::new(futures::future::ok(None))
+                            }
+                            TimeoutOutcome::Unknown(_) if count > nonce => {
+                                info!("Transaction {} of the operation was replaced", tx_hash);
+                                Box::new(futures::future::ok(None))
+                            }
+                            TimeoutOutcome::Unknown(_) => Box::new(
+                                salf.confirm_transaction(chain, tx_hash, contract, event, "value")
+                                    .timeout(Duration::from_secs(timeout))
+                                    .then(|res| match res {
+                                        Ok(amount) => Ok(Some(amount)),
+                                        Err(e) => match e.downcast_ref() {
+                                            Some(TokenBridgeError::TransactionReverted {
+                                                ..
+                                            }) => Ok(None),
+                                            _ => Err(e),
+                                        },
+                                    }),
+                            ),
+                        })
+                }),
+        )
+    }
+
+    /// Runs `operation` from its current stage until it is complete within
+    /// `timeouts.end_to_end`, checkpointing every stage it moves to. Once `cancel` is cancelled
+    /// the step in progress is dropped and the operation is returned in a
+    /// `TokenBridgeError::Cancelled` at the stage it had reached.
+    fn run_operation(
+        &self,
+        operation: Operation,
+        timeout: u64,
+        cancel: CancelToken,
+    ) -> Box<dyn Future<Item = Operation, Error = Error>> {
+        let salf = self.clone();
+        let end_to_end = self.timeouts.end_to_end;
+        let (kind, amount) = (operation.kind.into(), operation.amount.clone());
+        let running = RunningOperation::start(self, &operation);
+        self.guard_conversion(kind, amount.clone(), move || {
+            // an operation past `Pending` was confirmed when it was started
+            let confirmed = match operation.stage {
+                OperationStage::Pending => salf.confirm_large_transfer(kind, amount),
+                _ => Box::new(futures::future::ok(salf.with_large_transfer_confirmed())),
+            };
+            let run = confirmed.and_then(move |salf| {
+                let run = loop_fn(operation, move |mut operation| {
+                    if operation.is_complete() {
+                        return Box::new(futures::future::ok(Loop::Break(operation)))
+                            as Box<dyn Future<Item = _, Error = Error>>;
+                    }
+                    if cancel.is_cancelled() {
+                        return Box::new(futures::future::err(TokenBridge::cancelled(operation)));
+                    }
+                    let salf = salf.for_operation(&operation.id);
+                    Box::new(
+                        salf.next_stage(&operation, timeout)
+                            .select2(cancel.cancelled())
+                            .map_err(|e| e.split().0)
+                            .and_then(move |step| match step {
+                                Either::A((stage, _)) => {
+                                    operation.stage = stage;
+                                    // stages from here on checkpoint their transaction, even
+                                    // for an operation stored before that was done
+                                    operation.stage_tx = None;
+                                    operation.records_stage_tx = true;
+                                    salf.checkpoint(&operation)?;
+                                    Ok(Loop::Continue(operation))
+                                }
+                                Either::B(_) => Err(TokenBridge::cancelled(operation)),
+                            }),
+                    )
+                });
+                timeouts::limit("The conversion", end_to_end, Box::new(run))
+            });
+            Box::new(run.then(move |result| {
+                drop(running);
+                result
+            }))
+        })
+    }
+
+    /// The error for `operation` cancelled at its current stage. Every stage past `Pending` is
+    /// checkpointed already, so `resume_pending` can finish what was started, while an
+    /// operation that sent nothing yet is never stored and stays cancelled.
+    fn cancelled(operation: Operation) -> Error {
+        info!(
+            "Operation {} cancelled at {:?}",
+            operation.id, operation.stage
+        );
+        TokenBridgeError::Cancelled { operation }.into()
+    }
+
+    /// Performs the step `operation` is at and returns the stage after it. Steps that send
+    /// funds first check whether they already went out before a restart, see `stage_sent`.
+    fn next_stage(
+        &self,
+        operation: &Operation,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = OperationStage, Error = Error>> {
+        let salf = self.clone();
+        let own_address = self.own_address;
+        let amount = operation.amount.clone();
+        let dai_address = self.foreign_dai_contract_address;
+        let home_bridge = self.xdai_home_bridge_address;
+        let checked = self.clone();
+        let operation_sent = operation.clone();
+
+        match (operation.kind, operation.stage.clone()) {
+            (_, OperationStage::Complete { amount }) => {
+                Box::new(futures::future::ok(OperationStage::Complete { amount }))
+            }
+            // checking the bridge first avoids swapping into Dai that can't be bridged
+            (OperationKind::EthToXdai, OperationStage::Pending) => Box::new(
+                self.bridge_preflight(BridgeDirection::DaiToXdai)
+                    .and_then(move |_| salf.get_dai_balance(own_address).map(Dai::into_wei))
+                    .map(|dai_before| OperationStage::SwappingEthToDai { dai_before }),
+            ),
+            (OperationKind::EthToXdai, OperationStage::SwappingEthToDai { dai_before }) => {
+                Box::new(
+                    self.get_dai_balance(own_address)
+                        .map(Dai::into_wei)
+                        .and_then(move |balance| {
+                            let by_balance = if balance > dai_before {
+                                Some(balance - dai_before)
+                            } else {
+                                None
+                            };
+                            checked.stage_sent(
+                                &operation_sent,
+                                dai_address,
+                                ERC20_TRANSFER,
+                                timeout,
+                                by_balance,
+                            )
+                        })
+                        .and_then(move |sent| {
+                            let swapped: Box<dyn Future<Item = Uint256, Error = Error>> = match sent
+                            {
+                                Some(dai) => {
+                                    info!("ETH to Dai swap already done, resuming");
+                                    Box::new(futures::future::ok(dai))
+                                }
+                                None => Box::new(
+                                    salf.eth_to_dai_swap(Eth::from_wei(amount), timeout)
+                                        .map(Dai::into_wei),
+                                ),
+                            };
+                            swapped.and_then(move |dai| {
+                                salf.get_dai_balance(own_address).map(Dai::into_wei).map(
+                                    move |dai_before| OperationStage::BridgingDaiToXdai {
+                                        dai,
+                                        dai_before,
+                                    },
+                                )
+                            })
+                        }),
+                )
+            }
+            (OperationKind::EthToXdai, OperationStage::BridgingDaiToXdai { dai, dai_before }) => {
+                Box::new(
+                    self.get_dai_balance(own_address)
+                        .map(Dai::into_wei)
+                        .and_then({
+                            let dai = dai.clone();
+                            move |balance| {
+                                let by_balance = if balance + dai.clone() <= dai_before {
+                                    Some(dai)
+                                } else {
+                                    None
+                                };
+                                checked.stage_sent(
+                                    &operation_sent,
+                                    dai_address,
+                                    ERC20_TRANSFER,
+                                    timeout,
+                                    by_balance,
+                                )
+                            }
+                        })
+                        .and_then(move |sent| {
+                            if sent.is_some() {
+                                info!("Dai to xDai bridge transfer already sent, resuming");
+                                // the fee charged is unknown here, report the amount sent
+                                Box::new(futures::future::ok(OperationStage::Complete {
+                                    amount: Some(dai),
+                                }))
+                                    as Box<dyn Future<Item = _, Error = Error>>
+                            } else {
+                                Box::new(salf.dai_to_xdai_bridge(Dai::from_wei(dai), timeout).map(
+                                    |transfer| OperationStage::Complete {
+                                        amount: Some(transfer.expected_net_amount()),
+                                    },
+                                ))
+                            }
+                        }),
+                )
+            }
+            (OperationKind::XdaiToEth, OperationStage::Pending) => Box::new(
+                self.xdai_web3
+                    .eth_get_balance(own_address)
+                    .join(self.get_dai_balance(own_address).map(Dai::into_wei))
+                    .map(
+                        |(xdai_before, dai_before)| OperationStage::BridgingXdaiToDai {
+                            xdai_before,
+                            dai_before,
+                        },
+                    ),
+            ),
+            (
+                OperationKind::XdaiToEth,
+                OperationStage::BridgingXdaiToDai {
+                    xdai_before,
+                    dai_before,
+                },
+            ) => Box::new(
+                self.xdai_web3
+                    .eth_get_balance(own_address)
+                    .and_then({
+                        let amount = amount.clone();
+                        move |balance| {
+                            let by_balance = if balance + amount.clone() <= xdai_before {
+                                Some(amount)
+                            } else {
+                                None
+                            };
+                            checked.stage_sent(
+                                &operation_sent,
+                                home_bridge,
+                                HOME_BRIDGE_USER_REQUEST_FOR_SIGNATURE,
+                                timeout,
+                                by_balance,
+                            )
+                        }
+                    })
+                    .and_then(move |sent| {
+                        if sent.is_some() {
+                            info!("xDai to Dai bridge transfer already sent, resuming");
+                            Box::new(futures::future::ok(None))
+                                as Box<dyn Future<Item = _, Error = Error>>
+                        } else {
+                            Box::new(
+                                salf.xdai_to_dai_bridge(XDai::from_wei(amount), timeout)
+                                    .map(|transfer| Some(transfer.tx_hash)),
+                            )
+                        }
+                        .and_then(move |tx_hash| {
+                            salf.wait_for_dai_increase(dai_before.clone())
+                                .timeout(Duration::from_secs(timeout))
+                                .and_then(move |balance| {
+                                    // a transfer sent before a restart wasn't timed
+                                    if let Some(tx_hash) = tx_hash {
+                                        salf.bridge_latencies.arrived(&tx_hash);
+                                    }
+                                    let dai = balance.clone() - dai_before;
+                                    salf.emit(BridgeEvent::FundsArrived {
+                                        chain: Chain::Eth,
+                                        amount: dai.clone(),
+                                    });
+                                    Ok(OperationStage::SwappingDaiToEth {
+                                        dai,
+                                        dai_before: balance,
+                                    })
+                                })
+                        })
+                    }),
+            ),
+            (OperationKind::XdaiToEth, OperationStage::SwappingDaiToEth { dai, dai_before }) => {
+                Box::new(
+                    self.get_dai_balance(own_address)
+                        .map(Dai::into_wei)
+                        .and_then({
+                            let dai = dai.clone();
+                            move |balance| {
+                                let by_balance = if balance + dai.clone() <= dai_before {
+                                    Some(dai)
+                                } else {
+                                    None
+                                };
+                                checked.stage_sent(
+                                    &operation_sent,
+                                    dai_address,
+                                    ERC20_TRANSFER,
+                                    timeout,
+                                    by_balance,
+                                )
+                            }
+                        })
+                        .and_then(move |sent| {
+                            if sent.is_some() {
+                                info!("Dai to ETH swap already done, resuming");
+                                Box::new(futures::future::ok(OperationStage::Complete {
+                                    amount: None,
+                                }))
+                                    as Box<dyn Future<Item = _, Error = Error>>
+                            } else {
+                                Box::new(salf.dai_to_eth_swap(Dai::from_wei(dai), timeout).map(
+                                    |eth| OperationStage::Complete {
+                                        amount: Some(eth.into_wei()),
+                                    },
+                                ))
+                            }
+                        }),
+                )
+            }
+            (kind, stage) => Box::new(futures::future::err(format_err!(
+                "Operation {} is at {:?} which is not part of {:?}",
+                operation.id,
+                stage,
+                kind
+            ))),
+        }
+    }
+
+    /// Polls our Dai balance until it is above `dai_before`, returns the new balance
+    pub(crate) fn wait_for_dai_increase(
+        &self,
+        dai_before: Uint256,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let salf = self.clone();
+        Box::new(loop_fn((), move |_| {
+            let dai_before = dai_before.clone();
+            let salf = salf.clone();
+            salf.get_dai_balance(salf.own_address)
+                .map(Dai::into_wei)
+                .and_then(move |balance| {
+                    if balance > dai_before {
+                        Box::new(futures::future::ok(Loop::Break(balance)))
+                            as Box<dyn Future<Item = _, Error = Error>>
+                    } else {
+                        Box::new(
+                            Delay::new(BRIDGE_POLL_INTERVAL)
+                                .from_err()
+                                .map(|_| Loop::Continue(())),
+                        )
+                    }
+                })
+        }))
+    }
+}
+
+fn operation_output(operation: &Operation) -> Result<Uint256, Error> {
+    match operation.stage {
+        OperationStage::Complete {
+            amount: Some(ref amount),
+        } => Ok(amount.clone()),
+        _ => Err(format_err!(
+            "Operation {} finished without a known output",
+            operation.id
+        )),
+    }
+}
+
+/// Lists an operation in `running_operations` until dropped
+struct RunningOperation {
+    operations: Arc<Mutex<HashMap<String, Operation>>>,
+    id: String,
+}
+
+impl RunningOperation {
+    fn start(bridge: &TokenBridge, operation: &Operation) -> RunningOperation {
+        let operations = bridge.running_operations.clone();
+        operations
+            .lock()
+            .unwrap()
+            .insert(operation.id.clone(), operation.clone());
+        RunningOperation {
+            operations,
+            id: operation.id.clone(),
+        }
+    }
+}
+
+impl Drop for RunningOperation {
+    fn drop(&mut self) {
+        self.operations.lock().unwrap().remove(&self.id);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_bridge, MockBlockReader};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_json_file_store() {
+        let path = std::env::temp_dir().join(format!("operations-{}.json", rand::random::<u64>()));
+        let store = JsonFileStore::new(path.clone());
+        assert!(store.load_all().unwrap().is_empty());
+
+        let mut operation = Operation::new(OperationKind::EthToXdai, 100u32.into());
+        store.save(&operation).unwrap();
+        operation.stage = OperationStage::SwappingEthToDai {
+            dai_before: 5u32.into(),
+        };
+        store.save(&operation).unwrap();
+        let finished = Operation {
+            stage: OperationStage::Complete { amount: None },
+            ..Operation::new(OperationKind::XdaiToEth, 7u32.into())
+        };
+        store.save(&finished).unwrap();
+
+        let reopened = JsonFileStore::new(path.clone());
+        assert_eq!(reopened.load_all().unwrap().len(), 2);
+        assert_eq!(reopened.pending().unwrap(), vec![operation]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_idempotency_key() {
+        let path = std::env::temp_dir().join(format!("operations-{}.json", rand::random::<u64>()));
+        let store = Arc::new(JsonFileStore::new(path.clone()));
+        let done = Operation {
+            stage: OperationStage::Complete {
+                amount: Some(90u32.into()),
+            },
+            idempotency_key: Some("payout-1".to_string()),
+            ..Operation::new(OperationKind::EthToXdai, 100u32.into())
+        };
+        store.save(&done).unwrap();
+        assert_eq!(store.find_by_key("payout-1").unwrap(), Some(done.clone()));
+        assert_eq!(store.find_by_key("payout-2").unwrap(), None);
+
+        // the bridge can't reach any node, so nothing but the store may be used
+        let mut bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        assert!(bridge
+            .eth_to_xdai_idempotent(Eth::from_wei(100u32.into()), 60, "payout-1")
+            .wait()
+            .is_err());
+        bridge.operation_store = Some(store);
+        let retried = bridge
+            .eth_to_xdai_idempotent(Eth::from_wei(100u32.into()), 60, "payout-1")
+            .wait();
+        assert_eq!(retried.unwrap(), done);
+        assert!(bridge
+            .xdai_to_eth_idempotent(XDai::from_wei(100u32.into()), 60, "payout-1")
+            .wait()
+            .is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_pending_operations() {
+        let path = std::env::temp_dir().join(format!("operations-{}.json", rand::random::<u64>()));
+        let store = Arc::new(JsonFileStore::new(path.clone()));
+        let waiting = Operation {
+            stage: OperationStage::BridgingXdaiToDai {
+                xdai_before: 100u32.into(),
+                dai_before: 0u32.into(),
+            },
+            started_at: Some(now() - 60),
+            ..Operation::new(OperationKind::XdaiToEth, 20u32.into())
+        };
+        store.save(&waiting).unwrap();
+        let done = Operation {
+            stage: OperationStage::Complete { amount: None },
+            ..Operation::new(OperationKind::EthToXdai, 20u32.into())
+        };
+        store.save(&done).unwrap();
+
+        let mut bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        bridge.operation_store = Some(store);
+        let started = Operation::new(OperationKind::EthToXdai, 5u32.into());
+        let running = RunningOperation::start(&bridge, &started);
+
+        let statuses = bridge.pending_operations().unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses[0].operation, started);
+        assert!(statuses[0].running);
+        assert_eq!(statuses[0].step, 1);
+        assert_eq!(statuses[1].operation, waiting);
+        assert!(!statuses[1].running);
+        assert_eq!(
+            (statuses[1].step, statuses[1].next_action),
+            (2, "waiting for the bridge to pay out Dai on Eth")
+        );
+        assert!(statuses[1].age.unwrap() >= Duration::from_secs(60));
+        assert!(statuses[1].tx_hashes.is_empty());
+
+        drop(running);
+        assert_eq!(bridge.pending_operations().unwrap().len(), 1);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_stage_tx() {
+        let path = std::env::temp_dir().join(format!("operations-{}.json", rand::random::<u64>()));
+        let store = Arc::new(JsonFileStore::new(path.clone()));
+        let mut bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        bridge.operation_store = Some(store.clone());
+        let operation = Operation {
+            stage: OperationStage::SwappingDaiToEth {
+                dai: 20u32.into(),
+                dai_before: 50u32.into(),
+            },
+            ..Operation::new(OperationKind::XdaiToEth, 20u32.into())
+        };
+        let dai = bridge.foreign_dai_contract_address;
+
+        // nothing was broadcast, whatever the balance says
+        let sent = bridge.stage_sent(&operation, dai, ERC20_TRANSFER, 1, Some(20u32.into()));
+        assert_eq!(sent.wait().unwrap(), None);
+
+        let running = RunningOperation::start(&bridge, &operation);
+        let step = bridge.for_operation(&operation.id);
+        step.checkpoint_stage_tx(Chain::Eth, 7u32.into(), 3u32.into());
+        let stored = store.load_all().unwrap().remove(0);
+        assert_eq!(
+            stored.stage_tx,
+            Some(OperationTx {
+                chain: Chain::Eth,
+                tx_hash: 7u32.into(),
+                nonce: 3u32.into(),
+            })
+        );
+        assert_eq!(stored.stage, operation.stage);
+        drop(running);
+
+        // stored before transactions were checkpointed, the balance decides
+        let mut legacy = serde_json::to_value(&operation).unwrap();
+        legacy.as_object_mut().unwrap().remove("stage_tx");
+        legacy.as_object_mut().unwrap().remove("records_stage_tx");
+        let legacy: Operation = serde_json::from_value(legacy).unwrap();
+        assert!(!legacy.records_stage_tx);
+        let sent = bridge.stage_sent(&legacy, dai, ERC20_TRANSFER, 1, Some(20u32.into()));
+        assert_eq!(sent.wait().unwrap(), Some(20u32.into()));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! Cross-checks Uniswap quotes against a Chainlink price feed so that an unattended router does
+//! not swap into a manipulated or stale pool.
+
+use crate::abi::{decode_output, decode_values, AbiType, AbiValue};
+use crate::units::Eth;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::bail;
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use num::ToPrimitive;
+use num256::Uint256;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub use crate::contracts::MAINNET_ETH_USD_AGGREGATOR;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceOracle {
+    /// Address of the Chainlink ETH/USD aggregator on Eth
+    pub aggregator_address: Address,
+    /// How far the Uniswap price may be from the oracle price, in basis points
+    pub max_divergence_bps: u32,
+    /// Oracle answers older than this (by chain time) are refused
+    pub max_age: Duration,
+}
+
+impl PriceOracle {
+    /// The mainnet ETH/USD feed with the given divergence limit and a one hour staleness limit
+    pub fn mainnet(max_divergence_bps: u32) -> PriceOracle {
+        PriceOracle {
+            aggregator_address: Address::from_str(MAINNET_ETH_USD_AGGREGATOR).unwrap(),
+            max_divergence_bps,
+            max_age: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// Returns the distance between `price` and `reference` in basis points of `reference`
+pub fn divergence_bps(reference: Uint256, price: Uint256) -> u32 {
+    if reference == 0u32.into() {
+        return u32::MAX;
+    }
+    let difference = if price > reference {
+        price - reference.clone()
+    } else {
+        reference.clone() - price
+    };
+    (difference * 10_000u32.into() / reference)
+        .to_u32()
+        .unwrap_or(u32::MAX)
+}
+
+impl TokenBridge {
+    /// Price of one ETH in USD according to the configured oracle, scaled to 18 decimals so it
+    /// can be compared directly with Dai amounts. Errors if no oracle is configured or the
+    /// latest answer is older than the oracle's `max_age`.
+    pub fn get_oracle_eth_price(&self) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let oracle = match self.price_oracle.clone() {
+            Some(oracle) => oracle,
+            None => return Box::new(futures::future::err(format_err!("No price oracle set"))),
+        };
+        let web3 = self.eth_web3.clone();
+        let own_address = self.own_address;
+
+        Box::new(
+            web3.contract_call(
+                oracle.aggregator_address,
+                "latestRoundData()",
+                &[],
+                own_address,
+            )
+            .join(web3.contract_call(oracle.aggregator_address, "decimals()", &[], own_address))
+            .join(web3.eth_get_latest_block())
+            .and_then(move |((round_data, decimals), block)| {
+                // returns (roundId, answer, startedAt, updatedAt, answeredInRound)
+                let mut round_data =
+                    decode_values("latestRoundData()", &[AbiType::Uint256; 5], &round_data)?
+                        .into_iter()
+                        .map(AbiValue::into_uint256);
+                let answer = round_data.nth(1).unwrap()?;
+                let updated_at = round_data.nth(1).unwrap()?;
+                // int256, a set high bit means a negative answer
+                let answer_word: [u8; 32] = answer.clone().into();
+                if answer_word[0] & 0x80 != 0 {
+                    bail!("Chainlink returned a negative price {:?}", answer);
+                }
+                let decimals: Uint256 = decode_output("decimals()", &decimals)?;
+                let decimals = decimals.to_u32().unwrap_or(0);
+                if decimals > 18 {
+                    bail!("Unsupported oracle precision of {} decimals", decimals);
+                }
+
+                let age = if block.timestamp > updated_at {
+                    block.timestamp - updated_at
+                } else {
+                    0u32.into()
+                };
+                let age = Duration::from_secs(age.to_u64().unwrap_or(u64::MAX));
+                if age > oracle.max_age {
+                    return Err(TokenBridgeError::OracleStale { age }.into());
+                }
+
+                let scale: Uint256 = 10u64.pow(18 - decimals).into();
+                Ok(answer * scale)
+            }),
+        )
+    }
+
+    /// Compares the Uniswap price of one ETH in Dai against the configured oracle and errors
+    /// with `TokenBridgeError::OracleDivergence` if they are further apart than the oracle's
+    /// `max_divergence_bps`. Does nothing when no oracle is configured.
+    pub fn check_uniswap_against_oracle(&self) -> Box<dyn Future<Item = (), Error = Error>> {
+        let max_divergence_bps = match self.price_oracle {
+            Some(ref oracle) => oracle.max_divergence_bps,
+            None => return Box::new(futures::future::ok(())),
+        };
+        let one_eth = Eth::from_wei(1_000_000_000_000_000_000u64.into());
+
+        Box::new(
+            self.get_oracle_eth_price()
+                .join(self.eth_to_dai_price(one_eth))
+                .and_then(move |(oracle_price, uniswap_price)| {
+                    let uniswap_price = uniswap_price.into_wei();
+                    let divergence = divergence_bps(oracle_price.clone(), uniswap_price.clone());
+                    trace!(
+                        "oracle price {} uniswap price {} divergence {}bps",
+                        oracle_price,
+                        uniswap_price,
+                        divergence
+                    );
+                    if divergence > max_divergence_bps {
+                        Err(TokenBridgeError::OracleDivergence {
+                            oracle_price,
+                            uniswap_price,
+                        }
+                        .into())
+                    } else {
+                        Ok(())
+                    }
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_divergence_bps() {
+        assert_eq!(divergence_bps(200u32.into(), 200u32.into()), 0);
+        assert_eq!(divergence_bps(200u32.into(), 210u32.into()), 500);
+        assert_eq!(divergence_bps(200u32.into(), 190u32.into()), 500);
+        assert_eq!(divergence_bps(0u32.into(), 190u32.into()), u32::MAX);
+    }
+}
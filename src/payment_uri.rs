@@ -0,0 +1,270 @@
+//! `ethereum:` payment URIs as specified by ERC-681, for asking someone to top up a wallet by
+//! scanning a QR code. Only the forms wallets commonly produce are handled: a plain value
+//! transfer and an ERC20 `transfer`.
+//!
+//! ```text
+//! ethereum:<recipient>@<chain id>?value=<wei>
+//! ethereum:<token>@<chain id>/transfer?address=<recipient>&uint256=<amount>
+//! ```
+
+use crate::amounts::parse_amount;
+use crate::contracts::{identify, KnownContract};
+use crate::network::Network;
+use crate::units::{Dai, Eth, XDai};
+use crate::Chain;
+use clarity::Address;
+use failure::bail;
+use failure::format_err;
+use failure::Error;
+use num256::Uint256;
+use std::fmt;
+use std::str::FromStr;
+
+const SCHEME: &str = "ethereum:";
+
+/// What a `PaymentRequest` asks to be paid in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentAsset {
+    /// The native coin of the chain, ETH or xDai
+    Native,
+    /// The ERC20 token at this address
+    Token(Address),
+}
+
+/// A parsed or to be encoded `ethereum:` URI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentRequest {
+    pub recipient: Address,
+    /// The chain to pay on, wallets use whatever chain they are on if it is `None`
+    pub chain_id: Option<u64>,
+    pub asset: PaymentAsset,
+    /// In the smallest unit of `asset`, left for the payer to choose if `None`
+    pub amount: Option<Uint256>,
+}
+
+/// Parses an ERC-681 number, a decimal integer with an optional exponent such as `2.5e18`
+fn parse_number(number: &str) -> Result<Uint256, Error> {
+    let (mantissa, exponent) = match number.find(['e', 'E']) {
+        Some(i) => (&number[..i], &number[i + 1..]),
+        None => (number, "0"),
+    };
+    let exponent: u32 = exponent
+        .parse()
+        .map_err(|_| format_err!("Bad exponent in amount {}", number))?;
+    parse_amount(mantissa, exponent)
+        .map_err(|e| format_err!("Bad amount {} in payment URI: {}", number, e))
+}
+
+fn parse_address(address: &str) -> Result<Address, Error> {
+    if !address.starts_with("0x") {
+        bail!(
+            "Payment URI address {} is not a hex address, resolve ENS names first",
+            address
+        );
+    }
+    Address::from_str(address).map_err(|e| format_err!("Bad address {}: {}", address, e))
+}
+
+impl PaymentRequest {
+    /// A request for `amount` of ETH on the Eth chain of `network`
+    pub fn eth(network: &Network, recipient: Address, amount: Eth) -> PaymentRequest {
+        PaymentRequest {
+            recipient,
+            chain_id: Some(network.eth_chain_id()),
+            asset: PaymentAsset::Native,
+            amount: Some(amount.into_wei()),
+        }
+    }
+
+    /// A request for `amount` of xDai on the xDai chain of `network`
+    pub fn xdai(network: &Network, recipient: Address, amount: XDai) -> PaymentRequest {
+        PaymentRequest {
+            recipient,
+            chain_id: Some(network.xdai_chain_id()),
+            asset: PaymentAsset::Native,
+            amount: Some(amount.into_wei()),
+        }
+    }
+
+    /// A request for `amount` of the Dai token on the Eth chain of `network`
+    pub fn dai(network: &Network, recipient: Address, amount: Dai) -> PaymentRequest {
+        let addresses = network.addresses();
+        PaymentRequest {
+            recipient,
+            chain_id: Some(addresses.eth_chain_id),
+            asset: PaymentAsset::Token(addresses.foreign_dai_contract_address),
+            amount: Some(amount.into_wei()),
+        }
+    }
+
+    /// Parses an `ethereum:` URI, failing on function calls other than `transfer`
+    pub fn parse(uri: &str) -> Result<PaymentRequest, Error> {
+        let uri = uri.trim();
+        if uri.len() < SCHEME.len() || !uri[..SCHEME.len()].eq_ignore_ascii_case(SCHEME) {
+            bail!("{} is not an ethereum: URI", uri);
+        }
+        let rest = &uri[SCHEME.len()..];
+        let rest = rest.strip_prefix("pay-").unwrap_or(rest);
+        let (target, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (rest, ""),
+        };
+        let (target, function) = match target.find('/') {
+            Some(i) => (&target[..i], Some(&target[i + 1..])),
+            None => (target, None),
+        };
+        let (target, chain_id) = match target.find('@') {
+            Some(i) => {
+                let chain_id = target[i + 1..]
+                    .parse()
+                    .map_err(|_| format_err!("Bad chain id in payment URI {}", uri))?;
+                (&target[..i], Some(chain_id))
+            }
+            None => (target, None),
+        };
+        let target = parse_address(target)?;
+
+        let mut value = None;
+        let mut recipient = None;
+        let mut token_amount = None;
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, val) = match param.find('=') {
+                Some(i) => (&param[..i], &param[i + 1..]),
+                None => bail!("Payment URI parameter {} has no value", param),
+            };
+            match key {
+                "value" => value = Some(parse_number(val)?),
+                "address" => recipient = Some(parse_address(val)?),
+                "uint256" => token_amount = Some(parse_number(val)?),
+                // gas parameters and anything else are up to the payer
+                _ => {}
+            }
+        }
+
+        match function {
+            None => Ok(PaymentRequest {
+                recipient: target,
+                chain_id,
+                asset: PaymentAsset::Native,
+                amount: value,
+            }),
+            Some("transfer") => Ok(PaymentRequest {
+                recipient: recipient
+                    .ok_or_else(|| format_err!("Token transfer URI {} has no address", uri))?,
+                chain_id,
+                asset: PaymentAsset::Token(target),
+                amount: token_amount,
+            }),
+            Some(function) => bail!("Payment URI calls unsupported function {}", function),
+        }
+    }
+
+    /// Which chain of `network` the payment is on. `None` if `chain_id` is unset or belongs to
+    /// neither side of `network`.
+    pub fn chain(&self, network: &Network) -> Option<Chain> {
+        let addresses = network.addresses();
+        match self.chain_id {
+            Some(id) if id == addresses.eth_chain_id => Some(Chain::Eth),
+            Some(id) if id == addresses.xdai_chain_id => Some(Chain::Xdai),
+            _ => None,
+        }
+    }
+
+    /// The token asked for, if it is one the contract registry knows
+    pub fn known_token(&self) -> Option<&'static KnownContract> {
+        match (self.asset, self.chain_id) {
+            (PaymentAsset::Token(token), Some(chain_id)) => identify(chain_id, token),
+            _ => None,
+        }
+    }
+
+    /// The URI for this request, see `Display`
+    pub fn to_uri(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for PaymentRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let target = match self.asset {
+            PaymentAsset::Native => self.recipient,
+            PaymentAsset::Token(token) => token,
+        };
+        write!(f, "{}{}", SCHEME, target)?;
+        if let Some(chain_id) = self.chain_id {
+            write!(f, "@{}", chain_id)?;
+        }
+        match (self.asset, &self.amount) {
+            (PaymentAsset::Native, Some(amount)) => write!(f, "?value={}", amount),
+            (PaymentAsset::Native, None) => Ok(()),
+            (PaymentAsset::Token(_), Some(amount)) => {
+                write!(f, "/transfer?address={}&uint256={}", self.recipient, amount)
+            }
+            (PaymentAsset::Token(_), None) => {
+                write!(f, "/transfer?address={}", self.recipient)
+            }
+        }
+    }
+}
+
+impl FromStr for PaymentRequest {
+    type Err = Error;
+
+    fn from_str(uri: &str) -> Result<PaymentRequest, Error> {
+        PaymentRequest::parse(uri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::{ContractKind, MAINNET_DAI};
+
+    const RECIPIENT: &str = "0x00000000000000000000000000000000000000aa";
+
+    #[test]
+    fn test_parse_payment_uri() {
+        let recipient = Address::from_str(RECIPIENT).unwrap();
+        let request =
+            PaymentRequest::parse(&format!("ethereum:pay-{}@100?value=2.5e18", RECIPIENT)).unwrap();
+        assert_eq!(
+            request,
+            PaymentRequest::xdai(
+                &Network::Mainnet,
+                recipient,
+                XDai::from_wei(2_500_000_000_000_000_000u64.into())
+            )
+        );
+        assert_eq!(request.chain(&Network::Mainnet), Some(Chain::Xdai));
+
+        let transfer = PaymentRequest::parse(&format!(
+            "ethereum:{}@1/transfer?address={}&uint256=1e18&gas=100000",
+            MAINNET_DAI, RECIPIENT
+        ))
+        .unwrap();
+        assert_eq!(transfer.recipient, recipient);
+        assert_eq!(transfer.amount, Some(1_000_000_000_000_000_000u64.into()));
+        assert_eq!(transfer.known_token().unwrap().kind, ContractKind::Dai);
+
+        let bare = PaymentRequest::parse(&format!("ethereum:{}", RECIPIENT)).unwrap();
+        assert_eq!(bare.chain_id, None);
+        assert_eq!(bare.amount, None);
+
+        assert!(PaymentRequest::parse("bitcoin:abc").is_err());
+        assert!(PaymentRequest::parse("ethereum:alice.eth?value=1").is_err());
+        assert!(PaymentRequest::parse(&format!("ethereum:{}?value=1.5", RECIPIENT)).is_err());
+        assert!(PaymentRequest::parse(&format!("ethereum:{}/approve", MAINNET_DAI)).is_err());
+    }
+
+    #[test]
+    fn test_payment_uri_round_trip() {
+        let recipient = Address::from_str(RECIPIENT).unwrap();
+        let requests = vec![
+            PaymentRequest::eth(&Network::Mainnet, recipient, Eth::from_wei(1_000u32.into())),
+            PaymentRequest::dai(&Network::Mainnet, recipient, Dai::from_wei(5_000u32.into())),
+        ];
+        for request in requests {
+            assert_eq!(request.to_uri().parse::<PaymentRequest>().unwrap(), request);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Plain ETH and xDai payments, sent through the same nonce management, gas strategy and
+//! audit log as the bridging transactions and waited on until they are mined.
+
+use crate::audit::TxStatus;
+use crate::error::{TimeoutOutcome, TokenBridgeError};
+use crate::events::BridgeEvent;
+use crate::units::{Eth, XDai};
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use futures_timer::FutureExt;
+use num256::Uint256;
+use web30::types::SendTxOption;
+
+impl TokenBridge {
+    /// Sends `amount` of ETH to `to` and waits for the transaction to be mined. `options`
+    /// override the defaults for the Eth chain. Returns the tx hash.
+    pub fn send_eth(
+        &self,
+        to: Address,
+        amount: Eth,
+        options: Vec<SendTxOption>,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.in_account_order(move |salf| {
+            salf.send_payment(Chain::Eth, to, amount.into_wei(), options)
+        })
+    }
+
+    /// Sends `amount` of xDai to `to` and waits for the transaction to be mined. `options`
+    /// override the defaults for the xDai chain. Returns the tx hash.
+    pub fn send_xdai(
+        &self,
+        to: Address,
+        amount: XDai,
+        options: Vec<SendTxOption>,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.in_account_order(move |salf| {
+            salf.send_payment(Chain::Xdai, to, amount.into_wei(), options)
+        })
+    }
+
+    /// Checks the recipient according to `contract_recipient_check` and `value` against the
+    /// spending limits, sends it and waits up to `timeouts.confirmation` for the transaction to
+    /// be mined. A plain transfer can't revert, so a failed wait is `TokenBridgeError::TimedOut`
+    /// with the outcome unknown.
+    fn send_payment(
+        &self,
+        chain: Chain,
+        to: Address,
+        value: Uint256,
+        options: Vec<SendTxOption>,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let web3 = self.web3(chain);
+        let salf = self.clone();
+
+        Box::new(
+            self.check_transfer_recipient(&web3, to)
+                .and_then({
+                    let salf = self.clone();
+                    move |_| salf.send_transaction(chain, to, Vec::new(), value, options)
+                })
+                .and_then(move |tx_hash| {
+                    let mined = web3.wait_for_transaction(tx_hash.clone().into());
+                    let mined: Box<dyn Future<Item = _, Error = Error>> =
+                        match salf.timeouts.confirmation {
+                            Some(limit) => Box::new(mined.timeout(limit)),
+                            None => mined,
+                        };
+                    mined.then(move |res| match res {
+                        Ok(_) => {
+                            salf.audit_settled(chain, tx_hash.clone(), TxStatus::Executed);
+                            salf.emit(BridgeEvent::TxConfirmed {
+                                chain,
+                                tx_hash: tx_hash.clone(),
+                            });
+                            Ok(tx_hash)
+                        }
+                        Err(e) => {
+                            warn!("Waiting for payment {} failed with {:?}", tx_hash, e);
+                            salf.audit_settled(chain, tx_hash.clone(), TxStatus::Unknown);
+                            Err(TokenBridgeError::TimedOut {
+                                outcome: TimeoutOutcome::Unknown(tx_hash),
+                            }
+                            .into())
+                        }
+                    })
+                }),
+        )
+    }
+}
@@ -0,0 +1,281 @@
+//! Guards against the same conversion being started again while the first one is still
+//! running, like a caller stuck in a loop firing ten ETH to Dai swaps of the same balance. A
+//! `PendingRegistry` set as `pending_registry` tracks the conversions in flight by account,
+//! kind and amount bucket, and rejects or queues a second one with the same key.
+//!
+//! Amounts are bucketed by their leading digits, so that a retry of a balance that moved a
+//! little in the meantime still counts as the same conversion.
+
+use crate::operations::OperationKind;
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::Error;
+use futures::future::{loop_fn, Loop};
+use futures::Future;
+use futures_timer::Delay;
+use num256::Uint256;
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often a queued conversion checks whether it may start
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The leading digits amounts are compared by unless set otherwise
+const DEFAULT_SIGNIFICANT_DIGITS: usize = 3;
+
+/// The conversions the registry tells apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConversionKind {
+    EthToDai,
+    DaiToEth,
+    DaiToXdai,
+    XdaiToDai,
+    EthToXdai,
+    XdaiToEth,
+    /// Selling the token at this address for another one, see `token_to_token_swap`
+    TokenToToken(Address),
+    /// Buying the token at this address with xDai, see `xdai_to_token_swap`
+    XdaiToToken(Address),
+    /// Selling the token at this address for xDai, see `token_to_xdai_swap`
+    TokenToXdai(Address),
+    /// Sending `token` from `from` to the other chain over the OmniBridge
+    BridgeToken {
+        from: Chain,
+        token: Address,
+    },
+}
+
+impl From<OperationKind> for ConversionKind {
+    fn from(kind: OperationKind) -> ConversionKind {
+        match kind {
+            OperationKind::EthToXdai => ConversionKind::EthToXdai,
+            OperationKind::XdaiToEth => ConversionKind::XdaiToEth,
+        }
+    }
+}
+
+/// What happens to a conversion started while an identical one is pending
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Fail it with `TokenBridgeError::DuplicateConversion` without sending anything
+    Reject,
+    /// Start it once the pending one has finished, whether that succeeded or not
+    Queue,
+}
+
+/// What makes two conversions the same
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PendingKey {
+    pub account: Address,
+    pub kind: ConversionKind,
+    /// The amount with everything past its leading digits zeroed
+    pub bucket: Uint256,
+}
+
+/// The conversions in flight, shared by every clone of the bridge it is set on
+#[derive(Debug)]
+pub struct PendingRegistry {
+    pub policy: DuplicatePolicy,
+    /// How many leading digits of an amount have to match, see `bucket`
+    pub significant_digits: usize,
+    pending: Mutex<HashSet<PendingKey>>,
+}
+
+impl PendingRegistry {
+    pub fn new(policy: DuplicatePolicy) -> PendingRegistry {
+        PendingRegistry {
+            policy,
+            significant_digits: DEFAULT_SIGNIFICANT_DIGITS,
+            pending: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn with_significant_digits(mut self, significant_digits: usize) -> PendingRegistry {
+        self.significant_digits = significant_digits.max(1);
+        self
+    }
+
+    /// `amount` rounded down to its leading `significant_digits` digits, 1.2345 ETH and
+    /// 1.2349 ETH share a bucket at the default of three
+    pub fn bucket(&self, amount: &Uint256) -> Uint256 {
+        let digits = amount.to_string();
+        if digits.len() <= self.significant_digits {
+            return amount.clone();
+        }
+        let (leading, rest) = digits.split_at(self.significant_digits);
+        let rounded = format!("{}{}", leading, "0".repeat(rest.len()));
+        Uint256::from_str(&rounded).expect("rounding keeps the amount a decimal number")
+    }
+
+    pub fn key(&self, account: Address, kind: ConversionKind, amount: &Uint256) -> PendingKey {
+        PendingKey {
+            account,
+            kind,
+            bucket: self.bucket(amount),
+        }
+    }
+
+    pub fn is_pending(&self, key: &PendingKey) -> bool {
+        self.pending.lock().unwrap().contains(key)
+    }
+
+    /// The conversions in flight right now
+    pub fn pending(&self) -> Vec<PendingKey> {
+        self.pending.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Marks `key` as in flight until the returned claim is dropped, `None` if it already is
+    fn try_claim(registry: &Arc<PendingRegistry>, key: &PendingKey) -> Option<PendingClaim> {
+        if registry.pending.lock().unwrap().insert(key.clone()) {
+            Some(PendingClaim {
+                registry: registry.clone(),
+                key: key.clone(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Keeps a conversion registered as in flight, it is released when the conversion's future
+/// finishes or is dropped
+struct PendingClaim {
+    registry: Arc<PendingRegistry>,
+    key: PendingKey,
+}
+
+impl Drop for PendingClaim {
+    fn drop(&mut self) {
+        self.registry.pending.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl TokenBridge {
+    /// Runs the conversion of `amount` made by `conversion` unless `pending_registry` has an
+    /// identical one in flight for our account, in which case it is rejected or queued
+    pub(crate) fn guard_conversion<T, F>(
+        &self,
+        kind: ConversionKind,
+        amount: Uint256,
+        conversion: F,
+    ) -> Box<dyn Future<Item = T, Error = Error>>
+    where
+        T: 'static,
+        F: FnOnce() -> Box<dyn Future<Item = T, Error = Error>> + 'static,
+    {
+        let registry = match self.pending_registry {
+            Some(ref registry) => registry.clone(),
+            None => return conversion(),
+        };
+        let key = registry.key(self.own_address, kind, &amount);
+        if let Some(claim) = PendingRegistry::try_claim(&registry, &key) {
+            return Box::new(conversion().then(move |result| {
+                drop(claim);
+                result
+            }));
+        }
+        match registry.policy {
+            DuplicatePolicy::Reject => {
+                warn!("Rejecting {:?}, an identical conversion is pending", key);
+                Box::new(futures::future::err(
+                    TokenBridgeError::DuplicateConversion {
+                        kind,
+                        account: key.account,
+                    }
+                    .into(),
+                ))
+            }
+            DuplicatePolicy::Queue => {
+                info!("Queueing {:?} behind an identical pending conversion", key);
+                let wait = loop_fn((), move |_| {
+                    match PendingRegistry::try_claim(&registry, &key) {
+                        Some(claim) => Box::new(futures::future::ok(Loop::Break(claim)))
+                            as Box<dyn Future<Item = _, Error = Error>>,
+                        None => Box::new(
+                            Delay::new(QUEUE_POLL_INTERVAL)
+                                .from_err()
+                                .map(|_| Loop::Continue(())),
+                        ),
+                    }
+                });
+                Box::new(wait.and_then(move |claim| {
+                    conversion().then(move |result| {
+                        drop(claim);
+                        result
+                    })
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_bridge, MockBlockReader};
+    use futures::sync::oneshot;
+
+    #[test]
+    fn test_bucket() {
+        let registry = PendingRegistry::new(DuplicatePolicy::Reject);
+        assert_eq!(registry.bucket(&1_234_500u32.into()), 1_230_000u32.into());
+        assert_eq!(registry.bucket(&1_234_900u32.into()), 1_230_000u32.into());
+        assert_eq!(registry.bucket(&99u32.into()), 99u32.into());
+        let registry = registry.with_significant_digits(5);
+        assert_eq!(registry.bucket(&1_234_567u32.into()), 1_234_500u32.into());
+    }
+
+    #[test]
+    fn test_duplicate_rejected() {
+        let mut bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        let registry = Arc::new(PendingRegistry::new(DuplicatePolicy::Reject));
+        bridge.pending_registry = Some(registry.clone());
+
+        let (finish, finished) = oneshot::channel::<()>();
+        let first = bridge.guard_conversion(ConversionKind::EthToDai, 1_000_000u32.into(), || {
+            Box::new(finished.map_err(Error::from))
+        });
+        assert_eq!(registry.pending().len(), 1);
+
+        let second = bridge.guard_conversion(ConversionKind::EthToDai, 1_000_100u32.into(), || {
+            Box::new(futures::future::ok(()))
+        });
+        match second.wait().unwrap_err().downcast::<TokenBridgeError>() {
+            Ok(TokenBridgeError::DuplicateConversion { kind, .. }) => {
+                assert_eq!(kind, ConversionKind::EthToDai)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // other kinds and amounts go through
+        let other = bridge.guard_conversion(ConversionKind::DaiToEth, 1_000_000u32.into(), || {
+            Box::new(futures::future::ok(()))
+        });
+        assert!(other.wait().is_ok());
+
+        finish.send(()).unwrap();
+        first.wait().unwrap();
+        assert!(registry.pending().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_queued() {
+        let mut bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        let registry = Arc::new(PendingRegistry::new(DuplicatePolicy::Queue));
+        bridge.pending_registry = Some(registry.clone());
+
+        let first = bridge.guard_conversion(ConversionKind::XdaiToDai, 5u32.into(), || {
+            Box::new(futures::future::ok(1))
+        });
+        let second = bridge.guard_conversion(ConversionKind::XdaiToDai, 5u32.into(), || {
+            Box::new(futures::future::ok(2))
+        });
+        // dropping the first without running it releases it as well
+        drop(first);
+        assert_eq!(second.wait().unwrap(), 2);
+        assert!(registry.pending().is_empty());
+    }
+}
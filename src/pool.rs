@@ -0,0 +1,72 @@
+//! Sharing of Web3 handles between bridges, so that a process creating a bridge per payment
+//! doesn't open a new set of full node connections every time
+
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use web30::client::Web3;
+
+lazy_static! {
+    static ref GLOBAL: Web3Pool = Web3Pool::new();
+}
+
+/// Web3 handles by full node url and timeout. Clones share the same handles.
+#[derive(Clone, Default)]
+pub struct Web3Pool {
+    clients: Arc<Mutex<HashMap<(String, Duration), Web3>>>,
+}
+
+impl Web3Pool {
+    pub fn new() -> Web3Pool {
+        Web3Pool::default()
+    }
+
+    /// The pool bridges use unless they are given another one
+    pub fn global() -> Web3Pool {
+        GLOBAL.clone()
+    }
+
+    /// The handle for `url` with `timeout`, created on first use
+    pub fn get(&self, url: &str, timeout: Duration) -> Web3 {
+        self.clients
+            .lock()
+            .unwrap()
+            .entry((url.to_string(), timeout))
+            .or_insert_with(|| Web3::new(url, timeout))
+            .clone()
+    }
+
+    /// How many distinct handles have been created
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops all handles, bridges already holding one keep using it
+    pub fn clear(&self) {
+        self.clients.lock().unwrap().clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_web3_pool() {
+        let pool = Web3Pool::new();
+        let timeout = Duration::from_secs(5);
+        pool.get("http://localhost:8545", timeout);
+        pool.clone().get("http://localhost:8545", timeout);
+        assert_eq!(pool.len(), 1);
+        pool.get("http://localhost:8545", Duration::from_secs(10));
+        pool.get("http://localhost:8546", timeout);
+        assert_eq!(pool.len(), 3);
+        pool.clear();
+        assert!(pool.is_empty());
+    }
+}
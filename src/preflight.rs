@@ -0,0 +1,162 @@
+//! Checks that the bridge contracts are live before funds are sent to them. A paused, not yet
+//! initialized or unexpectedly upgraded bridge will happily accept a deposit that then never
+//! arrives on the other side.
+
+use crate::fee::BridgeDirection;
+use crate::retry::is_transient;
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+
+/// Settings for `check_bridge_available`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BridgePreflight {
+    /// Run the check before every bridge transfer, on by default
+    pub enabled: bool,
+    /// If set, the foreign bridge proxy must point at this implementation
+    pub foreign_implementation: Option<Address>,
+    /// If set, the home bridge proxy must point at this implementation
+    pub home_implementation: Option<Address>,
+}
+
+impl Default for BridgePreflight {
+    fn default() -> Self {
+        BridgePreflight {
+            enabled: true,
+            foreign_implementation: None,
+            home_implementation: None,
+        }
+    }
+}
+
+fn unavailable(reason: String) -> Error {
+    TokenBridgeError::BridgeUnavailable { reason }.into()
+}
+
+/// `error` from reading `call` of `bridge` as `BridgeUnavailable`, unless the node just didn't
+/// answer. A bridge that reverts or returns nothing for the call isn't one we can check.
+fn unanswered(bridge: Address, call: &'static str, error: Error) -> Error {
+    if is_transient(&error) {
+        return error;
+    }
+    unavailable(format!(
+        "bridge {} did not answer {}: {}",
+        bridge, call, error
+    ))
+}
+
+impl TokenBridge {
+    /// Errors with `TokenBridgeError::BridgeUnavailable` if the bridge receiving funds in
+    /// `direction` is not initialized, is paused, points at an unexpected implementation or,
+    /// for deposits, does not accept the configured Dai token. A bridge whose answer to one of
+    /// these calls is missing or doesn't decode is unavailable as well, the reason names the
+    /// call, set `bridge_preflight.enabled` to false for such a deployment.
+    pub fn check_bridge_available(
+        &self,
+        direction: BridgeDirection,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let (chain, bridge, pinned) = match direction {
+            BridgeDirection::DaiToXdai => (
+                Chain::Eth,
+                self.xdai_foreign_bridge_address,
+                self.bridge_preflight.foreign_implementation,
+            ),
+            BridgeDirection::XdaiToDai => (
+                Chain::Xdai,
+                self.xdai_home_bridge_address,
+                self.bridge_preflight.home_implementation,
+            ),
+        };
+        let dai = self.foreign_dai_contract_address;
+
+        let token_check: Box<dyn Future<Item = (), Error = Error>> = match direction {
+            BridgeDirection::DaiToXdai => Box::new(
+                self.call_view::<Address>(chain, bridge, "erc20token()", &[])
+                    .map_err(move |e| unanswered(bridge, "erc20token()", e))
+                    .and_then(move |token| {
+                        if token != dai {
+                            return Err(unavailable(format!(
+                                "foreign bridge accepts {} and not the configured Dai {}",
+                                token, dai
+                            )));
+                        }
+                        Ok(())
+                    }),
+            ),
+            BridgeDirection::XdaiToDai => Box::new(futures::future::ok(())),
+        };
+
+        Box::new(
+            self.call_view::<bool>(chain, bridge, "isInitialized()", &[])
+                .map_err(move |e| unanswered(bridge, "isInitialized()", e))
+                .join3(
+                    // not every bridge version can be paused, those return nothing
+                    self.call_view::<Vec<u8>>(chain, bridge, "paused()", &[]),
+                    self.call_view::<Address>(chain, bridge, "implementation()", &[])
+                        .map_err(move |e| unanswered(bridge, "implementation()", e)),
+                )
+                .and_then(move |(initialized, paused, implementation)| {
+                    if !initialized {
+                        return Err(unavailable(format!("bridge {} is not initialized", bridge)));
+                    }
+                    if paused.get(31) == Some(&1) {
+                        return Err(unavailable(format!("bridge {} is paused", bridge)));
+                    }
+                    if implementation == Address::default() {
+                        return Err(unavailable(format!(
+                            "bridge {} has no implementation",
+                            bridge
+                        )));
+                    }
+                    match pinned {
+                        Some(pinned) if pinned != implementation => Err(unavailable(format!(
+                            "bridge {} was upgraded to {}, expected {}",
+                            bridge, implementation, pinned
+                        ))),
+                        _ => Ok(()),
+                    }
+                })
+                .join(token_check)
+                .map(|_| ()),
+        )
+    }
+
+    /// `check_bridge_available` if the preflight is enabled
+    pub(crate) fn bridge_preflight(
+        &self,
+        direction: BridgeDirection,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        if self.bridge_preflight.enabled {
+            self.check_bridge_available(direction)
+        } else {
+            Box::new(futures::future::ok(()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::decode_output;
+    use std::io;
+
+    #[test]
+    fn test_unanswered() {
+        let bridge = Address::from_slice(&[7u8; 20]).unwrap();
+        // a bridge without the function returns nothing
+        let missing = decode_output::<bool>("isInitialized()", &[]).unwrap_err();
+        match unanswered(bridge, "isInitialized()", missing).downcast::<TokenBridgeError>() {
+            Ok(TokenBridgeError::BridgeUnavailable { reason }) => {
+                assert!(reason.contains("isInitialized()"))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let dropped = io::Error::new(io::ErrorKind::TimedOut, "no answer");
+        let error = unanswered(bridge, "isInitialized()", dropped.into());
+        assert!(error.downcast_ref::<io::Error>().is_some());
+    }
+}
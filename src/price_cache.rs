@@ -0,0 +1,172 @@
+//! Optional in-memory cache for Uniswap price quotes. Consumers that poll prices every few
+//! seconds would otherwise make the same contract call over and over. Only the informational
+//! price methods use it, swaps always quote their minimum output from the node.
+
+use crate::TokenBridge;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Amounts that agree in this many leading decimal digits share a cache entry by default
+pub const DEFAULT_SIGNIFICANT_DIGITS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PriceDirection {
+    EthToDai,
+    DaiToEth,
+}
+
+#[derive(Debug, Clone)]
+struct CachedQuote {
+    amount: Uint256,
+    quote: Uint256,
+    fetched: Instant,
+}
+
+/// Quotes keyed by direction and amount bucket. A hit for a different amount in the same
+/// bucket is scaled linearly from the cached quote, which is off by at most the change in price
+/// impact within the bucket.
+#[derive(Debug)]
+pub struct PriceCache {
+    /// How long a quote is served before it is fetched again
+    pub ttl: Duration,
+    /// Leading decimal digits of the amount that make up its bucket
+    pub significant_digits: usize,
+    entries: Mutex<HashMap<(PriceDirection, String), CachedQuote>>,
+}
+
+impl PriceCache {
+    pub fn new(ttl: Duration) -> PriceCache {
+        PriceCache {
+            ttl,
+            significant_digits: DEFAULT_SIGNIFICANT_DIGITS,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The cached quote for `amount`, if there is a fresh one in its bucket
+    pub fn get(&self, direction: PriceDirection, amount: &Uint256) -> Option<Uint256> {
+        let key = (direction, bucket(amount, self.significant_digits));
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(&key)?.clone();
+        if entry.fetched.elapsed() > self.ttl {
+            entries.remove(&key);
+            return None;
+        }
+        if entry.amount == *amount {
+            Some(entry.quote)
+        } else {
+            Some(entry.quote * amount.clone() / entry.amount)
+        }
+    }
+
+    pub fn insert(&self, direction: PriceDirection, amount: Uint256, quote: Uint256) {
+        if amount == 0u32.into() {
+            return;
+        }
+        let key = (direction, bucket(&amount, self.significant_digits));
+        self.entries.lock().unwrap().insert(
+            key,
+            CachedQuote {
+                amount,
+                quote,
+                fetched: Instant::now(),
+            },
+        );
+    }
+
+    /// Drops every cached quote, for example after sending a swap that moved the price
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// `amount` with everything after the first `digits` decimal digits zeroed
+fn bucket(amount: &Uint256, digits: usize) -> String {
+    amount
+        .to_string()
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if i < digits { c } else { '0' })
+        .collect()
+}
+
+impl TokenBridge {
+    /// Serves the quote for `amount` from `price_cache` if possible, otherwise gets it with
+    /// `fetch` and caches the result
+    pub(crate) fn cached_price<F>(
+        &self,
+        direction: PriceDirection,
+        amount: Uint256,
+        fetch: F,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>>
+    where
+        F: FnOnce(Uint256) -> Box<dyn Future<Item = Uint256, Error = Error>>,
+    {
+        let cache = match self.price_cache {
+            Some(ref cache) => cache.clone(),
+            None => return fetch(amount),
+        };
+        if let Some(quote) = cache.get(direction, &amount) {
+            trace!("Cached {:?} quote for {}: {}", direction, amount, quote);
+            return Box::new(futures::future::ok(quote));
+        }
+
+        Box::new(fetch(amount.clone()).map(move |quote| {
+            cache.insert(direction, amount, quote.clone());
+            quote
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(bucket(&123_456u32.into(), 3), "123000");
+        assert_eq!(bucket(&12u32.into(), 3), "12");
+    }
+
+    #[test]
+    fn test_cache_scales_and_expires() {
+        let cache = PriceCache::new(Duration::from_secs(60));
+        cache.insert(
+            PriceDirection::EthToDai,
+            100_000u32.into(),
+            200_000u32.into(),
+        );
+        assert_eq!(
+            cache.get(PriceDirection::EthToDai, &100_000u32.into()),
+            Some(200_000u32.into())
+        );
+        assert_eq!(
+            cache.get(PriceDirection::EthToDai, &100_050u32.into()),
+            Some(200_100u32.into())
+        );
+        assert_eq!(
+            cache.get(PriceDirection::EthToDai, &101_000u32.into()),
+            None
+        );
+        assert_eq!(
+            cache.get(PriceDirection::DaiToEth, &100_000u32.into()),
+            None
+        );
+
+        let cache = PriceCache::new(Duration::from_secs(0));
+        cache.insert(
+            PriceDirection::EthToDai,
+            100_000u32.into(),
+            200_000u32.into(),
+        );
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(
+            cache.get(PriceDirection::EthToDai, &100_000u32.into()),
+            None
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use crate::block_tag::BlockTag;
+use crate::units::{Dai, Eth};
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use failure::Error;
+use futures::Future;
+use num::ToPrimitive;
+use num256::Uint256;
+
+/// The marginal price is quoted for this fraction of the requested amount
+const MARGINAL_FRACTION: u64 = 1000;
+
+/// Returns how much worse, in basis points, trading `amount_in` for `amount_out` is than the
+/// marginal rate of `reference_in` for `reference_out`. Trades at or better than the marginal
+/// rate have an impact of zero.
+pub fn price_impact_bps(
+    reference_in: Uint256,
+    reference_out: Uint256,
+    amount_in: Uint256,
+    amount_out: Uint256,
+) -> u32 {
+    let zero: Uint256 = 0u32.into();
+    if reference_in == zero {
+        return 0;
+    }
+    let expected_out = amount_in * reference_out / reference_in;
+    if expected_out == zero || amount_out >= expected_out {
+        return 0;
+    }
+    let impact = (expected_out.clone() - amount_out) * 10_000u32.into() / expected_out;
+    // impact is at most 10_000 here since amount_out < expected_out
+    impact.to_u32().unwrap_or(10_000)
+}
+
+impl TokenBridge {
+    /// Price impact in basis points of selling `eth_amount` ETH for Dai, compared to the
+    /// marginal price of a much smaller sale. Both are quoted by the node, a cached quote is
+    /// scaled from another amount and would hide the impact.
+    pub fn eth_to_dai_price_impact(
+        &self,
+        eth_amount: Eth,
+    ) -> Box<dyn Future<Item = u32, Error = Error>> {
+        let eth_amount = eth_amount.into_wei();
+        let reference = eth_amount.clone() / MARGINAL_FRACTION.into();
+        if reference == 0u32.into() {
+            return Box::new(futures::future::ok(0));
+        }
+        Box::new(
+            self.fresh_eth_to_dai_price_at(Eth::from_wei(reference.clone()), BlockTag::Latest)
+                .join(
+                    self.fresh_eth_to_dai_price_at(
+                        Eth::from_wei(eth_amount.clone()),
+                        BlockTag::Latest,
+                    ),
+                )
+                .and_then(move |(reference_out, amount_out)| {
+                    Ok(price_impact_bps(
+                        reference,
+                        reference_out.into_wei(),
+                        eth_amount,
+                        amount_out.into_wei(),
+                    ))
+                }),
+        )
+    }
+
+    /// Price impact in basis points of selling `dai_amount` Dai for ETH, compared to the
+    /// marginal price of a much smaller sale.
+    pub fn dai_to_eth_price_impact(
+        &self,
+        dai_amount: Dai,
+    ) -> Box<dyn Future<Item = u32, Error = Error>> {
+        let dai_amount = dai_amount.into_wei();
+        let reference = dai_amount.clone() / MARGINAL_FRACTION.into();
+        if reference == 0u32.into() {
+            return Box::new(futures::future::ok(0));
+        }
+        Box::new(
+            self.fresh_dai_to_eth_price_at(Dai::from_wei(reference.clone()), BlockTag::Latest)
+                .join(
+                    self.fresh_dai_to_eth_price_at(
+                        Dai::from_wei(dai_amount.clone()),
+                        BlockTag::Latest,
+                    ),
+                )
+                .and_then(move |(reference_out, amount_out)| {
+                    Ok(price_impact_bps(
+                        reference,
+                        reference_out.into_wei(),
+                        dai_amount,
+                        amount_out.into_wei(),
+                    ))
+                }),
+        )
+    }
+
+    /// Errors with `TokenBridgeError::PriceImpactTooHigh` if selling `eth_amount` ETH would move
+    /// the price by more than `max_bps` basis points.
+    pub fn check_eth_to_dai_price_impact(
+        &self,
+        eth_amount: Eth,
+        max_bps: u32,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        Box::new(
+            self.eth_to_dai_price_impact(eth_amount)
+                .and_then(move |impact_bps| check_impact(impact_bps, max_bps)),
+        )
+    }
+
+    /// Errors with `TokenBridgeError::PriceImpactTooHigh` if selling `dai_amount` Dai would move
+    /// the price by more than `max_bps` basis points.
+    pub fn check_dai_to_eth_price_impact(
+        &self,
+        dai_amount: Dai,
+        max_bps: u32,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        Box::new(
+            self.dai_to_eth_price_impact(dai_amount)
+                .and_then(move |impact_bps| check_impact(impact_bps, max_bps)),
+        )
+    }
+}
+
+fn check_impact(impact_bps: u32, max_bps: u32) -> Result<(), Error> {
+    if impact_bps > max_bps {
+        Err(TokenBridgeError::PriceImpactTooHigh {
+            impact_bps,
+            max_bps,
+        }
+        .into())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_price_impact_bps() {
+        // same rate as the reference
+        assert_eq!(
+            price_impact_bps(
+                1u32.into(),
+                200u32.into(),
+                1000u32.into(),
+                200_000u32.into()
+            ),
+            0
+        );
+        // better than the reference
+        assert_eq!(
+            price_impact_bps(
+                1u32.into(),
+                200u32.into(),
+                1000u32.into(),
+                300_000u32.into()
+            ),
+            0
+        );
+        // 5% worse than the reference
+        assert_eq!(
+            price_impact_bps(
+                1u32.into(),
+                200u32.into(),
+                1000u32.into(),
+                190_000u32.into()
+            ),
+            500
+        );
+        // nothing out at all
+        assert_eq!(
+            price_impact_bps(1u32.into(), 200u32.into(), 1000u32.into(), 0u32.into()),
+            10_000
+        );
+    }
+}
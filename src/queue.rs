@@ -0,0 +1,178 @@
+//! Runs the state changing calls of each account one at a time, in the order they were made.
+//! Every public method that sends transactions, swaps, bridge transfers, payments, approvals,
+//! wrapping and withdrawals alike, waits for the ones of the same account started before it,
+//! so two threads sharing a bridge can't race each other for nonces, allowances or the balance
+//! a swap was sized from. Reads never wait, and different accounts don't wait for each other.
+//!
+//! A call runs with a bridge that holds its turn, calls it makes itself, like the approval
+//! before a swap, run right away instead of queueing behind it. Each call holds its place only
+//! while it runs, the steps of `eth_to_xdai` and `xdai_to_eth` are queued one by one, so calls
+//! made in between may run between two steps.
+
+use crate::TokenBridge;
+use clarity::Address;
+use failure::Error;
+use futures::sync::oneshot;
+use futures::Future;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A call waiting for its turn, and how to wake it up once it has it
+struct Waiter {
+    id: u64,
+    wake: Option<oneshot::Sender<()>>,
+}
+
+/// The calls of every account that are running or waiting to, shared by all clones of a bridge
+#[derive(Default)]
+pub struct OperationQueue {
+    next_id: AtomicU64,
+    /// The first call of each account is the one running
+    accounts: Mutex<HashMap<Address, VecDeque<Waiter>>>,
+}
+
+impl OperationQueue {
+    /// How many calls of `account` are running or waiting
+    pub fn len(&self, account: Address) -> usize {
+        self.accounts
+            .lock()
+            .unwrap()
+            .get(&account)
+            .map(VecDeque::len)
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self, account: Address) -> bool {
+        self.len(account) == 0
+    }
+
+    /// Queues a call for `account`. The returned future resolves once it is the call's turn,
+    /// which lasts until the returned place is dropped.
+    fn enqueue(
+        queue: &Arc<OperationQueue>,
+        account: Address,
+    ) -> (QueuePlace, Box<dyn Future<Item = (), Error = Error>>) {
+        let id = queue.next_id.fetch_add(1, Ordering::SeqCst);
+        let place = QueuePlace {
+            queue: queue.clone(),
+            account,
+            id,
+        };
+        let mut accounts = queue.accounts.lock().unwrap();
+        let waiters = accounts.entry(account).or_default();
+        if waiters.is_empty() {
+            waiters.push_back(Waiter { id, wake: None });
+            return (place, Box::new(futures::future::ok(())));
+        }
+        let (wake, woken) = oneshot::channel();
+        waiters.push_back(Waiter {
+            id,
+            wake: Some(wake),
+        });
+        (place, Box::new(woken.from_err()))
+    }
+
+    fn leave(&self, account: Address, id: u64) {
+        let mut accounts = self.accounts.lock().unwrap();
+        let waiters = match accounts.get_mut(&account) {
+            Some(waiters) => waiters,
+            None => return,
+        };
+        let was_running = waiters.front().map(|waiter| waiter.id) == Some(id);
+        waiters.retain(|waiter| waiter.id != id);
+        if waiters.is_empty() {
+            accounts.remove(&account);
+        } else if was_running {
+            if let Some(wake) = waiters.front_mut().and_then(|waiter| waiter.wake.take()) {
+                // the next call may have been dropped already, it leaves the queue then
+                let _ = wake.send(());
+            }
+        }
+    }
+}
+
+/// A call's place in its account's queue, given up when dropped
+struct QueuePlace {
+    queue: Arc<OperationQueue>,
+    account: Address,
+    id: u64,
+}
+
+impl Drop for QueuePlace {
+    fn drop(&mut self) {
+        self.queue.leave(self.account, self.id);
+    }
+}
+
+impl TokenBridge {
+    /// Runs the state changing call made by `call` once every call of our account queued
+    /// before it has finished. `call` is given the bridge to make it with, which holds the
+    /// turn, right away if this bridge holds it already.
+    pub(crate) fn in_account_order<T, F>(&self, call: F) -> Box<dyn Future<Item = T, Error = Error>>
+    where
+        T: 'static,
+        F: FnOnce(TokenBridge) -> Box<dyn Future<Item = T, Error = Error>> + 'static,
+    {
+        if self.holds_account_turn {
+            return call(self.clone());
+        }
+        let (place, turn) = OperationQueue::enqueue(&self.operation_queue, self.own_address);
+        let mut salf = self.clone();
+        salf.holds_account_turn = true;
+        Box::new(turn.and_then(move |_| {
+            call(salf).then(move |result| {
+                drop(place);
+                result
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_bridge, MockBlockReader};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_calls_run_in_order() {
+        let bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        let started = Rc::new(RefCell::new(Vec::new()));
+        let call = |n: u32| {
+            let started = started.clone();
+            move |_| {
+                started.borrow_mut().push(n);
+                Box::new(futures::future::ok(n)) as Box<dyn Future<Item = u32, Error = Error>>
+            }
+        };
+
+        let first = bridge.in_account_order(call(1));
+        let second = bridge.clone().in_account_order(call(2));
+        let third = bridge.in_account_order(call(3));
+        let fourth = bridge.in_account_order(call(4));
+        assert_eq!(bridge.operation_queue.len(bridge.own_address), 4);
+
+        // calls dropped while waiting or running give up their place
+        drop(third);
+        drop(first);
+        assert_eq!(second.wait().unwrap(), 2);
+        assert_eq!(fourth.wait().unwrap(), 4);
+        assert_eq!(*started.borrow(), vec![2, 4]);
+        assert!(bridge.operation_queue.is_empty(bridge.own_address));
+    }
+
+    #[test]
+    fn test_nested_calls_run_in_turn() {
+        let bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        // a call made by a call that holds the turn doesn't wait for it
+        let nested = bridge.in_account_order(|salf| {
+            salf.in_account_order(|_| {
+                Box::new(futures::future::ok(1u32)) as Box<dyn Future<Item = u32, Error = Error>>
+            })
+        });
+        assert_eq!(nested.wait().unwrap(), 1);
+        assert!(bridge.operation_queue.is_empty(bridge.own_address));
+    }
+}
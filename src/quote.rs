@@ -0,0 +1,105 @@
+//! Both sides of the Uniswap ETH/Dai market for a given size in one call
+
+use crate::abi::decode_output;
+use crate::units::{Dai, Eth};
+use crate::Chain;
+use crate::TokenBridge;
+use failure::Error;
+use futures::Future;
+use num::ToPrimitive;
+
+/// Prices for trading `eth_amount` ETH against Dai on Uniswap, fees and price impact included
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceQuote {
+    pub eth_amount: Eth,
+    /// Dai received for selling `eth_amount` ETH
+    pub sell_quote: Dai,
+    /// Dai it costs to buy `eth_amount` ETH
+    pub buy_quote: Dai,
+    /// Halfway between the two quotes
+    pub mid_price: Dai,
+    /// Difference between the quotes relative to the mid price, in basis points
+    pub spread_bps: u32,
+}
+
+impl PriceQuote {
+    pub fn new(eth_amount: Eth, sell_quote: Dai, buy_quote: Dai) -> PriceQuote {
+        let sell = sell_quote.wei().clone();
+        let buy = buy_quote.wei().clone();
+        let mid = (sell.clone() + buy.clone()) / 2u32.into();
+        let spread_bps = if mid == 0u32.into() || buy <= sell {
+            0
+        } else {
+            ((buy - sell) * 10_000u32.into() / mid.clone())
+                .to_u32()
+                .unwrap_or(u32::MAX)
+        };
+        PriceQuote {
+            eth_amount,
+            sell_quote,
+            buy_quote,
+            mid_price: Dai::from_wei(mid),
+            spread_bps,
+        }
+    }
+}
+
+impl TokenBridge {
+    /// How much Dai has to be sold to buy exactly `eth_amount` ETH
+    pub fn dai_cost_of_eth(&self, eth_amount: Eth) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        let web3 = self.eth_web3.clone();
+        let uniswap_address = self.uniswap_address;
+        let own_address = self.own_address;
+        let eth_amount = eth_amount.into_wei();
+
+        let cost = self.with_retry(Chain::Eth, "getTokenToEthOutputPrice(uint256)", move || {
+            Box::new(
+                web3.contract_call(
+                    uniswap_address,
+                    "getTokenToEthOutputPrice(uint256)",
+                    &[eth_amount.clone().into()],
+                    own_address,
+                )
+                .and_then(|dai_sold| decode_output("getTokenToEthOutputPrice(uint256)", &dai_sold)),
+            )
+        });
+        Box::new(cost.map(Dai::from_wei))
+    }
+
+    /// Quotes buying and selling `eth_amount` ETH for Dai at once
+    pub fn get_price_quote(
+        &self,
+        eth_amount: Eth,
+    ) -> Box<dyn Future<Item = PriceQuote, Error = Error>> {
+        Box::new(
+            self.eth_to_dai_price(eth_amount.clone())
+                .join(self.dai_cost_of_eth(eth_amount.clone()))
+                .map(move |(sell_quote, buy_quote)| {
+                    PriceQuote::new(eth_amount, sell_quote, buy_quote)
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_price_quote() {
+        let quote = PriceQuote::new(
+            Eth::from_wei(1u32.into()),
+            Dai::from_wei(198u32.into()),
+            Dai::from_wei(202u32.into()),
+        );
+        assert_eq!(quote.mid_price, Dai::from_wei(200u32.into()));
+        assert_eq!(quote.spread_bps, 200);
+
+        let empty = PriceQuote::new(
+            Eth::from_wei(1u32.into()),
+            Dai::from_wei(0u32.into()),
+            Dai::from_wei(0u32.into()),
+        );
+        assert_eq!(empty.spread_bps, 0);
+    }
+}
@@ -0,0 +1,458 @@
+//! Keeps the xDai balance of an account within a band by converting from and to ETH, which is
+//! how an Althea router keeps enough xDai around to pay for bandwidth.
+//!
+//! The xDai balance lags behind conversions, the Dai of `eth_to_xdai` is paid out by the bridge
+//! some time after it is sent. Until a conversion shows up in the balance it is counted as if
+//! it had, so a check in between doesn't convert the same shortfall or excess again.
+
+use crate::api::TokenBridgeApi;
+use crate::error::TokenBridgeError;
+use crate::units::{Dai, XDai};
+use crate::TokenBridge;
+use failure::bail;
+use failure::Error;
+use futures::Future;
+use futures::Stream;
+use futures_timer::Interval;
+use num256::Uint256;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Thresholds for the `Rebalancer`, all amounts are in wei of xDai (or Dai, which is the same
+/// thing on the other side of the bridge). Nothing is converted while the balance is between
+/// `low_xdai` and `high_xdai`, outside of that it is brought back to `target_xdai`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RebalancerConfig {
+    pub low_xdai: Uint256,
+    pub target_xdai: Uint256,
+    pub high_xdai: Uint256,
+    /// The most that may be converted in either direction per UTC day
+    pub max_daily_conversion: Uint256,
+    /// How often balances are checked
+    pub interval: Duration,
+    /// Timeout in seconds for each conversion
+    pub timeout: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RebalanceAction {
+    /// The balance is inside the band or the daily cap is used up
+    Nothing,
+    /// Convert ETH worth this much Dai to xDai
+    ToXdai { amount: Uint256 },
+    /// Convert this much xDai to ETH
+    ToEth { amount: Uint256 },
+}
+
+/// Decides what to do for `xdai_balance` when `converted_today` has already been converted
+pub fn plan_rebalance(
+    config: &RebalancerConfig,
+    xdai_balance: Uint256,
+    converted_today: Uint256,
+) -> RebalanceAction {
+    let remaining = if converted_today < config.max_daily_conversion {
+        config.max_daily_conversion.clone() - converted_today
+    } else {
+        0u32.into()
+    };
+    let capped = |amount: Uint256| {
+        if amount > remaining {
+            remaining.clone()
+        } else {
+            amount
+        }
+    };
+
+    if xdai_balance < config.low_xdai {
+        let amount = capped(config.target_xdai.clone() - xdai_balance);
+        if amount == 0u32.into() {
+            warn!("xDai balance is low but the daily conversion cap is used up");
+            return RebalanceAction::Nothing;
+        }
+        RebalanceAction::ToXdai { amount }
+    } else if xdai_balance > config.high_xdai {
+        let amount = capped(xdai_balance - config.target_xdai.clone());
+        if amount == 0u32.into() {
+            warn!("xDai balance is high but the daily conversion cap is used up");
+            return RebalanceAction::Nothing;
+        }
+        RebalanceAction::ToEth { amount }
+    } else {
+        RebalanceAction::Nothing
+    }
+}
+
+/// How much has been converted on which UTC day
+#[derive(Debug, Clone, Default)]
+struct DailyTotal {
+    day: u64,
+    converted: Uint256,
+}
+
+/// A conversion that has been sent but has not fully shown up in the xDai balance yet
+#[derive(Debug, Clone)]
+struct Outstanding {
+    id: u64,
+    action: RebalanceAction,
+    /// The xDai balance the conversion was planned from
+    balance_before: Uint256,
+    /// When the conversion finished, it is given up on `timeout` seconds after that
+    finished: Option<Instant>,
+}
+
+impl Outstanding {
+    /// How much of the conversion is still missing from `xdai_balance`
+    fn remaining(&self, xdai_balance: &Uint256) -> Uint256 {
+        let (amount, moved) = match self.action {
+            RebalanceAction::Nothing => return 0u32.into(),
+            RebalanceAction::ToXdai { ref amount } => {
+                (amount, saturating_sub(xdai_balance, &self.balance_before))
+            }
+            RebalanceAction::ToEth { ref amount } => {
+                (amount, saturating_sub(&self.balance_before, xdai_balance))
+            }
+        };
+        saturating_sub(amount, &moved)
+    }
+}
+
+fn saturating_sub(a: &Uint256, b: &Uint256) -> Uint256 {
+    if a > b {
+        a.clone() - b.clone()
+    } else {
+        0u32.into()
+    }
+}
+
+#[derive(Clone)]
+pub struct Rebalancer {
+    bridge: Arc<dyn TokenBridgeApi>,
+    config: RebalancerConfig,
+    daily_total: Arc<Mutex<DailyTotal>>,
+    outstanding: Arc<Mutex<Vec<Outstanding>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Rebalancer {
+    pub fn new(bridge: TokenBridge, config: RebalancerConfig) -> Result<Rebalancer, Error> {
+        Rebalancer::with_api(Arc::new(bridge), config)
+    }
+
+    /// A rebalancer converting through any `TokenBridgeApi`, such as a `SimulatedTokenBridge`
+    pub fn with_api(
+        bridge: Arc<dyn TokenBridgeApi>,
+        config: RebalancerConfig,
+    ) -> Result<Rebalancer, Error> {
+        if !(config.low_xdai <= config.target_xdai && config.target_xdai <= config.high_xdai) {
+            bail!("Rebalancer thresholds must satisfy low <= target <= high");
+        }
+        Ok(Rebalancer {
+            bridge,
+            config,
+            daily_total: Arc::new(Mutex::new(DailyTotal::default())),
+            outstanding: Arc::new(Mutex::new(Vec::new())),
+            next_id: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// The amount converted so far today
+    pub fn converted_today(&self) -> Uint256 {
+        let mut daily_total = self.daily_total.lock().unwrap();
+        let today = current_day();
+        if daily_total.day != today {
+            *daily_total = DailyTotal {
+                day: today,
+                converted: 0u32.into(),
+            };
+        }
+        daily_total.converted.clone()
+    }
+
+    fn add_converted(&self, amount: Uint256) {
+        self.converted_today();
+        let mut daily_total = self.daily_total.lock().unwrap();
+        daily_total.converted = daily_total.converted.clone() + amount;
+    }
+
+    /// `xdai_balance` with the conversions that haven't arrived yet counted in, conversions
+    /// that have arrived or been given up on are forgotten
+    fn settled_balance(&self, xdai_balance: Uint256) -> Uint256 {
+        let timeout = Duration::from_secs(self.config.timeout);
+        let mut outstanding = self.outstanding.lock().unwrap();
+        outstanding.retain(|conversion| {
+            let expired = conversion
+                .finished
+                .map(|finished| finished.elapsed() >= timeout)
+                .unwrap_or(false);
+            !expired && conversion.remaining(&xdai_balance) > 0u32.into()
+        });
+        outstanding
+            .iter()
+            .fold(xdai_balance.clone(), |balance, conversion| {
+                let remaining = conversion.remaining(&xdai_balance);
+                match conversion.action {
+                    RebalanceAction::ToEth { .. } => saturating_sub(&balance, &remaining),
+                    _ => balance + remaining,
+                }
+            })
+    }
+
+    /// Remembers `action`, planned from `xdai_balance`, as sent. Returns its id.
+    fn add_outstanding(&self, action: RebalanceAction, xdai_balance: Uint256) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.outstanding.lock().unwrap().push(Outstanding {
+            id,
+            action,
+            balance_before: xdai_balance,
+            finished: None,
+        });
+        id
+    }
+
+    /// Marks conversion `id` as finished. A conversion that failed without timing out didn't
+    /// move anything and is forgotten right away.
+    fn finish_outstanding(&self, id: u64, moved_funds: bool) {
+        let mut outstanding = self.outstanding.lock().unwrap();
+        if moved_funds {
+            if let Some(conversion) = outstanding.iter_mut().find(|c| c.id == id) {
+                conversion.finished = Some(Instant::now());
+            }
+        } else {
+            outstanding.retain(|c| c.id != id);
+        }
+    }
+
+    /// Checks the xDai balance once and converts if it is outside the band. Returns what was
+    /// done.
+    pub fn rebalance_once(&self) -> Box<dyn Future<Item = RebalanceAction, Error = Error>> {
+        let salf = self.clone();
+        let bridge = self.bridge.clone();
+
+        Box::new(
+            bridge
+                .get_xdai_balance(bridge.own_address())
+                .and_then(move |xdai_balance| {
+                    let xdai_balance = xdai_balance.into_wei();
+                    let action = plan_rebalance(
+                        &salf.config,
+                        salf.settled_balance(xdai_balance.clone()),
+                        salf.converted_today(),
+                    );
+                    trace!("rebalancer action {:?}", action);
+                    let timeout = salf.config.timeout;
+                    // counted as it is sent, a conversion that fails or times out after
+                    // that may still have moved the funds
+                    let conversion: Box<dyn Future<Item = (), Error = Error>> = match action {
+                        RebalanceAction::Nothing => Box::new(futures::future::ok(())),
+                        RebalanceAction::ToXdai { ref amount } => {
+                            let salf = salf.clone();
+                            let amount = amount.clone();
+                            Box::new(
+                                salf.bridge
+                                    .eth_cost_of_dai(Dai::from_wei(amount.clone()))
+                                    .and_then(move |eth_amount| {
+                                        salf.add_converted(amount.clone());
+                                        let id = salf.add_outstanding(
+                                            RebalanceAction::ToXdai { amount },
+                                            xdai_balance,
+                                        );
+                                        salf.bridge
+                                            .eth_to_xdai(eth_amount, timeout)
+                                            .then(move |res| salf.finished(id, res))
+                                    }),
+                            )
+                        }
+                        RebalanceAction::ToEth { ref amount } => {
+                            salf.add_converted(amount.clone());
+                            let id = salf.add_outstanding(action.clone(), xdai_balance);
+                            let salf = salf.clone();
+                            Box::new(
+                                salf.bridge
+                                    .xdai_to_eth(XDai::from_wei(amount.clone()), timeout)
+                                    .then(move |res| salf.finished(id, res)),
+                            )
+                        }
+                    };
+                    conversion.map(move |_| {
+                        if action != RebalanceAction::Nothing {
+                            info!("Rebalanced {:?}", action);
+                        }
+                        action
+                    })
+                }),
+        )
+    }
+
+    fn finished<T>(&self, id: u64, res: Result<T, Error>) -> Result<(), Error> {
+        let moved_funds = match res {
+            Ok(_) => true,
+            Err(ref e) => matches!(e.downcast_ref(), Some(TokenBridgeError::TimedOut { .. })),
+        };
+        self.finish_outstanding(id, moved_funds);
+        res.map(|_| ())
+    }
+
+    /// Rebalances every `interval` forever. Failed rounds are logged and retried on the next
+    /// tick.
+    pub fn run(self) -> Box<dyn Future<Item = (), Error = Error>> {
+        Box::new(
+            Interval::new(self.config.interval)
+                .from_err()
+                .for_each(move |_| {
+                    self.rebalance_once().then(|res| {
+                        if let Err(e) = res {
+                            error!("Rebalancing failed {:?}", e);
+                        }
+                        Ok(())
+                    })
+                }),
+        )
+    }
+}
+
+fn current_day() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs() / SECONDS_PER_DAY)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulated::SimulatedTokenBridge;
+    use crate::units::Eth;
+    use clarity::Address;
+
+    fn config() -> RebalancerConfig {
+        RebalancerConfig {
+            low_xdai: 10u32.into(),
+            target_xdai: 20u32.into(),
+            high_xdai: 40u32.into(),
+            max_daily_conversion: 15u32.into(),
+            interval: Duration::from_secs(60),
+            timeout: 600,
+        }
+    }
+
+    #[test]
+    fn test_plan_rebalance() {
+        let config = config();
+        assert_eq!(
+            plan_rebalance(&config, 15u32.into(), 0u32.into()),
+            RebalanceAction::Nothing
+        );
+        assert_eq!(
+            plan_rebalance(&config, 8u32.into(), 0u32.into()),
+            RebalanceAction::ToXdai {
+                amount: 12u32.into()
+            }
+        );
+        assert_eq!(
+            plan_rebalance(&config, 50u32.into(), 0u32.into()),
+            RebalanceAction::ToEth {
+                amount: 15u32.into()
+            }
+        );
+        // capped by what is left of the daily limit
+        assert_eq!(
+            plan_rebalance(&config, 8u32.into(), 10u32.into()),
+            RebalanceAction::ToXdai {
+                amount: 5u32.into()
+            }
+        );
+        assert_eq!(
+            plan_rebalance(&config, 8u32.into(), 15u32.into()),
+            RebalanceAction::Nothing
+        );
+    }
+
+    #[test]
+    fn test_rebalance_once() {
+        let simulated = SimulatedTokenBridge::new(
+            Address::from_slice(&[1u8; 20]).unwrap(),
+            Dai::from_wei(2_000_000_000_000_000_000u64.into()),
+        );
+        let own_address = simulated.own_address();
+        simulated.set_eth_balance(own_address, Eth::from_wei(1_000u32.into()));
+        simulated.set_xdai_balance(own_address, XDai::from_wei(8u32.into()));
+        let rebalancer = Rebalancer::with_api(Arc::new(simulated.clone()), config()).unwrap();
+
+        assert_eq!(
+            rebalancer.rebalance_once().wait().unwrap(),
+            RebalanceAction::ToXdai {
+                amount: 12u32.into()
+            }
+        );
+        assert_eq!(
+            simulated.get_xdai_balance(own_address).wait().unwrap(),
+            XDai::from_wei(20u32.into())
+        );
+        assert_eq!(rebalancer.converted_today(), 12u32.into());
+        assert_eq!(
+            rebalancer.rebalance_once().wait().unwrap(),
+            RebalanceAction::Nothing
+        );
+    }
+
+    #[test]
+    fn test_timed_out_conversion_counts() {
+        let simulated = SimulatedTokenBridge::new(
+            Address::from_slice(&[1u8; 20]).unwrap(),
+            Dai::from_wei(2_000_000_000_000_000_000u64.into()),
+        );
+        let own_address = simulated.own_address();
+        simulated.set_eth_balance(own_address, Eth::from_wei(1_000u32.into()));
+        simulated.set_xdai_balance(own_address, XDai::from_wei(8u32.into()));
+        // slower than the timeout, executed but reported as timed out
+        simulated.set_latency(Duration::from_secs(700));
+        let rebalancer = Rebalancer::with_api(Arc::new(simulated), config()).unwrap();
+
+        assert!(rebalancer.rebalance_once().wait().is_err());
+        assert_eq!(rebalancer.converted_today(), 12u32.into());
+    }
+
+    #[test]
+    fn test_conversion_not_arrived_yet() {
+        let simulated = SimulatedTokenBridge::new(
+            Address::from_slice(&[1u8; 20]).unwrap(),
+            Dai::from_wei(2_000_000_000_000_000_000u64.into()),
+        );
+        let own_address = simulated.own_address();
+        simulated.set_eth_balance(own_address, Eth::from_wei(1_000u32.into()));
+        simulated.set_xdai_balance(own_address, XDai::from_wei(8u32.into()));
+        simulated.set_bridge_delay(Duration::from_secs(300));
+        let rebalancer = Rebalancer::with_api(Arc::new(simulated.clone()), config()).unwrap();
+
+        assert_eq!(
+            rebalancer.rebalance_once().wait().unwrap(),
+            RebalanceAction::ToXdai {
+                amount: 12u32.into()
+            }
+        );
+        // the bridge hasn't paid out yet, the balance is still low
+        assert_eq!(
+            simulated.get_xdai_balance(own_address).wait().unwrap(),
+            XDai::from_wei(8u32.into())
+        );
+        assert_eq!(
+            rebalancer.rebalance_once().wait().unwrap(),
+            RebalanceAction::Nothing
+        );
+        assert_eq!(rebalancer.converted_today(), 12u32.into());
+
+        simulated.advance(Duration::from_secs(300));
+        assert_eq!(
+            simulated.get_xdai_balance(own_address).wait().unwrap(),
+            XDai::from_wei(20u32.into())
+        );
+        assert_eq!(
+            rebalancer.rebalance_once().wait().unwrap(),
+            RebalanceAction::Nothing
+        );
+        assert!(rebalancer.outstanding.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,240 @@
+//! Receipts of completed conversions for payout and accounting records. A receipt is signed with
+//! the key of the node that made the conversion, so that whoever collects them can check they
+//! were not edited afterwards.
+
+use crate::address_book::AddressBook;
+use crate::operations::OperationKind;
+use crate::Chain;
+use clarity::utils::bytes_to_hex_str;
+use clarity::{Address, PrivateKey, Signature};
+use failure::Error;
+use num256::Uint256;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+
+/// The columns of `Receipt::to_csv_row`
+pub const RECEIPT_CSV_HEADER: &str = "operation_id,kind,address,amount_in,amount_out,bridge_fee,\
+eth_gas_cost,xdai_gas_cost,eth_price,started_at,completed_at,transactions";
+
+/// The columns of `Receipt::to_labeled_csv_row`, `RECEIPT_CSV_HEADER` and the label of `address`
+pub const LABELED_RECEIPT_CSV_HEADER: &str = "operation_id,kind,address,amount_in,amount_out,\
+bridge_fee,eth_gas_cost,xdai_gas_cost,eth_price,started_at,completed_at,transactions,address_label";
+
+/// A transaction sent as part of a conversion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReceiptTx {
+    pub chain: Chain,
+    pub tx_hash: Uint256,
+}
+
+/// A completed ETH <-> xDai conversion. All amounts are in wei.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    /// The id of the `Operation` that made the conversion
+    pub operation_id: String,
+    pub kind: OperationKind,
+    /// The address that converted
+    pub address: Address,
+    /// The ETH or xDai put in
+    pub amount_in: Uint256,
+    /// The xDai or ETH received
+    pub amount_out: Uint256,
+    /// The Dai kept by the bridge
+    pub bridge_fee: Uint256,
+    /// Gas paid on Eth, in ETH
+    pub eth_gas_cost: Uint256,
+    /// Gas paid on xDai, in xDai
+    pub xdai_gas_cost: Uint256,
+    /// The Dai one ETH was swapped at
+    pub eth_price: Uint256,
+    /// Unix time in seconds
+    pub started_at: u64,
+    /// Unix time in seconds
+    pub completed_at: u64,
+    pub transactions: Vec<ReceiptTx>,
+}
+
+/// Quotes `field` if it would otherwise break up a CSV row
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl Receipt {
+    /// The Keccak-256 hash of the receipt's JSON, which is what gets signed
+    pub fn hash(&self) -> Result<[u8; 32], Error> {
+        let json = serde_json::to_vec(self)?;
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&Keccak256::digest(&json));
+        Ok(hash)
+    }
+
+    pub fn sign(self, key: &PrivateKey) -> Result<SignedReceipt, Error> {
+        let signature = key.sign_hash(&self.hash()?);
+        Ok(SignedReceipt {
+            receipt: self,
+            v: signature.v,
+            r: signature.r,
+            s: signature.s,
+        })
+    }
+
+    /// The receipt as a row under `RECEIPT_CSV_HEADER`, transactions are listed in one column as
+    /// `chain:hash` separated by spaces
+    pub fn to_csv_row(&self) -> String {
+        let transactions: Vec<String> = self
+            .transactions
+            .iter()
+            .map(|tx| {
+                let hash: [u8; 32] = tx.tx_hash.clone().into();
+                format!("{:?}:0x{}", tx.chain, bytes_to_hex_str(&hash))
+            })
+            .collect();
+        [
+            csv_field(&self.operation_id),
+            format!("{:?}", self.kind),
+            self.address.to_string(),
+            self.amount_in.to_string(),
+            self.amount_out.to_string(),
+            self.bridge_fee.to_string(),
+            self.eth_gas_cost.to_string(),
+            self.xdai_gas_cost.to_string(),
+            self.eth_price.to_string(),
+            self.started_at.to_string(),
+            self.completed_at.to_string(),
+            transactions.join(" "),
+        ]
+        .join(",")
+    }
+
+    /// `to_csv_row` with the label `book` has for `address`, empty if it has none
+    pub fn to_labeled_csv_row(&self, book: &AddressBook) -> String {
+        format!(
+            "{},{}",
+            self.to_csv_row(),
+            csv_field(book.label(self.address).unwrap_or(""))
+        )
+    }
+}
+
+/// A `Receipt` with the signature of the node that made it over `Receipt::hash`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedReceipt {
+    pub receipt: Receipt,
+    pub v: Uint256,
+    pub r: Uint256,
+    pub s: Uint256,
+}
+
+impl SignedReceipt {
+    /// The address that signed the receipt. Check it against `receipt.address` or a known node
+    /// address, a receipt signed by anyone else may have been made up.
+    pub fn signer(&self) -> Result<Address, Error> {
+        let signature = Signature::new(self.v.clone(), self.r.clone(), self.s.clone());
+        signature.recover(&self.receipt.hash()?)
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<SignedReceipt, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// `receipts` as a CSV document with a header row, without the signatures
+pub fn receipts_to_csv(receipts: &[SignedReceipt]) -> String {
+    let mut csv = String::from(RECEIPT_CSV_HEADER);
+    csv.push('\n');
+    for signed in receipts {
+        csv.push_str(&signed.receipt.to_csv_row());
+        csv.push('\n');
+    }
+    csv
+}
+
+/// `receipts_to_csv` with the label `book` has for the address of each receipt
+pub fn receipts_to_labeled_csv(receipts: &[SignedReceipt], book: &AddressBook) -> String {
+    let mut csv = String::from(LABELED_RECEIPT_CSV_HEADER);
+    csv.push('\n');
+    for signed in receipts {
+        csv.push_str(&signed.receipt.to_labeled_csv_row(book));
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ether(amount: u64) -> Uint256 {
+        Uint256::from(amount) * 1_000_000_000_000_000_000u64.into()
+    }
+
+    fn receipt() -> Receipt {
+        Receipt {
+            operation_id: "00000000000000ff".to_string(),
+            kind: OperationKind::EthToXdai,
+            address: Address::default(),
+            amount_in: ether(1),
+            amount_out: ether(199),
+            bridge_fee: ether(1),
+            eth_gas_cost: 2_000_000_000_000_000u64.into(),
+            xdai_gas_cost: 0u32.into(),
+            eth_price: ether(200),
+            started_at: 1_600_000_000,
+            completed_at: 1_600_000_600,
+            transactions: vec![
+                ReceiptTx {
+                    chain: Chain::Eth,
+                    tx_hash: 1u32.into(),
+                },
+                ReceiptTx {
+                    chain: Chain::Eth,
+                    tx_hash: 2u32.into(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_receipt_csv() {
+        let columns = RECEIPT_CSV_HEADER.split(',').count();
+        let row = receipt().to_csv_row();
+        assert_eq!(row.split(',').count(), columns);
+        assert!(row.starts_with("00000000000000ff,EthToXdai,"));
+        assert!(row.ends_with(&format!("Eth:0x{:064x} Eth:0x{:064x}", 1, 2)));
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+
+        let mut book = AddressBook::new();
+        book.insert(Address::default(), "Router, east");
+        let labeled = receipt().to_labeled_csv_row(&book);
+        assert_eq!(labeled, format!("{},\"Router, east\"", row));
+        assert_eq!(LABELED_RECEIPT_CSV_HEADER.split(',').count(), columns + 1);
+    }
+
+    #[test]
+    fn test_receipt_hash() {
+        let mut edited = receipt();
+        edited.amount_out = ether(299);
+        assert_eq!(receipt().hash().unwrap(), receipt().hash().unwrap());
+        assert_ne!(receipt().hash().unwrap(), edited.hash().unwrap());
+    }
+
+    #[test]
+    fn test_signed_receipt_json() {
+        let signed = SignedReceipt {
+            receipt: receipt(),
+            v: 27u32.into(),
+            r: 1u32.into(),
+            s: 2u32.into(),
+        };
+        let json = signed.to_json().unwrap();
+        assert_eq!(SignedReceipt::from_json(&json).unwrap(), signed);
+    }
+}
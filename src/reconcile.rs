@@ -0,0 +1,200 @@
+//! Finding out what happened to a transaction when waiting for its event failed, so that a
+//! timeout says whether money moved instead of leaving the caller to guess.
+
+use crate::audit::TxStatus;
+use crate::error::TimeoutOutcome;
+use crate::logs::EventDefinition;
+use crate::timeouts::Timeouts;
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::utils::bytes_to_hex_str;
+use clarity::Address;
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use futures_timer::FutureExt;
+use num256::Uint256;
+use std::time::Duration;
+use web30::types::{Log, NewFilter};
+
+/// Whether `log` was emitted by the transaction `tx_hash`
+pub(crate) fn emitted_by(log: &Log, tx_hash: &Uint256) -> bool {
+    match log.transaction_hash {
+        // compare as numbers, the hash may or may not have leading zeros
+        Some(ref found) => Uint256::from_bytes_be(found) == *tx_hash,
+        None => false,
+    }
+}
+
+/// Picks the outcome from the block `tx_hash` was mined in, if any, and the logs of `event` in
+/// that block
+fn outcome_from_logs(
+    tx_hash: Uint256,
+    mined: bool,
+    logs: &[Log],
+    event: EventDefinition,
+    amount_param: &'static str,
+) -> Result<TimeoutOutcome, Error> {
+    if !mined {
+        return Ok(TimeoutOutcome::Unknown(tx_hash));
+    }
+    match logs.iter().find(|log| emitted_by(log, &tx_hash)) {
+        Some(log) => Ok(TimeoutOutcome::ExecutedButLate(
+            event.decode(log)?.uint(amount_param)?,
+        )),
+        // mined without the event means it reverted
+        None => Ok(TimeoutOutcome::DefinitelyNotExecuted),
+    }
+}
+
+impl TokenBridge {
+    /// Looks up whether `tx_hash` was mined on `chain` and, if it was, whether it emitted
+    /// `event` from `contract`. The amount reported for an executed transaction is the
+    /// `amount_param` parameter of the event.
+    pub fn reconcile_transaction(
+        &self,
+        chain: Chain,
+        tx_hash: Uint256,
+        contract: Address,
+        event: EventDefinition,
+        amount_param: &'static str,
+    ) -> Box<dyn Future<Item = TimeoutOutcome, Error = Error>> {
+        let web3 = self.web3(chain);
+        Box::new(
+            web3.eth_get_transaction_by_hash(tx_hash.clone())
+                .and_then(move |tx| {
+                    let block = match tx.and_then(|tx| tx.block_number) {
+                        Some(block) => block,
+                        None => {
+                            return Box::new(futures::future::ok(TimeoutOutcome::Unknown(tx_hash)))
+                                as Box<dyn Future<Item = _, Error = Error>>
+                        }
+                    };
+                    let block = format!("0x{}", block.to_str_radix(16));
+                    let filter = NewFilter {
+                        from_block: Some(block.clone()),
+                        to_block: Some(block),
+                        address: vec![contract],
+                        topics: Some(vec![Some(vec![Some(format!(
+                            "0x{}",
+                            bytes_to_hex_str(&event.topic0())
+                        ))])]),
+                    };
+                    Box::new(web3.eth_get_logs(filter).and_then(move |logs| {
+                        outcome_from_logs(tx_hash, true, &logs, event, amount_param)
+                    }))
+                }),
+        )
+    }
+
+    /// Waits for `tx_hash` to be mined and checks that it emitted `event` from `contract`, as
+    /// opposed to someone else's transaction emitting it. Resolves to the `amount_param`
+    /// parameter of the event or fails with `TokenBridgeError::TransactionReverted`.
+    pub(crate) fn confirm_transaction(
+        &self,
+        chain: Chain,
+        tx_hash: Uint256,
+        contract: Address,
+        event: EventDefinition,
+        amount_param: &'static str,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let salf = self.clone();
+        Box::new(
+            self.web3(chain)
+                .wait_for_transaction(tx_hash.clone().into())
+                .and_then(move |_| {
+                    salf.reconcile_transaction(
+                        chain,
+                        tx_hash.clone(),
+                        contract,
+                        event,
+                        amount_param,
+                    )
+                    .and_then(move |outcome| match outcome {
+                        TimeoutOutcome::ExecutedButLate(amount) => Ok(amount),
+                        TimeoutOutcome::DefinitelyNotExecuted => {
+                            Err(TokenBridgeError::TransactionReverted { tx_hash }.into())
+                        }
+                        TimeoutOutcome::Unknown(_) => Err(format_err!(
+                            "Transaction {} was mined but the node does not know it",
+                            tx_hash
+                        )),
+                    })
+                }),
+        )
+    }
+
+    /// Waits up to `timeout` for `wait`, the wait for the event of the already sent `tx_hash`.
+    /// If it fails the transaction is reconciled, see `reconcile_transaction`, and the error is
+    /// `TokenBridgeError::TimedOut` with the outcome. A `TokenBridgeError::TransactionReverted`
+    /// from `wait` is passed on as it is.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn wait_or_reconcile<T: 'static>(
+        &self,
+        chain: Chain,
+        tx_hash: Uint256,
+        wait: Box<dyn Future<Item = T, Error = Error>>,
+        timeout: Duration,
+        contract: Address,
+        event: EventDefinition,
+        amount_param: &'static str,
+    ) -> Box<dyn Future<Item = T, Error = Error>> {
+        let salf = self.clone();
+        let (audited, executed) = (self.clone(), tx_hash.clone());
+        let timeout = Timeouts::cap(timeout, self.timeouts.confirmation);
+        let wait = wait.timeout(timeout).map(move |val| {
+            audited.audit_settled(chain, executed, TxStatus::Executed);
+            val
+        });
+        Box::new(wait.or_else(move |e| {
+            if let Some(TokenBridgeError::TransactionReverted { .. }) = e.downcast_ref() {
+                salf.audit_settled(chain, tx_hash, TxStatus::NotExecuted);
+                return Box::new(futures::future::err(e))
+                    as Box<dyn Future<Item = T, Error = Error>>;
+            }
+            warn!(
+                "Waiting for {} of transaction {} failed with {:?}, reconciling",
+                event.signature, tx_hash, e
+            );
+            Box::new(
+                salf.reconcile_transaction(chain, tx_hash.clone(), contract, event, amount_param)
+                    .then(move |outcome| {
+                        let outcome = match outcome {
+                            Ok(outcome) => outcome,
+                            Err(e) => {
+                                warn!("Reconciling transaction {} failed with {:?}", tx_hash, e);
+                                TimeoutOutcome::Unknown(tx_hash.clone())
+                            }
+                        };
+                        let status = match outcome {
+                            TimeoutOutcome::DefinitelyNotExecuted => TxStatus::NotExecuted,
+                            TimeoutOutcome::ExecutedButLate(_) => TxStatus::Executed,
+                            TimeoutOutcome::Unknown(_) => TxStatus::Unknown,
+                        };
+                        salf.audit_settled(chain, tx_hash, status);
+                        Err(TokenBridgeError::TimedOut { outcome }.into())
+                    }),
+            )
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logs::ERC20_APPROVAL;
+
+    #[test]
+    fn test_outcome_from_logs() {
+        let tx_hash: Uint256 = 7u32.into();
+        assert_eq!(
+            outcome_from_logs(tx_hash.clone(), false, &[], ERC20_APPROVAL, "value").unwrap(),
+            TimeoutOutcome::Unknown(tx_hash.clone())
+        );
+        assert_eq!(
+            outcome_from_logs(tx_hash, true, &[], ERC20_APPROVAL, "value").unwrap(),
+            TimeoutOutcome::DefinitelyNotExecuted
+        );
+    }
+}
@@ -0,0 +1,715 @@
+//! Gasless Dai to xDai bridging for routers that hold Dai on Eth but no ETH to pay gas with.
+//! The router signs a `RelayRequest` and, the first time, a Dai `permit()` for the relayer,
+//! then posts them to a relayer that sends the transactions and takes its fee in Dai.
+//!
+//! The relayer pulls the Dai with `transferFrom` and sends it straight to the foreign bridge,
+//! which pays it out to the router on xDai like any other transfer from it. A Dai permit can't
+//! be limited to an amount, so the relayer is trusted with all of the router's Dai on Eth, only
+//! use a relayer you run or trust. `RelayVerifier` holds the checks a relayer has to make
+//! before sending anything for a request, give it a `RelayedStore` so that a restart doesn't
+//! let the requests it already relayed through again.
+
+use crate::contracts::ETH_MAINNET_CHAIN_ID;
+use crate::encoding;
+use crate::fee::{bridge_fee_amount, BridgeDirection, BridgeTransfer};
+use crate::message::is_signed_by;
+use crate::metrics;
+use crate::pending::ConversionKind;
+use crate::spending::SpendAsset;
+use crate::typed_data::{dai_permit, typed_data_hash, Eip712Domain, TypedStruct};
+use crate::units::Dai;
+use crate::withdrawal::split_signature;
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::bail;
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use futures_timer::FutureExt;
+use num256::Uint256;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a relay request and the permit sent with it stay valid, in seconds
+pub const RELAY_REQUEST_LIFETIME: u64 = 60 * 60;
+
+/// Takes `amount` Dai from `from`, pays `fee` of it to `relayer` and bridges the rest to `from`
+/// on xDai. `nonce` is random, it only makes requests for the same amount distinct.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayRequest {
+    pub from: Address,
+    pub relayer: Address,
+    pub amount: Uint256,
+    pub fee: Uint256,
+    pub nonce: Uint256,
+    /// Unix time in seconds after which the request may no longer be relayed
+    pub deadline: Uint256,
+}
+
+impl RelayRequest {
+    pub fn typed_struct(&self) -> TypedStruct {
+        TypedStruct::new("RelayRequest")
+            .address("from", self.from)
+            .address("relayer", self.relayer)
+            .uint("amount", self.amount.clone())
+            .uint("fee", self.fee.clone())
+            .uint("nonce", self.nonce.clone())
+            .uint("deadline", self.deadline.clone())
+    }
+}
+
+/// The domain relay requests are signed in, tied to the foreign bridge they go to
+pub fn relay_domain(chain_id: u64, foreign_bridge: Address) -> Eip712Domain {
+    Eip712Domain {
+        name: Some("Auto Bridge Relay".to_string()),
+        version: Some("1".to_string()),
+        chain_id: Some(chain_id.into()),
+        verifying_contract: Some(foreign_bridge),
+        salt: None,
+    }
+}
+
+/// A signed Dai `permit()` allowing the relayer of the request it is sent with to spend all of
+/// the sender's Dai, see `typed_data::dai_permit`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayPermit {
+    /// The sender's `nonces()` on the Dai contract
+    pub nonce: Uint256,
+    pub expiry: Uint256,
+    /// 65 byte r ++ s ++ v
+    pub signature: Vec<u8>,
+}
+
+/// What a router posts to a relayer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedRelayRequest {
+    pub request: RelayRequest,
+    /// 65 byte r ++ s ++ v signature of `request` by `request.from`
+    pub signature: Vec<u8>,
+    /// Left out once the relayer is already approved
+    pub permit: Option<RelayPermit>,
+}
+
+/// A relayer's address and the fee it charges for relaying an amount
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayQuote {
+    pub relayer: Address,
+    pub fee: Uint256,
+}
+
+/// A relayer's answer to a `SignedRelayRequest`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayResponse {
+    /// The transaction sending the Dai to the foreign bridge
+    pub tx_hash: Uint256,
+}
+
+/// A relayer, see `HttpRelayer` for one reached over HTTP
+pub trait RelayerApi: Send + Sync {
+    /// What relaying `amount` Dai would cost
+    fn quote(&self, amount: &Uint256) -> Box<dyn Future<Item = RelayQuote, Error = Error>>;
+
+    /// Submits `request`, resolves to the hash of the transaction to the foreign bridge
+    fn relay(
+        &self,
+        request: &SignedRelayRequest,
+    ) -> Box<dyn Future<Item = RelayResponse, Error = Error>>;
+}
+
+/// A relayer serving `GET {base_url}/quote?amount=` with a `RelayQuote` and
+/// `POST {base_url}/relay` of a `SignedRelayRequest` with a `RelayResponse`, both as JSON
+#[cfg(feature = "relayer")]
+pub struct HttpRelayer {
+    pub base_url: String,
+    client: reqwest::r#async::Client,
+}
+
+#[cfg(feature = "relayer")]
+impl HttpRelayer {
+    pub fn new(base_url: &str, timeout: Duration) -> Result<HttpRelayer, Error> {
+        Ok(HttpRelayer {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: reqwest::r#async::Client::builder()
+                .timeout(timeout)
+                .build()?,
+        })
+    }
+}
+
+#[cfg(feature = "relayer")]
+impl RelayerApi for HttpRelayer {
+    fn quote(&self, amount: &Uint256) -> Box<dyn Future<Item = RelayQuote, Error = Error>> {
+        Box::new(
+            self.client
+                .get(&format!("{}/quote?amount={}", self.base_url, amount))
+                .send()
+                .and_then(|response| response.error_for_status())
+                .and_then(|mut response| response.text())
+                .from_err()
+                .and_then(|body| Ok(serde_json::from_str(&body)?)),
+        )
+    }
+
+    fn relay(
+        &self,
+        request: &SignedRelayRequest,
+    ) -> Box<dyn Future<Item = RelayResponse, Error = Error>> {
+        Box::new(
+            self.client
+                .post(&format!("{}/relay", self.base_url))
+                .json(request)
+                .send()
+                .and_then(|response| response.error_for_status())
+                .and_then(|mut response| response.text())
+                .from_err()
+                .and_then(|body| Ok(serde_json::from_str(&body)?)),
+        )
+    }
+}
+
+/// A request relayed by a `RelayVerifier`, kept until its deadline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayedRequest {
+    /// The EIP-712 hash of the request
+    pub hash: [u8; 32],
+    /// Unix time in seconds after which it can't be relayed anymore
+    pub deadline: u64,
+}
+
+/// Where the requests a relayer has relayed are kept between restarts. Without one a restarted
+/// relayer would relay the same requests again until their deadlines.
+pub trait RelayedStore: Send + Sync {
+    /// Replaces the stored requests with `relayed`
+    fn save(&self, relayed: &[RelayedRequest]) -> Result<(), Error>;
+    fn load(&self) -> Result<Vec<RelayedRequest>, Error>;
+}
+
+/// Keeps the relayed requests in a single JSON file, rewritten on every request
+pub struct JsonRelayedStore {
+    path: PathBuf,
+}
+
+impl JsonRelayedStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> JsonRelayedStore {
+        JsonRelayedStore { path: path.into() }
+    }
+}
+
+impl RelayedStore for JsonRelayedStore {
+    fn save(&self, relayed: &[RelayedRequest]) -> Result<(), Error> {
+        // write then rename so a crash mid write can't leave a truncated file behind
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(relayed)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<RelayedRequest>, Error> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let contents = fs::read_to_string(&self.path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+/// The checks a relayer makes before relaying a request for `dai` on chain `chain_id`, shared
+/// by every request it serves so that none is relayed twice
+pub struct RelayVerifier {
+    pub relayer: Address,
+    pub dai: Address,
+    pub foreign_bridge: Address,
+    pub chain_id: u64,
+    /// Requests paying less are refused
+    pub min_fee: Uint256,
+    /// The requests relayed so far by their hash, with their deadlines so they can be forgotten
+    /// once they can't be relayed anymore
+    relayed: Mutex<HashMap<[u8; 32], u64>>,
+    store: Option<Box<dyn RelayedStore>>,
+}
+
+impl RelayVerifier {
+    /// A verifier that forgets what it relayed on every restart, see `with_store`
+    pub fn new(
+        relayer: Address,
+        dai: Address,
+        foreign_bridge: Address,
+        chain_id: u64,
+        min_fee: Uint256,
+    ) -> RelayVerifier {
+        RelayVerifier {
+            relayer,
+            dai,
+            foreign_bridge,
+            chain_id,
+            min_fee,
+            relayed: Mutex::new(HashMap::new()),
+            store: None,
+        }
+    }
+
+    /// A verifier that carries on from the requests saved in `store`, so that a restarted
+    /// relayer still refuses the ones it relayed before
+    pub fn with_store(
+        relayer: Address,
+        dai: Address,
+        foreign_bridge: Address,
+        chain_id: u64,
+        min_fee: Uint256,
+        store: Box<dyn RelayedStore>,
+    ) -> Result<RelayVerifier, Error> {
+        let relayed = store
+            .load()?
+            .into_iter()
+            .map(|request| (request.hash, request.deadline))
+            .collect();
+        Ok(RelayVerifier {
+            relayed: Mutex::new(relayed),
+            store: Some(store),
+            ..RelayVerifier::new(relayer, dai, foreign_bridge, chain_id, min_fee)
+        })
+    }
+
+    /// Checks that `signed` is for this relayer, pays at least `min_fee`, hasn't expired or
+    /// been relayed before and is signed by its sender, failing with
+    /// `TokenBridgeError::InvalidRelayRequest` if not. A request that passes is counted as
+    /// relayed, and saved if there is a store, the Dai nonce of its permit and the sender's
+    /// balance are only checked on chain.
+    pub fn verify(&self, signed: &SignedRelayRequest) -> Result<(), Error> {
+        self.verify_at(signed, now())
+    }
+
+    fn verify_at(&self, signed: &SignedRelayRequest, now: u64) -> Result<(), Error> {
+        let invalid = |reason: &str| -> Error {
+            TokenBridgeError::InvalidRelayRequest {
+                reason: reason.to_string(),
+            }
+            .into()
+        };
+        let request = &signed.request;
+        if request.relayer != self.relayer {
+            return Err(invalid("for another relayer"));
+        }
+        if request.deadline <= now.into() {
+            return Err(invalid("past its deadline"));
+        }
+        if request.fee < self.min_fee {
+            return Err(invalid("fee too low"));
+        }
+        if request.fee >= request.amount {
+            return Err(invalid("fee not less than the amount"));
+        }
+
+        let domain = relay_domain(self.chain_id, self.foreign_bridge);
+        let hash = typed_data_hash(&domain, &request.typed_struct());
+        if !is_signed_by(request.from, &hash, &signed.signature)? {
+            return Err(invalid("not signed by its sender"));
+        }
+        if let Some(ref permit) = signed.permit {
+            let message = dai_permit(
+                request.from,
+                self.relayer,
+                permit.nonce.clone(),
+                permit.expiry.clone(),
+                true,
+            );
+            let hash = typed_data_hash(&Eip712Domain::dai(self.chain_id, self.dai), &message);
+            if !is_signed_by(request.from, &hash, &permit.signature)? {
+                return Err(invalid("permit not signed by the sender"));
+            }
+        }
+
+        let mut relayed = self.relayed.lock().unwrap();
+        relayed.retain(|_, deadline| *deadline > now);
+        if relayed.contains_key(&hash) {
+            return Err(invalid("already relayed"));
+        }
+        // deadlines beyond u64 are as good as never
+        let deadline = request.deadline.to_string().parse().unwrap_or(u64::MAX);
+        relayed.insert(hash, deadline);
+        if let Some(ref store) = self.store {
+            let requests: Vec<RelayedRequest> = relayed
+                .iter()
+                .map(|(hash, deadline)| RelayedRequest {
+                    hash: *hash,
+                    deadline: *deadline,
+                })
+                .collect();
+            // a request we couldn't save could be relayed again after a restart
+            if let Err(e) = store.save(&requests) {
+                relayed.remove(&hash);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// The calls the relayer sends, in order, for a verified request: the permit if there is
+    /// one, the fee to the relayer and the rest to the foreign bridge
+    pub fn relay_calls(
+        &self,
+        signed: &SignedRelayRequest,
+    ) -> Result<Vec<(Address, Vec<u8>)>, Error> {
+        let request = &signed.request;
+        let mut calls = Vec::new();
+        if let Some(ref permit) = signed.permit {
+            let (v, r, s) = split_signature(&permit.signature)?;
+            calls.push((
+                self.dai,
+                encoding::dai_permit(
+                    request.from,
+                    self.relayer,
+                    permit.nonce.clone(),
+                    permit.expiry.clone(),
+                    true,
+                    if v < 27 { v + 27 } else { v },
+                    &r,
+                    &s,
+                ),
+            ));
+        }
+        if request.fee > 0u32.into() {
+            calls.push((
+                self.dai,
+                encoding::erc20_transfer_from(request.from, self.relayer, request.fee.clone()),
+            ));
+        }
+        calls.push((
+            self.dai,
+            encoding::erc20_transfer_from(
+                request.from,
+                self.foreign_bridge,
+                request.amount.clone() - request.fee.clone(),
+            ),
+        ));
+        Ok(calls)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0)
+}
+
+impl TokenBridge {
+    /// The chain id Eth signatures are made for, mainnet unless `eth_chain_id` says otherwise
+    fn eth_signing_chain_id(&self) -> u64 {
+        self.eth_chain_id.unwrap_or(ETH_MAINNET_CHAIN_ID)
+    }
+
+    /// Like `dai_to_xdai_bridge` but the transactions are sent and paid for by `relayer`, which
+    /// keeps its fee of at most `max_fee` out of `dai_amount`. Resolves once the transfer to
+    /// the bridge is mined, with the amount after the relayer's fee.
+    pub fn dai_to_xdai_gasless(
+        &self,
+        dai_amount: Dai,
+        max_fee: Dai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        // the relayer pays the gas, there is no point selling Dai for it
+        let mut salf = self.clone();
+        salf.gas_top_up = None;
+        salf.send_conversion(
+            ConversionKind::DaiToXdai,
+            dai_amount.wei().clone(),
+            move |salf| salf.run_dai_to_xdai_gasless(dai_amount, max_fee, timeout),
+        )
+    }
+
+    fn run_dai_to_xdai_gasless(
+        &self,
+        dai_amount: Dai,
+        max_fee: Dai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        let relayer = match self.relayer {
+            Some(ref relayer) => relayer.clone(),
+            None => return Box::new(futures::future::err(format_err!("No relayer configured"))),
+        };
+        let dai_amount = dai_amount.into_wei();
+        let max_fee = max_fee.into_wei();
+        let eth_web3 = self.eth_web3.clone();
+        let latencies = self.bridge_latencies.clone();
+
+        Box::new(
+            self.bridge_preflight(BridgeDirection::DaiToXdai)
+                .and_then({
+                    let dai_amount = dai_amount.clone();
+                    let relayer = relayer.clone();
+                    move |_| relayer.quote(&dai_amount)
+                })
+                .and_then({
+                    let dai_amount = dai_amount.clone();
+                    move |quote| {
+                        if quote.fee > max_fee || quote.fee >= dai_amount {
+                            bail!("Relayer fee {} is above the {} allowed", quote.fee, max_fee);
+                        }
+                        Ok(quote)
+                    }
+                })
+                .and_then({
+                    let salf = self.clone();
+                    move |quote| {
+                        salf.sign_relay_request(quote, dai_amount)
+                            .join(salf.get_bridge_fee(BridgeDirection::DaiToXdai))
+                    }
+                })
+                .and_then({
+                    let salf = self.clone();
+                    move |(signed, fee)| {
+                        // the relayer moves our Dai, counted right before it is asked to
+                        salf.record_spending(
+                            SpendAsset::Token(salf.foreign_dai_contract_address),
+                            &signed.request.amount,
+                        )?;
+                        Ok((signed, fee))
+                    }
+                })
+                .and_then(move |(signed, fee)| {
+                    relayer
+                        .relay(&signed)
+                        .map(move |response| (signed, response, fee))
+                })
+                .and_then(move |(signed, response, fee)| {
+                    let tx_hash = response.tx_hash;
+                    let amount = signed.request.amount - signed.request.fee;
+                    info!("Relayed {} Dai to the bridge in {:#x}", amount, tx_hash);
+                    metrics::bridge_deposit(BridgeDirection::DaiToXdai);
+                    latencies.sent(BridgeDirection::DaiToXdai, tx_hash.clone());
+                    eth_web3
+                        .wait_for_transaction(tx_hash.clone().into())
+                        .timeout(Duration::from_secs(timeout))
+                        .map(move |_| BridgeTransfer {
+                            tx_hash,
+                            expected_fee: bridge_fee_amount(amount.clone(), fee),
+                            amount,
+                        })
+                }),
+        )
+    }
+
+    /// Signs a request to relay `amount` for `quote`, with a permit for the relayer unless it
+    /// can already spend that much of our Dai
+    fn sign_relay_request(
+        &self,
+        quote: RelayQuote,
+        amount: Uint256,
+    ) -> Box<dyn Future<Item = SignedRelayRequest, Error = Error>> {
+        try_future!(self.check_spender(quote.relayer));
+        let chain_id = self.eth_signing_chain_id();
+        let dai = self.foreign_dai_contract_address;
+        let own_address = self.own_address;
+        let relayer = quote.relayer;
+        let deadline: Uint256 = (now() + RELAY_REQUEST_LIFETIME).into();
+        let request = RelayRequest {
+            from: own_address,
+            relayer: quote.relayer,
+            amount: amount.clone(),
+            fee: quote.fee,
+            nonce: rand::random::<u64>().into(),
+            deadline: deadline.clone(),
+        };
+        let domain = relay_domain(chain_id, self.xdai_foreign_bridge_address);
+        let salf = self.clone();
+
+        let permit = self
+            .get_token_allowance(dai, quote.relayer)
+            .and_then(move |allowance| {
+                if allowance >= amount {
+                    return Box::new(futures::future::ok(None))
+                        as Box<dyn Future<Item = _, Error = Error>>;
+                }
+                Box::new(
+                    salf.call_view::<Uint256>(
+                        Chain::Eth,
+                        dai,
+                        "nonces(address)",
+                        &[own_address.into()],
+                    )
+                    .and_then(move |nonce| {
+                        let message =
+                            dai_permit(own_address, relayer, nonce.clone(), deadline.clone(), true);
+                        salf.sign_typed_data(&Eip712Domain::dai(chain_id, dai), &message)
+                            .map(move |signature| {
+                                Some(RelayPermit {
+                                    nonce,
+                                    expiry: deadline,
+                                    signature,
+                                })
+                            })
+                    }),
+                )
+            });
+        let signature = self.sign_typed_data(&domain, &request.typed_struct());
+        Box::new(
+            permit
+                .join(signature)
+                .map(move |(permit, signature)| SignedRelayRequest {
+                    request,
+                    signature,
+                    permit,
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_bridge, MockBlockReader};
+    use clarity::PrivateKey;
+    use std::sync::Arc;
+
+    const NOW: u64 = 1_600_000_000;
+
+    fn signed_request(bridge: &TokenBridge, verifier: &RelayVerifier) -> SignedRelayRequest {
+        let from = PrivateKey::from_slice(&[2u8; 32])
+            .unwrap()
+            .to_public_key()
+            .unwrap();
+        let request = RelayRequest {
+            from,
+            relayer: verifier.relayer,
+            amount: 1_000u32.into(),
+            fee: 10u32.into(),
+            nonce: 7u32.into(),
+            deadline: (NOW + RELAY_REQUEST_LIFETIME).into(),
+        };
+        let domain = relay_domain(verifier.chain_id, verifier.foreign_bridge);
+        let signature = bridge
+            .sign_typed_data(&domain, &request.typed_struct())
+            .wait()
+            .unwrap();
+        let permit = dai_permit(
+            from,
+            verifier.relayer,
+            0u32.into(),
+            request.deadline.clone(),
+            true,
+        );
+        let permit_signature = bridge
+            .sign_typed_data(&Eip712Domain::dai(verifier.chain_id, verifier.dai), &permit)
+            .wait()
+            .unwrap();
+        SignedRelayRequest {
+            request,
+            signature,
+            permit: Some(RelayPermit {
+                nonce: 0u32.into(),
+                expiry: (NOW + RELAY_REQUEST_LIFETIME).into(),
+                signature: permit_signature,
+            }),
+        }
+    }
+
+    fn invalid(result: Result<(), Error>) -> bool {
+        match result {
+            Err(e) => matches!(
+                e.downcast_ref::<TokenBridgeError>(),
+                Some(TokenBridgeError::InvalidRelayRequest { .. })
+            ),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn test_relay_verifier() {
+        let bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        let verifier = RelayVerifier::new(
+            Address::from_slice(&[0x7e; 20]).unwrap(),
+            bridge.foreign_dai_contract_address,
+            bridge.xdai_foreign_bridge_address,
+            ETH_MAINNET_CHAIN_ID,
+            5u32.into(),
+        );
+        let signed = signed_request(&bridge, &verifier);
+
+        let mut tampered = signed.clone();
+        tampered.request.amount = 2_000u32.into();
+        assert!(invalid(verifier.verify_at(&tampered, NOW)));
+        let mut other_permit = signed.clone();
+        other_permit.permit.as_mut().unwrap().nonce = 1u32.into();
+        assert!(invalid(verifier.verify_at(&other_permit, NOW)));
+        let past_deadline = NOW + RELAY_REQUEST_LIFETIME;
+        assert!(invalid(verifier.verify_at(&signed, past_deadline)));
+
+        verifier.verify_at(&signed, NOW).unwrap();
+        // the same request can't be relayed twice
+        assert!(invalid(verifier.verify_at(&signed, NOW + 1)));
+
+        let cheap = RelayVerifier::new(
+            verifier.relayer,
+            verifier.dai,
+            verifier.foreign_bridge,
+            verifier.chain_id,
+            50u32.into(),
+        );
+        assert!(invalid(cheap.verify_at(&signed, NOW)));
+    }
+
+    #[test]
+    fn test_json_relayed_store() {
+        let path = std::env::temp_dir().join(format!("relayed-{}.json", rand::random::<u64>()));
+        let bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        let verifier = |path: &PathBuf| {
+            RelayVerifier::with_store(
+                Address::from_slice(&[0x7e; 20]).unwrap(),
+                bridge.foreign_dai_contract_address,
+                bridge.xdai_foreign_bridge_address,
+                ETH_MAINNET_CHAIN_ID,
+                5u32.into(),
+                Box::new(JsonRelayedStore::new(path)),
+            )
+            .unwrap()
+        };
+        let first = verifier(&path);
+        let signed = signed_request(&bridge, &first);
+        first.verify_at(&signed, NOW).unwrap();
+
+        // a restarted relayer still refuses it
+        assert!(invalid(verifier(&path).verify_at(&signed, NOW + 1)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_relay_calls() {
+        let bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        let verifier = RelayVerifier::new(
+            Address::from_slice(&[0x7e; 20]).unwrap(),
+            bridge.foreign_dai_contract_address,
+            bridge.xdai_foreign_bridge_address,
+            ETH_MAINNET_CHAIN_ID,
+            0u32.into(),
+        );
+        let mut signed = signed_request(&bridge, &verifier);
+        let calls = verifier.relay_calls(&signed).unwrap();
+        assert_eq!(calls.len(), 3);
+        assert!(calls.iter().all(|(to, _)| *to == verifier.dai));
+        assert_eq!(calls[0].1.len(), 4 + 8 * 32);
+        assert_eq!(
+            calls[2].1,
+            encoding::erc20_transfer_from(
+                signed.request.from,
+                verifier.foreign_bridge,
+                990u32.into()
+            )
+        );
+        assert_eq!(
+            calls[1].1,
+            encoding::erc20_transfer_from(signed.request.from, verifier.relayer, 10u32.into())
+        );
+
+        // without a permit or a fee only the transfer to the bridge is left
+        signed.permit = None;
+        signed.request.fee = 0u32.into();
+        assert_eq!(verifier.relay_calls(&signed).unwrap().len(), 1);
+    }
+}
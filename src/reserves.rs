@@ -0,0 +1,149 @@
+//! The liquidity of the ETH/Dai pool swaps go through, for sizing swaps and noticing when the
+//! pool gets too thin to trade against safely.
+
+use crate::abi::{decode_values, AbiType, AbiValue};
+use crate::units::{Dai, Eth};
+use crate::Chain;
+use crate::TokenBridge;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+
+/// Reserves of the ETH/Dai pool. For router backends the ETH side is the WETH of the pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolReserves {
+    pub eth: Eth,
+    pub dai: Dai,
+}
+
+/// How much can be sold into a constant product pool holding `reserve_in` of what is sold
+/// before the price moves `impact_bps` basis points against the seller. Selling `x` gets
+/// `reserve_in / (reserve_in + x)` of the marginal rate, solved for `x`.
+fn depth(reserve_in: &Uint256, impact_bps: u32) -> Uint256 {
+    let impact_bps = impact_bps.min(9_999);
+    reserve_in.clone() * impact_bps.into() / (10_000 - impact_bps).into()
+}
+
+impl PoolReserves {
+    /// The marginal price of the pool in Dai wei per ETH, before the swap fee. Zero for an
+    /// empty pool.
+    pub fn eth_price(&self) -> Uint256 {
+        if *self.eth.wei() == 0u32.into() {
+            return 0u32.into();
+        }
+        self.dai.wei().clone() * 1_000_000_000_000_000_000u64.into() / self.eth.wei().clone()
+    }
+
+    /// The most ETH that can be sold for Dai with at most `impact_bps` price impact, as
+    /// measured by `price_impact_bps`
+    pub fn eth_depth(&self, impact_bps: u32) -> Eth {
+        Eth::from_wei(depth(self.eth.wei(), impact_bps))
+    }
+
+    /// The most Dai that can be sold for ETH with at most `impact_bps` price impact
+    pub fn dai_depth(&self, impact_bps: u32) -> Dai {
+        Dai::from_wei(depth(self.dai.wei(), impact_bps))
+    }
+}
+
+/// Splits the output of a V2 pair's `getReserves()` into the reserves of token0 and token1
+fn decode_pair_reserves(output: &[u8]) -> Result<(Uint256, Uint256), Error> {
+    // returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast)
+    let mut reserves = decode_values("getReserves()", &[AbiType::Uint256; 3], output)?
+        .into_iter()
+        .map(AbiValue::into_uint256);
+    Ok((reserves.next().unwrap()?, reserves.next().unwrap()?))
+}
+
+impl TokenBridge {
+    /// The current reserves of the pool of `swap_backend`, the balances of the exchange at
+    /// `uniswap_address` for Uniswap V1 or the WETH/Dai pair of the router's factory
+    pub fn get_pool_reserves(&self) -> Box<dyn Future<Item = PoolReserves, Error = Error>> {
+        match self.swap_backend.router() {
+            None => Box::new(
+                self.eth_web3
+                    .eth_get_balance(self.uniswap_address)
+                    .join(self.get_dai_balance(self.uniswap_address))
+                    .map(|(eth, dai)| PoolReserves {
+                        eth: Eth::from_wei(eth),
+                        dai,
+                    }),
+            ),
+            Some(router) => {
+                let salf = self.clone();
+                let weth = self.weth_address;
+                let dai = self.foreign_dai_contract_address;
+                Box::new(
+                    self.call_view(Chain::Eth, router, "factory()", &[])
+                        .and_then({
+                            let salf = self.clone();
+                            move |factory| {
+                                salf.call_view(
+                                    Chain::Eth,
+                                    factory,
+                                    "getPair(address,address)",
+                                    &[weth.into(), dai.into()],
+                                )
+                            }
+                        })
+                        .and_then(move |pair| {
+                            salf.call_view::<Vec<u8>>(Chain::Eth, pair, "getReserves()", &[])
+                        })
+                        .and_then(move |output| {
+                            let (reserve0, reserve1) = decode_pair_reserves(&output)?;
+                            // pairs sort their tokens by address
+                            let (eth, dai) = if weth.as_bytes() < dai.as_bytes() {
+                                (reserve0, reserve1)
+                            } else {
+                                (reserve1, reserve0)
+                            };
+                            Ok(PoolReserves {
+                                eth: Eth::from_wei(eth),
+                                dai: Dai::from_wei(dai),
+                            })
+                        }),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_impact::price_impact_bps;
+
+    #[test]
+    fn test_depth() {
+        let reserves = PoolReserves {
+            eth: Eth::from_wei(1_000_000u32.into()),
+            dai: Dai::from_wei(200_000_000u32.into()),
+        };
+        // 1% of impact is reached selling a hundredth of the reserve, roughly
+        assert_eq!(reserves.eth_depth(100), Eth::from_wei(10_101u32.into()));
+        assert_eq!(reserves.dai_depth(0), Dai::from_wei(0u32.into()));
+        assert_eq!(
+            reserves.eth_price(),
+            Uint256::from(200u32) * 1_000_000_000_000_000_000u64.into()
+        );
+
+        // checked against the impact of the constant product output
+        let sold = reserves.eth_depth(100).into_wei();
+        let eth: Uint256 = 1_000_000u32.into();
+        let dai: Uint256 = 200_000_000u32.into();
+        let bought = sold.clone() * dai.clone() / (eth.clone() + sold.clone());
+        assert_eq!(price_impact_bps(eth, dai, sold, bought), 100);
+    }
+
+    #[test]
+    fn test_decode_pair_reserves() {
+        let mut output = vec![0u8; 96];
+        output[31] = 1;
+        output[63] = 2;
+        assert_eq!(
+            decode_pair_reserves(&output).unwrap(),
+            (1u32.into(), 2u32.into())
+        );
+        assert!(decode_pair_reserves(&output[..40]).is_err());
+    }
+}
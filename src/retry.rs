@@ -0,0 +1,163 @@
+//! Retrying of read only RPC calls that fail for transient reasons, like a full node that is
+//! briefly unreachable over a flaky router uplink.
+//!
+//! This is only ever applied to calls without side effects. Re-sending a transaction because
+//! the node didn't answer could send it twice, so sends are never retried here.
+
+use crate::events::BridgeEvent;
+use crate::instrument::Span;
+use crate::metrics;
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use failure::Error;
+use futures::future::{loop_fn, Loop};
+use futures::Future;
+use futures_timer::Delay;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total tries including the first one, 1 disables retrying
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for every retry after that
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Randomize each wait to between half and all of the backoff, so that many routers
+    /// sharing a node don't retry in lockstep
+    pub jitter: bool,
+    /// Which errors are worth retrying
+    pub is_retryable: fn(&Error) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            jitter: true,
+            is_retryable: is_transient,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that tries everything exactly once
+    pub fn never() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// The wait after failed attempt number `attempt`, counting from 1, before jitter
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff(attempt);
+        if self.jitter {
+            backoff / 2 + backoff.mul_f64(rand::random::<f64>() / 2.0)
+        } else {
+            backoff
+        }
+    }
+}
+
+/// The default `is_retryable`, errors of the transport or a timeout, where the node never
+/// answered. What it did answer, like revert data, an RPC error or output that doesn't decode,
+/// comes back the same when asked again. Those are `TokenBridgeError`s or plain messages from
+/// `bail!` and `format_err!`, while transport errors and timeouts keep their own types.
+pub fn is_transient(error: &Error) -> bool {
+    error.downcast_ref::<TokenBridgeError>().is_none()
+        && error.name() != Some("failure::ErrorMessage")
+}
+
+impl TokenBridge {
+    /// Runs the read only call `name` to `chain` made by `call`, calling it again according to
+    /// `retry_policy` while it fails. `name` is the contract function or RPC method called, see
+    /// `faults`.
+    pub(crate) fn with_retry<T, F>(
+        &self,
+        chain: Chain,
+        name: &str,
+        call: F,
+    ) -> Box<dyn Future<Item = T, Error = Error>>
+    where
+        T: 'static,
+        F: Fn() -> Box<dyn Future<Item = T, Error = Error>> + 'static,
+    {
+        let policy = self.retry_policy;
+        let salf = Arc::new(self.clone());
+        let name = name.to_string();
+        let call = Arc::new(call);
+
+        Box::new(loop_fn(1u32, move |attempt| {
+            let salf = salf.clone();
+            let call = call.clone();
+            let request = salf.inject_call_fault(chain, &name, move || call());
+            Span::rpc(chain, attempt).instrument(request).then(
+                move |res| -> Box<dyn Future<Item = Loop<T, u32>, Error = Error>> {
+                    let e = match res {
+                        Ok(val) => return Box::new(futures::future::ok(Loop::Break(val))),
+                        Err(e) => e,
+                    };
+                    if is_transient(&e) {
+                        metrics::rpc_error(chain);
+                    }
+                    if attempt >= policy.max_attempts || !(policy.is_retryable)(&e) {
+                        return Box::new(futures::future::err(e));
+                    }
+                    let delay = policy.delay(attempt);
+                    warn!(
+                        "Attempt {} failed with {:?}, retrying in {:?}",
+                        attempt, e, delay
+                    );
+                    salf.emit(BridgeEvent::Retrying {
+                        attempt,
+                        reason: e.to_string(),
+                    });
+                    Box::new(
+                        Delay::new(delay)
+                            .from_err()
+                            .map(move |_| Loop::Continue(attempt + 1)),
+                    )
+                },
+            )
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            jitter: false,
+            is_retryable: is_transient,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(2));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(4), Duration::from_secs(5));
+        assert_eq!(policy.backoff(100), Duration::from_secs(5));
+
+        let reset = io::Error::new(io::ErrorKind::ConnectionReset, "connection reset");
+        assert!(is_transient(&reset.into()));
+        assert!(!is_transient(&TokenBridgeError::ReadOnly.into()));
+        assert!(!is_transient(&failure::format_err!("execution reverted")));
+    }
+}
@@ -0,0 +1,498 @@
+//! Picking the cheapest way to convert between ETH and xDai. Every candidate route is quoted
+//! with its swap, bridge fee and gas, and the one leaving the most at the end is executed.
+//!
+//! All candidates swap on Eth, a route swapping on the xDai side would need ETH bridged as a
+//! token, which this crate doesn't do.
+
+use crate::aggregator::{eth_pseudo_token, AggregatorQuote, QuoteRequest, SwapVenue};
+use crate::cost::{saturating_sub, DAI_TRANSFER_GAS, REFERENCE_ETH, SWAP_GAS, XDAI_BRIDGE_GAS};
+use crate::fee::{bridge_fee_amount, BridgeDirection};
+use crate::logs::SwapBackend;
+use crate::operations::OperationKind;
+use crate::router::{mainnet_sushiswap_router, ROUTER_SWAP_GAS_LIMIT};
+use crate::units::{Dai, Eth, XDai};
+use crate::Chain;
+use crate::TokenBridge;
+use failure::bail;
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use futures_timer::FutureExt;
+use num256::Uint256;
+use std::time::Duration;
+
+/// Gas assumed for an aggregator swap when the quote doesn't say
+const AGGREGATOR_SWAP_GAS: u64 = 300_000;
+
+/// Where the swap step of a route happens
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteVenue {
+    Exchange(SwapBackend),
+    /// The aggregator of the bridge, by its `AggregatorApi::name`
+    Aggregator(String),
+}
+
+/// One way to do a conversion. `EthToXdai` swaps on Eth and bridges the Dai, `XdaiToEth`
+/// bridges to Dai and swaps it on Eth.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub kind: OperationKind,
+    pub venue: RouteVenue,
+}
+
+/// What a route is expected to leave. Amounts are in what the conversion gives, xDai for
+/// `EthToXdai` and ETH for `XdaiToEth`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteEstimate {
+    pub route: Route,
+    /// Received at the end, after the swap and the bridge fee
+    pub output: Uint256,
+    /// Gas of every transaction on the route, valued at the mid price
+    pub gas_cost: Uint256,
+    /// `output` minus `gas_cost`, what routes are ranked by
+    pub net_output: Uint256,
+}
+
+/// The parts of an estimate shared by every route
+#[derive(Debug, Clone)]
+struct RoutePrices {
+    bridge_fee: Uint256,
+    eth_gas_price: Uint256,
+    xdai_gas_price: Uint256,
+    /// The Dai value of `REFERENCE_ETH`
+    mid_price: Uint256,
+}
+
+/// Puts an estimate together from `swap_out`, what the swap step quotes, and the gas of the
+/// swap transaction
+fn route_estimate(
+    route: Route,
+    swap_out: Uint256,
+    swap_gas: Uint256,
+    prices: &RoutePrices,
+) -> RouteEstimate {
+    let reference: Uint256 = REFERENCE_ETH.into();
+    let (output, gas_cost) = match route.kind {
+        OperationKind::EthToXdai => {
+            let fee = bridge_fee_amount(swap_out.clone(), prices.bridge_fee.clone());
+            let eth_gas = prices.eth_gas_price.clone() * (swap_gas + DAI_TRANSFER_GAS.into());
+            (
+                saturating_sub(swap_out, fee),
+                eth_gas * prices.mid_price.clone() / reference,
+            )
+        }
+        OperationKind::XdaiToEth => {
+            let eth_gas = prices.eth_gas_price.clone() * swap_gas;
+            let xdai_gas = prices.xdai_gas_price.clone() * XDAI_BRIDGE_GAS.into();
+            let xdai_gas_in_eth = if prices.mid_price == 0u32.into() {
+                0u32.into()
+            } else {
+                xdai_gas * reference / prices.mid_price.clone()
+            };
+            (swap_out, eth_gas + xdai_gas_in_eth)
+        }
+    };
+    RouteEstimate {
+        route,
+        net_output: saturating_sub(output.clone(), gas_cost.clone()),
+        output,
+        gas_cost,
+    }
+}
+
+/// Sorts `estimates` best first
+fn rank(mut estimates: Vec<RouteEstimate>) -> Vec<RouteEstimate> {
+    estimates.sort_by(|a, b| b.net_output.cmp(&a.net_output));
+    estimates
+}
+
+fn backend_swap_gas(backend: SwapBackend) -> u64 {
+    match backend {
+        SwapBackend::UniswapV1 => SWAP_GAS,
+        SwapBackend::SushiSwap { .. } => ROUTER_SWAP_GAS_LIMIT,
+    }
+}
+
+/// What goes into the swap step of converting `amount`, the bridge fee comes off first when
+/// going to ETH
+fn swap_input(kind: OperationKind, amount: Uint256, bridge_fee: Uint256) -> Uint256 {
+    match kind {
+        OperationKind::EthToXdai => amount,
+        OperationKind::XdaiToEth => {
+            let fee = bridge_fee_amount(amount.clone(), bridge_fee);
+            saturating_sub(amount, fee)
+        }
+    }
+}
+
+/// Quotes every candidate route of a conversion and executes the cheapest
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutePlanner {
+    /// The exchanges on Eth to consider. The aggregator of the bridge is considered as well if
+    /// it has one.
+    pub backends: Vec<SwapBackend>,
+}
+
+impl Default for RoutePlanner {
+    fn default() -> Self {
+        RoutePlanner {
+            backends: vec![
+                SwapBackend::UniswapV1,
+                SwapBackend::SushiSwap {
+                    router: mainnet_sushiswap_router(),
+                },
+            ],
+        }
+    }
+}
+
+impl RoutePlanner {
+    pub fn new(backends: Vec<SwapBackend>) -> RoutePlanner {
+        RoutePlanner { backends }
+    }
+
+    /// Estimates converting `amount` along every candidate route, best first. `amount` is ETH
+    /// for `EthToXdai` and xDai for `XdaiToEth`, like `Operation::amount`. Routes that can't be
+    /// quoted are left out, this fails only if none can.
+    pub fn estimate(
+        &self,
+        bridge: &TokenBridge,
+        kind: OperationKind,
+        amount: Uint256,
+    ) -> Box<dyn Future<Item = Vec<RouteEstimate>, Error = Error>> {
+        let bridge_direction = match kind {
+            OperationKind::EthToXdai => BridgeDirection::DaiToXdai,
+            OperationKind::XdaiToEth => BridgeDirection::XdaiToDai,
+        };
+        let backends = self.backends.clone();
+        let salf = bridge.clone();
+
+        Box::new(
+            bridge
+                .get_price_quote(Eth::from_wei(REFERENCE_ETH.into()))
+                .join4(
+                    bridge.get_bridge_fee(bridge_direction),
+                    bridge.gas_price(Chain::Eth),
+                    bridge.gas_price(Chain::Xdai),
+                )
+                .and_then(move |(quote, bridge_fee, eth_gas_price, xdai_gas_price)| {
+                    let prices = RoutePrices {
+                        bridge_fee,
+                        eth_gas_price,
+                        xdai_gas_price,
+                        mid_price: quote.mid_price.into_wei(),
+                    };
+                    let swap_in = swap_input(kind, amount, prices.bridge_fee.clone());
+
+                    let mut candidates: Vec<Box<dyn Future<Item = _, Error = Error>>> = backends
+                        .into_iter()
+                        .map(|backend| {
+                            let prices = prices.clone();
+                            let route = Route {
+                                kind,
+                                venue: RouteVenue::Exchange(backend),
+                            };
+                            Box::new(exchange_quote(&salf, backend, kind, swap_in.clone()).then(
+                                move |swap_out| match swap_out {
+                                    Ok(swap_out) => Ok(Some(route_estimate(
+                                        route,
+                                        swap_out,
+                                        backend_swap_gas(backend).into(),
+                                        &prices,
+                                    ))),
+                                    Err(e) => {
+                                        warn!("Quote from {:?} failed with {:?}", backend, e);
+                                        Ok(None)
+                                    }
+                                },
+                            ))
+                                as Box<dyn Future<Item = _, Error = Error>>
+                        })
+                        .collect();
+                    candidates.push(Box::new(
+                        salf.aggregator_quote(aggregator_request(&salf, kind, swap_in))
+                            .map(move |quote| {
+                                quote.map(|(name, quote)| {
+                                    let route = Route {
+                                        kind,
+                                        venue: RouteVenue::Aggregator(name),
+                                    };
+                                    let gas =
+                                        quote.gas.unwrap_or_else(|| AGGREGATOR_SWAP_GAS.into());
+                                    route_estimate(route, quote.buy_amount, gas, &prices)
+                                })
+                            }),
+                    ));
+
+                    futures::future::join_all(candidates).and_then(|estimates| {
+                        let estimates: Vec<RouteEstimate> =
+                            estimates.into_iter().flatten().collect();
+                        if estimates.is_empty() {
+                            bail!("No route could be quoted");
+                        }
+                        Ok(rank(estimates))
+                    })
+                }),
+        )
+    }
+
+    /// Converts `amount` along the route with the highest `net_output`. Resolves to the route
+    /// taken and what it gave, xDai for `EthToXdai` and ETH for `XdaiToEth`. Exchange routes
+    /// run as a checkpointed `eth_to_xdai` or `xdai_to_eth` on that exchange.
+    pub fn execute(
+        &self,
+        bridge: &TokenBridge,
+        kind: OperationKind,
+        amount: Uint256,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = (Route, Uint256), Error = Error>> {
+        let salf = bridge.clone();
+        Box::new(
+            self.estimate(bridge, kind, amount.clone())
+                .and_then(move |estimates| {
+                    // estimate fails rather than returning nothing
+                    let route = estimates[0].route.clone();
+                    info!("Converting {} along {:?}", amount, route);
+                    execute_route(&salf, route.clone(), amount, timeout)
+                        .map(move |output| (route, output))
+                }),
+        )
+    }
+}
+
+/// What the swap step of `kind` quotes on `backend`
+fn exchange_quote(
+    bridge: &TokenBridge,
+    backend: SwapBackend,
+    kind: OperationKind,
+    swap_in: Uint256,
+) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    let mut on_backend = bridge.clone();
+    on_backend.swap_backend = backend;
+    match kind {
+        OperationKind::EthToXdai => Box::new(
+            on_backend
+                .eth_to_dai_price(Eth::from_wei(swap_in))
+                .map(Dai::into_wei),
+        ),
+        OperationKind::XdaiToEth => Box::new(
+            on_backend
+                .dai_to_eth_price(Dai::from_wei(swap_in))
+                .map(Eth::into_wei),
+        ),
+    }
+}
+
+fn aggregator_request(bridge: &TokenBridge, kind: OperationKind, swap_in: Uint256) -> QuoteRequest {
+    let dai = bridge.foreign_dai_contract_address;
+    let (sell_token, buy_token) = match kind {
+        OperationKind::EthToXdai => (eth_pseudo_token(), dai),
+        OperationKind::XdaiToEth => (dai, eth_pseudo_token()),
+    };
+    QuoteRequest {
+        sell_token,
+        buy_token,
+        sell_amount: swap_in,
+        taker: bridge.own_address,
+        slippage_bps: bridge.slippage_bps,
+    }
+}
+
+fn execute_route(
+    bridge: &TokenBridge,
+    route: Route,
+    amount: Uint256,
+    timeout: u64,
+) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    match route.venue {
+        RouteVenue::Exchange(backend) => {
+            let mut on_backend = bridge.clone();
+            on_backend.swap_backend = backend;
+            match route.kind {
+                OperationKind::EthToXdai => Box::new(
+                    on_backend
+                        .eth_to_xdai(Eth::from_wei(amount), timeout)
+                        .map(XDai::into_wei),
+                ),
+                OperationKind::XdaiToEth => Box::new(
+                    on_backend
+                        .xdai_to_eth(XDai::from_wei(amount), timeout)
+                        .map(Eth::into_wei),
+                ),
+            }
+        }
+        RouteVenue::Aggregator(name) => match route.kind {
+            OperationKind::EthToXdai => aggregator_eth_to_xdai(bridge, name, amount, timeout),
+            OperationKind::XdaiToEth => aggregator_xdai_to_eth(bridge, name, amount, timeout),
+        },
+    }
+}
+
+/// Quotes the aggregator again for the swap step, the quote the route was picked on may be
+/// stale by the time it runs
+fn fresh_aggregator_quote(
+    bridge: &TokenBridge,
+    name: String,
+    kind: OperationKind,
+    swap_in: Uint256,
+) -> Box<dyn Future<Item = AggregatorQuote, Error = Error>> {
+    Box::new(
+        bridge
+            .aggregator_quote(aggregator_request(bridge, kind, swap_in))
+            .and_then(move |quote| match quote {
+                Some((_, quote)) => Ok(quote),
+                None => Err(format_err!("{} no longer quotes", name)),
+            }),
+    )
+}
+
+/// Swaps on the aggregator then bridges the Dai that arrived, resolves to the xDai the bridge
+/// is expected to pay out
+fn aggregator_eth_to_xdai(
+    bridge: &TokenBridge,
+    name: String,
+    amount: Uint256,
+    timeout: u64,
+) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    let salf = bridge.clone();
+    let own_address = bridge.own_address;
+    Box::new(
+        bridge
+            .get_dai_balance(own_address)
+            .join(fresh_aggregator_quote(
+                bridge,
+                name.clone(),
+                OperationKind::EthToXdai,
+                amount,
+            ))
+            .and_then(move |(dai_before, quote)| {
+                salf.send_aggregator_swap(SwapVenue::Aggregator(name), quote, timeout)
+                    .and_then(move |_| {
+                        salf.get_dai_balance(own_address).map(move |after| {
+                            let swapped = saturating_sub(after.into_wei(), dai_before.into_wei());
+                            (salf, swapped)
+                        })
+                    })
+            })
+            .and_then(move |(salf, swapped)| {
+                salf.dai_to_xdai_bridge(Dai::from_wei(swapped), timeout)
+                    .map(|transfer| transfer.expected_net_amount())
+            }),
+    )
+}
+
+/// Bridges to Dai, waits up to `timeout` seconds for it to arrive and swaps what arrived on the
+/// aggregator, resolves to the ETH quoted
+fn aggregator_xdai_to_eth(
+    bridge: &TokenBridge,
+    name: String,
+    amount: Uint256,
+    timeout: u64,
+) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    let salf = bridge.clone();
+    let dai = bridge.foreign_dai_contract_address;
+    Box::new(
+        bridge
+            .get_dai_balance(bridge.own_address)
+            .map(Dai::into_wei)
+            .and_then(move |dai_before| {
+                salf.xdai_to_dai_bridge(XDai::from_wei(amount), timeout)
+                    .and_then(move |_| {
+                        salf.wait_for_dai_increase(dai_before.clone())
+                            .timeout(Duration::from_secs(timeout))
+                            .map(move |after| (salf, after - dai_before))
+                    })
+            })
+            .and_then(move |(salf, arrived)| {
+                let quote = fresh_aggregator_quote(
+                    &salf,
+                    name.clone(),
+                    OperationKind::XdaiToEth,
+                    arrived.clone(),
+                );
+                quote.and_then(move |quote| {
+                    salf.ensure_token_approved(
+                        dai,
+                        quote.allowance_target,
+                        arrived,
+                        Duration::from_secs(timeout),
+                    )
+                    .and_then(move |_| {
+                        salf.send_aggregator_swap(SwapVenue::Aggregator(name), quote, timeout)
+                    })
+                })
+            })
+            .map(|(_, eth)| eth),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: u64 = 1_000_000_000_000_000_000;
+
+    fn prices() -> RoutePrices {
+        RoutePrices {
+            // 1%
+            bridge_fee: 10_000_000_000_000_000u64.into(),
+            eth_gas_price: 1_000_000_000u64.into(),
+            xdai_gas_price: 1_000_000_000u64.into(),
+            // one ETH is 200 Dai
+            mid_price: 200_000_000_000_000_000u64.into(),
+        }
+    }
+
+    fn route(kind: OperationKind, backend: SwapBackend) -> Route {
+        Route {
+            kind,
+            venue: RouteVenue::Exchange(backend),
+        }
+    }
+
+    #[test]
+    fn test_eth_to_xdai_estimate() {
+        let sushiswap = SwapBackend::SushiSwap {
+            router: mainnet_sushiswap_router(),
+        };
+        let uniswap = route_estimate(
+            route(OperationKind::EthToXdai, SwapBackend::UniswapV1),
+            Uint256::from(ONE) * 200u32.into(),
+            SWAP_GAS.into(),
+            &prices(),
+        );
+        assert_eq!(uniswap.output, Uint256::from(ONE) * 198u32.into());
+        // 160k gas at 1 gwei is 0.00016 ETH, or 0.032 Dai
+        assert_eq!(uniswap.gas_cost, 32_000_000_000_000_000u64.into());
+
+        // quotes a little more but spends more than that on gas
+        let sushiswap = route_estimate(
+            route(OperationKind::EthToXdai, sushiswap),
+            Uint256::from(ONE) * 200u32.into() + 10_000_000_000_000_000u64.into(),
+            ROUTER_SWAP_GAS_LIMIT.into(),
+            &prices(),
+        );
+        assert!(sushiswap.output > uniswap.output);
+        assert_eq!(rank(vec![sushiswap, uniswap.clone()])[0], uniswap);
+    }
+
+    #[test]
+    fn test_xdai_to_eth_estimate() {
+        assert_eq!(
+            swap_input(OperationKind::XdaiToEth, 100u32.into(), prices().bridge_fee),
+            99u32.into()
+        );
+        let estimate = route_estimate(
+            route(OperationKind::XdaiToEth, SwapBackend::UniswapV1),
+            (ONE / 2).into(),
+            SWAP_GAS.into(),
+            &prices(),
+        );
+        // 80k gas on Eth, plus 100k on xDai which is 0.0001 xDai or 0.0000005 ETH
+        assert_eq!(
+            estimate.gas_cost,
+            (80_000_000_000_000u64 + 500_000_000_000u64).into()
+        );
+        assert_eq!(estimate.output, (ONE / 2).into());
+    }
+}
@@ -0,0 +1,416 @@
+//! Swaps through Uniswap V2 style routers such as SushiSwap's, used when `swap_backend` is
+//! `SwapBackend::SushiSwap`. The router is all that needs configuring, it finds the pair itself.
+
+use crate::block_tag::BlockTag;
+use crate::encoding;
+use crate::events::BridgeEvent;
+use crate::health;
+use crate::logs::{EventDefinition, ERC20_TRANSFER};
+use crate::metrics;
+use crate::minimum_output;
+use crate::price_cache::PriceDirection;
+use crate::units::{Dai, Eth};
+use crate::weth::WETH_WITHDRAWAL;
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::Address;
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::str::FromStr;
+use std::time::Duration;
+use web30::types::SendTxOption;
+
+pub use crate::contracts::MAINNET_SUSHISWAP_ROUTER;
+
+pub fn mainnet_sushiswap_router() -> Address {
+    Address::from_str(MAINNET_SUSHISWAP_ROUTER).unwrap()
+}
+
+/// Router swaps touch the pair, the token and the wrapped native token
+pub(crate) const ROUTER_SWAP_GAS_LIMIT: u64 = 200_000;
+
+/// One side of a router swap, which token moves where. The router wraps and unwraps the native
+/// coin itself, so `wrapped_native` only matters for the path and for measuring the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RouterMarket {
+    pub chain: Chain,
+    pub router: Address,
+    pub wrapped_native: Address,
+    pub token: Address,
+}
+
+impl TokenBridge {
+    /// Dai and WETH on Eth traded on the router of `swap_backend`
+    pub(crate) fn dai_market(&self) -> Result<RouterMarket, Error> {
+        match self.swap_backend.router() {
+            Some(router) => Ok(RouterMarket {
+                chain: Chain::Eth,
+                router,
+                wrapped_native: self.weth_address,
+                token: self.foreign_dai_contract_address,
+            }),
+            None => Err(format_err!(
+                "{:?} is not a router backend",
+                self.swap_backend
+            )),
+        }
+    }
+
+    /// `eth_to_dai_price` on the router
+    pub(crate) fn router_eth_to_dai_price(
+        &self,
+        amount: Uint256,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let market = try_future!(self.dai_market());
+        self.cached_price(PriceDirection::EthToDai, amount, move |amount| {
+            self.router_amount_out(
+                Chain::Eth,
+                market.router,
+                amount,
+                vec![market.wrapped_native, market.token],
+            )
+        })
+    }
+
+    /// `dai_to_eth_price` on the router
+    pub(crate) fn router_dai_to_eth_price(
+        &self,
+        amount: Uint256,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let market = try_future!(self.dai_market());
+        self.cached_price(PriceDirection::DaiToEth, amount, move |amount| {
+            self.router_amount_out(
+                Chain::Eth,
+                market.router,
+                amount,
+                vec![market.token, market.wrapped_native],
+            )
+        })
+    }
+
+    /// `eth_cost_of_dai` on the router
+    pub(crate) fn router_eth_cost_of_dai(
+        &self,
+        dai_amount: Uint256,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let market = try_future!(self.dai_market());
+        self.router_amount_in(
+            Chain::Eth,
+            market.router,
+            dai_amount,
+            vec![market.wrapped_native, market.token],
+        )
+    }
+
+    /// `eth_to_dai_swap_to` on the router
+    pub(crate) fn router_eth_to_dai_swap(
+        &self,
+        eth_amount: Eth,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        let market = try_future!(self.dai_market());
+        let salf = self.clone();
+        Box::new(
+            self.swap_checks(eth_amount.wei().clone(), true)
+                .and_then(move |_| {
+                    salf.router_swap_native_for_tokens(
+                        market,
+                        eth_amount.into_wei(),
+                        recipient,
+                        timeout,
+                    )
+                })
+                .map(|dai| {
+                    metrics::swap_executed("eth_to_dai");
+                    Dai::from_wei(dai)
+                }),
+        )
+    }
+
+    /// `dai_to_eth_swap_to` on the router, approving the router first if needed and
+    /// `auto_approve` is on
+    pub(crate) fn router_dai_to_eth_swap(
+        &self,
+        dai_amount: Dai,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        let market = try_future!(self.dai_market());
+        let ensure_approved = if self.auto_approve {
+            self.ensure_token_approved(
+                market.token,
+                market.router,
+                dai_amount.wei().clone(),
+                Duration::from_secs(timeout),
+            )
+        } else {
+            Box::new(futures::future::ok(()))
+        };
+        let salf = self.clone();
+        Box::new(
+            ensure_approved
+                .and_then({
+                    let salf = self.clone();
+                    let dai_amount = dai_amount.clone();
+                    move |_| salf.swap_checks(dai_amount.into_wei(), false)
+                })
+                .and_then(move |_| {
+                    salf.router_swap_tokens_for_native(
+                        market,
+                        dai_amount.into_wei(),
+                        recipient,
+                        timeout,
+                    )
+                })
+                .map(|eth| {
+                    metrics::swap_executed("dai_to_eth");
+                    Eth::from_wei(eth)
+                }),
+        )
+    }
+
+    /// What selling `amount_in` along `path` on `router` gives, from `getAmountsOut`
+    pub fn router_amount_out(
+        &self,
+        chain: Chain,
+        router: Address,
+        amount_in: Uint256,
+        path: Vec<Address>,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.router_amount_out_at(chain, router, amount_in, path, BlockTag::Latest)
+    }
+
+    /// `router_amount_out` at `block`, see `call_view_at`
+    pub fn router_amount_out_at(
+        &self,
+        chain: Chain,
+        router: Address,
+        amount_in: Uint256,
+        path: Vec<Address>,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        Box::new(
+            self.call_view_at::<Vec<Uint256>>(
+                chain,
+                router,
+                "getAmountsOut(uint256,address[])",
+                &[amount_in.into(), path.into()],
+                block,
+            )
+            .and_then(|amounts| match amounts.last() {
+                Some(amount) => Ok(amount.clone()),
+                None => Err(format_err!("Router returned no amounts")),
+            }),
+        )
+    }
+
+    /// What has to be sold along `path` on `router` to get `amount_out`, from `getAmountsIn`
+    pub fn router_amount_in(
+        &self,
+        chain: Chain,
+        router: Address,
+        amount_out: Uint256,
+        path: Vec<Address>,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        Box::new(
+            self.call_view::<Vec<Uint256>>(
+                chain,
+                router,
+                "getAmountsIn(uint256,address[])",
+                &[amount_out.into(), path.into()],
+            )
+            .and_then(|amounts| match amounts.first() {
+                Some(amount) => Ok(amount.clone()),
+                None => Err(format_err!("Router returned no amounts")),
+            }),
+        )
+    }
+
+    /// The router call selling `amount` for at least the current quote minus our slippage
+    /// allowance, expiring `timeout` seconds after the latest block
+    fn router_swap_payload(
+        &self,
+        market: RouterMarket,
+        amount: Uint256,
+        native_in: bool,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        let slippage_bps = self.slippage_bps;
+        let max_lag = match market.chain {
+            Chain::Eth => self.max_eth_node_lag,
+            Chain::Xdai => None,
+        };
+        let path = if native_in {
+            vec![market.wrapped_native, market.token]
+        } else {
+            vec![market.token, market.wrapped_native]
+        };
+
+        // the average price is of the Dai pool, other markets keep their quote
+        let is_dai_market =
+            market.chain == Chain::Eth && market.token == self.foreign_dai_contract_address;
+        let direction = if native_in {
+            PriceDirection::EthToDai
+        } else {
+            PriceDirection::DaiToEth
+        };
+        let salf = self.clone();
+        let quote_path = path.clone();
+        let quote_amount = amount.clone();
+
+        Box::new(
+            self.at_latest_block(market.chain, move |block| {
+                let twap = salf.clone();
+                Box::new(
+                    salf.router_amount_out_at(
+                        market.chain,
+                        market.router,
+                        quote_amount,
+                        quote_path,
+                        block,
+                    )
+                    .and_then(move |quote| {
+                        if is_dai_market {
+                            twap.twap_adjusted_quote(direction, quote)
+                        } else {
+                            Box::new(futures::future::ok(quote))
+                        }
+                    }),
+                )
+            })
+            .and_then(move |(block, expected)| {
+                if let Some(max_lag) = max_lag {
+                    health::ensure_fresh(&block.timestamp, max_lag)?;
+                }
+                let min_out = minimum_output(expected, slippage_bps);
+                let deadline = block.timestamp + timeout.into();
+                Ok(if native_in {
+                    encoding::router_swap_exact_eth_for_tokens(min_out, path, recipient, deadline)
+                } else {
+                    encoding::router_swap_exact_tokens_for_eth(
+                        amount, min_out, path, recipient, deadline,
+                    )
+                })
+            }),
+        )
+    }
+
+    /// Sells `amount` of the native coin for the token of `market`, paid to `recipient`.
+    /// Resolves to the tokens received once the swap is mined, measured by the token's Transfer
+    /// out of the pair.
+    pub(crate) fn router_swap_native_for_tokens(
+        &self,
+        market: RouterMarket,
+        amount: Uint256,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let salf = self.clone();
+        Box::new(
+            self.router_swap_payload(market, amount.clone(), true, recipient, timeout)
+                .and_then(move |payload| {
+                    salf.send_router_swap(
+                        market,
+                        payload,
+                        amount,
+                        market.token,
+                        ERC20_TRANSFER,
+                        "value",
+                        recipient,
+                        timeout,
+                    )
+                }),
+        )
+    }
+
+    /// Sells `amount` of the token of `market` for the native coin, paid to `recipient`. The
+    /// router has to be approved to spend the token already. Resolves to the native coin
+    /// received once the swap is mined, measured by the router unwrapping it.
+    pub(crate) fn router_swap_tokens_for_native(
+        &self,
+        market: RouterMarket,
+        amount: Uint256,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let salf = try_future!(self.spending_token(market.token, &amount));
+        Box::new(
+            self.router_swap_payload(market, amount, false, recipient, timeout)
+                .and_then(move |payload| {
+                    salf.send_router_swap(
+                        market,
+                        payload,
+                        0u32.into(),
+                        market.wrapped_native,
+                        WETH_WITHDRAWAL,
+                        "wad",
+                        recipient,
+                        timeout,
+                    )
+                }),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn send_router_swap(
+        &self,
+        market: RouterMarket,
+        payload: Vec<u8>,
+        value: Uint256,
+        output_contract: Address,
+        output_event: EventDefinition,
+        output_param: &'static str,
+        recipient: Address,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let chain = market.chain;
+        let own_address = self.own_address;
+        let salf = self.clone();
+
+        Box::new(
+            self.send_swap_transaction(
+                chain,
+                market.router,
+                payload,
+                value,
+                vec![SendTxOption::GasLimit(ROUTER_SWAP_GAS_LIMIT.into())],
+            )
+            .and_then(move |tx_hash| {
+                let confirmed = salf.confirm_transaction(
+                    chain,
+                    tx_hash.clone(),
+                    output_contract,
+                    output_event,
+                    output_param,
+                );
+                salf.wait_or_reconcile(
+                    chain,
+                    tx_hash,
+                    confirmed,
+                    Duration::from_secs(timeout),
+                    output_contract,
+                    output_event,
+                    output_param,
+                )
+                .map(move |amount_out| {
+                    salf.emit(BridgeEvent::EventObserved {
+                        chain,
+                        contract: output_contract,
+                        event: output_event.signature.to_string(),
+                    });
+                    if recipient == own_address {
+                        salf.emit(BridgeEvent::FundsArrived {
+                            chain,
+                            amount: amount_out.clone(),
+                        });
+                    }
+                    amount_out
+                })
+            }),
+        )
+    }
+}
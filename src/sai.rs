@@ -0,0 +1,104 @@
+//! Legacy single collateral Dai (SAI). The bridge switched from SAI to Dai when MakerDAO
+//! launched multi collateral Dai, so routers set up before that may still hold SAI that the
+//! bridge no longer accepts. MakerDAO's migration contract swaps it for Dai one to one.
+
+use crate::encoding;
+use crate::logs::ERC20_TRANSFER;
+use crate::units::Dai;
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use std::str::FromStr;
+use std::time::Duration;
+use web30::types::SendTxOption;
+
+pub use crate::contracts::{MAINNET_SAI, MAINNET_SAI_MIGRATION};
+
+/// The migration moves the SAI through a MakerDAO vault, which takes a lot more gas than a
+/// token transfer
+const SAI_MIGRATION_GAS_LIMIT: u64 = 400_000;
+
+pub fn mainnet_sai() -> Address {
+    Address::from_str(MAINNET_SAI).unwrap()
+}
+
+pub fn mainnet_sai_migration() -> Address {
+    Address::from_str(MAINNET_SAI_MIGRATION).unwrap()
+}
+
+impl TokenBridge {
+    /// SAI held by `address`, SAI has the same 18 decimals as Dai
+    pub fn get_sai_balance(&self, address: Address) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        Box::new(
+            self.get_token_balance(self.sai_address, address)
+                .map(Dai::from_wei),
+        )
+    }
+
+    /// Swaps all of our SAI for Dai through the migration contract, approving it first if
+    /// needed. Resolves to the Dai received, zero without sending anything if we hold no SAI.
+    /// Fails with `TokenBridgeError::TransactionReverted` if MakerDAO has closed the migration.
+    pub fn migrate_sai_to_dai(
+        &self,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        self.in_account_order(move |salf| salf.run_sai_migration(timeout))
+    }
+
+    fn run_sai_migration(&self, timeout: Duration) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        let salf = self.clone();
+        let sai = self.sai_address;
+        let migration = self.sai_migration_address;
+        let dai = self.foreign_dai_contract_address;
+
+        Box::new(
+            self.get_sai_balance(self.own_address)
+                .and_then(move |balance| {
+                    let amount = balance.into_wei();
+                    if amount == 0u32.into() {
+                        return Box::new(futures::future::ok(Dai::from_wei(amount)))
+                            as Box<dyn Future<Item = Dai, Error = Error>>;
+                    }
+                    info!("Migrating {} SAI to Dai", amount);
+                    let sender = salf.clone();
+                    Box::new(
+                        salf.ensure_token_approved(sai, migration, amount.clone(), timeout)
+                            .and_then({
+                                let amount = amount.clone();
+                                move |_| sender.spending_token(sai, &amount)
+                            })
+                            .and_then(move |sender| {
+                                sender.send_transaction(
+                                    Chain::Eth,
+                                    migration,
+                                    encoding::sai_migration_swap_sai_to_dai(amount),
+                                    0u32.into(),
+                                    vec![SendTxOption::GasLimit(SAI_MIGRATION_GAS_LIMIT.into())],
+                                )
+                            })
+                            .and_then(move |tx_hash| {
+                                let confirmed = salf.confirm_transaction(
+                                    Chain::Eth,
+                                    tx_hash.clone(),
+                                    dai,
+                                    ERC20_TRANSFER,
+                                    "value",
+                                );
+                                salf.wait_or_reconcile(
+                                    Chain::Eth,
+                                    tx_hash,
+                                    confirmed,
+                                    timeout,
+                                    dai,
+                                    ERC20_TRANSFER,
+                                    "value",
+                                )
+                                .map(Dai::from_wei)
+                            }),
+                    )
+                }),
+        )
+    }
+}
@@ -0,0 +1,97 @@
+//! Signing of transactions, either with a local key or by something external such as a
+//! hardware wallet or a remote signing service
+
+use crate::message::eip191_hash;
+use crate::typed_data::{typed_data_hash, Eip712Domain, TypedStruct};
+use clarity::{Address, PrivateKey, Signature, Transaction};
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+
+/// Signs transactions for a single address
+pub trait Signer: Send + Sync {
+    /// The address transactions signed by this signer are sent from
+    fn address(&self) -> Address;
+
+    /// Signs `tx`, using EIP-155 replay protection for `chain_id` if one is given, and returns
+    /// the signed transaction
+    fn sign_transaction(
+        &self,
+        tx: Transaction,
+        chain_id: Option<u64>,
+    ) -> Box<dyn Future<Item = Transaction, Error = Error>>;
+
+    /// Signs `message` with the EIP-191 prefix, see `crate::message`. Signers that can only
+    /// sign transactions leave this out.
+    fn sign_message(&self, _message: &[u8]) -> Box<dyn Future<Item = Signature, Error = Error>> {
+        Box::new(futures::future::err(format_err!(
+            "Signer for {} can't sign messages",
+            self.address()
+        )))
+    }
+
+    /// Signs the EIP-712 `message` in `domain`, see `crate::typed_data`. Signers that can only
+    /// sign transactions leave this out.
+    fn sign_typed_data(
+        &self,
+        _domain: &Eip712Domain,
+        _message: &TypedStruct,
+    ) -> Box<dyn Future<Item = Signature, Error = Error>> {
+        Box::new(futures::future::err(format_err!(
+            "Signer for {} can't sign typed data",
+            self.address()
+        )))
+    }
+}
+
+/// A `Signer` holding the private key in memory
+#[derive(Clone)]
+pub struct LocalSigner {
+    secret: PrivateKey,
+    address: Address,
+}
+
+impl LocalSigner {
+    pub fn new(secret: PrivateKey) -> Result<LocalSigner, Error> {
+        let address = secret.to_public_key()?;
+        Ok(LocalSigner { secret, address })
+    }
+
+    /// Skips deriving the address from the key, for when the caller already knows it
+    pub fn with_address(secret: PrivateKey, address: Address) -> LocalSigner {
+        LocalSigner { secret, address }
+    }
+
+    pub fn secret(&self) -> &PrivateKey {
+        &self.secret
+    }
+}
+
+impl Signer for LocalSigner {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn sign_transaction(
+        &self,
+        tx: Transaction,
+        chain_id: Option<u64>,
+    ) -> Box<dyn Future<Item = Transaction, Error = Error>> {
+        Box::new(futures::future::ok(tx.sign(&self.secret, chain_id)))
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Box<dyn Future<Item = Signature, Error = Error>> {
+        Box::new(futures::future::ok(
+            self.secret.sign_hash(&eip191_hash(message)),
+        ))
+    }
+
+    fn sign_typed_data(
+        &self,
+        domain: &Eip712Domain,
+        message: &TypedStruct,
+    ) -> Box<dyn Future<Item = Signature, Error = Error>> {
+        let hash = typed_data_hash(domain, message);
+        Box::new(futures::future::ok(self.secret.sign_hash(&hash)))
+    }
+}
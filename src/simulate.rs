@@ -0,0 +1,174 @@
+//! Dry runs of transactions with `eth_call` and `eth_estimateGas` against the latest block.
+//! Anything that would revert once mined, an expired approval, a paused contract or a bad
+//! deadline, shows up here without spending gas.
+
+use crate::abi::decode_output;
+use crate::encoding;
+use crate::fee::BridgeDirection;
+use crate::units::{Dai, Eth, XDai};
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use web30::types::TransactionRequest;
+
+/// What a transaction would do if it was mined on top of the latest block
+#[derive(Debug, Clone, PartialEq)]
+pub struct Simulation<T> {
+    /// The decoded return value of the call
+    pub output: T,
+    pub gas_estimate: Uint256,
+}
+
+impl TokenBridge {
+    /// Runs the transaction `send_transaction` would send with `eth_call` and estimates its
+    /// gas. Fails with `TokenBridgeError::SimulationReverted` if the node rejects either, which
+    /// is what a revert looks like over RPC. Returns the raw output of the call.
+    pub fn simulate_transaction(
+        &self,
+        chain: Chain,
+        to: Address,
+        data: Vec<u8>,
+        value: Uint256,
+    ) -> Box<dyn Future<Item = Simulation<Vec<u8>>, Error = Error>> {
+        let web3 = self.web3(chain);
+        let request = TransactionRequest {
+            from: self.own_address,
+            to: Some(to),
+            gas: None,
+            gas_price: None,
+            value: Some(value.into()),
+            data: Some(data.into()),
+            nonce: None,
+        };
+
+        Box::new(
+            web3.eth_call(request.clone())
+                .join(web3.eth_estimate_gas(request))
+                .map_err(|e| {
+                    TokenBridgeError::SimulationReverted {
+                        reason: e.to_string(),
+                    }
+                    .into()
+                })
+                .map(|(output, gas_estimate)| Simulation {
+                    output: output.to_vec(),
+                    gas_estimate,
+                }),
+        )
+    }
+
+    /// Dry run of `eth_to_dai_swap` with the same calldata, returns the Dai it would buy
+    pub fn simulate_eth_to_dai_swap(
+        &self,
+        eth_amount: Eth,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Simulation<Dai>, Error = Error>> {
+        let salf = self.clone();
+        let uniswap_address = self.uniswap_address;
+        Box::new(
+            self.eth_to_dai_swap_payload(eth_amount.clone(), self.own_address, timeout)
+                .and_then(move |swap| {
+                    salf.simulate_transaction(
+                        Chain::Eth,
+                        uniswap_address,
+                        swap.data,
+                        eth_amount.into_wei(),
+                    )
+                })
+                .and_then(|simulation| {
+                    Ok(Simulation {
+                        output: Dai::from_wei(decode_output(
+                            "ethToTokenSwapInput(uint256,uint256)",
+                            &simulation.output,
+                        )?),
+                        gas_estimate: simulation.gas_estimate,
+                    })
+                }),
+        )
+    }
+
+    /// Dry run of `dai_to_eth_swap` with the same calldata, returns the ETH it would buy. This
+    /// reverts if Uniswap is not approved to spend our Dai yet.
+    pub fn simulate_dai_to_eth_swap(
+        &self,
+        dai_amount: Dai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Simulation<Eth>, Error = Error>> {
+        let salf = self.clone();
+        let uniswap_address = self.uniswap_address;
+        Box::new(
+            self.dai_to_eth_swap_payload(dai_amount, self.own_address, timeout)
+                .and_then(move |swap| {
+                    salf.simulate_transaction(Chain::Eth, uniswap_address, swap.data, 0u32.into())
+                })
+                .and_then(|simulation| {
+                    Ok(Simulation {
+                        output: Eth::from_wei(decode_output(
+                            "tokenToEthSwapInput(uint256,uint256,uint256)",
+                            &simulation.output,
+                        )?),
+                        gas_estimate: simulation.gas_estimate,
+                    })
+                }),
+        )
+    }
+
+    /// Dry run of `dai_to_xdai_bridge`, returns the xDai expected to arrive after the bridge fee
+    pub fn simulate_dai_to_xdai_bridge(
+        &self,
+        dai_amount: Dai,
+    ) -> Box<dyn Future<Item = Simulation<XDai>, Error = Error>> {
+        let salf = self.clone();
+        let payload =
+            encoding::erc20_transfer(self.xdai_foreign_bridge_address, dai_amount.wei().clone());
+        let dai_address = self.foreign_dai_contract_address;
+        Box::new(
+            self.bridge_preflight(BridgeDirection::DaiToXdai)
+                .and_then(move |_| {
+                    salf.simulate_transaction(Chain::Eth, dai_address, payload, 0u32.into())
+                        .join(
+                            salf.net_bridged_amount(
+                                BridgeDirection::DaiToXdai,
+                                dai_amount.into_wei(),
+                            ),
+                        )
+                })
+                .map(|(simulation, net)| Simulation {
+                    output: XDai::from_wei(net),
+                    gas_estimate: simulation.gas_estimate,
+                }),
+        )
+    }
+
+    /// Dry run of `xdai_to_dai_bridge`, returns the Dai expected to be released after the
+    /// bridge fee
+    pub fn simulate_xdai_to_dai_bridge(
+        &self,
+        xdai_amount: XDai,
+    ) -> Box<dyn Future<Item = Simulation<Dai>, Error = Error>> {
+        let salf = self.clone();
+        let home_bridge = self.xdai_home_bridge_address;
+        Box::new(
+            self.bridge_preflight(BridgeDirection::XdaiToDai)
+                .and_then(move |_| {
+                    salf.simulate_transaction(
+                        Chain::Xdai,
+                        home_bridge,
+                        Vec::new(),
+                        xdai_amount.wei().clone(),
+                    )
+                    .join(
+                        salf.net_bridged_amount(BridgeDirection::XdaiToDai, xdai_amount.into_wei()),
+                    )
+                })
+                .map(|(simulation, net)| Simulation {
+                    output: Dai::from_wei(net),
+                    gas_estimate: simulation.gas_estimate,
+                }),
+        )
+    }
+}
@@ -0,0 +1,518 @@
+//! An in-memory `TokenBridgeApi` for testing code built on the bridge against scenarios that
+//! are hard to set up on a real chain, like a bridge that takes half an hour to pay out or a
+//! swap that reverts once.
+//!
+//! Time is simulated rather than waited for. Every call moves the clock forward by the
+//! configured latency and bridge transfers arrive once the clock is the bridge delay past the
+//! time they were sent, so tests run instantly and always come out the same.
+
+use crate::api::TokenBridgeApi;
+use crate::error::{TimeoutOutcome, TokenBridgeError};
+use crate::fee::BridgeTransfer;
+use crate::units::{Dai, Eth, XDai};
+use crate::Chain;
+use clarity::Address;
+use failure::bail;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The calls of a `SimulatedTokenBridge` that failures can be injected into. Conversions that
+/// go through several steps fail at the step that was told to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SimulatedCall {
+    EthToDaiSwap,
+    DaiToEthSwap,
+    DaiToXdaiBridge,
+    XdaiToDaiBridge,
+}
+
+/// A bridge transfer that hasn't been paid out on the other side yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedTransfer {
+    /// `Chain::Xdai` for xDai paid out by the home bridge, `Chain::Eth` for Dai paid out by the
+    /// foreign bridge
+    pub chain: Chain,
+    pub recipient: Address,
+    pub amount: Uint256,
+    /// The simulated time it is paid out at
+    pub arrives_at: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Ledger {
+    eth: HashMap<Address, Uint256>,
+    dai: HashMap<Address, Uint256>,
+    xdai: HashMap<Address, Uint256>,
+    /// Dai wei per ETH
+    eth_price: Uint256,
+    latency: Duration,
+    bridge_delay: Duration,
+    now: Duration,
+    in_flight: Vec<SimulatedTransfer>,
+    failures: HashMap<SimulatedCall, u32>,
+    transactions: u64,
+}
+
+fn one_eth() -> Uint256 {
+    1_000_000_000_000_000_000u64.into()
+}
+
+fn balance(balances: &HashMap<Address, Uint256>, address: Address) -> Uint256 {
+    balances
+        .get(&address)
+        .cloned()
+        .unwrap_or_else(|| 0u32.into())
+}
+
+fn credit(balances: &mut HashMap<Address, Uint256>, address: Address, amount: Uint256) {
+    let new_balance = balance(balances, address) + amount;
+    balances.insert(address, new_balance);
+}
+
+fn debit(
+    balances: &mut HashMap<Address, Uint256>,
+    address: Address,
+    amount: Uint256,
+) -> Result<(), Error> {
+    let current = balance(balances, address);
+    if current < amount {
+        bail!("Balance {} of {} is less than {}", current, address, amount);
+    }
+    balances.insert(address, current - amount);
+    Ok(())
+}
+
+impl Ledger {
+    /// Moves the clock forward by `by` and pays out the transfers that have arrived by then
+    fn advance(&mut self, by: Duration) {
+        self.now += by;
+        let now = self.now;
+        let (arrived, in_flight) = self
+            .in_flight
+            .drain(..)
+            .partition(|transfer| transfer.arrives_at <= now);
+        self.in_flight = in_flight;
+        for transfer in arrived {
+            let balances = match transfer.chain {
+                Chain::Eth => &mut self.dai,
+                Chain::Xdai => &mut self.xdai,
+            };
+            credit(balances, transfer.recipient, transfer.amount);
+        }
+    }
+
+    fn send_to_bridge(&mut self, chain: Chain, recipient: Address, amount: Uint256) {
+        self.in_flight.push(SimulatedTransfer {
+            chain,
+            recipient,
+            amount,
+            arrives_at: self.now + self.bridge_delay,
+        });
+        // a bridge without a delay pays out in the same block
+        self.advance(Duration::from_secs(0));
+    }
+}
+
+/// A `TokenBridgeApi` with balances, an exchange rate and a clock of its own. Clones share
+/// their state, so a test can keep one to set up scenarios and inspect balances while the code
+/// under test uses another.
+#[derive(Debug, Clone)]
+pub struct SimulatedTokenBridge {
+    own_address: Address,
+    ledger: Arc<Mutex<Ledger>>,
+}
+
+impl SimulatedTokenBridge {
+    /// A bridge for `own_address` with empty balances where one ETH trades for `eth_price` Dai
+    /// both ways, calls take no time and the bridge pays out immediately
+    pub fn new(own_address: Address, eth_price: Dai) -> SimulatedTokenBridge {
+        SimulatedTokenBridge {
+            own_address,
+            ledger: Arc::new(Mutex::new(Ledger {
+                eth_price: eth_price.into_wei(),
+                ..Ledger::default()
+            })),
+        }
+    }
+
+    pub fn set_eth_balance(&self, address: Address, amount: Eth) {
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.eth.insert(address, amount.into_wei());
+    }
+
+    pub fn set_dai_balance(&self, address: Address, amount: Dai) {
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.dai.insert(address, amount.into_wei());
+    }
+
+    pub fn set_xdai_balance(&self, address: Address, amount: XDai) {
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.xdai.insert(address, amount.into_wei());
+    }
+
+    /// Sets the Dai one ETH trades for
+    pub fn set_eth_price(&self, eth_price: Dai) {
+        self.ledger.lock().unwrap().eth_price = eth_price.into_wei();
+    }
+
+    /// How far every call moves the clock. Swaps and bridge transfers slower than their
+    /// `timeout` still go through but fail with `TokenBridgeError::TimedOut`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.ledger.lock().unwrap().latency = latency;
+    }
+
+    /// How long bridge transfers take to be paid out on the other side
+    pub fn set_bridge_delay(&self, delay: Duration) {
+        self.ledger.lock().unwrap().bridge_delay = delay;
+    }
+
+    /// Makes the next `times` calls of `call` revert with `TokenBridgeError::TransactionReverted`
+    /// without moving any funds
+    pub fn fail_next(&self, call: SimulatedCall, times: u32) {
+        let mut ledger = self.ledger.lock().unwrap();
+        *ledger.failures.entry(call).or_insert(0) += times;
+    }
+
+    /// Moves the clock forward by `by`, paying out the bridge transfers that arrive meanwhile
+    pub fn advance(&self, by: Duration) {
+        self.ledger.lock().unwrap().advance(by);
+    }
+
+    /// The simulated time since the bridge was created
+    pub fn now(&self) -> Duration {
+        self.ledger.lock().unwrap().now
+    }
+
+    /// The bridge transfers not paid out yet, oldest first
+    pub fn in_flight(&self) -> Vec<SimulatedTransfer> {
+        self.ledger.lock().unwrap().in_flight.clone()
+    }
+
+    /// Sends a transaction doing `action`. The clock moves by the latency first, then the
+    /// transaction either reverts if a failure was injected into `call` or is executed. A
+    /// transaction slower than `timeout` seconds is executed but reported as timed out.
+    fn transact<T, F>(
+        &self,
+        call: SimulatedCall,
+        timeout: Option<u64>,
+        action: F,
+    ) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Ledger, Uint256) -> Result<T, Error>,
+    {
+        let mut ledger = self.ledger.lock().unwrap();
+        let latency = ledger.latency;
+        ledger.advance(latency);
+        ledger.transactions += 1;
+        let tx_hash: Uint256 = ledger.transactions.into();
+
+        if let Some(remaining) = ledger.failures.get_mut(&call) {
+            if *remaining > 0 {
+                *remaining -= 1;
+                return Err(TokenBridgeError::TransactionReverted { tx_hash }.into());
+            }
+        }
+        let output = action(&mut ledger, tx_hash.clone())?;
+        match timeout {
+            Some(timeout) if latency > Duration::from_secs(timeout) => {
+                Err(TokenBridgeError::TimedOut {
+                    outcome: TimeoutOutcome::Unknown(tx_hash),
+                }
+                .into())
+            }
+            _ => Ok(output),
+        }
+    }
+
+    fn eth_price(&self) -> Result<Uint256, Error> {
+        let eth_price = self.ledger.lock().unwrap().eth_price.clone();
+        if eth_price == 0u32.into() {
+            bail!("Simulated ETH price is zero");
+        }
+        Ok(eth_price)
+    }
+
+    fn swap_eth(&self, amount: Eth, timeout: u64) -> Result<Dai, Error> {
+        let own_address = self.own_address;
+        let dai = amount.wei().clone() * self.eth_price()? / one_eth();
+        self.transact(SimulatedCall::EthToDaiSwap, Some(timeout), |ledger, _| {
+            debit(&mut ledger.eth, own_address, amount.into_wei())?;
+            credit(&mut ledger.dai, own_address, dai.clone());
+            Ok(Dai::from_wei(dai))
+        })
+    }
+
+    fn swap_dai(&self, amount: Dai, timeout: u64) -> Result<Eth, Error> {
+        let own_address = self.own_address;
+        let eth = amount.wei().clone() * one_eth() / self.eth_price()?;
+        self.transact(SimulatedCall::DaiToEthSwap, Some(timeout), |ledger, _| {
+            debit(&mut ledger.dai, own_address, amount.into_wei())?;
+            credit(&mut ledger.eth, own_address, eth.clone());
+            Ok(Eth::from_wei(eth))
+        })
+    }
+
+    fn bridge_dai(&self, amount: Dai, timeout: u64) -> Result<BridgeTransfer, Error> {
+        let own_address = self.own_address;
+        self.transact(
+            SimulatedCall::DaiToXdaiBridge,
+            Some(timeout),
+            |ledger, tx_hash| {
+                debit(&mut ledger.dai, own_address, amount.wei().clone())?;
+                ledger.send_to_bridge(Chain::Xdai, own_address, amount.wei().clone());
+                Ok(BridgeTransfer {
+                    tx_hash,
+                    amount: amount.into_wei(),
+                    expected_fee: 0u32.into(),
+                })
+            },
+        )
+    }
+
+    fn bridge_xdai(&self, amount: XDai, timeout: u64) -> Result<BridgeTransfer, Error> {
+        let own_address = self.own_address;
+        self.transact(
+            SimulatedCall::XdaiToDaiBridge,
+            Some(timeout),
+            |ledger, tx_hash| {
+                debit(&mut ledger.xdai, own_address, amount.wei().clone())?;
+                ledger.send_to_bridge(Chain::Eth, own_address, amount.wei().clone());
+                Ok(BridgeTransfer {
+                    tx_hash,
+                    amount: amount.into_wei(),
+                    expected_fee: 0u32.into(),
+                })
+            },
+        )
+    }
+
+    /// Bridges `amount` xDai and waits up to `timeout` seconds of simulated time for the Dai to
+    /// arrive before swapping it
+    fn withdraw_to_eth(&self, amount: XDai, timeout: u64) -> Result<Eth, Error> {
+        let transfer = self.bridge_xdai(amount, timeout)?;
+        {
+            let mut ledger = self.ledger.lock().unwrap();
+            let timeout = Duration::from_secs(timeout);
+            if ledger.bridge_delay > timeout {
+                ledger.advance(timeout);
+                return Err(TokenBridgeError::TimedOut {
+                    outcome: TimeoutOutcome::Unknown(transfer.tx_hash),
+                }
+                .into());
+            }
+            let delay = ledger.bridge_delay;
+            ledger.advance(delay);
+        }
+        self.swap_dai(Dai::from_wei(transfer.amount), timeout)
+    }
+}
+
+impl TokenBridgeApi for SimulatedTokenBridge {
+    fn own_address(&self) -> Address {
+        self.own_address
+    }
+
+    fn get_eth_balance(&self, address: Address) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        let ledger = self.ledger.lock().unwrap();
+        Box::new(futures::future::ok(Eth::from_wei(balance(
+            &ledger.eth,
+            address,
+        ))))
+    }
+
+    fn get_dai_balance(&self, address: Address) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        let ledger = self.ledger.lock().unwrap();
+        Box::new(futures::future::ok(Dai::from_wei(balance(
+            &ledger.dai,
+            address,
+        ))))
+    }
+
+    fn get_xdai_balance(&self, address: Address) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        let ledger = self.ledger.lock().unwrap();
+        Box::new(futures::future::ok(XDai::from_wei(balance(
+            &ledger.xdai,
+            address,
+        ))))
+    }
+
+    fn eth_to_dai_price(&self, amount: Eth) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        Box::new(futures::future::result(self.eth_price().map(|eth_price| {
+            Dai::from_wei(amount.into_wei() * eth_price / one_eth())
+        })))
+    }
+
+    fn dai_to_eth_price(&self, amount: Dai) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        Box::new(futures::future::result(self.eth_price().map(|eth_price| {
+            Eth::from_wei(amount.into_wei() * one_eth() / eth_price)
+        })))
+    }
+
+    fn eth_cost_of_dai(&self, amount: Dai) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        // rounded up so that swapping the cost buys at least `amount`
+        Box::new(futures::future::result(self.eth_price().map(|eth_price| {
+            let one: Uint256 = 1u32.into();
+            Eth::from_wei((amount.into_wei() * one_eth() + eth_price.clone() - one) / eth_price)
+        })))
+    }
+
+    fn eth_to_dai_swap(
+        &self,
+        amount: Eth,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        Box::new(futures::future::result(self.swap_eth(amount, timeout)))
+    }
+
+    fn dai_to_eth_swap(
+        &self,
+        amount: Dai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        Box::new(futures::future::result(self.swap_dai(amount, timeout)))
+    }
+
+    fn dai_to_xdai_bridge(
+        &self,
+        amount: Dai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        Box::new(futures::future::result(self.bridge_dai(amount, timeout)))
+    }
+
+    fn xdai_to_dai_bridge(
+        &self,
+        amount: XDai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        Box::new(futures::future::result(self.bridge_xdai(amount, timeout)))
+    }
+
+    fn eth_to_xdai(
+        &self,
+        amount: Eth,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        let bridged = self
+            .swap_eth(amount, timeout)
+            .and_then(|dai| self.bridge_dai(dai, timeout))
+            .map(|transfer| XDai::from_wei(transfer.expected_net_amount()));
+        Box::new(futures::future::result(bridged))
+    }
+
+    fn xdai_to_eth(
+        &self,
+        amount: XDai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        Box::new(futures::future::result(
+            self.withdraw_to_eth(amount, timeout),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ether(amount: u64) -> Uint256 {
+        Uint256::from(amount) * one_eth()
+    }
+
+    fn bridge() -> SimulatedTokenBridge {
+        let bridge = SimulatedTokenBridge::new(
+            Address::from_slice(&[1u8; 20]).unwrap(),
+            Dai::from_wei(ether(200)),
+        );
+        bridge.set_eth_balance(bridge.own_address, Eth::from_wei(ether(1)));
+        bridge.set_xdai_balance(bridge.own_address, XDai::from_wei(ether(400)));
+        bridge
+    }
+
+    fn is_reverted(error: &Error) -> bool {
+        matches!(
+            error.downcast_ref::<TokenBridgeError>(),
+            Some(TokenBridgeError::TransactionReverted { .. })
+        )
+    }
+
+    fn is_timed_out(error: &Error) -> bool {
+        matches!(
+            error.downcast_ref::<TokenBridgeError>(),
+            Some(TokenBridgeError::TimedOut { .. })
+        )
+    }
+
+    #[test]
+    fn test_swap_reverts_once() {
+        let bridge = bridge();
+        let own_address = bridge.own_address;
+        bridge.fail_next(SimulatedCall::EthToDaiSwap, 1);
+
+        let error = bridge
+            .eth_to_xdai(Eth::from_wei(ether(1)), 60)
+            .wait()
+            .unwrap_err();
+        assert!(is_reverted(&error));
+        assert_eq!(
+            bridge.get_eth_balance(own_address).wait().unwrap(),
+            Eth::from_wei(ether(1))
+        );
+
+        let bridged = bridge.eth_to_xdai(Eth::from_wei(ether(1)), 60).wait();
+        assert_eq!(bridged.unwrap(), XDai::from_wei(ether(200)));
+        assert_eq!(
+            bridge.get_xdai_balance(own_address).wait().unwrap(),
+            XDai::from_wei(ether(600))
+        );
+    }
+
+    #[test]
+    fn test_bridge_delayed() {
+        let bridge = bridge();
+        let own_address = bridge.own_address;
+        bridge.set_bridge_delay(Duration::from_secs(30 * 60));
+
+        let error = bridge
+            .xdai_to_eth(XDai::from_wei(ether(200)), 600)
+            .wait()
+            .unwrap_err();
+        assert!(is_timed_out(&error));
+        assert_eq!(bridge.now(), Duration::from_secs(600));
+        assert_eq!(bridge.in_flight().len(), 1);
+
+        bridge.advance(Duration::from_secs(20 * 60));
+        assert!(bridge.in_flight().is_empty());
+        assert_eq!(
+            bridge.get_dai_balance(own_address).wait().unwrap(),
+            Dai::from_wei(ether(200))
+        );
+        let swapped = bridge.dai_to_eth_swap(Dai::from_wei(ether(200)), 60).wait();
+        assert_eq!(swapped.unwrap(), Eth::from_wei(ether(1)));
+    }
+
+    #[test]
+    fn test_slow_swap_times_out() {
+        let bridge = bridge();
+        bridge.set_latency(Duration::from_secs(120));
+
+        let error = bridge
+            .eth_to_dai_swap(Eth::from_wei(ether(1)), 60)
+            .wait()
+            .unwrap_err();
+        assert!(is_timed_out(&error));
+        // like a transaction mined after the timeout, the swap still happened
+        assert_eq!(
+            bridge.get_dai_balance(bridge.own_address).wait().unwrap(),
+            Dai::from_wei(ether(200))
+        );
+        assert!(bridge
+            .eth_to_dai_swap(Eth::from_wei(ether(1)), 60)
+            .wait()
+            .is_err());
+    }
+}
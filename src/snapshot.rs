@@ -0,0 +1,249 @@
+//! Everything a router needs to decide on a conversion, fetched in one request per chain.
+//!
+//! web30 has no JSON-RPC batch transport, so the Eth side reads are batched on chain instead,
+//! through a single `eth_call` to the Multicall contract's `aggregate`.
+
+use crate::abi::{address_word, decode_output};
+use crate::units::{Dai, Eth, XDai};
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::abi::{derive_method_id, encode_call, Token};
+use clarity::Address;
+use failure::bail;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::str::FromStr;
+use web30::types::TransactionRequest;
+
+pub use crate::contracts::MAINNET_MULTICALL;
+
+pub fn mainnet_multicall() -> Address {
+    Address::from_str(MAINNET_MULTICALL).unwrap()
+}
+
+/// Balances, allowance and prices at one point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct BridgeSnapshot {
+    /// The Eth block the Eth side values were read at
+    pub eth_block: Uint256,
+    pub eth_balance: Eth,
+    pub dai_balance: Dai,
+    pub xdai_balance: XDai,
+    /// How much of our Dai Uniswap may spend
+    pub uniswap_dai_allowance: Dai,
+    /// Dai received for selling one ETH
+    pub eth_to_dai_price: Dai,
+    /// ETH received for selling one Dai
+    pub dai_to_eth_price: Eth,
+}
+
+fn word(value: usize) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[24..32].copy_from_slice(&(value as u64).to_be_bytes());
+    word
+}
+
+fn read_word(data: &[u8], offset: usize) -> Result<usize, Error> {
+    let bytes = match data.get(offset..offset.saturating_add(32)) {
+        Some(bytes) => bytes,
+        None => bail!("Multicall output too short at {}: {:?}", offset, data),
+    };
+    if bytes[0..24].iter().any(|b| *b != 0) {
+        bail!("Multicall offset or length too large {:?}", bytes);
+    }
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&bytes[24..32]);
+    Ok(u64::from_be_bytes(value) as usize)
+}
+
+/// Calldata for Multicall's `aggregate((address,bytes)[])`
+pub fn encode_aggregate(calls: &[(Address, Vec<u8>)]) -> Vec<u8> {
+    let mut out = derive_method_id("aggregate((address,bytes)[])").to_vec();
+    out.extend_from_slice(&word(32));
+    out.extend_from_slice(&word(calls.len()));
+
+    // each (address, bytes) tuple is dynamic, so the array starts with their offsets
+    let mut tuples = Vec::new();
+    let mut offsets = Vec::new();
+    for (target, data) in calls {
+        offsets.push(calls.len() * 32 + tuples.len());
+        tuples.extend_from_slice(&address_word(*target));
+        tuples.extend_from_slice(&word(64));
+        tuples.extend_from_slice(&word(data.len()));
+        tuples.extend_from_slice(data);
+        let padding = (32 - data.len() % 32) % 32;
+        tuples.resize(tuples.len() + padding, 0);
+    }
+    for offset in offsets {
+        out.extend_from_slice(&word(offset));
+    }
+    out.extend_from_slice(&tuples);
+    out
+}
+
+/// Decodes the `(uint256 blockNumber, bytes[] returnData)` returned by `aggregate`
+pub fn decode_aggregate(output: &[u8]) -> Result<(Uint256, Vec<Vec<u8>>), Error> {
+    if output.len() < 64 {
+        bail!("Multicall output too short {:?}", output);
+    }
+    let block = Uint256::from_bytes_be(&output[0..32]);
+    let array = read_word(output, 32)?;
+    let count = read_word(output, array)?;
+    let elements = array + 32;
+
+    let mut results = Vec::new();
+    for i in 0..count {
+        let start = elements.saturating_add(read_word(output, elements + i * 32)?);
+        let length = read_word(output, start)?;
+        match output.get(start + 32..(start + 32).saturating_add(length)) {
+            Some(data) => results.push(data.to_vec()),
+            None => bail!("Multicall result {} out of range {:?}", i, output),
+        }
+    }
+    Ok((block, results))
+}
+
+impl TokenBridge {
+    /// Reads our ETH, Dai and xDai balances, the Uniswap Dai allowance and the price of one ETH
+    /// and one Dai in a single request to each full node.
+    pub fn snapshot(&self) -> Box<dyn Future<Item = BridgeSnapshot, Error = Error>> {
+        let own_address = self.own_address;
+        let one: Uint256 = 1_000_000_000_000_000_000u64.into();
+        let calls: Vec<(Address, &'static str, Vec<Token>)> = vec![
+            (
+                self.multicall_address,
+                "getEthBalance(address)",
+                vec![own_address.into()],
+            ),
+            (
+                self.foreign_dai_contract_address,
+                "balanceOf(address)",
+                vec![own_address.into()],
+            ),
+            (
+                self.foreign_dai_contract_address,
+                "allowance(address,address)",
+                vec![own_address.into(), self.uniswap_address.into()],
+            ),
+            (
+                self.uniswap_address,
+                "getEthToTokenInputPrice(uint256)",
+                vec![one.clone().into()],
+            ),
+            (
+                self.uniswap_address,
+                "getTokenToEthInputPrice(uint256)",
+                vec![one.into()],
+            ),
+        ];
+        let signatures: Vec<&'static str> = calls.iter().map(|call| call.1).collect();
+        let data = encode_aggregate(
+            &calls
+                .iter()
+                .map(|(target, signature, args)| (*target, encode_call(signature, args)))
+                .collect::<Vec<_>>(),
+        );
+        let eth_web3 = self.web3(Chain::Eth);
+        let multicall_address = self.multicall_address;
+
+        let eth_side = self.with_retry(Chain::Eth, "aggregate((address,bytes)[])", move || {
+            eth_web3.eth_call(TransactionRequest {
+                from: own_address,
+                to: Some(multicall_address),
+                gas: None,
+                gas_price: None,
+                value: None,
+                data: Some(data.clone().into()),
+                nonce: None,
+            })
+        });
+        let xdai_web3 = self.web3(Chain::Xdai);
+        let xdai_side = self.with_retry(Chain::Xdai, "eth_getBalance", move || {
+            xdai_web3.eth_get_balance(own_address)
+        });
+
+        Box::new(
+            eth_side
+                .join(xdai_side)
+                .and_then(move |(output, xdai_balance)| {
+                    let (eth_block, results) = decode_aggregate(&output)?;
+                    if results.len() != signatures.len() {
+                        bail!(
+                            "Expected {} multicall results, got {}",
+                            signatures.len(),
+                            results.len()
+                        );
+                    }
+                    let mut values: Vec<Uint256> = Vec::new();
+                    for (result, signature) in results.iter().zip(signatures.iter()) {
+                        values.push(decode_output(signature, result)?);
+                    }
+                    Ok(BridgeSnapshot {
+                        eth_block,
+                        eth_balance: Eth::from_wei(values[0].clone()),
+                        dai_balance: Dai::from_wei(values[1].clone()),
+                        xdai_balance: XDai::from_wei(xdai_balance),
+                        uniswap_dai_allowance: Dai::from_wei(values[2].clone()),
+                        eth_to_dai_price: Dai::from_wei(values[3].clone()),
+                        dai_to_eth_price: Eth::from_wei(values[4].clone()),
+                    })
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_decode_aggregate() {
+        let mut output = Vec::new();
+        output.extend_from_slice(&word(7)); // block
+        output.extend_from_slice(&word(64)); // offset of the array
+        output.extend_from_slice(&word(2)); // two results
+        output.extend_from_slice(&word(64));
+        output.extend_from_slice(&word(128));
+        output.extend_from_slice(&word(32));
+        output.extend_from_slice(&word(5));
+        output.extend_from_slice(&word(3));
+        output.extend_from_slice(&[1, 2, 3]);
+        output.extend_from_slice(&[0u8; 29]);
+
+        let (block, results) = decode_aggregate(&output).unwrap();
+        assert_eq!(block, 7u32.into());
+        assert_eq!(results, vec![word(5).to_vec(), vec![1, 2, 3]]);
+        assert!(decode_aggregate(&output[0..100]).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_decode_aggregate_doesnt_panic(output in vec(any::<u8>(), 0..300)) {
+            let _ = decode_aggregate(&output);
+        }
+
+        #[test]
+        fn prop_decode_aggregate_round_trip(results in vec(vec(any::<u8>(), 0..70), 0..5)) {
+            // the same layout as the calldata, with a block number in front of the array
+            let mut output = word(9).to_vec();
+            output.extend_from_slice(&word(64));
+            output.extend_from_slice(&word(results.len()));
+            let mut offset = results.len() * 32;
+            let mut tails = Vec::new();
+            for result in &results {
+                output.extend_from_slice(&word(offset));
+                let mut tail = word(result.len()).to_vec();
+                tail.extend_from_slice(result);
+                tail.resize(tail.len() + (32 - result.len() % 32) % 32, 0);
+                offset += tail.len();
+                tails.extend_from_slice(&tail);
+            }
+            output.extend_from_slice(&tails);
+
+            prop_assert_eq!(decode_aggregate(&output).unwrap(), (9u32.into(), results));
+        }
+    }
+}
@@ -0,0 +1,457 @@
+//! Limits on how fast funds can leave our account, so that a misbehaving or compromised process
+//! using the bridge can't drain it faster than the policy allows. Every swap, bridge transfer
+//! and payment counts what it sends against the limits of that asset before it is sent, the
+//! steps of `eth_to_xdai` and `xdai_to_eth` count separately.
+//!
+//! An operation the limits refuse fails before anything is done for it. Spends are counted
+//! right before the transaction moving the funds is broadcast, whether or not it then goes
+//! through, since a failed broadcast or wait doesn't tell us that nothing was sent. With a `SpendingStore` the counters
+//! survive a restart.
+
+use crate::pending::ConversionKind;
+use crate::Chain;
+use crate::TokenBridge;
+use crate::TokenBridgeError;
+use clarity::Address;
+use failure::format_err;
+use failure::Error;
+use num256::Uint256;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The rolling window `SpendingLimits::per_day` applies to, in seconds
+pub const SPENDING_WINDOW: u64 = 24 * 60 * 60;
+
+/// What is spent, each with its own limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SpendAsset {
+    Eth,
+    Xdai,
+    /// The ERC20 token at this address, Dai is `Token(dai_address)`
+    Token(Address),
+}
+
+impl SpendAsset {
+    /// The native coin of `chain`
+    pub fn native(chain: Chain) -> SpendAsset {
+        match chain {
+            Chain::Eth => SpendAsset::Eth,
+            Chain::Xdai => SpendAsset::Xdai,
+        }
+    }
+}
+
+/// The limits of one asset, in its smallest unit
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpendingLimits {
+    /// The most a single swap, transfer or payment may send
+    pub per_operation: Option<Uint256>,
+    /// The most that may be sent within `SPENDING_WINDOW`
+    pub per_day: Option<Uint256>,
+}
+
+/// The limits of every asset, assets without limits can be spent freely
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpendingPolicy {
+    pub limits: HashMap<SpendAsset, SpendingLimits>,
+}
+
+impl SpendingPolicy {
+    pub fn with_limits(mut self, asset: SpendAsset, limits: SpendingLimits) -> SpendingPolicy {
+        self.limits.insert(asset, limits);
+        self
+    }
+}
+
+/// An amount sent at `at` seconds since the epoch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Spend {
+    pub asset: SpendAsset,
+    pub amount: Uint256,
+    pub at: u64,
+}
+
+/// Where the spends within the window are kept between restarts
+pub trait SpendingStore: Send + Sync {
+    /// Replaces the stored spends with `spends`
+    fn save(&self, spends: &[Spend]) -> Result<(), Error>;
+    fn load(&self) -> Result<Vec<Spend>, Error>;
+}
+
+/// Keeps the spends in a single JSON file, rewritten on every spend
+pub struct JsonSpendingStore {
+    path: PathBuf,
+}
+
+impl JsonSpendingStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> JsonSpendingStore {
+        JsonSpendingStore { path: path.into() }
+    }
+}
+
+impl SpendingStore for JsonSpendingStore {
+    fn save(&self, spends: &[Spend]) -> Result<(), Error> {
+        // write then rename so a crash mid write can't leave a truncated file behind
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(spends)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<Spend>, Error> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let contents = fs::read_to_string(&self.path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+/// Enforces a `SpendingPolicy`, shared by all clones of a bridge
+pub struct SpendingLimiter {
+    policy: SpendingPolicy,
+    store: Option<Box<dyn SpendingStore>>,
+    /// The spends within the window, oldest first
+    spends: Mutex<Vec<Spend>>,
+}
+
+impl SpendingLimiter {
+    /// A limiter that starts counting from zero on every restart
+    pub fn new(policy: SpendingPolicy) -> SpendingLimiter {
+        SpendingLimiter {
+            policy,
+            store: None,
+            spends: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// A limiter that carries on from the spends saved in `store`
+    pub fn with_store(
+        policy: SpendingPolicy,
+        store: Box<dyn SpendingStore>,
+    ) -> Result<SpendingLimiter, Error> {
+        let spends = store.load()?;
+        Ok(SpendingLimiter {
+            policy,
+            store: Some(store),
+            spends: Mutex::new(spends),
+        })
+    }
+
+    pub fn policy(&self) -> &SpendingPolicy {
+        &self.policy
+    }
+
+    /// How much of `asset` was sent within the last `SPENDING_WINDOW`
+    pub fn spent_today(&self, asset: SpendAsset) -> Uint256 {
+        let spends = self.spends.lock().unwrap();
+        spent_since(&spends, asset, now().saturating_sub(SPENDING_WINDOW))
+    }
+
+    /// Counts `amount` of `asset` as spent now, or fails with
+    /// `TokenBridgeError::SpendingLimitExceeded` if that is more than the policy allows
+    pub fn spend(&self, asset: SpendAsset, amount: &Uint256) -> Result<(), Error> {
+        self.spend_at(asset, amount, now())
+    }
+
+    /// Fails like `spend` would without counting anything, for refusing an operation before
+    /// anything is done for it
+    pub fn check(&self, asset: SpendAsset, amount: &Uint256) -> Result<(), Error> {
+        let mut spends = self.spends.lock().unwrap();
+        self.check_at(&mut spends, asset, amount, now())
+    }
+
+    /// Drops the spends that left the window before `at` and checks `amount` against what is
+    /// left
+    fn check_at(
+        &self,
+        spends: &mut Vec<Spend>,
+        asset: SpendAsset,
+        amount: &Uint256,
+        at: u64,
+    ) -> Result<(), Error> {
+        let limits = match self.policy.limits.get(&asset) {
+            Some(limits) => limits,
+            None => return Ok(()),
+        };
+        let exceeded = |allowed: Uint256| -> Error {
+            warn!(
+                "Spending {} of {:?} refused, only {} is allowed",
+                amount, asset, allowed
+            );
+            TokenBridgeError::SpendingLimitExceeded {
+                asset,
+                amount: amount.clone(),
+                allowed,
+            }
+            .into()
+        };
+        if let Some(ref per_operation) = limits.per_operation {
+            if amount > per_operation {
+                return Err(exceeded(per_operation.clone()));
+            }
+        }
+
+        let window_start = at.saturating_sub(SPENDING_WINDOW);
+        spends.retain(|spend| spend.at > window_start);
+        if let Some(ref per_day) = limits.per_day {
+            let spent = spent_since(spends, asset, window_start);
+            if spent.clone() + amount.clone() > *per_day {
+                let allowed = if *per_day > spent {
+                    per_day.clone() - spent
+                } else {
+                    0u32.into()
+                };
+                return Err(exceeded(allowed));
+            }
+        }
+        Ok(())
+    }
+
+    fn spend_at(&self, asset: SpendAsset, amount: &Uint256, at: u64) -> Result<(), Error> {
+        if !self.policy.limits.contains_key(&asset) {
+            return Ok(());
+        }
+        let mut spends = self.spends.lock().unwrap();
+        self.check_at(&mut spends, asset, amount, at)?;
+        spends.push(Spend {
+            asset,
+            amount: amount.clone(),
+            at,
+        });
+        if let Some(ref store) = self.store {
+            if let Err(e) = store.save(&spends) {
+                // a spend we couldn't persist would be forgotten on restart
+                spends.pop();
+                return Err(format_err!("Could not save spending counters: {}", e));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn spent_since(spends: &[Spend], asset: SpendAsset, since: u64) -> Uint256 {
+    spends
+        .iter()
+        .filter(|spend| spend.asset == asset && spend.at > since)
+        .fold(0u32.into(), |total: Uint256, spend| {
+            total + spend.amount.clone()
+        })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0)
+}
+
+impl TokenBridge {
+    /// What a conversion of `kind` spends
+    pub(crate) fn conversion_asset(&self, kind: ConversionKind) -> SpendAsset {
+        match kind {
+            ConversionKind::EthToDai | ConversionKind::EthToXdai => SpendAsset::Eth,
+            ConversionKind::DaiToEth | ConversionKind::DaiToXdai => {
+                SpendAsset::Token(self.foreign_dai_contract_address)
+            }
+            ConversionKind::XdaiToDai
+            | ConversionKind::XdaiToEth
+            | ConversionKind::XdaiToToken(_) => SpendAsset::Xdai,
+            ConversionKind::TokenToToken(token)
+            | ConversionKind::TokenToXdai(token)
+            | ConversionKind::BridgeToken { token, .. } => SpendAsset::Token(token),
+        }
+    }
+
+    /// Counts `amount` of `asset` against `spending_limiter` if one is set, to be called
+    /// right before sending it
+    pub(crate) fn record_spending(&self, asset: SpendAsset, amount: &Uint256) -> Result<(), Error> {
+        match self.spending_limiter {
+            Some(ref limiter) => limiter.spend(asset, amount),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails like `record_spending` would without counting anything, so that an operation the
+    /// limits refuse fails before anything is asked of the node
+    pub(crate) fn check_spending(&self, asset: SpendAsset, amount: &Uint256) -> Result<(), Error> {
+        match self.spending_limiter {
+            Some(ref limiter) => limiter.check(asset, amount),
+            None => Ok(()),
+        }
+    }
+
+    /// A copy of this bridge whose transactions count `amount` of `token` against the limits
+    /// as they are broadcast, to send the transaction moving the tokens with. Fails right away
+    /// if the limits wouldn't allow it.
+    pub(crate) fn spending_token(
+        &self,
+        token: Address,
+        amount: &Uint256,
+    ) -> Result<TokenBridge, Error> {
+        self.check_spending(SpendAsset::Token(token), amount)?;
+        let mut bridge = self.clone();
+        bridge.token_spend = Some((token, amount.clone()));
+        Ok(bridge)
+    }
+
+    /// What a transaction on `chain` with `value` attached sent with this bridge spends
+    pub(crate) fn transaction_spends(
+        &self,
+        chain: Chain,
+        value: &Uint256,
+    ) -> Vec<(SpendAsset, Uint256)> {
+        let mut spends = Vec::new();
+        if *value > 0u32.into() {
+            spends.push((SpendAsset::native(chain), value.clone()));
+        }
+        if let Some((token, ref amount)) = self.token_spend {
+            spends.push((SpendAsset::Token(token), amount.clone()));
+        }
+        spends
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_bridge, MockBlockReader};
+    use crate::units::Eth;
+    use futures::Future;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn exceeded(result: Result<(), Error>) -> Option<Uint256> {
+        match result.err()?.downcast::<TokenBridgeError>() {
+            Ok(TokenBridgeError::SpendingLimitExceeded { allowed, .. }) => Some(allowed),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_spending_limits() {
+        let limiter = SpendingLimiter::new(SpendingPolicy::default().with_limits(
+            SpendAsset::Eth,
+            SpendingLimits {
+                per_operation: Some(60u32.into()),
+                per_day: Some(100u32.into()),
+            },
+        ));
+        let at = 1_000_000;
+
+        assert_eq!(
+            exceeded(limiter.spend_at(SpendAsset::Eth, &61u32.into(), at)),
+            Some(60u32.into())
+        );
+        limiter
+            .spend_at(SpendAsset::Eth, &60u32.into(), at)
+            .unwrap();
+        assert_eq!(
+            exceeded(limiter.spend_at(SpendAsset::Eth, &50u32.into(), at + 10)),
+            Some(40u32.into())
+        );
+        limiter
+            .spend_at(SpendAsset::Eth, &40u32.into(), at + 10)
+            .unwrap();
+        // assets without limits aren't counted against anything
+        limiter
+            .spend_at(SpendAsset::Xdai, &1_000u32.into(), at + 10)
+            .unwrap();
+
+        // the window rolls, the first spend drops out of it a day later
+        let later = at + SPENDING_WINDOW;
+        assert!(exceeded(limiter.spend_at(SpendAsset::Eth, &60u32.into(), later - 1)).is_some());
+        limiter
+            .spend_at(SpendAsset::Eth, &60u32.into(), later)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_check() {
+        let limiter = SpendingLimiter::new(SpendingPolicy::default().with_limits(
+            SpendAsset::Eth,
+            SpendingLimits {
+                per_operation: None,
+                per_day: Some(100u32.into()),
+            },
+        ));
+        // checking counts nothing, only the spend itself does
+        limiter.check(SpendAsset::Eth, &100u32.into()).unwrap();
+        assert_eq!(limiter.spent_today(SpendAsset::Eth), 0u32.into());
+        limiter.spend(SpendAsset::Eth, &70u32.into()).unwrap();
+        assert_eq!(
+            exceeded(limiter.check(SpendAsset::Eth, &40u32.into())),
+            Some(30u32.into())
+        );
+        assert_eq!(limiter.spent_today(SpendAsset::Eth), 70u32.into());
+    }
+
+    #[test]
+    fn test_json_spending_store() {
+        let path = std::env::temp_dir().join(format!("spending-{}.json", rand::random::<u64>()));
+        let policy = SpendingPolicy::default().with_limits(
+            SpendAsset::Xdai,
+            SpendingLimits {
+                per_operation: None,
+                per_day: Some(100u32.into()),
+            },
+        );
+        let limiter =
+            SpendingLimiter::with_store(policy.clone(), Box::new(JsonSpendingStore::new(&path)))
+                .unwrap();
+        limiter.spend(SpendAsset::Xdai, &70u32.into()).unwrap();
+
+        // a restarted process carries on where the last one stopped
+        let limiter =
+            SpendingLimiter::with_store(policy, Box::new(JsonSpendingStore::new(&path))).unwrap();
+        assert_eq!(limiter.spent_today(SpendAsset::Xdai), 70u32.into());
+        assert!(exceeded(limiter.spend(SpendAsset::Xdai, &40u32.into())).is_some());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_wrap_eth_over_limit() {
+        let mut bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        let limiter = Arc::new(SpendingLimiter::new(SpendingPolicy::default().with_limits(
+            SpendAsset::Eth,
+            SpendingLimits {
+                per_operation: Some(1_000u32.into()),
+                per_day: None,
+            },
+        )));
+        bridge.spending_limiter = Some(limiter.clone());
+
+        // refused before anything is asked of the node
+        let wrapped = bridge
+            .wrap_eth(Eth::from_wei(1_001u32.into()), Duration::from_secs(1))
+            .wait()
+            .map(|_| ());
+        assert_eq!(exceeded(wrapped), Some(1_000u32.into()));
+        assert_eq!(limiter.spent_today(SpendAsset::Eth), 0u32.into());
+    }
+
+    #[test]
+    fn test_unwrap_weth_over_limit() {
+        let mut bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        let weth = SpendAsset::Token(bridge.weth_address);
+        let limiter = Arc::new(SpendingLimiter::new(SpendingPolicy::default().with_limits(
+            weth,
+            SpendingLimits {
+                per_operation: Some(1_000u32.into()),
+                per_day: None,
+            },
+        )));
+        bridge.spending_limiter = Some(limiter.clone());
+
+        let unwrapped = bridge
+            .unwrap_weth(Eth::from_wei(1_001u32.into()), Duration::from_secs(1))
+            .wait()
+            .map(|_| ());
+        assert_eq!(exceeded(unwrapped), Some(1_000u32.into()));
+        assert_eq!(limiter.spent_today(weth), 0u32.into());
+    }
+}
@@ -0,0 +1,193 @@
+//! Splitting a large swap into several smaller ones sent at random intervals. Each part moves
+//! the price less than the whole would, and a bot watching for our swaps can't tell when the
+//! next one comes or how large the conversion is.
+
+use crate::price_cache::PriceDirection;
+use crate::units::{Dai, Eth};
+use crate::TokenBridge;
+use failure::format_err;
+use failure::Error;
+use futures::{Future, Stream};
+use futures_timer::Delay;
+use num256::Uint256;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// The outcome of a swap executed as one or more parts
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwapResult {
+    pub direction: PriceDirection,
+    /// Sold across all parts, ETH for `EthToDai` and Dai for `DaiToEth`
+    pub amount_in: Uint256,
+    /// Received across all parts
+    pub amount_out: Uint256,
+    /// How many swaps were sent
+    pub parts: u32,
+}
+
+/// How a swap is executed, the default sends it whole right away
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionPolicy {
+    /// How many swaps of about equal size the amount is split into
+    pub parts: u32,
+    /// Each part after the first waits a random time up to this before being sent
+    pub max_delay: Duration,
+}
+
+impl Default for ExecutionPolicy {
+    fn default() -> Self {
+        ExecutionPolicy {
+            parts: 1,
+            max_delay: Duration::from_secs(0),
+        }
+    }
+}
+
+/// Splits `amount` into `parts` equal amounts, the last one taking the remainder
+fn split_amount(amount: Uint256, parts: u32) -> Vec<Uint256> {
+    let parts = parts.max(1);
+    let part = amount.clone() / parts.into();
+    let mut amounts = vec![part.clone(); parts as usize - 1];
+    amounts.push(amount - part * (parts - 1).into());
+    amounts
+}
+
+fn random_delay(max_delay: Duration) -> Duration {
+    max_delay.mul_f64(rand::random::<f64>())
+}
+
+impl ExecutionPolicy {
+    /// Sends swaps as `parts` swaps, waiting a random time up to `max_delay` before each after
+    /// the first
+    pub fn split(parts: u32, max_delay: Duration) -> ExecutionPolicy {
+        ExecutionPolicy { parts, max_delay }
+    }
+
+    /// Sells `eth_amount` for Dai with `eth_to_dai_swap`, one part at a time. Each part may take
+    /// up to `timeout` seconds. If a part fails the parts already sent stay executed, the error
+    /// says how far it got.
+    pub fn eth_to_dai_swap(
+        &self,
+        bridge: &TokenBridge,
+        eth_amount: Eth,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = SwapResult, Error = Error>> {
+        let bridge = bridge.clone();
+        self.execute(
+            PriceDirection::EthToDai,
+            eth_amount.into_wei(),
+            move |amount| {
+                Box::new(
+                    bridge
+                        .eth_to_dai_swap(Eth::from_wei(amount), timeout)
+                        .map(Dai::into_wei),
+                )
+            },
+        )
+    }
+
+    /// Sells `dai_amount` for ETH with `dai_to_eth_swap`, see `eth_to_dai_swap`
+    pub fn dai_to_eth_swap(
+        &self,
+        bridge: &TokenBridge,
+        dai_amount: Dai,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = SwapResult, Error = Error>> {
+        let bridge = bridge.clone();
+        self.execute(
+            PriceDirection::DaiToEth,
+            dai_amount.into_wei(),
+            move |amount| {
+                Box::new(
+                    bridge
+                        .dai_to_eth_swap(Dai::from_wei(amount), timeout)
+                        .map(Eth::into_wei),
+                )
+            },
+        )
+    }
+
+    fn execute<F>(
+        &self,
+        direction: PriceDirection,
+        amount: Uint256,
+        swap: F,
+    ) -> Box<dyn Future<Item = SwapResult, Error = Error>>
+    where
+        F: Fn(Uint256) -> Box<dyn Future<Item = Uint256, Error = Error>> + 'static,
+    {
+        let swap = Rc::new(swap);
+        let amounts = split_amount(amount, self.parts);
+        let total_parts = amounts.len();
+        let max_delay = self.max_delay;
+        let started = SwapResult {
+            direction,
+            amount_in: 0u32.into(),
+            amount_out: 0u32.into(),
+            parts: 0,
+        };
+
+        Box::new(
+            futures::stream::iter_ok(amounts).fold(started, move |done, amount| {
+                let delay: Box<dyn Future<Item = (), Error = Error>> = if done.parts == 0 {
+                    Box::new(futures::future::ok(()))
+                } else {
+                    Box::new(Delay::new(random_delay(max_delay)).from_err())
+                };
+                let swap = swap.clone();
+                let part = amount.clone();
+                delay
+                    .and_then(move |_| swap(part))
+                    .then(move |res| match res {
+                        Ok(amount_out) => {
+                            trace!(
+                                "Part {} of {} sold {} for {}",
+                                done.parts + 1,
+                                total_parts,
+                                amount,
+                                amount_out
+                            );
+                            Ok(SwapResult {
+                                amount_in: done.amount_in + amount,
+                                amount_out: done.amount_out + amount_out,
+                                parts: done.parts + 1,
+                                ..done
+                            })
+                        }
+                        Err(e) => Err(format_err!(
+                            "Split swap stopped after {} of {} parts, {} sold for {}: {}",
+                            done.parts,
+                            total_parts,
+                            done.amount_in,
+                            done.amount_out,
+                            e
+                        )),
+                    })
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_amount() {
+        assert_eq!(
+            split_amount(10u32.into(), 3),
+            vec![3u32.into(), 3u32.into(), 4u32.into()]
+        );
+        assert_eq!(split_amount(10u32.into(), 1), vec![10u32.into()]);
+        assert_eq!(split_amount(10u32.into(), 0), vec![10u32.into()]);
+    }
+
+    #[test]
+    fn test_random_delay() {
+        let max_delay = Duration::from_secs(60);
+        for _ in 0..100 {
+            assert!(random_delay(max_delay) <= max_delay);
+        }
+        assert_eq!(random_delay(Duration::from_secs(0)), Duration::from_secs(0));
+    }
+}
@@ -0,0 +1,354 @@
+//! The ETH -> stablecoin -> xDai chain pipeline for any stablecoin, so that it can run on USDC
+//! for partners that settle in it instead of Dai. Dai goes over the xDai bridge and arrives as native xDai,
+//! other tokens go over the OmniBridge and arrive as their bridged token on xDai.
+//!
+//! Amounts of the coin are `TokenAmount`s, USDC has 6 decimals rather than the 18 of ETH, Dai
+//! and xDai. Passing an amount with the wrong decimals fails instead of moving a million times
+//! more or less than intended.
+
+use crate::amounts::ETH_DECIMALS;
+use crate::contracts::{
+    MAINNET_OMNIBRIDGE_FOREIGN, MAINNET_OMNIBRIDGE_HOME, MAINNET_UNISWAP_V2_ROUTER,
+};
+use crate::encoding;
+use crate::fee::BridgeTransfer;
+use crate::logs::ERC20_TRANSFER;
+use crate::pending::ConversionKind;
+use crate::router::RouterMarket;
+use crate::units::{Dai, Eth, TokenAmount, XDai};
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::str::FromStr;
+use std::time::Duration;
+use web30::types::SendTxOption;
+
+pub use crate::contracts::{MAINNET_USDC, XDAI_BRIDGED_USDC};
+
+/// A mediator call pulls the tokens and locks or burns them
+const OMNIBRIDGE_RELAY_GAS_LIMIT: u64 = 250_000;
+
+/// How a stablecoin gets to the xDai chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StablecoinBridge {
+    /// The xDai bridge at `xdai_foreign_bridge_address`, only for Dai, paid out as xDai
+    XdaiBridge,
+    /// The OmniBridge, paid out as the bridged token `xdai_token`
+    OmniBridge {
+        foreign_mediator: Address,
+        home_mediator: Address,
+        xdai_token: Address,
+    },
+}
+
+/// A stablecoin on Eth, the router it is bought with and how it is bridged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stablecoin {
+    pub symbol: String,
+    /// The token on Eth
+    pub token: Address,
+    pub decimals: u32,
+    /// Uniswap V2 style router on Eth trading the token against WETH
+    pub router: Address,
+    pub bridge: StablecoinBridge,
+}
+
+impl Stablecoin {
+    /// Dai on mainnet, bought on Uniswap V2 and bridged to xDai by the xDai bridge
+    pub fn dai(dai_address: Address) -> Stablecoin {
+        Stablecoin {
+            symbol: "DAI".to_string(),
+            token: dai_address,
+            decimals: ETH_DECIMALS,
+            router: Address::from_str(MAINNET_UNISWAP_V2_ROUTER).unwrap(),
+            bridge: StablecoinBridge::XdaiBridge,
+        }
+    }
+
+    /// USDC on mainnet, bought on Uniswap V2 and bridged by the OmniBridge
+    pub fn usdc() -> Stablecoin {
+        Stablecoin {
+            symbol: "USDC".to_string(),
+            token: Address::from_str(MAINNET_USDC).unwrap(),
+            decimals: 6,
+            router: Address::from_str(MAINNET_UNISWAP_V2_ROUTER).unwrap(),
+            bridge: StablecoinBridge::OmniBridge {
+                foreign_mediator: Address::from_str(MAINNET_OMNIBRIDGE_FOREIGN).unwrap(),
+                home_mediator: Address::from_str(MAINNET_OMNIBRIDGE_HOME).unwrap(),
+                xdai_token: Address::from_str(XDAI_BRIDGED_USDC).unwrap(),
+            },
+        }
+    }
+
+    /// `value` in the smallest unit of this coin
+    pub fn amount(&self, value: Uint256) -> TokenAmount {
+        TokenAmount::new(value, self.decimals)
+    }
+
+    /// Parses a decimal amount like "12.5" of this coin
+    pub fn parse(&self, amount: &str) -> Result<TokenAmount, Error> {
+        TokenAmount::parse(amount, self.decimals)
+    }
+
+    /// `amount` with the symbol, failing if it is not an amount of this coin
+    pub fn format(&self, amount: &TokenAmount) -> Result<String, Error> {
+        amount.value_in(self.decimals)?;
+        Ok(format!("{} {}", amount, self.symbol))
+    }
+
+    fn market(&self, weth: Address) -> RouterMarket {
+        RouterMarket {
+            chain: Chain::Eth,
+            router: self.router,
+            wrapped_native: weth,
+            token: self.token,
+        }
+    }
+}
+
+impl TokenBridge {
+    /// `coin` held by `address` on Eth
+    pub fn get_stablecoin_balance(
+        &self,
+        coin: &Stablecoin,
+        address: Address,
+    ) -> Box<dyn Future<Item = TokenAmount, Error = Error>> {
+        let coin = coin.clone();
+        Box::new(
+            self.get_token_balance(coin.token, address)
+                .map(move |value| coin.amount(value)),
+        )
+    }
+
+    /// What `coin` is held as by `address` on xDai, its xDai balance for Dai. Bridged tokens
+    /// keep the decimals of the original.
+    pub fn get_bridged_stablecoin_balance(
+        &self,
+        coin: &Stablecoin,
+        address: Address,
+    ) -> Box<dyn Future<Item = TokenAmount, Error = Error>> {
+        match coin.bridge {
+            StablecoinBridge::XdaiBridge => Box::new(
+                self.xdai_web3
+                    .eth_get_balance(address)
+                    .map(|value| TokenAmount::new(value, ETH_DECIMALS)),
+            ),
+            StablecoinBridge::OmniBridge { xdai_token, .. } => {
+                let coin = coin.clone();
+                Box::new(
+                    self.get_token_balance_on(Chain::Xdai, xdai_token, address)
+                        .map(move |value| coin.amount(value)),
+                )
+            }
+        }
+    }
+
+    /// How much `coin` selling `eth_amount` on its router gives
+    pub fn eth_to_stablecoin_price(
+        &self,
+        coin: &Stablecoin,
+        eth_amount: Eth,
+    ) -> Box<dyn Future<Item = TokenAmount, Error = Error>> {
+        let coin = coin.clone();
+        Box::new(
+            self.router_amount_out(
+                Chain::Eth,
+                coin.router,
+                eth_amount.into_wei(),
+                vec![self.weth_address, coin.token],
+            )
+            .map(move |value| coin.amount(value)),
+        )
+    }
+
+    /// Sells `eth_amount` for `coin` on its router, resolving to the amount of `coin` bought
+    pub fn eth_to_stablecoin_swap(
+        &self,
+        coin: &Stablecoin,
+        eth_amount: Eth,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = TokenAmount, Error = Error>> {
+        let coin = coin.clone();
+        self.in_account_order(move |salf| {
+            Box::new(
+                salf.router_swap_native_for_tokens(
+                    coin.market(salf.weth_address),
+                    eth_amount.into_wei(),
+                    salf.own_address,
+                    timeout,
+                )
+                .map(move |value| coin.amount(value)),
+            )
+        })
+    }
+
+    /// Sells `amount` of `coin` for ETH on its router, approving the router first if needed and
+    /// `auto_approve` is on
+    pub fn stablecoin_to_eth_swap(
+        &self,
+        coin: &Stablecoin,
+        amount: TokenAmount,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        let amount = try_future!(amount.value_in(coin.decimals));
+        let market = coin.market(self.weth_address);
+        self.in_account_order(move |salf| {
+            let ensure_approved = if salf.auto_approve {
+                salf.ensure_token_approved(
+                    market.token,
+                    market.router,
+                    amount.clone(),
+                    Duration::from_secs(timeout),
+                )
+            } else {
+                Box::new(futures::future::ok(()))
+            };
+            Box::new(
+                ensure_approved
+                    .and_then(move |_| {
+                        salf.router_swap_tokens_for_native(
+                            market,
+                            amount,
+                            salf.own_address,
+                            timeout,
+                        )
+                    })
+                    .map(Eth::from_wei),
+            )
+        })
+    }
+
+    /// Bridges `amount` of `coin` from Eth to our address on xDai. Dai goes through
+    /// `dai_to_xdai_bridge`, OmniBridge fees are not looked up and reported as zero. The
+    /// amounts of the transfer are in the smallest unit of `coin`.
+    pub fn stablecoin_to_xdai_bridge(
+        &self,
+        coin: &Stablecoin,
+        amount: TokenAmount,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        let amount = try_future!(amount.value_in(coin.decimals));
+        match coin.bridge {
+            StablecoinBridge::XdaiBridge => self.dai_to_xdai_bridge(Dai::from_wei(amount), timeout),
+            StablecoinBridge::OmniBridge {
+                foreign_mediator, ..
+            } => self.omnibridge_relay(Chain::Eth, foreign_mediator, coin.token, amount, timeout),
+        }
+    }
+
+    /// Bridges `amount` of `coin` as held on xDai back to our address on Eth, see
+    /// `stablecoin_to_xdai_bridge`
+    pub fn stablecoin_from_xdai_bridge(
+        &self,
+        coin: &Stablecoin,
+        amount: TokenAmount,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        let amount = try_future!(amount.value_in(coin.decimals));
+        match coin.bridge {
+            StablecoinBridge::XdaiBridge => {
+                self.xdai_to_dai_bridge(XDai::from_wei(amount), timeout)
+            }
+            StablecoinBridge::OmniBridge {
+                home_mediator,
+                xdai_token,
+                ..
+            } => self.omnibridge_relay(Chain::Xdai, home_mediator, xdai_token, amount, timeout),
+        }
+    }
+
+    /// Approves `mediator` for `token` on `chain` if needed and relays `amount` to our address
+    /// on the other chain if the spending limits allow and it is confirmed when
+    /// `large_transfers` asks for it, resolving once the mediator took the tokens
+    fn omnibridge_relay(
+        &self,
+        chain: Chain,
+        mediator: Address,
+        token: Address,
+        amount: Uint256,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        self.send_conversion(
+            ConversionKind::BridgeToken { from: chain, token },
+            amount.clone(),
+            move |salf| salf.run_omnibridge_relay(chain, mediator, token, amount, timeout),
+        )
+    }
+
+    fn run_omnibridge_relay(
+        &self,
+        chain: Chain,
+        mediator: Address,
+        token: Address,
+        amount: Uint256,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = BridgeTransfer, Error = Error>> {
+        let salf = self.clone();
+        let recipient = self.own_address;
+        let timeout = Duration::from_secs(timeout);
+
+        Box::new(
+            self.ensure_token_approved_on(chain, token, mediator, amount.clone(), timeout)
+                .and_then({
+                    let salf = self.clone();
+                    let amount = amount.clone();
+                    move |_| salf.spending_token(token, &amount)
+                })
+                .and_then({
+                    let amount = amount.clone();
+                    move |sender| {
+                        sender.send_transaction(
+                            chain,
+                            mediator,
+                            encoding::omnibridge_relay_tokens(token, recipient, amount),
+                            0u32.into(),
+                            vec![SendTxOption::GasLimit(OMNIBRIDGE_RELAY_GAS_LIMIT.into())],
+                        )
+                    }
+                })
+                .and_then(move |tx_hash| {
+                    let confirmed = salf.confirm_transaction(
+                        chain,
+                        tx_hash.clone(),
+                        token,
+                        ERC20_TRANSFER,
+                        "value",
+                    );
+                    salf.wait_or_reconcile(
+                        chain,
+                        tx_hash.clone(),
+                        confirmed,
+                        timeout,
+                        token,
+                        ERC20_TRANSFER,
+                        "value",
+                    )
+                    .map(move |amount| BridgeTransfer {
+                        tx_hash,
+                        amount,
+                        expected_fee: 0u32.into(),
+                    })
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usdc_amounts() {
+        let usdc = Stablecoin::usdc();
+        let amount = usdc.parse("12.5").unwrap();
+        assert_eq!(amount.value, 12_500_000u32.into());
+        assert_eq!(usdc.format(&amount).unwrap(), "12.5 USDC");
+        // an 18 decimal amount is refused rather than read as a trillion USDC
+        let dai = Stablecoin::dai(Address::default()).parse("12.5").unwrap();
+        assert!(usdc.format(&dai).is_err());
+        assert_eq!(dai.rescale(6).unwrap(), amount);
+    }
+}
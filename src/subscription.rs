@@ -0,0 +1,204 @@
+//! Waiting for contract events through a push based log subscription, such as a WebSocket
+//! connection to the full node, instead of polling it over HTTP. Waits fall back to polling
+//! when no subscriber is set for the chain or the subscription fails.
+
+use crate::instrument::Span;
+use crate::timeouts;
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::abi::derive_signature;
+use clarity::utils::bytes_to_hex_str;
+use clarity::Address;
+use failure::format_err;
+use failure::Error;
+use futures::future::{loop_fn, Loop};
+use futures::Future;
+use futures::Stream;
+use futures_timer::Delay;
+use std::time::Duration;
+use web30::types::{Log, NewFilter};
+
+/// How event waits poll the full node when there is no `LogSubscriber`, and how long they may
+/// take. Also paces the block polling of `wait_for_confirmations`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventPolling {
+    /// Wait after the first poll that found nothing, doubled after every poll after that
+    pub interval: Duration,
+    /// Waits between polls never grow past this, set it to `interval` to poll at a steady rate
+    pub max_interval: Duration,
+    /// Event waits fail after this long, `None` leaves it to the timeouts of the callers
+    pub max_wait: Option<Duration>,
+}
+
+impl Default for EventPolling {
+    fn default() -> Self {
+        EventPolling {
+            interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(5),
+            max_wait: None,
+        }
+    }
+}
+
+impl EventPolling {
+    /// The wait after empty poll number `attempt`, counting from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.interval
+            .checked_mul(factor)
+            .unwrap_or(self.max_interval)
+            .min(self.max_interval)
+    }
+}
+
+/// Something that can push logs matching a filter as they are mined
+pub trait LogSubscriber: Send + Sync {
+    fn subscribe_logs(&self, filter: NewFilter) -> Box<dyn Stream<Item = Log, Error = Error>>;
+}
+
+/// Builds the filter for `event` on `contract`, the topics restrict the indexed arguments in
+/// order and `None` matches anything
+pub fn event_filter(
+    contract: Address,
+    event: &str,
+    topic1: Option<Vec<[u8; 32]>>,
+    topic2: Option<Vec<[u8; 32]>>,
+    topic3: Option<Vec<[u8; 32]>>,
+) -> NewFilter {
+    let to_hex = |topic: &[u8]| Some(format!("0x{}", bytes_to_hex_str(topic)));
+    let to_filter = |topics: Option<Vec<[u8; 32]>>| {
+        topics.map(|topics| topics.iter().map(|topic| to_hex(topic)).collect())
+    };
+    NewFilter {
+        from_block: None,
+        to_block: None,
+        address: vec![contract],
+        topics: Some(vec![
+            Some(vec![to_hex(&derive_signature(event))]),
+            to_filter(topic1),
+            to_filter(topic2),
+            to_filter(topic3),
+        ]),
+    }
+}
+
+impl TokenBridge {
+    /// The subscriber for `chain`, if any
+    fn log_subscriber(&self, chain: Chain) -> Option<&dyn LogSubscriber> {
+        match chain {
+            Chain::Eth => self.eth_log_subscriber.as_ref().map(|s| s.as_ref()),
+            Chain::Xdai => self.xdai_log_subscriber.as_ref().map(|s| s.as_ref()),
+        }
+    }
+
+    /// Resolves with the first `event` emitted by `contract` on `chain` that matches the topics,
+    /// see `event_filter`. Uses the chain's `LogSubscriber` if one is set and polls the full
+    /// node as configured by `event_polling` otherwise. The event is only returned once it has
+    /// `confirmations` blocks on top, all of it within `event_polling.max_wait`.
+    pub fn wait_for_event(
+        &self,
+        chain: Chain,
+        contract: Address,
+        event: &'static str,
+        topic1: Option<Vec<[u8; 32]>>,
+        topic2: Option<Vec<[u8; 32]>>,
+        topic3: Option<Vec<[u8; 32]>>,
+    ) -> Box<dyn Future<Item = Log, Error = Error>> {
+        let salf = self.clone();
+        let confirmations = self.confirmations;
+        let wait = self
+            .wait_for_first_event(chain, contract, event, topic1, topic2, topic3)
+            .and_then(move |log| salf.wait_for_confirmations(chain, log, confirmations));
+        Span::wait_for_event(chain, contract, event).instrument(timeouts::limit(
+            "Waiting for the event",
+            self.event_polling.max_wait,
+            Box::new(wait),
+        ))
+    }
+
+    /// Polls the logs of `filter` from the latest block on according to `event_polling` until
+    /// one shows up
+    fn poll_for_event(
+        &self,
+        chain: Chain,
+        filter: NewFilter,
+    ) -> Box<dyn Future<Item = Log, Error = Error>> {
+        let web3 = self.web3(chain);
+        let polling = self.event_polling;
+        let salf = self.clone();
+
+        Box::new(web3.eth_block_number().and_then(move |start| {
+            let filter = NewFilter {
+                from_block: Some(format!("0x{}", start.to_str_radix(16))),
+                ..filter
+            };
+            loop_fn(1u32, move |attempt| {
+                let web3 = salf.web3(chain);
+                let filter = filter.clone();
+                salf.with_retry(chain, "eth_getLogs", move || {
+                    web3.eth_get_logs(filter.clone())
+                })
+                .and_then(move |logs| {
+                    match logs.into_iter().find(|log| log.removed != Some(true)) {
+                        Some(log) => Box::new(futures::future::ok(Loop::Break(log)))
+                            as Box<dyn Future<Item = _, Error = Error>>,
+                        None => Box::new(
+                            Delay::new(polling.delay(attempt))
+                                .from_err()
+                                .map(move |_| Loop::Continue(attempt + 1)),
+                        ),
+                    }
+                })
+            })
+        }))
+    }
+
+    fn wait_for_first_event(
+        &self,
+        chain: Chain,
+        contract: Address,
+        event: &'static str,
+        topic1: Option<Vec<[u8; 32]>>,
+        topic2: Option<Vec<[u8; 32]>>,
+        topic3: Option<Vec<[u8; 32]>>,
+    ) -> Box<dyn Future<Item = Log, Error = Error>> {
+        let filter = event_filter(contract, event, topic1, topic2, topic3);
+        let subscriber = match self.log_subscriber(chain) {
+            Some(subscriber) => subscriber,
+            None => return self.poll_for_event(chain, filter),
+        };
+
+        let salf = self.clone();
+        Box::new(
+            subscriber
+                .subscribe_logs(filter.clone())
+                .into_future()
+                .map_err(|(e, _)| e)
+                .and_then(|(log, _)| {
+                    log.ok_or_else(|| format_err!("Log subscription ended without an event"))
+                })
+                .or_else(move |e| {
+                    warn!("Log subscription failed with {:?}, polling instead", e);
+                    salf.poll_for_event(chain, filter)
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_polling_delay() {
+        let polling = EventPolling {
+            interval: Duration::from_millis(500),
+            max_interval: Duration::from_secs(3),
+            max_wait: None,
+        };
+        assert_eq!(polling.delay(1), Duration::from_millis(500));
+        assert_eq!(polling.delay(3), Duration::from_secs(2));
+        assert_eq!(polling.delay(4), Duration::from_secs(3));
+        assert_eq!(polling.delay(100), Duration::from_secs(3));
+    }
+}
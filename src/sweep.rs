@@ -0,0 +1,171 @@
+//! Converting a whole balance, for draining the wallet of a router that is being
+//! decommissioned. The gas of the conversion is held back from the balance it is paid from,
+//! estimated from the gas limits the transactions are sent with so a little dust stays behind.
+
+use crate::cost::{saturating_sub, DAI_TRANSFER_GAS, SWAP_GAS, XDAI_BRIDGE_GAS};
+use crate::fee::BridgeDirection;
+use crate::router::ROUTER_SWAP_GAS_LIMIT;
+use crate::units::{Dai, Eth, XDai};
+use crate::Chain;
+use crate::TokenBridge;
+use failure::bail;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+
+/// What is left of `balance` after paying for `gas` at `gas_price`, zero if that is below
+/// `minimum`
+fn max_sendable(balance: Uint256, gas_price: Uint256, gas: u64, minimum: Uint256) -> Uint256 {
+    let sendable = saturating_sub(balance, gas_price * gas.into());
+    if sendable < minimum {
+        0u32.into()
+    } else {
+        sendable
+    }
+}
+
+impl TokenBridge {
+    /// Gas limit of one swap on the configured `swap_backend`
+    fn swap_gas(&self) -> u64 {
+        match self.swap_backend.router() {
+            Some(_) => ROUTER_SWAP_GAS_LIMIT,
+            None => SWAP_GAS,
+        }
+    }
+
+    /// The most ETH `eth_to_xdai` can convert, our balance minus the gas of the swap and the
+    /// bridge transfer. Zero if that is below the bridge minimum at the current price.
+    pub fn max_convertible_eth(&self) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        let gas = self.swap_gas() + DAI_TRANSFER_GAS;
+        let salf = self.clone();
+
+        Box::new(
+            self.eth_web3
+                .eth_get_balance(self.own_address)
+                .join3(
+                    self.gas_price(Chain::Eth),
+                    self.get_bridge_minimum(BridgeDirection::DaiToXdai),
+                )
+                .and_then(move |(balance, gas_price, minimum_dai)| {
+                    let sendable = max_sendable(balance, gas_price, gas, 0u32.into());
+                    salf.eth_to_dai_price(Eth::from_wei(sendable.clone()))
+                        .map(move |dai| {
+                            if dai.into_wei() < minimum_dai {
+                                Eth::from_wei(0u32.into())
+                            } else {
+                                Eth::from_wei(sendable)
+                            }
+                        })
+                }),
+        )
+    }
+
+    /// The most Dai `dai_to_eth_swap` can sell, all of it as long as our ETH covers the gas of
+    /// the swap and zero otherwise
+    pub fn max_convertible_dai(&self) -> Box<dyn Future<Item = Dai, Error = Error>> {
+        let gas = self.swap_gas();
+
+        Box::new(
+            self.get_dai_balance(self.own_address)
+                .join3(
+                    self.eth_web3.eth_get_balance(self.own_address),
+                    self.gas_price(Chain::Eth),
+                )
+                .map(move |(dai, eth, gas_price)| {
+                    if eth < gas_price * gas.into() {
+                        Dai::from_wei(0u32.into())
+                    } else {
+                        dai
+                    }
+                }),
+        )
+    }
+
+    /// The most xDai `xdai_to_dai_bridge` can send, our balance minus the gas of the transfer.
+    /// Zero if that is below the bridge minimum.
+    pub fn max_bridgeable_xdai(&self) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        Box::new(
+            self.xdai_web3
+                .eth_get_balance(self.own_address)
+                .join3(
+                    self.gas_price(Chain::Xdai),
+                    self.get_bridge_minimum(BridgeDirection::XdaiToDai),
+                )
+                .map(|(balance, gas_price, minimum)| {
+                    XDai::from_wei(max_sendable(balance, gas_price, XDAI_BRIDGE_GAS, minimum))
+                }),
+        )
+    }
+
+    /// Converts all of our ETH to xDai with `eth_to_xdai`, see `max_convertible_eth`
+    pub fn sweep_eth_to_xdai(&self, timeout: u64) -> Box<dyn Future<Item = XDai, Error = Error>> {
+        let salf = self.clone();
+        Box::new(
+            self.max_convertible_eth()
+                .and_then(move |eth| {
+                    if eth.wei() == &0u32.into() {
+                        bail!("Not enough ETH to convert after gas");
+                    }
+                    info!("Sweeping {} to xDai", eth);
+                    Ok(salf.eth_to_xdai(eth, timeout))
+                })
+                .flatten(),
+        )
+    }
+
+    /// Sells all of our Dai for ETH with `dai_to_eth_swap`, see `max_convertible_dai`
+    pub fn sweep_dai_to_eth(&self, timeout: u64) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        let salf = self.clone();
+        Box::new(
+            self.max_convertible_dai()
+                .and_then(move |dai| {
+                    if dai.wei() == &0u32.into() {
+                        bail!("No Dai to sell or not enough ETH for gas");
+                    }
+                    info!("Sweeping {} to ETH", dai);
+                    Ok(salf.dai_to_eth_swap(dai, timeout))
+                })
+                .flatten(),
+        )
+    }
+
+    /// Converts all of our xDai to ETH with `xdai_to_eth`, see `max_bridgeable_xdai`
+    pub fn sweep_xdai_to_eth(&self, timeout: u64) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        let salf = self.clone();
+        Box::new(
+            self.max_bridgeable_xdai()
+                .and_then(move |xdai| {
+                    if xdai.wei() == &0u32.into() {
+                        bail!("Not enough xDai to bridge after gas");
+                    }
+                    info!("Sweeping {} to ETH", xdai);
+                    Ok(salf.xdai_to_eth(xdai, timeout))
+                })
+                .flatten(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_sendable() {
+        // 1000 minus 10 gas at 5
+        assert_eq!(
+            max_sendable(1000u32.into(), 5u32.into(), 10, 0u32.into()),
+            950u32.into()
+        );
+        // below the minimum
+        assert_eq!(
+            max_sendable(1000u32.into(), 5u32.into(), 10, 951u32.into()),
+            0u32.into()
+        );
+        // can't even pay for gas
+        assert_eq!(
+            max_sendable(10u32.into(), 5u32.into(), 10, 0u32.into()),
+            0u32.into()
+        );
+    }
+}
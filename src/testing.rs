@@ -0,0 +1,560 @@
+//! A local devnet for deterministic end to end tests. `Devnet::deploy` places minimal mock
+//! contracts on a pair of anvil or ganache nodes standing in for Eth and xDai, and
+//! `Devnet::token_bridge` builds a `TokenBridge` wired to them.
+//!
+//! The mocks are hand assembled EVM bytecode placed with `anvil_setCode` or ganache's
+//! `evm_setAccountCode`, so no compiler is needed to run the tests:
+//!
+//! * the exchange quotes and swaps at a fixed rate and emits the Uniswap V1 swap events, but
+//!   moves neither ETH nor Dai
+//! * the Dai token keeps balances and emits `Transfer` and `Approval`, every allowance is
+//!   unlimited
+//! * the bridges accept anything and answer every call with zero. What the validators would
+//!   do on the other side is done by `relay_dai_to_xdai` and `relay_xdai_to_dai`.
+
+use crate::abi::address_word;
+use crate::block_tag::{rpc_request, rpc_result};
+use crate::builder::TokenBridgeBuilder;
+use crate::logs::{ERC20_APPROVAL, ERC20_TRANSFER};
+use crate::logs::{UNISWAP_V1_ETH_PURCHASE, UNISWAP_V1_TOKEN_PURCHASE};
+use crate::units::{Dai, XDai};
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::abi::derive_method_id;
+use clarity::utils::bytes_to_hex_str;
+use clarity::{Address, PrivateKey};
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use num::Bounded;
+use num256::Uint256;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+const ADD: u8 = 0x01;
+const MUL: u8 = 0x02;
+const SUB: u8 = 0x03;
+const DIV: u8 = 0x04;
+const LT: u8 = 0x10;
+const EQ: u8 = 0x14;
+const SHR: u8 = 0x1c;
+const CALLER: u8 = 0x33;
+const CALLVALUE: u8 = 0x34;
+const CALLDATALOAD: u8 = 0x35;
+const MSTORE: u8 = 0x52;
+const SLOAD: u8 = 0x54;
+const SSTORE: u8 = 0x55;
+const JUMPI: u8 = 0x57;
+const JUMPDEST: u8 = 0x5b;
+const PUSH1: u8 = 0x60;
+const PUSH2: u8 = 0x61;
+const DUP1: u8 = 0x80;
+const DUP2: u8 = 0x81;
+const DUP3: u8 = 0x82;
+const SWAP1: u8 = 0x90;
+const SWAP2: u8 = 0x91;
+const LOG3: u8 = 0xa3;
+const LOG4: u8 = 0xa4;
+const RETURN: u8 = 0xf3;
+const REVERT: u8 = 0xfd;
+
+/// The first account anvil funds
+pub const ANVIL_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+/// Chain id of an anvil node started without `--chain-id`
+pub const ANVIL_CHAIN_ID: u64 = 31337;
+
+/// Where `Devnet::deploy` places the mocks, on the Eth node apart from the home bridge
+const EXCHANGE: [u8; 20] = [0xa1; 20];
+const DAI: [u8; 20] = [0xa2; 20];
+const FOREIGN_BRIDGE: [u8; 20] = [0xa3; 20];
+const HOME_BRIDGE: [u8; 20] = [0xa4; 20];
+
+/// One instruction of a mock contract
+enum Asm {
+    Op(u8),
+    Push(Vec<u8>),
+    /// A `PUSH2` of the offset of a `Label`
+    Target(&'static str),
+    /// The `JUMPDEST` a `Target` of the same name jumps to
+    Label(&'static str),
+}
+
+fn push(value: Uint256) -> Asm {
+    let word: [u8; 32] = value.into();
+    let first = word.iter().position(|b| *b != 0).unwrap_or(31);
+    Asm::Push(word[first..].to_vec())
+}
+
+fn push_u8(value: u8) -> Asm {
+    Asm::Push(vec![value])
+}
+
+/// Bytecode of `code` with the labels resolved
+fn assemble(code: &[Asm]) -> Vec<u8> {
+    let mut labels = HashMap::new();
+    let mut offset = 0;
+    for item in code {
+        offset += match item {
+            Asm::Op(_) => 1,
+            Asm::Push(bytes) => 1 + bytes.len(),
+            Asm::Target(_) => 3,
+            Asm::Label(label) => {
+                labels.insert(*label, offset);
+                1
+            }
+        };
+    }
+
+    let mut bytecode = Vec::with_capacity(offset);
+    for item in code {
+        match item {
+            Asm::Op(op) => bytecode.push(*op),
+            Asm::Push(bytes) => {
+                assert!(!bytes.is_empty() && bytes.len() <= 32);
+                bytecode.push(PUSH1 + bytes.len() as u8 - 1);
+                bytecode.extend_from_slice(bytes);
+            }
+            Asm::Target(label) => {
+                let target = labels[label] as u16;
+                bytecode.push(PUSH2);
+                bytecode.extend_from_slice(&target.to_be_bytes());
+            }
+            Asm::Label(_) => bytecode.push(JUMPDEST),
+        }
+    }
+    bytecode
+}
+
+/// Returns the word on top of the stack
+fn return_top() -> Vec<Asm> {
+    vec![
+        push_u8(0),
+        Asm::Op(MSTORE),
+        push_u8(32),
+        push_u8(0),
+        Asm::Op(RETURN),
+    ]
+}
+
+/// Pushes argument `n` of the call
+fn arg(n: u8) -> Vec<Asm> {
+    vec![push_u8(4 + 32 * n), Asm::Op(CALLDATALOAD)]
+}
+
+/// Jumps to the label of the function called, falls through for anything else
+fn dispatch(functions: &[(&str, &'static str)]) -> Vec<Asm> {
+    let mut code = vec![
+        push_u8(0),
+        Asm::Op(CALLDATALOAD),
+        push_u8(0xe0),
+        Asm::Op(SHR),
+    ];
+    for (signature, label) in functions {
+        code.push(Asm::Op(DUP1));
+        code.push(Asm::Push(derive_method_id(signature).to_vec()));
+        code.push(Asm::Op(EQ));
+        code.push(Asm::Target(label));
+        code.push(Asm::Op(JUMPI));
+    }
+    code
+}
+
+fn topic(topic: [u8; 32]) -> Asm {
+    Asm::Push(topic.to_vec())
+}
+
+fn ether() -> Uint256 {
+    1_000_000_000_000_000_000u64.into()
+}
+
+/// A Uniswap V1 exchange trading at `eth_price` Dai wei per ETH without fees
+fn exchange_code(eth_price: Uint256) -> Vec<u8> {
+    let eth_to_dai = || vec![push(eth_price.clone()), Asm::Op(MUL), push(ether())];
+    let dai_to_eth = || vec![push(ether()), Asm::Op(MUL), push(eth_price.clone())];
+    let divide = || vec![Asm::Op(SWAP1), Asm::Op(DIV)];
+
+    let mut code = dispatch(&[
+        ("getEthToTokenInputPrice(uint256)", "eth_price"),
+        ("getTokenToEthInputPrice(uint256)", "dai_price"),
+        ("ethToTokenSwapInput(uint256,uint256)", "eth_to_dai"),
+        (
+            "ethToTokenTransferInput(uint256,uint256,address)",
+            "eth_to_dai",
+        ),
+        ("tokenToEthSwapInput(uint256,uint256,uint256)", "dai_to_eth"),
+        (
+            "tokenToEthTransferInput(uint256,uint256,uint256,address)",
+            "dai_to_eth",
+        ),
+    ]);
+    code.push(push_u8(0));
+    code.extend(return_top());
+
+    code.push(Asm::Label("eth_price"));
+    code.extend(arg(0));
+    code.extend(eth_to_dai());
+    code.extend(divide());
+    code.extend(return_top());
+
+    code.push(Asm::Label("dai_price"));
+    code.extend(arg(0));
+    code.extend(dai_to_eth());
+    code.extend(divide());
+    code.extend(return_top());
+
+    // TokenPurchase(buyer, eth_sold, tokens_bought), all indexed
+    code.push(Asm::Label("eth_to_dai"));
+    code.push(Asm::Op(CALLVALUE));
+    code.extend(eth_to_dai());
+    code.extend(divide());
+    code.push(Asm::Op(DUP1));
+    code.push(Asm::Op(CALLVALUE));
+    code.push(Asm::Op(CALLER));
+    code.push(topic(UNISWAP_V1_TOKEN_PURCHASE.definition.topic0()));
+    code.extend(vec![push_u8(0), push_u8(0), Asm::Op(LOG4)]);
+    code.extend(return_top());
+
+    // EthPurchase(buyer, tokens_sold, eth_bought), all indexed
+    code.push(Asm::Label("dai_to_eth"));
+    code.extend(arg(0));
+    code.push(Asm::Op(DUP1));
+    code.extend(dai_to_eth());
+    code.extend(divide());
+    code.push(Asm::Op(DUP1));
+    code.push(Asm::Op(SWAP2));
+    code.push(Asm::Op(CALLER));
+    code.push(topic(UNISWAP_V1_ETH_PURCHASE.definition.topic0()));
+    code.extend(vec![push_u8(0), push_u8(0), Asm::Op(LOG4)]);
+    code.extend(return_top());
+
+    assemble(&code)
+}
+
+/// An ERC20 token keeping the balance of each holder in the storage slot of its address.
+/// `credit(from, to, amount)` adds to the balance of `to` and logs a transfer from `from`.
+fn token_code() -> Vec<u8> {
+    let transfer = ERC20_TRANSFER.topic0();
+    let mut code = dispatch(&[
+        ("balanceOf(address)", "balance"),
+        ("transfer(address,uint256)", "transfer"),
+        ("approve(address,uint256)", "approve"),
+        ("allowance(address,address)", "allowance"),
+        ("decimals()", "decimals"),
+        ("credit(address,address,uint256)", "credit"),
+    ]);
+    code.push(push_u8(0));
+    code.extend(return_top());
+
+    code.push(Asm::Label("balance"));
+    code.extend(arg(0));
+    code.push(Asm::Op(SLOAD));
+    code.extend(return_top());
+
+    code.push(Asm::Label("transfer"));
+    code.extend(arg(1));
+    code.extend(vec![Asm::Op(CALLER), Asm::Op(SLOAD)]);
+    code.extend(vec![Asm::Op(DUP2), Asm::Op(DUP2), Asm::Op(LT)]);
+    code.extend(vec![Asm::Target("insufficient"), Asm::Op(JUMPI)]);
+    code.extend(vec![Asm::Op(DUP2), Asm::Op(SWAP1), Asm::Op(SUB)]);
+    code.extend(vec![Asm::Op(CALLER), Asm::Op(SSTORE)]);
+    code.extend(arg(0));
+    code.extend(vec![
+        Asm::Op(DUP1),
+        Asm::Op(SLOAD),
+        Asm::Op(DUP3),
+        Asm::Op(ADD),
+    ]);
+    code.extend(vec![Asm::Op(SWAP1), Asm::Op(SSTORE)]);
+    code.extend(vec![push_u8(0), Asm::Op(MSTORE)]);
+    code.extend(arg(0));
+    code.push(Asm::Op(CALLER));
+    code.push(topic(transfer));
+    code.extend(vec![push_u8(32), push_u8(0), Asm::Op(LOG3)]);
+    code.push(push_u8(1));
+    code.extend(return_top());
+
+    code.push(Asm::Label("approve"));
+    code.extend(arg(1));
+    code.extend(vec![push_u8(0), Asm::Op(MSTORE)]);
+    code.extend(arg(0));
+    code.push(Asm::Op(CALLER));
+    code.push(topic(ERC20_APPROVAL.topic0()));
+    code.extend(vec![push_u8(32), push_u8(0), Asm::Op(LOG3)]);
+    code.push(push_u8(1));
+    code.extend(return_top());
+
+    code.push(Asm::Label("allowance"));
+    code.push(push(Uint256::max_value()));
+    code.extend(return_top());
+
+    code.push(Asm::Label("decimals"));
+    code.push(push_u8(18));
+    code.extend(return_top());
+
+    code.push(Asm::Label("credit"));
+    code.extend(arg(1));
+    code.extend(vec![Asm::Op(DUP1), Asm::Op(SLOAD)]);
+    code.extend(arg(2));
+    code.extend(vec![Asm::Op(ADD), Asm::Op(SWAP1), Asm::Op(SSTORE)]);
+    code.extend(arg(2));
+    code.extend(vec![push_u8(0), Asm::Op(MSTORE)]);
+    code.extend(arg(1));
+    code.extend(arg(0));
+    code.push(topic(transfer));
+    code.extend(vec![push_u8(32), push_u8(0), Asm::Op(LOG3)]);
+    code.push(push_u8(1));
+    code.extend(return_top());
+
+    code.push(Asm::Label("insufficient"));
+    code.extend(vec![push_u8(0), push_u8(0), Asm::Op(REVERT)]);
+
+    assemble(&code)
+}
+
+/// A bridge that takes anything sent to it and answers every call with zero
+fn bridge_code() -> Vec<u8> {
+    let mut code = vec![push_u8(0)];
+    code.extend(return_top());
+    assemble(&code)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    format!("0x{}", bytes_to_hex_str(bytes))
+}
+
+fn quantity(value: &Uint256) -> String {
+    format!("0x{}", value.to_str_radix(16))
+}
+
+/// Where the devnet nodes are and who pays for the tests
+#[derive(Debug, Clone)]
+pub struct DevnetConfig {
+    pub eth_url: String,
+    pub xdai_url: String,
+    pub eth_chain_id: u64,
+    pub xdai_chain_id: u64,
+    /// A key funded on both nodes
+    pub key: PrivateKey,
+    /// Dai wei per ETH the mock exchange trades at
+    pub eth_price: Uint256,
+}
+
+impl Default for DevnetConfig {
+    /// Two anvil nodes started with their defaults on ports 8545 and 8546, ETH at 200 Dai
+    fn default() -> Self {
+        DevnetConfig {
+            eth_url: "http://localhost:8545".to_string(),
+            xdai_url: "http://localhost:8546".to_string(),
+            eth_chain_id: ANVIL_CHAIN_ID,
+            xdai_chain_id: ANVIL_CHAIN_ID,
+            key: PrivateKey::from_str(ANVIL_KEY).unwrap(),
+            eth_price: Uint256::from(200u32) * ether(),
+        }
+    }
+}
+
+/// The mock contracts placed by `deploy` and the nodes they are on
+pub struct Devnet {
+    pub config: DevnetConfig,
+    pub uniswap_address: Address,
+    pub dai_address: Address,
+    pub foreign_bridge_address: Address,
+    pub home_bridge_address: Address,
+    client: reqwest::Client,
+}
+
+impl Devnet {
+    /// Places the mocks, replacing any placed before, and gives the exchange a pool of 10,000
+    /// ETH and the Dai they are worth so reserve and price impact checks have something to
+    /// read
+    pub fn deploy(config: DevnetConfig) -> Result<Devnet, Error> {
+        let address = |bytes: [u8; 20]| Address::from_slice(&bytes);
+        let devnet = Devnet {
+            uniswap_address: address(EXCHANGE)?,
+            dai_address: address(DAI)?,
+            foreign_bridge_address: address(FOREIGN_BRIDGE)?,
+            home_bridge_address: address(HOME_BRIDGE)?,
+            client: reqwest::Client::new(),
+            config,
+        };
+
+        devnet.set_code(
+            Chain::Eth,
+            devnet.uniswap_address,
+            &exchange_code(devnet.config.eth_price.clone()),
+        )?;
+        devnet.set_code(Chain::Eth, devnet.dai_address, &token_code())?;
+        devnet.set_code(Chain::Eth, devnet.foreign_bridge_address, &bridge_code())?;
+        devnet.set_code(Chain::Xdai, devnet.home_bridge_address, &bridge_code())?;
+
+        let pool_eth = Uint256::from(10_000u32) * ether();
+        let pool_dai = pool_eth.clone() * devnet.config.eth_price.clone() / ether();
+        devnet.set_balance(Chain::Eth, devnet.uniswap_address, pool_eth)?;
+        devnet.set_dai_balance(devnet.uniswap_address, Dai::from_wei(pool_dai))?;
+        Ok(devnet)
+    }
+
+    /// A bridge paying from `config.key` and using the mocks. Bridge preflight checks are off,
+    /// the mock bridges aren't proxies and have no token to check.
+    pub fn token_bridge(&self) -> Result<TokenBridge, Error> {
+        let mut bridge = TokenBridgeBuilder::new()
+            .uniswap_address(self.uniswap_address)
+            .foreign_dai_contract_address(self.dai_address)
+            .xdai_foreign_bridge_address(self.foreign_bridge_address)
+            .xdai_home_bridge_address(self.home_bridge_address)
+            .eth_chain_id(self.config.eth_chain_id)
+            .xdai_chain_id(self.config.xdai_chain_id)
+            .secret(self.config.key.clone())
+            .eth_full_node_url(self.config.eth_url.clone())
+            .xdai_full_node_url(self.config.xdai_url.clone())
+            .build()?;
+        bridge.bridge_preflight.enabled = false;
+        Ok(bridge)
+    }
+
+    /// Sends `method` to the node of `chain`, falling back to the next name in `methods` for
+    /// calls anvil and ganache name differently
+    fn rpc(&self, chain: Chain, methods: &[&str], params: Value) -> Result<Value, Error> {
+        let url = match chain {
+            Chain::Eth => &self.config.eth_url,
+            Chain::Xdai => &self.config.xdai_url,
+        };
+        let mut error = format_err!("No RPC method given");
+        for method in methods {
+            let body = self
+                .client
+                .post(url)
+                .json(&rpc_request(method, params.clone()))
+                .send()?
+                .text()?;
+            match rpc_result(&body) {
+                Ok(result) => return Ok(result),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    fn set_code(&self, chain: Chain, address: Address, code: &[u8]) -> Result<(), Error> {
+        self.rpc(
+            chain,
+            &["anvil_setCode", "evm_setAccountCode"],
+            json!([hex(address.as_bytes()), hex(code)]),
+        )
+        .map(|_| ())
+    }
+
+    /// Sets the ETH or xDai balance of `address` on `chain`
+    pub fn set_balance(
+        &self,
+        chain: Chain,
+        address: Address,
+        balance: Uint256,
+    ) -> Result<(), Error> {
+        self.rpc(
+            chain,
+            &["anvil_setBalance", "evm_setAccountBalance"],
+            json!([hex(address.as_bytes()), quantity(&balance)]),
+        )
+        .map(|_| ())
+    }
+
+    pub fn get_balance(&self, chain: Chain, address: Address) -> Result<Uint256, Error> {
+        let balance = self.rpc(
+            chain,
+            &["eth_getBalance"],
+            json!([hex(address.as_bytes()), "latest"]),
+        )?;
+        match balance.as_str() {
+            Some(balance) => Ok(Uint256::from_str(balance)?),
+            None => Err(format_err!("Expected a hex quantity, got {}", balance)),
+        }
+    }
+
+    /// Sets the mock Dai balance of `address` without logging a transfer
+    pub fn set_dai_balance(&self, address: Address, balance: Dai) -> Result<(), Error> {
+        let slot = address_word(address);
+        let value: [u8; 32] = balance.into_wei().into();
+        self.rpc(
+            Chain::Eth,
+            &["anvil_setStorageAt", "evm_setAccountStorageAt"],
+            json!([hex(self.dai_address.as_bytes()), hex(&slot), hex(&value)]),
+        )
+        .map(|_| ())
+    }
+
+    /// Does what the validators do for a deposit of `amount` Dai: pays out `amount` of xDai
+    /// to `recipient`
+    pub fn relay_dai_to_xdai(&self, recipient: Address, amount: XDai) -> Result<(), Error> {
+        let balance = self.get_balance(Chain::Xdai, recipient)?;
+        self.set_balance(Chain::Xdai, recipient, balance + amount.into_wei())
+    }
+
+    /// Does what the validators do for a withdrawal of `amount` xDai: pays out `amount` of Dai
+    /// to `recipient`, logged as a transfer from the foreign bridge. The credit is sent by
+    /// `bridge`, resolving to its tx hash once it is mined.
+    pub fn relay_xdai_to_dai(
+        &self,
+        bridge: &TokenBridge,
+        recipient: Address,
+        amount: Dai,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        bridge.send_call(
+            Chain::Eth,
+            self.dai_address,
+            "credit(address,address,uint256)",
+            &[
+                self.foreign_bridge_address.into(),
+                recipient.into(),
+                amount.into_wei().into(),
+            ],
+            0u32.into(),
+            Vec::new(),
+            Duration::from_secs(60),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble() {
+        let code = assemble(&[
+            Asm::Target("end"),
+            Asm::Op(JUMPI),
+            push(Uint256::from(0x1234u32)),
+            Asm::Label("end"),
+            push_u8(0),
+        ]);
+        // PUSH2 0x0007 JUMPI PUSH2 0x1234 JUMPDEST PUSH1 0
+        assert_eq!(
+            code,
+            vec![PUSH2, 0, 7, JUMPI, PUSH2, 0x12, 0x34, JUMPDEST, PUSH1, 0]
+        );
+        assert_eq!(assemble(&[push(0u32.into())]), vec![PUSH1, 0]);
+        assert_eq!(assemble(&[push(Uint256::max_value())]).len(), 33);
+    }
+
+    #[test]
+    fn test_mock_code() {
+        // every jump of the mocks lands on a JUMPDEST
+        for code in [exchange_code(200u32.into()), token_code(), bridge_code()] {
+            let mut i = 0;
+            while i < code.len() {
+                let op = code[i];
+                if op == PUSH2 && code.get(i + 3) == Some(&JUMPI) {
+                    let target = (code[i + 1] as usize) << 8 | code[i + 2] as usize;
+                    assert_eq!(code[target], JUMPDEST);
+                }
+                i += match op {
+                    0x60..=0x7f => (op - PUSH1) as usize + 2,
+                    _ => 1,
+                };
+            }
+        }
+    }
+}
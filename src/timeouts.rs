@@ -0,0 +1,85 @@
+//! Limits on how long each kind of wait may take, shared by every long running function. They
+//! come on top of the `timeout` arguments some functions take, whichever runs out first ends
+//! the wait.
+
+use failure::Error;
+use futures::future::Either;
+use futures::Future;
+use futures_timer::Delay;
+use std::io;
+use std::time::Duration;
+
+/// `None` leaves a kind of wait unlimited apart from the RPC timeout of each call and the
+/// `timeout` arguments of the function waiting. Event waits are limited by
+/// `EventPolling::max_wait`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Looking up the nonce and gas, signing and handing a transaction to the full node
+    pub tx_submission: Option<Duration>,
+    /// A sent transaction being mined. Running out is reported as
+    /// `TokenBridgeError::TimedOut` with what is known about the transaction.
+    pub confirmation: Option<Duration>,
+    /// A whole `eth_to_xdai` or `xdai_to_eth` conversion, or each operation finished by
+    /// `resume_pending`. The operation stays checkpointed at the stage it reached.
+    pub end_to_end: Option<Duration>,
+}
+
+impl Timeouts {
+    /// `timeout` shortened to `limit` if that is shorter
+    pub(crate) fn cap(timeout: Duration, limit: Option<Duration>) -> Duration {
+        match limit {
+            Some(limit) => timeout.min(limit),
+            None => timeout,
+        }
+    }
+}
+
+/// Fails `future` with an error naming `what` if it takes longer than `limit`
+pub(crate) fn limit<T: 'static>(
+    what: &'static str,
+    limit: Option<Duration>,
+    future: Box<dyn Future<Item = T, Error = Error>>,
+) -> Box<dyn Future<Item = T, Error = Error>> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return future,
+    };
+    Box::new(
+        future
+            .select2(Delay::new(limit))
+            .then(move |res| match res {
+                Ok(Either::A((val, _))) => Ok(val),
+                Ok(Either::B(_)) => {
+                    let message = format!("{} took longer than {:?}", what, limit);
+                    Err(io::Error::new(io::ErrorKind::TimedOut, message).into())
+                }
+                Err(Either::A((e, _))) => Err(e),
+                Err(Either::B((e, _))) => Err(e.into()),
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit() {
+        assert_eq!(
+            Timeouts::cap(Duration::from_secs(60), Some(Duration::from_secs(5))),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            Timeouts::cap(Duration::from_secs(60), None),
+            Duration::from_secs(60)
+        );
+
+        let quick: Box<dyn Future<Item = u32, Error = Error>> = Box::new(futures::future::ok(1));
+        assert_eq!(
+            limit("quick", Some(Duration::from_secs(5)), quick)
+                .wait()
+                .unwrap(),
+            1
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Direct token to token swaps through Uniswap V1 exchanges
+
+use crate::block_tag::BlockTag;
+use crate::encoding;
+use crate::events::BridgeEvent;
+use crate::logs::UNISWAP_V1_TOKEN_PURCHASE;
+use crate::metrics;
+use crate::minimum_output;
+use crate::pending::ConversionKind;
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::time::Duration;
+use web30::types::SendTxOption;
+
+impl TokenBridge {
+    /// Price in ETH of selling `amount` tokens to the Uniswap V1 `exchange`
+    pub fn get_token_to_eth_price(
+        &self,
+        exchange: Address,
+        amount: Uint256,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.get_token_to_eth_price_at(exchange, amount, BlockTag::Latest)
+    }
+
+    /// `get_token_to_eth_price` at `block`, see `call_view_at`
+    pub fn get_token_to_eth_price_at(
+        &self,
+        exchange: Address,
+        amount: Uint256,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.call_view_at(
+            Chain::Eth,
+            exchange,
+            "getTokenToEthInputPrice(uint256)",
+            &[amount.into()],
+            block,
+        )
+    }
+
+    /// Price in tokens of selling `amount` ETH to the Uniswap V1 `exchange`
+    pub fn get_eth_to_token_price(
+        &self,
+        exchange: Address,
+        amount: Uint256,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.get_eth_to_token_price_at(exchange, amount, BlockTag::Latest)
+    }
+
+    /// `get_eth_to_token_price` at `block`, see `call_view_at`
+    pub fn get_eth_to_token_price_at(
+        &self,
+        exchange: Address,
+        amount: Uint256,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.call_view_at(
+            Chain::Eth,
+            exchange,
+            "getEthToTokenInputPrice(uint256)",
+            &[amount.into()],
+            block,
+        )
+    }
+
+    /// Price in `to_exchange` tokens of selling `amount` `from_exchange` tokens, routed through
+    /// ETH the same way `tokenToTokenSwapInput` does. Returns (eth in the middle, tokens bought).
+    pub fn get_token_to_token_price(
+        &self,
+        from_exchange: Address,
+        to_exchange: Address,
+        amount: Uint256,
+    ) -> Box<dyn Future<Item = (Uint256, Uint256), Error = Error>> {
+        self.get_token_to_token_price_at(from_exchange, to_exchange, amount, BlockTag::Latest)
+    }
+
+    /// `get_token_to_token_price` at `block`, see `call_view_at`
+    pub fn get_token_to_token_price_at(
+        &self,
+        from_exchange: Address,
+        to_exchange: Address,
+        amount: Uint256,
+        block: BlockTag,
+    ) -> Box<dyn Future<Item = (Uint256, Uint256), Error = Error>> {
+        let salf = self.clone();
+
+        Box::new(
+            self.get_token_to_eth_price_at(from_exchange, amount, block.clone())
+                .and_then(move |eth_bought| {
+                    salf.get_eth_to_token_price_at(to_exchange, eth_bought.clone(), block)
+                        .map(move |tokens_bought| (eth_bought, tokens_bought))
+                }),
+        )
+    }
+
+    /// Sell `amount` of `from_token` for `to_token` in a single transaction using the V1
+    /// `tokenToTokenSwapInput` call on `from_exchange`, approving `from_exchange` first if needed.
+    /// `from_exchange` and `to_exchange` are the Uniswap V1 exchanges of the two tokens.
+    /// This function will error out if it takes longer than 'timeout' and the transaction is
+    /// guaranteed not to be accepted on the blockchain after this time. The error is then
+    /// `TokenBridgeError::TimedOut` saying whether the swap went through anyway.
+    pub fn token_to_token_swap(
+        &self,
+        from_token: Address,
+        from_exchange: Address,
+        to_token: Address,
+        to_exchange: Address,
+        amount: Uint256,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.send_conversion(
+            ConversionKind::TokenToToken(from_token),
+            amount.clone(),
+            move |salf| {
+                salf.run_token_to_token_swap(
+                    from_token,
+                    from_exchange,
+                    to_token,
+                    to_exchange,
+                    amount,
+                    timeout,
+                )
+            },
+        )
+    }
+
+    fn run_token_to_token_swap(
+        &self,
+        from_token: Address,
+        from_exchange: Address,
+        to_token: Address,
+        to_exchange: Address,
+        amount: Uint256,
+        timeout: u64,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let slippage_bps = self.slippage_bps;
+        let salf = self.clone();
+
+        Box::new(
+            self.ensure_token_approved(
+                from_token,
+                from_exchange,
+                amount.clone(),
+                Duration::from_secs(timeout),
+            )
+            .and_then(move |_| {
+                let quote = salf.clone();
+                let quote_amount = amount.clone();
+                salf.at_latest_block(Chain::Eth, move |block| {
+                    quote.get_token_to_token_price_at(
+                        from_exchange,
+                        to_exchange,
+                        quote_amount,
+                        block,
+                    )
+                })
+                .and_then(move |(block, (expected_eth, expected_tokens))| {
+                    let min_eth = minimum_output(expected_eth, slippage_bps);
+                    let min_tokens = minimum_output(expected_tokens, slippage_bps);
+                    let deadline = block.timestamp + timeout.into();
+                    let sender = salf.spending_token(from_token, &amount);
+                    let payload = encoding::uniswap_token_to_token_swap(
+                        amount, min_tokens, min_eth, deadline, to_token,
+                    );
+
+                    futures::future::result(sender)
+                        .and_then(move |sender| {
+                            sender.send_swap_transaction(
+                                Chain::Eth,
+                                from_exchange,
+                                payload,
+                                0u32.into(),
+                                vec![SendTxOption::GasLimit(150_000u64.into())],
+                            )
+                        })
+                        .and_then(move |tx_hash| {
+                            // The TokenPurchase our transaction emitted on the second exchange
+                            // holds the amount we received, someone else's swap on the same
+                            // exchanges can't be mistaken for ours
+                            let confirmed = salf.confirm_transaction(
+                                Chain::Eth,
+                                tx_hash.clone(),
+                                to_exchange,
+                                UNISWAP_V1_TOKEN_PURCHASE.definition,
+                                UNISWAP_V1_TOKEN_PURCHASE.amount_out,
+                            );
+                            salf.wait_or_reconcile(
+                                Chain::Eth,
+                                tx_hash,
+                                confirmed,
+                                Duration::from_secs(timeout),
+                                to_exchange,
+                                UNISWAP_V1_TOKEN_PURCHASE.definition,
+                                UNISWAP_V1_TOKEN_PURCHASE.amount_out,
+                            )
+                            .map(move |tokens| {
+                                salf.emit(BridgeEvent::EventObserved {
+                                    chain: Chain::Eth,
+                                    contract: to_exchange,
+                                    event: UNISWAP_V1_TOKEN_PURCHASE
+                                        .definition
+                                        .signature
+                                        .to_string(),
+                                });
+                                metrics::swap_executed("token_to_token");
+                                salf.emit(BridgeEvent::FundsArrived {
+                                    chain: Chain::Eth,
+                                    amount: tokens.clone(),
+                                });
+                                tokens
+                            })
+                        })
+                })
+            }),
+        )
+    }
+}
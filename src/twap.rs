@@ -0,0 +1,195 @@
+//! Time weighted average prices of the ETH/Dai pool. A single block's price can be moved by
+//! anyone willing to trade against the pool for a block, an average over the last blocks
+//! costs an attacker far more to move.
+//!
+//! Neither Uniswap V1 nor web30 give us past prices, so the average is built from samples of
+//! the pool reserves taken while the process runs, see `run_twap_sampler`.
+
+use crate::price_cache::PriceDirection;
+use crate::TokenBridge;
+use failure::bail;
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use futures::Stream;
+use futures_timer::Interval;
+use num::ToPrimitive;
+use num256::Uint256;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The pool price at one block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriceSample {
+    pub block: u64,
+    /// Unix time of the block in seconds
+    pub timestamp: u64,
+    /// Dai wei per ETH, see `PoolReserves::eth_price`
+    pub eth_price: Uint256,
+}
+
+/// Samples of the pool price over the last `window_blocks` blocks
+#[derive(Debug)]
+pub struct TwapOracle {
+    pub window_blocks: u64,
+    samples: Mutex<VecDeque<PriceSample>>,
+}
+
+/// Each sample weighted by the time until the next one, so the price of the latest sample
+/// doesn't count until a later sample is taken. `None` if the samples span no time.
+fn time_weighted_average(samples: &VecDeque<PriceSample>) -> Option<Uint256> {
+    let mut weighted: Uint256 = 0u32.into();
+    let mut total = 0u64;
+    for (sample, next) in samples.iter().zip(samples.iter().skip(1)) {
+        let weight = next.timestamp.saturating_sub(sample.timestamp);
+        weighted = weighted + sample.eth_price.clone() * weight.into();
+        total += weight;
+    }
+    if total == 0 {
+        None
+    } else {
+        Some(weighted / total.into())
+    }
+}
+
+impl TwapOracle {
+    pub fn new(window_blocks: u64) -> TwapOracle {
+        TwapOracle {
+            window_blocks,
+            samples: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Adds `sample`, replacing an earlier sample of the same block and dropping those that
+    /// fell out of the window
+    pub fn record(&self, sample: PriceSample) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.iter().any(|old| old.block > sample.block) {
+            // an older block than we already have, from a lagging node
+            return;
+        }
+        if samples.back().map(|last| last.block) == Some(sample.block) {
+            samples.pop_back();
+        }
+        let oldest = sample.block.saturating_sub(self.window_blocks);
+        samples.push_back(sample);
+        while let Some(first) = samples.front() {
+            if first.block >= oldest {
+                break;
+            }
+            samples.pop_front();
+        }
+    }
+
+    /// The time weighted average Dai per ETH over the window, `None` until there are samples of
+    /// at least two blocks
+    pub fn average(&self) -> Option<Uint256> {
+        time_weighted_average(&self.samples.lock().unwrap())
+    }
+}
+
+impl TokenBridge {
+    fn twap_oracle(&self) -> Result<&TwapOracle, Error> {
+        match self.twap {
+            Some(ref twap) => Ok(twap),
+            None => bail!("No TWAP oracle is configured"),
+        }
+    }
+
+    /// Records the current pool price in `twap`, resolving to the sample
+    pub fn sample_twap(&self) -> Box<dyn Future<Item = PriceSample, Error = Error>> {
+        try_future!(self.twap_oracle());
+        let salf = self.clone();
+        Box::new(
+            self.eth_web3
+                .eth_get_latest_block()
+                .join(self.get_pool_reserves())
+                .and_then(move |(block, reserves)| {
+                    let sample = PriceSample {
+                        block: block.number.to_u64().ok_or_else(|| {
+                            format_err!("Block number {} too large", block.number)
+                        })?,
+                        timestamp: block.timestamp.to_u64().unwrap_or(0),
+                        eth_price: reserves.eth_price(),
+                    };
+                    salf.twap_oracle()?.record(sample.clone());
+                    Ok(sample)
+                }),
+        )
+    }
+
+    /// Samples the pool price for `twap` every `interval` forever, roughly once a block is
+    /// enough. Failed samples are logged and skipped.
+    pub fn run_twap_sampler(self, interval: Duration) -> Box<dyn Future<Item = (), Error = Error>> {
+        Box::new(Interval::new(interval).from_err().for_each(move |_| {
+            self.sample_twap().then(|res| {
+                if let Err(e) = res {
+                    warn!("Sampling the pool price failed {:?}", e);
+                }
+                Ok(())
+            })
+        }))
+    }
+
+    /// `quote` for a swap in `direction` moved from the current pool price to the average
+    /// price, keeping the fee and price impact it includes. Unchanged if `twap` isn't set.
+    pub(crate) fn twap_adjusted_quote(
+        &self,
+        direction: PriceDirection,
+        quote: Uint256,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        if self.twap.is_none() {
+            return Box::new(futures::future::ok(quote));
+        }
+        let salf = self.clone();
+        Box::new(self.sample_twap().and_then(move |spot| {
+            let average = match salf.twap_oracle()?.average() {
+                Some(average) => average,
+                None => bail!("Not enough TWAP samples to price the swap yet"),
+            };
+            let zero: Uint256 = 0u32.into();
+            if spot.eth_price == zero || average == zero {
+                bail!("Pool has no liquidity");
+            }
+            Ok(match direction {
+                PriceDirection::EthToDai => quote * average / spot.eth_price,
+                PriceDirection::DaiToEth => quote * spot.eth_price / average,
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(block: u64, timestamp: u64, eth_price: u32) -> PriceSample {
+        PriceSample {
+            block,
+            timestamp,
+            eth_price: eth_price.into(),
+        }
+    }
+
+    #[test]
+    fn test_twap() {
+        let twap = TwapOracle::new(10);
+        twap.record(sample(100, 1000, 200));
+        assert_eq!(twap.average(), None);
+        twap.record(sample(101, 1010, 300));
+        assert_eq!(twap.average(), Some(200u32.into()));
+        twap.record(sample(102, 1040, 1_000_000));
+        // 200 for 10 seconds and 300 for 30, the manipulated last block doesn't count yet
+        assert_eq!(twap.average(), Some(275u32.into()));
+
+        // a sample of the same block replaces the earlier one, older blocks are ignored
+        twap.record(sample(102, 1040, 500));
+        twap.record(sample(99, 990, 1));
+        assert_eq!(twap.samples.lock().unwrap().len(), 3);
+
+        // the first two fall out of the window
+        twap.record(sample(112, 1140, 500));
+        assert_eq!(twap.average(), Some(500u32.into()));
+    }
+}
@@ -0,0 +1,512 @@
+//! Building, signing and sending transactions through the configured `Signer`. The `build_*_tx`
+//! functions produce signed raw transactions without touching the network, they can be sent
+//! later, possibly from somewhere else, with `broadcast_raw`.
+
+use crate::audit::SentTx;
+use crate::encoding;
+use crate::events::BridgeEvent;
+use crate::instrument::Span;
+use crate::metrics;
+use crate::timeouts;
+use crate::units::{Dai, Eth, XDai};
+use crate::Chain;
+use crate::GasStrategy;
+use crate::TokenBridge;
+use clarity::{Address, Transaction};
+use failure::Error;
+use futures::Future;
+use num::Bounded;
+use num256::Uint256;
+use web30::types::{SendTxOption, TransactionRequest};
+
+/// Flashbots Protect, which keeps transactions out of the public mempool and only includes
+/// them if they don't revert
+pub const FLASHBOTS_PROTECT_RPC: &str = "https://rpc.flashbots.net";
+
+/// Everything needed to build a transaction that would otherwise be looked up on the full node
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawTxParams {
+    pub nonce: Uint256,
+    pub gas_price: Uint256,
+    pub gas_limit: Uint256,
+}
+
+/// Overrides for the transactions a method sends, see `TokenBridge::with_tx_params`. Anything
+/// set here replaces what the method would have used, whether hardcoded or a bridge default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TxParams {
+    pub gas_price: Option<Uint256>,
+    /// Multiplies the gas price suggested by the full node, ignored if a gas price is set
+    pub gas_price_multiplier: Option<u64>,
+    pub gas_limit: Option<Uint256>,
+    /// Only makes sense for methods that send a single transaction
+    pub nonce: Option<Uint256>,
+    pub network_id: Option<u64>,
+}
+
+impl TxParams {
+    fn overrides(&self) -> Vec<SendTxOption> {
+        let mut overrides = Vec::new();
+        if let Some(ref gas_price) = self.gas_price {
+            overrides.push(SendTxOption::GasPrice(gas_price.clone()));
+        }
+        if let Some(multiplier) = self.gas_price_multiplier {
+            overrides.push(SendTxOption::GasPriceMultiplier(multiplier));
+        }
+        if let Some(ref gas_limit) = self.gas_limit {
+            overrides.push(SendTxOption::GasLimit(gas_limit.clone()));
+        }
+        if let Some(ref nonce) = self.nonce {
+            overrides.push(SendTxOption::Nonce(nonce.clone()));
+        }
+        if let Some(network_id) = self.network_id {
+            overrides.push(SendTxOption::NetworkId(network_id));
+        }
+        overrides
+    }
+
+    /// `options` with every option set here replaced
+    fn apply(&self, options: Vec<SendTxOption>) -> Vec<SendTxOption> {
+        let overrides = self.overrides();
+        let mut options: Vec<SendTxOption> = options
+            .into_iter()
+            .filter(|option| {
+                !overrides
+                    .iter()
+                    .any(|o| std::mem::discriminant(o) == std::mem::discriminant(option))
+            })
+            .collect();
+        options.extend(overrides);
+        options
+    }
+}
+
+impl TokenBridge {
+    /// A copy of this bridge whose transactions use `params` instead of what each method would
+    /// send them with, for callers that need different gas settings for a single call
+    pub fn with_tx_params(&self, params: TxParams) -> TokenBridge {
+        let mut bridge = self.clone();
+        bridge.tx_params = params;
+        bridge
+    }
+
+    /// Builds a transaction on `chain` calling `to` with `data` and `value` attached, signs it
+    /// with our signer and sends it. Nonce, gas price and gas limit are looked up on the full
+    /// node unless given in `options`. Fails without sending if `to` is not allowed by
+    /// `destination_allowlist`. Returns the tx hash.
+    pub fn send_transaction(
+        &self,
+        chain: Chain,
+        to: Address,
+        data: Vec<u8>,
+        value: Uint256,
+        options: Vec<SendTxOption>,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.sign_and_send(chain, to, data, value, options, false)
+    }
+
+    /// `send_transaction` for swaps, which go through `private_relay_url` when it is set and
+    /// they are on Eth
+    pub(crate) fn send_swap_transaction(
+        &self,
+        chain: Chain,
+        to: Address,
+        data: Vec<u8>,
+        value: Uint256,
+        options: Vec<SendTxOption>,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        self.sign_and_send(chain, to, data, value, options, true)
+    }
+
+    fn sign_and_send(
+        &self,
+        chain: Chain,
+        to: Address,
+        data: Vec<u8>,
+        value: Uint256,
+        options: Vec<SendTxOption>,
+        private: bool,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        // fail before querying the node if we can't sign anyway
+        try_future!(self.signer());
+        try_future!(self.check_destination(to, &data));
+        // every transaction moving funds passes here, what it spends is checked now so that a
+        // refused one fails before querying the node, and counted right before the broadcast
+        let spends = self.transaction_spends(chain, &value);
+        for (asset, amount) in spends.iter() {
+            try_future!(self.check_spending(*asset, amount));
+        }
+        // the transaction of an operation step is the one moving its funds, not an approval
+        let moves_funds = !spends.is_empty();
+        let web3 = self.web3(chain);
+        let own_address = self.own_address;
+
+        let mut nonce = None;
+        let mut gas_price = None;
+        let mut gas_price_multiplier = None;
+        let mut gas_limit = None;
+        let mut chain_id = None;
+        for option in self.tx_options(chain, self.tx_params.apply(options)) {
+            match option {
+                SendTxOption::Nonce(value) => nonce = Some(value),
+                SendTxOption::GasPrice(value) => gas_price = Some(value),
+                SendTxOption::GasPriceMultiplier(value) => gas_price_multiplier = Some(value),
+                SendTxOption::GasLimit(value) => gas_limit = Some(value),
+                SendTxOption::NetworkId(value) => chain_id = Some(value),
+            }
+        }
+
+        let nonce: Box<dyn Future<Item = Uint256, Error = Error>> = match nonce {
+            Some(nonce) => Box::new(futures::future::ok(nonce)),
+            None => {
+                let accounts = self.accounts.clone();
+                Box::new(
+                    web3.eth_get_transaction_count(own_address)
+                        .map(move |count| accounts.next_nonce(chain, own_address, count)),
+                )
+            }
+        };
+        let gas_price: Box<dyn Future<Item = Uint256, Error = Error>> = match gas_price {
+            Some(gas_price) => Box::new(futures::future::ok(gas_price)),
+            None => match gas_price_multiplier {
+                Some(multiplier) => Box::new(
+                    web3.eth_gas_price()
+                        .map(move |gas_price| gas_price * multiplier.into()),
+                ),
+                None => web3.eth_gas_price(),
+            },
+        };
+        let gas_limit: Box<dyn Future<Item = Uint256, Error = Error>> = match gas_limit {
+            Some(gas_limit) => Box::new(futures::future::ok(gas_limit)),
+            None => web3.eth_estimate_gas(TransactionRequest {
+                from: own_address,
+                to: Some(to),
+                gas: None,
+                gas_price: None,
+                value: Some(value.clone().into()),
+                data: Some(data.clone().into()),
+                nonce: None,
+            }),
+        };
+
+        let simulation: Box<dyn Future<Item = (), Error = Error>> = if self.simulate_before_send {
+            Box::new(
+                self.simulate_transaction(chain, to, data.clone(), value.clone())
+                    .map(|_| ()),
+            )
+        } else {
+            Box::new(futures::future::ok(()))
+        };
+        let purpose = self.gas_purpose(to, &data, private);
+        let salf = self.clone();
+        let span = Span::transaction(chain, to, &value);
+        let submission_limit = self.timeouts.tx_submission;
+
+        span.clone().instrument(timeouts::limit(
+            "Submitting the transaction",
+            submission_limit,
+            Box::new(simulation.and_then(move |_| {
+                nonce
+                    .join3(gas_price, gas_limit)
+                    .and_then(move |(nonce, gas_price, gas_limit)| {
+                        span.record("nonce", &nonce);
+                        span.record("gas_price", &gas_price);
+                        span.record("gas_limit", &gas_limit);
+                        let sent = SentTx {
+                            chain,
+                            to,
+                            value: value.clone(),
+                            data: data.clone(),
+                            nonce: nonce.clone(),
+                            gas_price: gas_price.clone(),
+                            gas_limit: gas_limit.clone(),
+                        };
+                        let params = RawTxParams {
+                            nonce,
+                            gas_price,
+                            gas_limit,
+                        };
+                        salf.sign_transaction(chain_id, to, data, value, params)
+                            .and_then(move |bytes| {
+                                let recorded = spends.iter().try_for_each(|(asset, amount)| {
+                                    salf.record_spending(*asset, amount)
+                                });
+                                if let Err(e) = recorded {
+                                    salf.accounts.reset_nonce(chain, own_address);
+                                    return Box::new(futures::future::err(e))
+                                        as Box<dyn Future<Item = Uint256, Error = Error>>;
+                                }
+                                let sender = salf.clone();
+                                let broadcast = salf.inject_transaction_fault(
+                                    chain,
+                                    purpose,
+                                    bytes,
+                                    move |bytes| {
+                                        if private {
+                                            sender.broadcast_private(chain, bytes)
+                                        } else {
+                                            sender.broadcast_raw(chain, bytes)
+                                        }
+                                    },
+                                );
+                                Box::new(broadcast.then(move |res| {
+                                    match res {
+                                        Ok(ref tx_hash) => {
+                                            span.record("tx_hash", tx_hash);
+                                            metrics::transaction_sent(
+                                                chain,
+                                                &sent.gas_price,
+                                                &sent.gas_limit,
+                                            );
+                                            salf.record_gas(
+                                                chain,
+                                                tx_hash.clone(),
+                                                purpose,
+                                                sent.gas_limit.clone(),
+                                                sent.gas_price.clone(),
+                                            );
+                                            if moves_funds {
+                                                salf.checkpoint_stage_tx(
+                                                    chain,
+                                                    tx_hash.clone(),
+                                                    sent.nonce.clone(),
+                                                );
+                                            }
+                                        }
+                                        Err(_) => salf.accounts.reset_nonce(chain, own_address),
+                                    }
+                                    salf.audit_sent(sent, res.as_ref());
+                                    res
+                                }))
+                            })
+                    })
+            })),
+        ))
+    }
+
+    /// The gas price transactions on `chain` would be sent with right now
+    pub(crate) fn gas_price(&self, chain: Chain) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let strategy = match chain {
+            Chain::Eth => &self.eth_gas_strategy,
+            Chain::Xdai => &self.xdai_gas_strategy,
+        };
+        match strategy {
+            GasStrategy::Fixed(price) => Box::new(futures::future::ok(price.clone())),
+            GasStrategy::Node => self.web3(chain).eth_gas_price(),
+        }
+    }
+
+    /// Sends a transaction signed earlier by one of the `build_*_tx` functions to `chain`.
+    /// Returns the tx hash.
+    pub fn broadcast_raw(
+        &self,
+        chain: Chain,
+        tx: Vec<u8>,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let salf = self.clone();
+        Box::new(
+            self.web3(chain)
+                .eth_send_raw_transaction(tx)
+                .map(move |tx_hash| {
+                    salf.emit(BridgeEvent::TxSubmitted {
+                        chain,
+                        tx_hash: tx_hash.clone(),
+                    });
+                    tx_hash
+                }),
+        )
+    }
+
+    /// Sends a signed transaction to `private_relay_url`, or to the full node like
+    /// `broadcast_raw` if there is no relay or the transaction is on xDai
+    fn broadcast_private(
+        &self,
+        chain: Chain,
+        tx: Vec<u8>,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let relay = match (chain, &self.private_relay_url) {
+            (Chain::Eth, Some(url)) => self.web3_pool.get(url, self.eth_rpc_timeout),
+            _ => return self.broadcast_raw(chain, tx),
+        };
+        let salf = self.clone();
+        Box::new(relay.eth_send_raw_transaction(tx).map(move |tx_hash| {
+            trace!("Sent {:#066x} through the private relay", tx_hash);
+            salf.emit(BridgeEvent::TxSubmitted {
+                chain,
+                tx_hash: tx_hash.clone(),
+            });
+            tx_hash
+        }))
+    }
+
+    /// Builds and signs a transaction on `chain` calling `to` with `data` and `value` attached,
+    /// returning the encoded raw transaction. Nothing is looked up on or sent to the full node.
+    pub fn build_transaction(
+        &self,
+        chain: Chain,
+        to: Address,
+        data: Vec<u8>,
+        value: Uint256,
+        params: RawTxParams,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        let chain_id = match chain {
+            Chain::Eth => self.eth_chain_id,
+            Chain::Xdai => self.xdai_chain_id,
+        };
+        self.sign_transaction(chain_id, to, data, value, params)
+    }
+
+    fn sign_transaction(
+        &self,
+        chain_id: Option<u64>,
+        to: Address,
+        data: Vec<u8>,
+        value: Uint256,
+        params: RawTxParams,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        let signer = try_future!(self.signer());
+        let tx = Transaction {
+            nonce: params.nonce,
+            gas_price: params.gas_price,
+            gas_limit: params.gas_limit,
+            to,
+            value,
+            data,
+            signature: None,
+        };
+        Box::new(
+            signer
+                .sign_transaction(tx, chain_id)
+                .and_then(|signed| signed.to_bytes()),
+        )
+    }
+
+    /// Offline version of `eth_transfer`
+    pub fn build_eth_transfer_tx(
+        &self,
+        to: Address,
+        amount: Eth,
+        params: RawTxParams,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        self.build_transaction(Chain::Eth, to, Vec::new(), amount.into_wei(), params)
+    }
+
+    /// Offline version of `eth_to_dai_swap`, the swap reverts if it would give less than
+    /// `min_dai` or is mined after the unix time `deadline`
+    pub fn build_eth_to_dai_swap_tx(
+        &self,
+        eth_amount: Eth,
+        min_dai: Dai,
+        deadline: Uint256,
+        params: RawTxParams,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        let payload = encoding::uniswap_eth_to_token_swap(min_dai.into_wei(), deadline);
+        self.build_transaction(
+            Chain::Eth,
+            self.uniswap_address,
+            payload,
+            eth_amount.into_wei(),
+            params,
+        )
+    }
+
+    /// Offline version of `dai_to_eth_swap`, the swap reverts if it would give less than
+    /// `min_eth` or is mined after the unix time `deadline`. Uniswap has to be approved to
+    /// spend our Dai already, see `build_approve_uniswap_dai_transfers_tx`.
+    pub fn build_dai_to_eth_swap_tx(
+        &self,
+        dai_amount: Dai,
+        min_eth: Eth,
+        deadline: Uint256,
+        params: RawTxParams,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        let payload = encoding::uniswap_token_to_eth_swap(
+            dai_amount.into_wei(),
+            min_eth.into_wei(),
+            deadline,
+        );
+        self.build_transaction(
+            Chain::Eth,
+            self.uniswap_address,
+            payload,
+            0u32.into(),
+            params,
+        )
+    }
+
+    /// Offline version of `approve_uniswap_dai_transfers`
+    pub fn build_approve_uniswap_dai_transfers_tx(
+        &self,
+        params: RawTxParams,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        let payload = encoding::erc20_approve(self.uniswap_address, Uint256::max_value());
+        self.build_transaction(
+            Chain::Eth,
+            self.foreign_dai_contract_address,
+            payload,
+            0u32.into(),
+            params,
+        )
+    }
+
+    /// Offline version of `dai_to_xdai_bridge`
+    pub fn build_dai_to_xdai_bridge_tx(
+        &self,
+        dai_amount: Dai,
+        params: RawTxParams,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        let payload =
+            encoding::erc20_transfer(self.xdai_foreign_bridge_address, dai_amount.into_wei());
+        self.build_transaction(
+            Chain::Eth,
+            self.foreign_dai_contract_address,
+            payload,
+            0u32.into(),
+            params,
+        )
+    }
+
+    /// Offline version of `xdai_to_dai_bridge`, to be broadcast on the xDai chain
+    pub fn build_xdai_to_dai_bridge_tx(
+        &self,
+        xdai_amount: XDai,
+        params: RawTxParams,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        self.build_transaction(
+            Chain::Xdai,
+            self.xdai_home_bridge_address,
+            Vec::new(),
+            xdai_amount.into_wei(),
+            params,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tx_params_apply() {
+        let params = TxParams {
+            gas_limit: Some(120_000u32.into()),
+            gas_price_multiplier: Some(2),
+            ..TxParams::default()
+        };
+        assert_eq!(
+            params.apply(vec![
+                SendTxOption::GasLimit(80_000u32.into()),
+                SendTxOption::NetworkId(1),
+            ]),
+            vec![
+                SendTxOption::NetworkId(1),
+                SendTxOption::GasPriceMultiplier(2),
+                SendTxOption::GasLimit(120_000u32.into()),
+            ]
+        );
+        assert_eq!(
+            TxParams::default().apply(vec![SendTxOption::NetworkId(1)]),
+            vec![SendTxOption::NetworkId(1)]
+        );
+    }
+}
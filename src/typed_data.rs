@@ -0,0 +1,382 @@
+//! EIP-712 typed structured data, the signatures Dai's `permit()` takes and that meta
+//! transactions and bridge messages are built on. Structs are put together at runtime with
+//! `TypedStruct`, whose field order is the order of the type's members:
+//!
+//! ```ignore
+//! let permit = TypedStruct::new("Permit")
+//!     .address("holder", holder)
+//!     .address("spender", spender)
+//!     .uint("nonce", nonce)
+//!     .uint("expiry", expiry)
+//!     .bool("allowed", true);
+//! let signature = bridge.sign_typed_data(&Eip712Domain::dai(1, dai), &permit);
+//! ```
+
+use crate::message::{is_signed_by, signature_to_bytes};
+use crate::TokenBridge;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use sha3::{Digest, Keccak256};
+use std::collections::BTreeMap;
+
+/// The value of a struct member, which also determines its EIP-712 type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypedValue {
+    Address(Address),
+    Bool(bool),
+    Uint256(Uint256),
+    Bytes32([u8; 32]),
+    Bytes(Vec<u8>),
+    String(String),
+    Struct(TypedStruct),
+}
+
+impl TypedValue {
+    /// The type as it is written in the struct's type string
+    fn type_name(&self) -> &str {
+        match self {
+            TypedValue::Address(_) => "address",
+            TypedValue::Bool(_) => "bool",
+            TypedValue::Uint256(_) => "uint256",
+            TypedValue::Bytes32(_) => "bytes32",
+            TypedValue::Bytes(_) => "bytes",
+            TypedValue::String(_) => "string",
+            TypedValue::Struct(value) => &value.name,
+        }
+    }
+
+    /// The 32 byte word the value is encoded as, dynamic values and structs by their hash
+    fn encode(&self) -> [u8; 32] {
+        let mut word = [0u8; 32];
+        match self {
+            TypedValue::Address(address) => word[12..].copy_from_slice(address.as_bytes()),
+            TypedValue::Bool(value) => word[31] = *value as u8,
+            TypedValue::Uint256(value) => word = value.clone().into(),
+            TypedValue::Bytes32(value) => word = *value,
+            TypedValue::Bytes(value) => word = keccak256(value),
+            TypedValue::String(value) => word = keccak256(value.as_bytes()),
+            TypedValue::Struct(value) => word = value.hash(),
+        }
+        word
+    }
+}
+
+/// An instance of a struct type named `name`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypedStruct {
+    pub name: String,
+    pub fields: Vec<(String, TypedValue)>,
+}
+
+impl TypedStruct {
+    pub fn new<S: Into<String>>(name: S) -> TypedStruct {
+        TypedStruct {
+            name: name.into(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Appends the member `name` holding `value`
+    pub fn field<S: Into<String>>(mut self, name: S, value: TypedValue) -> TypedStruct {
+        self.fields.push((name.into(), value));
+        self
+    }
+
+    pub fn address<S: Into<String>>(self, name: S, value: Address) -> TypedStruct {
+        self.field(name, TypedValue::Address(value))
+    }
+
+    pub fn bool<S: Into<String>>(self, name: S, value: bool) -> TypedStruct {
+        self.field(name, TypedValue::Bool(value))
+    }
+
+    pub fn uint<S: Into<String>>(self, name: S, value: Uint256) -> TypedStruct {
+        self.field(name, TypedValue::Uint256(value))
+    }
+
+    pub fn bytes32<S: Into<String>>(self, name: S, value: [u8; 32]) -> TypedStruct {
+        self.field(name, TypedValue::Bytes32(value))
+    }
+
+    pub fn bytes<S: Into<String>>(self, name: S, value: Vec<u8>) -> TypedStruct {
+        self.field(name, TypedValue::Bytes(value))
+    }
+
+    pub fn string<S: Into<String>, V: Into<String>>(self, name: S, value: V) -> TypedStruct {
+        self.field(name, TypedValue::String(value.into()))
+    }
+
+    /// Appends the member `name` holding the nested struct `value`
+    pub fn member<S: Into<String>>(self, name: S, value: TypedStruct) -> TypedStruct {
+        self.field(name, TypedValue::Struct(value))
+    }
+
+    /// This struct's own type, such as `Mail(Person from,Person to,string contents)`
+    fn own_type(&self) -> String {
+        let members: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(name, value)| format!("{} {}", value.type_name(), name))
+            .collect();
+        format!("{}({})", self.name, members.join(","))
+    }
+
+    /// Adds the types of the structs this one refers to, directly or not, to `types`
+    fn referenced_types(&self, types: &mut BTreeMap<String, String>) {
+        for (_, value) in self.fields.iter() {
+            if let TypedValue::Struct(nested) = value {
+                if !types.contains_key(&nested.name) {
+                    types.insert(nested.name.clone(), nested.own_type());
+                    nested.referenced_types(types);
+                }
+            }
+        }
+    }
+
+    /// The encoded type: this struct's type followed by the ones it refers to sorted by name
+    pub fn encode_type(&self) -> String {
+        let mut types = BTreeMap::new();
+        self.referenced_types(&mut types);
+        types.remove(&self.name);
+        let mut encoded = self.own_type();
+        for referenced in types.values() {
+            encoded.push_str(referenced);
+        }
+        encoded
+    }
+
+    pub fn type_hash(&self) -> [u8; 32] {
+        keccak256(self.encode_type().as_bytes())
+    }
+
+    /// `hashStruct`, the hash of the type followed by every member's encoding
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Keccak256::new();
+        hasher.input(self.type_hash());
+        for (_, value) in self.fields.iter() {
+            hasher.input(value.encode());
+        }
+        to_word(&hasher.result())
+    }
+}
+
+/// The domain a signature is valid in, fields left unset are not part of it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Eip712Domain {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub chain_id: Option<Uint256>,
+    pub verifying_contract: Option<Address>,
+    pub salt: Option<[u8; 32]>,
+}
+
+impl Eip712Domain {
+    /// The domain of the Dai contract at `dai` on chain `chain_id`, which `permit()` checks
+    pub fn dai(chain_id: u64, dai: Address) -> Eip712Domain {
+        Eip712Domain {
+            name: Some("Dai Stablecoin".to_string()),
+            version: Some("1".to_string()),
+            chain_id: Some(chain_id.into()),
+            verifying_contract: Some(dai),
+            salt: None,
+        }
+    }
+
+    fn as_struct(&self) -> TypedStruct {
+        let mut domain = TypedStruct::new("EIP712Domain");
+        if let Some(ref name) = self.name {
+            domain = domain.string("name", name.clone());
+        }
+        if let Some(ref version) = self.version {
+            domain = domain.string("version", version.clone());
+        }
+        if let Some(ref chain_id) = self.chain_id {
+            domain = domain.uint("chainId", chain_id.clone());
+        }
+        if let Some(contract) = self.verifying_contract {
+            domain = domain.address("verifyingContract", contract);
+        }
+        if let Some(salt) = self.salt {
+            domain = domain.bytes32("salt", salt);
+        }
+        domain
+    }
+
+    pub fn separator(&self) -> [u8; 32] {
+        self.as_struct().hash()
+    }
+}
+
+/// Dai's `permit()` message allowing, or with `allowed` false revoking, `spender` to spend all
+/// of `holder`'s Dai, `nonce` is the holder's current `nonces()` and `expiry` 0 never expires
+pub fn dai_permit(
+    holder: Address,
+    spender: Address,
+    nonce: Uint256,
+    expiry: Uint256,
+    allowed: bool,
+) -> TypedStruct {
+    TypedStruct::new("Permit")
+        .address("holder", holder)
+        .address("spender", spender)
+        .uint("nonce", nonce)
+        .uint("expiry", expiry)
+        .bool("allowed", allowed)
+}
+
+/// The hash that is signed for `message` in `domain`
+pub fn typed_data_hash(domain: &Eip712Domain, message: &TypedStruct) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.input([0x19, 0x01]);
+    hasher.input(domain.separator());
+    hasher.input(message.hash());
+    to_word(&hasher.result())
+}
+
+/// Whether the 65 byte r ++ s ++ v `signature` of `message` in `domain` was made by `address`,
+/// a signature that isn't 65 bytes long is an error
+pub fn verify_typed_data(
+    address: Address,
+    domain: &Eip712Domain,
+    message: &TypedStruct,
+    signature: &[u8],
+) -> Result<bool, Error> {
+    is_signed_by(address, &typed_data_hash(domain, message), signature)
+}
+
+fn keccak256(data: &[u8]) -> [u8; 32] {
+    to_word(&Keccak256::digest(data))
+}
+
+fn to_word(hash: &[u8]) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word.copy_from_slice(hash);
+    word
+}
+
+impl TokenBridge {
+    /// Signs `message` in `domain` with our key, returning the 65 byte r ++ s ++ v signature
+    pub fn sign_typed_data(
+        &self,
+        domain: &Eip712Domain,
+        message: &TypedStruct,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        let signer = try_future!(self.signer());
+        Box::new(
+            signer
+                .sign_typed_data(domain, message)
+                .and_then(|signature| signature_to_bytes(&signature)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_bridge, MockBlockReader};
+    use crate::signer::{LocalSigner, Signer};
+    use clarity::utils::bytes_to_hex_str;
+    use clarity::PrivateKey;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    fn person(name: &str, wallet: &str) -> TypedStruct {
+        TypedStruct::new("Person")
+            .string("name", name)
+            .address("wallet", Address::from_str(wallet).unwrap())
+    }
+
+    /// The example of the EIP-712 specification
+    fn mail() -> (Eip712Domain, TypedStruct) {
+        let domain = Eip712Domain {
+            name: Some("Ether Mail".to_string()),
+            version: Some("1".to_string()),
+            chain_id: Some(1u32.into()),
+            verifying_contract: Some(
+                Address::from_str("0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC").unwrap(),
+            ),
+            salt: None,
+        };
+        let mail = TypedStruct::new("Mail")
+            .member(
+                "from",
+                person("Cow", "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"),
+            )
+            .member(
+                "to",
+                person("Bob", "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"),
+            )
+            .string("contents", "Hello, Bob!");
+        (domain, mail)
+    }
+
+    #[test]
+    fn test_mail_example() {
+        let (domain, mail) = mail();
+        assert_eq!(
+            mail.encode_type(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            bytes_to_hex_str(&mail.type_hash()),
+            "a0cedeb2dc280ba39b857546d74f5549c3a1d7bdc2dd96bf881f76108e23dac2"
+        );
+        assert_eq!(
+            bytes_to_hex_str(&domain.separator()),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+        assert_eq!(
+            bytes_to_hex_str(&mail.hash()),
+            "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+        );
+        assert_eq!(
+            bytes_to_hex_str(&typed_data_hash(&domain, &mail)),
+            "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+
+        // signed with keccak256("cow"), the key of the `from` wallet
+        let key = PrivateKey::from_slice(&keccak256(b"cow")).unwrap();
+        let signature = LocalSigner::new(key)
+            .unwrap()
+            .sign_typed_data(&domain, &mail)
+            .wait()
+            .unwrap();
+        let signature = signature_to_bytes(&signature).unwrap();
+        assert_eq!(
+            bytes_to_hex_str(&signature),
+            "4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d\
+             07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b915621c"
+        );
+        let cow = Address::from_str("0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826").unwrap();
+        assert!(verify_typed_data(cow, &domain, &mail, &signature).unwrap());
+        let other = Eip712Domain {
+            chain_id: Some(100u32.into()),
+            ..domain.clone()
+        };
+        assert!(!verify_typed_data(cow, &other, &mail, &signature).unwrap());
+    }
+
+    #[test]
+    fn test_dai_permit() {
+        let bridge = mock_bridge(Arc::new(MockBlockReader::default()));
+        let holder = PrivateKey::from_slice(&[2u8; 32])
+            .unwrap()
+            .to_public_key()
+            .unwrap();
+        let spender = Address::from_slice(&[0x5e; 20]).unwrap();
+        let permit = dai_permit(holder, spender, 0u32.into(), 0u32.into(), true);
+        // PERMIT_TYPEHASH of the Dai contract
+        assert_eq!(
+            bytes_to_hex_str(&permit.type_hash()),
+            "ea2aa0a1be11a07ed86d755c93467f4f82362b452371d1ba94d1715123511acb"
+        );
+
+        let domain = Eip712Domain::dai(1, bridge.foreign_dai_contract_address);
+        let signature = bridge.sign_typed_data(&domain, &permit).wait().unwrap();
+        assert!(verify_typed_data(holder, &domain, &permit, &signature).unwrap());
+        let revoke = dai_permit(holder, spender, 0u32.into(), 0u32.into(), false);
+        assert!(!verify_typed_data(holder, &domain, &revoke, &signature).unwrap());
+    }
+}
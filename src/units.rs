@@ -0,0 +1,211 @@
+//! Wei denominated amounts of each currency the bridge deals with. Keeping them apart in the
+//! type system stops Dai from being passed where ETH is expected, converting between them
+//! always has to be spelled out.
+
+use crate::amounts::{format_amount, parse_amount, scale_decimals, ETH_DECIMALS};
+use failure::bail;
+use failure::Error;
+use num256::Uint256;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Add, Sub};
+
+macro_rules! amount_type {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(
+            Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+        )]
+        pub struct $name(Uint256);
+
+        impl $name {
+            pub fn from_wei(wei: Uint256) -> $name {
+                $name(wei)
+            }
+
+            pub fn wei(&self) -> &Uint256 {
+                &self.0
+            }
+
+            pub fn into_wei(self) -> Uint256 {
+                self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{} {}-wei", self.0, stringify!($name))
+            }
+        }
+
+        impl Add for $name {
+            type Output = $name;
+
+            fn add(self, other: $name) -> $name {
+                $name(self.0 + other.0)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = $name;
+
+            fn sub(self, other: $name) -> $name {
+                $name(self.0 - other.0)
+            }
+        }
+    };
+}
+
+amount_type!(
+    /// ETH on the Eth chain
+    Eth
+);
+amount_type!(
+    /// Dai on the Eth chain
+    Dai
+);
+amount_type!(
+    /// xDai, the native currency of the xDai chain
+    XDai
+);
+
+impl Dai {
+    /// The xDai minted for this much Dai by the bridge, which is one to one before fees
+    pub fn bridged_to_xdai(self) -> XDai {
+        XDai(self.0)
+    }
+}
+
+impl XDai {
+    /// The Dai released for this much xDai by the bridge, which is one to one before fees
+    pub fn bridged_to_dai(self) -> Dai {
+        Dai(self.0)
+    }
+}
+
+/// An amount of a token with any number of decimals, for tokens like USDC that don't have the
+/// 18 of ETH and Dai. Amounts with different decimals can't be combined without rescaling one
+/// of them first, so six decimal amounts are never mistaken for wei.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TokenAmount {
+    /// In the smallest unit of the token
+    pub value: Uint256,
+    pub decimals: u32,
+}
+
+impl TokenAmount {
+    pub fn new(value: Uint256, decimals: u32) -> TokenAmount {
+        TokenAmount { value, decimals }
+    }
+
+    /// Parses a decimal amount like "12.5" of a token with `decimals` decimals
+    pub fn parse(amount: &str, decimals: u32) -> Result<TokenAmount, Error> {
+        Ok(TokenAmount::new(parse_amount(amount, decimals)?, decimals))
+    }
+
+    /// The same amount with `decimals` decimals, failing if that would lose precision
+    pub fn rescale(&self, decimals: u32) -> Result<TokenAmount, Error> {
+        let rescaled = self.rescale_floor(decimals);
+        if scale_decimals(rescaled.value.clone(), decimals, self.decimals) != self.value {
+            bail!("{} does not fit into {} decimals", self, decimals);
+        }
+        Ok(rescaled)
+    }
+
+    /// The same amount with `decimals` decimals, rounded down if it has fewer
+    pub fn rescale_floor(&self, decimals: u32) -> TokenAmount {
+        TokenAmount::new(
+            scale_decimals(self.value.clone(), self.decimals, decimals),
+            decimals,
+        )
+    }
+
+    /// The amount with 18 decimals, for comparing it against ETH, Dai or xDai
+    pub fn to_wei(&self) -> Uint256 {
+        self.rescale_floor(ETH_DECIMALS).value
+    }
+
+    /// The value in the smallest unit of a token with `decimals` decimals, or an error if this
+    /// amount is of a token with a different number of decimals
+    pub fn value_in(&self, decimals: u32) -> Result<Uint256, Error> {
+        if self.decimals != decimals {
+            bail!(
+                "Expected an amount with {} decimals, got {} with {}",
+                decimals,
+                self.value,
+                self.decimals
+            );
+        }
+        Ok(self.value.clone())
+    }
+
+    pub fn checked_add(&self, other: &TokenAmount) -> Result<TokenAmount, Error> {
+        let other = other.value_in(self.decimals)?;
+        Ok(TokenAmount::new(self.value.clone() + other, self.decimals))
+    }
+
+    pub fn checked_sub(&self, other: &TokenAmount) -> Result<TokenAmount, Error> {
+        let other = other.value_in(self.decimals)?;
+        if other > self.value {
+            bail!("Can not subtract {} from {}", other, self);
+        }
+        Ok(TokenAmount::new(self.value.clone() - other, self.decimals))
+    }
+}
+
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            format_amount(&self.value, self.decimals, self.decimals)
+        )
+    }
+}
+
+impl From<Dai> for TokenAmount {
+    fn from(dai: Dai) -> TokenAmount {
+        TokenAmount::new(dai.0, ETH_DECIMALS)
+    }
+}
+
+impl From<XDai> for TokenAmount {
+    fn from(xdai: XDai) -> TokenAmount {
+        TokenAmount::new(xdai.0, ETH_DECIMALS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amounts() {
+        let dai = Dai::from_wei(5u32.into()) + Dai::from_wei(3u32.into());
+        assert_eq!(dai.wei(), &8u32.into());
+        assert_eq!(dai.to_string(), "8 Dai-wei");
+        assert_eq!(dai.bridged_to_xdai(), XDai::from_wei(8u32.into()));
+    }
+
+    #[test]
+    fn test_token_amount() {
+        let usdc = TokenAmount::parse("1.5", 6).unwrap();
+        assert_eq!(usdc.value, 1_500_000u32.into());
+        assert_eq!(usdc.to_string(), "1.5");
+        assert_eq!(usdc.to_wei(), 1_500_000_000_000_000_000u64.into());
+        assert_eq!(usdc.rescale(18).unwrap().rescale(6).unwrap(), usdc);
+
+        let dust = TokenAmount::new(1u32.into(), 18);
+        assert!(dust.rescale(6).is_err());
+        assert_eq!(dust.rescale_floor(6).value, 0u32.into());
+
+        let dai = TokenAmount::from(Dai::from_wei(1_500_000u32.into()));
+        assert!(usdc.checked_add(&dai).is_err());
+        assert!(usdc.value_in(18).is_err());
+        assert_eq!(
+            usdc.checked_add(&usdc).unwrap(),
+            TokenAmount::parse("3", 6).unwrap()
+        );
+        assert!(TokenAmount::new(1u32.into(), 6).checked_sub(&usdc).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! Health of the validators that run the xDai bridge. Transfers in either direction are only
+//! paid out once `requiredSignatures` validators have signed them on the home bridge, so a
+//! bridge with too few validators signing accepts deposits that then sit unprocessed.
+//!
+//! Validators are counted as active if they signed an affirmation (Dai to xDai) or a user
+//! request (xDai to Dai) within the blocks looked at. A window without any transfers shows no
+//! one as active, it should be long enough to span some bridge traffic.
+
+use crate::logs::{EventDefinition, EventParam, ParamType};
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::utils::bytes_to_hex_str;
+use clarity::Address;
+use failure::bail;
+use failure::format_err;
+use failure::Error;
+use futures::Future;
+use num::ToPrimitive;
+use num256::Uint256;
+use web30::types::{Log, NewFilter};
+
+/// Emitted by the home bridge for every validator signing the payout of a Dai deposit
+pub const HOME_BRIDGE_SIGNED_FOR_AFFIRMATION: EventDefinition = EventDefinition {
+    signature: "SignedForAffirmation(address,bytes32)",
+    params: &[
+        EventParam {
+            name: "signer",
+            kind: ParamType::Address,
+            indexed: true,
+        },
+        EventParam {
+            name: "transactionHash",
+            kind: ParamType::Bytes32,
+            indexed: false,
+        },
+    ],
+};
+
+/// Emitted by the home bridge for every validator signing an xDai withdrawal
+pub const HOME_BRIDGE_SIGNED_FOR_USER_REQUEST: EventDefinition = EventDefinition {
+    signature: "SignedForUserRequest(address,bytes32)",
+    params: &[
+        EventParam {
+            name: "signer",
+            kind: ParamType::Address,
+            indexed: true,
+        },
+        EventParam {
+            name: "messageHash",
+            kind: ParamType::Bytes32,
+            indexed: false,
+        },
+    ],
+};
+
+/// What a validator has signed in the blocks looked at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatorActivity {
+    pub validator: Address,
+    /// Dai to xDai payouts signed
+    pub affirmations: u32,
+    /// xDai to Dai withdrawals signed
+    pub user_requests: u32,
+    pub last_signed_block: Option<Uint256>,
+}
+
+impl ValidatorActivity {
+    pub fn is_active(&self) -> bool {
+        self.affirmations + self.user_requests > 0
+    }
+}
+
+/// The validator set of the bridge and how much of it is signing, see
+/// `check_validator_health`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatorHealth {
+    /// The home bridge's `BridgeValidators` contract
+    pub validator_contract: Address,
+    /// How many validators have to sign a transfer before it is paid out
+    pub required_signatures: u64,
+    pub validators: Vec<ValidatorActivity>,
+    pub from_block: Uint256,
+    pub to_block: Uint256,
+}
+
+impl ValidatorHealth {
+    /// How many validators signed anything in the window
+    pub fn active(&self) -> usize {
+        self.validators
+            .iter()
+            .filter(|validator| validator.is_active())
+            .count()
+    }
+
+    /// Whether enough validators are signing for transfers to be paid out at all
+    pub fn has_quorum(&self) -> bool {
+        self.active() as u64 >= self.required_signatures
+    }
+
+    /// Whether some validators have stopped signing. Transfers are still paid out as long as
+    /// there is a quorum, but with less room for further validators going down.
+    pub fn is_degraded(&self) -> bool {
+        self.active() < self.validators.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SignatureKind {
+    Affirmation,
+    UserRequest,
+}
+
+/// `validators` with their `signatures` counted, signatures by anyone else are ignored
+fn tally(
+    validators: &[Address],
+    signatures: &[(Address, SignatureKind, Uint256)],
+) -> Vec<ValidatorActivity> {
+    validators
+        .iter()
+        .map(|validator| {
+            let mut activity = ValidatorActivity {
+                validator: *validator,
+                affirmations: 0,
+                user_requests: 0,
+                last_signed_block: None,
+            };
+            for (signer, kind, block) in signatures {
+                if signer != validator {
+                    continue;
+                }
+                match kind {
+                    SignatureKind::Affirmation => activity.affirmations += 1,
+                    SignatureKind::UserRequest => activity.user_requests += 1,
+                }
+                if activity.last_signed_block.as_ref() < Some(block) {
+                    activity.last_signed_block = Some(block.clone());
+                }
+            }
+            activity
+        })
+        .collect()
+}
+
+fn decode_signature(log: &Log) -> Result<(Address, SignatureKind, Uint256), Error> {
+    let (definition, kind) = match log.topics.first() {
+        Some(topic) if topic[..] == HOME_BRIDGE_SIGNED_FOR_AFFIRMATION.topic0()[..] => (
+            HOME_BRIDGE_SIGNED_FOR_AFFIRMATION,
+            SignatureKind::Affirmation,
+        ),
+        _ => (
+            HOME_BRIDGE_SIGNED_FOR_USER_REQUEST,
+            SignatureKind::UserRequest,
+        ),
+    };
+    let event = definition.decode(log)?;
+    let block = log
+        .block_number
+        .clone()
+        .ok_or_else(|| format_err!("{} log is not in a block", definition.signature))?;
+    Ok((event.address("signer")?, kind, block))
+}
+
+fn topic(definition: &EventDefinition) -> Option<String> {
+    Some(format!("0x{}", bytes_to_hex_str(&definition.topic0())))
+}
+
+impl TokenBridge {
+    /// Reads the validator set of the home bridge and counts the signatures each validator
+    /// made in the last `lookback_blocks` xDai blocks
+    pub fn check_validator_health(
+        &self,
+        lookback_blocks: u64,
+    ) -> Box<dyn Future<Item = ValidatorHealth, Error = Error>> {
+        let home_bridge = self.xdai_home_bridge_address;
+        let web3 = self.xdai_web3.clone();
+        let salf = self.clone();
+
+        Box::new(
+            self.call_view::<Address>(Chain::Xdai, home_bridge, "validatorContract()", &[])
+                .join(self.xdai_web3.eth_block_number())
+                .and_then(move |(validator_contract, to_block)| {
+                    let lookback = Uint256::from(lookback_blocks);
+                    let from_block = if to_block > lookback {
+                        to_block.clone() - lookback
+                    } else {
+                        0u32.into()
+                    };
+                    let filter = NewFilter {
+                        from_block: Some(format!("0x{}", from_block.to_str_radix(16))),
+                        to_block: Some(format!("0x{}", to_block.to_str_radix(16))),
+                        address: vec![home_bridge],
+                        topics: Some(vec![Some(vec![
+                            topic(&HOME_BRIDGE_SIGNED_FOR_AFFIRMATION),
+                            topic(&HOME_BRIDGE_SIGNED_FOR_USER_REQUEST),
+                        ])]),
+                    };
+                    salf.call_view::<Vec<Address>>(
+                        Chain::Xdai,
+                        validator_contract,
+                        "validatorList()",
+                        &[],
+                    )
+                    .join3(
+                        salf.call_view::<Uint256>(
+                            Chain::Xdai,
+                            validator_contract,
+                            "requiredSignatures()",
+                            &[],
+                        ),
+                        web3.eth_get_logs(filter),
+                    )
+                    .and_then(
+                        move |(validators, required_signatures, logs)| {
+                            let signatures =
+                                logs.iter()
+                                    .map(decode_signature)
+                                    .collect::<Result<Vec<_>, Error>>()?;
+                            let required_signatures = match required_signatures.to_u64() {
+                                Some(required) => required,
+                                None => bail!(
+                                    "requiredSignatures {} is out of range",
+                                    required_signatures
+                                ),
+                            };
+                            let health = ValidatorHealth {
+                                validator_contract,
+                                required_signatures,
+                                validators: tally(&validators, &signatures),
+                                from_block,
+                                to_block,
+                            };
+                            if !health.has_quorum() {
+                                warn!(
+                                    "{} of {} bridge validators signed in {} blocks, {} required",
+                                    health.active(),
+                                    health.validators.len(),
+                                    lookback_blocks,
+                                    health.required_signatures
+                                );
+                            }
+                            Ok(health)
+                        },
+                    )
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::address_word;
+
+    #[test]
+    fn test_signed_event() {
+        let signer = Address::from_slice(&[0xa1; 20]).unwrap();
+        let topic0 = HOME_BRIDGE_SIGNED_FOR_USER_REQUEST.topic0();
+        let signer_topic = address_word(signer);
+        let event = HOME_BRIDGE_SIGNED_FOR_USER_REQUEST
+            .decode_raw(&[&topic0[..], &signer_topic[..]], &[7u8; 32])
+            .unwrap();
+        assert_eq!(event.address("signer").unwrap(), signer);
+        assert!(HOME_BRIDGE_SIGNED_FOR_AFFIRMATION
+            .decode_raw(&[&topic0[..], &signer_topic[..]], &[7u8; 32])
+            .is_err());
+    }
+
+    #[test]
+    fn test_tally() {
+        let validators: Vec<Address> = (1u8..=3)
+            .map(|n| Address::from_slice(&[n; 20]).unwrap())
+            .collect();
+        let outsider = Address::from_slice(&[9; 20]).unwrap();
+        let signatures = vec![
+            (validators[0], SignatureKind::Affirmation, 10u32.into()),
+            (validators[0], SignatureKind::UserRequest, 12u32.into()),
+            (validators[1], SignatureKind::Affirmation, 11u32.into()),
+            (outsider, SignatureKind::Affirmation, 13u32.into()),
+        ];
+        let activity = tally(&validators, &signatures);
+        assert_eq!(activity[0].affirmations, 1);
+        assert_eq!(activity[0].user_requests, 1);
+        assert_eq!(activity[0].last_signed_block, Some(12u32.into()));
+        assert!(!activity[2].is_active());
+
+        let mut health = ValidatorHealth {
+            validator_contract: Address::default(),
+            required_signatures: 2,
+            validators: activity,
+            from_block: 0u32.into(),
+            to_block: 13u32.into(),
+        };
+        assert_eq!(health.active(), 2);
+        assert!(health.has_quorum());
+        assert!(health.is_degraded());
+        health.required_signatures = 3;
+        assert!(!health.has_quorum());
+    }
+}
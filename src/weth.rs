@@ -0,0 +1,124 @@
+//! Wrapping ETH into WETH and back, which router based exchanges trade instead of plain ETH
+
+use crate::encoding;
+use crate::logs::{EventDefinition, EventParam, ParamType};
+use crate::units::Eth;
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::Address;
+use failure::Error;
+use futures::Future;
+use num256::Uint256;
+use std::str::FromStr;
+use std::time::Duration;
+use web30::types::SendTxOption;
+
+pub use crate::contracts::MAINNET_WETH;
+
+pub fn mainnet_weth() -> Address {
+    Address::from_str(MAINNET_WETH).unwrap()
+}
+
+pub const WETH_DEPOSIT: EventDefinition = EventDefinition {
+    signature: "Deposit(address,uint256)",
+    params: &[
+        EventParam {
+            name: "dst",
+            kind: ParamType::Address,
+            indexed: true,
+        },
+        EventParam {
+            name: "wad",
+            kind: ParamType::Uint256,
+            indexed: false,
+        },
+    ],
+};
+
+pub const WETH_WITHDRAWAL: EventDefinition = EventDefinition {
+    signature: "Withdrawal(address,uint256)",
+    params: &[
+        EventParam {
+            name: "src",
+            kind: ParamType::Address,
+            indexed: true,
+        },
+        EventParam {
+            name: "wad",
+            kind: ParamType::Uint256,
+            indexed: false,
+        },
+    ],
+};
+
+impl TokenBridge {
+    /// WETH held by `address`
+    pub fn get_weth_balance(&self, address: Address) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        Box::new(
+            self.get_token_balance(self.weth_address, address)
+                .map(Eth::from_wei),
+        )
+    }
+
+    /// Wraps `amount` of our ETH into WETH, resolving to the amount wrapped once the deposit is
+    /// mined. On timeout the error is `TokenBridgeError::TimedOut` saying whether it went
+    /// through.
+    pub fn wrap_eth(
+        &self,
+        amount: Eth,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        self.in_account_order(move |salf| {
+            salf.send_weth_call(
+                encoding::weth_deposit(),
+                amount.into_wei(),
+                WETH_DEPOSIT,
+                timeout,
+            )
+        })
+    }
+
+    /// Unwraps `amount` of our WETH back into ETH, see `wrap_eth`
+    pub fn unwrap_weth(
+        &self,
+        amount: Eth,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        self.in_account_order(move |salf| {
+            let sender = try_future!(salf.spending_token(salf.weth_address, amount.wei()));
+            sender.send_weth_call(
+                encoding::weth_withdraw(amount.into_wei()),
+                0u32.into(),
+                WETH_WITHDRAWAL,
+                timeout,
+            )
+        })
+    }
+
+    fn send_weth_call(
+        &self,
+        payload: Vec<u8>,
+        value: Uint256,
+        event: EventDefinition,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = Eth, Error = Error>> {
+        let weth = self.weth_address;
+        let salf = self.clone();
+
+        Box::new(
+            self.send_transaction(
+                Chain::Eth,
+                weth,
+                payload,
+                value,
+                vec![SendTxOption::GasLimit(60_000u64.into())],
+            )
+            .and_then(move |tx_hash| {
+                let confirmed =
+                    salf.confirm_transaction(Chain::Eth, tx_hash.clone(), weth, event, "wad");
+                salf.wait_or_reconcile(Chain::Eth, tx_hash, confirmed, timeout, weth, event, "wad")
+                    .map(Eth::from_wei)
+            }),
+        )
+    }
+}
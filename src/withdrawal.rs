@@ -0,0 +1,355 @@
+//! Finishing xDai to Dai withdrawals ourselves on bridges where the validators only collect
+//! signatures on the xDai side and someone has to relay them to Eth with `executeSignatures`.
+//!
+//! Sending xDai to the home bridge emits `UserRequestForSignature`, once enough validators have
+//! signed the home bridge emits `CollectedSignatures` with the hash of the signed message. The
+//! message is `recipient (20 bytes) ++ value (32) ++ xDai tx hash (32) ++ foreign bridge (20)`.
+
+use crate::abi::AbiType;
+use crate::logs::{EventDefinition, EventParam, ParamType};
+use crate::Chain;
+use crate::TokenBridge;
+use clarity::abi::{derive_signature, Token};
+use clarity::utils::bytes_to_hex_str;
+use failure::bail;
+use failure::Error;
+use futures::future::{join_all, loop_fn, Loop};
+use futures::Future;
+use futures_timer::{Delay, FutureExt};
+use num::ToPrimitive;
+use num256::Uint256;
+use std::time::Duration;
+use web30::types::{Log, NewFilter};
+
+/// How often the home bridge is checked for collected signatures
+const SIGNATURE_POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Emitted by the home bridge for every xDai sent to it to be paid out as Dai on Eth
+pub const HOME_BRIDGE_USER_REQUEST_FOR_SIGNATURE: EventDefinition = EventDefinition {
+    signature: "UserRequestForSignature(address,uint256)",
+    params: &[
+        EventParam {
+            name: "recipient",
+            kind: ParamType::Address,
+            indexed: false,
+        },
+        EventParam {
+            name: "value",
+            kind: ParamType::Uint256,
+            indexed: false,
+        },
+    ],
+};
+
+/// A withdrawal message and the validator signatures over it, ready for `executeSignatures`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectedSignatures {
+    pub message: Vec<u8>,
+    /// 65 byte r ++ s ++ v signatures
+    pub signatures: Vec<Vec<u8>>,
+}
+
+/// The xDai transaction hash a withdrawal message was created for
+pub fn message_tx_hash(message: &[u8]) -> Option<&[u8]> {
+    message.get(52..84)
+}
+
+/// Splits a 65 byte r ++ s ++ v signature into its parts
+pub fn split_signature(signature: &[u8]) -> Result<(u8, Vec<u8>, Vec<u8>), Error> {
+    if signature.len() != 65 {
+        bail!("Expected a 65 byte signature, got {:?}", signature);
+    }
+    Ok((
+        signature[64],
+        signature[0..32].to_vec(),
+        signature[32..64].to_vec(),
+    ))
+}
+
+impl TokenBridge {
+    /// Looks for signatures collected for the xDai to Dai transfer made in `xdai_tx_hash`,
+    /// `None` if the validators have not finished signing yet.
+    pub fn get_collected_signatures(
+        &self,
+        xdai_tx_hash: Uint256,
+    ) -> Box<dyn Future<Item = Option<CollectedSignatures>, Error = Error>> {
+        let web3 = self.xdai_web3.clone();
+        let home_bridge = self.xdai_home_bridge_address;
+        let salf = self.clone();
+        let tx_hash_bytes = to_word(&xdai_tx_hash);
+
+        Box::new(
+            web3.eth_get_transaction_by_hash(xdai_tx_hash.clone())
+                .and_then(move |tx| match tx.and_then(|tx| tx.block_number) {
+                    Some(block) => Ok(block),
+                    None => bail!("xDai transaction {:#x} is not in a block yet", xdai_tx_hash),
+                })
+                .and_then(move |block| {
+                    let event = derive_signature("CollectedSignatures(address,bytes32,uint256)");
+                    web3.eth_get_logs(NewFilter {
+                        from_block: Some(format!("0x{}", block.to_str_radix(16))),
+                        to_block: Some("latest".to_string()),
+                        address: vec![home_bridge],
+                        topics: Some(vec![Some(vec![Some(format!(
+                            "0x{}",
+                            bytes_to_hex_str(&event)
+                        ))])]),
+                    })
+                })
+                .and_then(move |logs| {
+                    // the event doesn't name the transaction, so fetch each signed message
+                    // and see if it is ours
+                    let candidates = logs
+                        .into_iter()
+                        .filter_map(|log| collected_signatures_event(&log))
+                        .map(|(message_hash, count)| {
+                            salf.get_home_bridge_message(message_hash.clone())
+                                .map(move |message| (message_hash, count, message))
+                        })
+                        .collect::<Vec<_>>();
+                    join_all(candidates).map(move |messages| (salf, messages))
+                })
+                .and_then(move |(salf, messages)| {
+                    let ours = messages.into_iter().find(|(_, _, message)| {
+                        message_tx_hash(message) == Some(&tx_hash_bytes[..])
+                    });
+                    match ours {
+                        Some((message_hash, count, message)) => {
+                            Box::new(salf.get_home_bridge_signatures(message_hash, count).map(
+                                move |signatures| {
+                                    Some(CollectedSignatures {
+                                        message,
+                                        signatures,
+                                    })
+                                },
+                            ))
+                                as Box<dyn Future<Item = _, Error = Error>>
+                        }
+                        None => Box::new(futures::future::ok(None)),
+                    }
+                }),
+        )
+    }
+
+    /// Polls `get_collected_signatures` until the validators are done or `timeout` runs out
+    pub fn wait_for_collected_signatures(
+        &self,
+        xdai_tx_hash: Uint256,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = CollectedSignatures, Error = Error>> {
+        let salf = self.clone();
+        Box::new(
+            loop_fn((), move |_| {
+                let (bridge, xdai_tx_hash) = (salf.clone(), xdai_tx_hash.clone());
+                salf.with_retry(Chain::Xdai, "eth_getLogs", move || {
+                    bridge.get_collected_signatures(xdai_tx_hash.clone())
+                })
+                .and_then(|signatures| match signatures {
+                    Some(signatures) => Box::new(futures::future::ok(Loop::Break(signatures)))
+                        as Box<dyn Future<Item = _, Error = Error>>,
+                    None => Box::new(
+                        Delay::new(SIGNATURE_POLL_INTERVAL)
+                            .from_err()
+                            .map(|_| Loop::Continue(())),
+                    ),
+                })
+            })
+            .timeout(timeout),
+        )
+    }
+
+    /// Whether the withdrawal for `xdai_tx_hash` has already been executed on Eth, by us or a
+    /// third party relayer
+    pub fn is_withdrawal_relayed(
+        &self,
+        xdai_tx_hash: Uint256,
+    ) -> Box<dyn Future<Item = bool, Error = Error>> {
+        self.call_view(
+            Chain::Eth,
+            self.xdai_foreign_bridge_address,
+            "relayedMessages(bytes32)",
+            // a bytes32 argument encodes the same as a uint256 one
+            &[xdai_tx_hash.into()],
+        )
+    }
+
+    /// Submits `executeSignatures` on the foreign bridge, releasing the Dai. Returns the tx
+    /// hash.
+    pub fn execute_signatures(
+        &self,
+        collected: CollectedSignatures,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+        let mut vs = Vec::new();
+        let mut rs = Vec::new();
+        let mut ss = Vec::new();
+        for signature in collected.signatures.iter() {
+            let (v, r, s) = try_future!(split_signature(signature));
+            vs.push(Token::Uint(u32::from(v).into()));
+            rs.push(Token::Bytes(r));
+            ss.push(Token::Bytes(s));
+        }
+
+        self.send_call(
+            Chain::Eth,
+            self.xdai_foreign_bridge_address,
+            "executeSignatures(uint8[],bytes32[],bytes32[],bytes)",
+            &[
+                Token::Dynamic(vs),
+                Token::Dynamic(rs),
+                Token::Dynamic(ss),
+                Token::UnboundedBytes(collected.message),
+            ],
+            0u32.into(),
+            Vec::new(),
+            timeout,
+        )
+    }
+
+    /// Waits for the validators to sign the withdrawal made in `xdai_tx_hash` and executes it
+    /// on Eth unless someone else already has. Returns the tx hash if we executed it.
+    pub fn complete_withdrawal(
+        &self,
+        xdai_tx_hash: Uint256,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = Option<Uint256>, Error = Error>> {
+        let relayed = self.clone();
+        let salf = self.clone();
+        execute_unless_relayed(
+            self.wait_for_collected_signatures(xdai_tx_hash.clone(), timeout),
+            move || relayed.is_withdrawal_relayed(xdai_tx_hash),
+            move |collected| salf.execute_signatures(collected, timeout),
+        )
+    }
+
+    fn get_home_bridge_message(
+        &self,
+        message_hash: Vec<u8>,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = Error>> {
+        Box::new(
+            self.call_view_values(
+                Chain::Xdai,
+                self.xdai_home_bridge_address,
+                "message(bytes32)",
+                &[Token::Bytes(message_hash)],
+                &[AbiType::Bytes],
+            )
+            .and_then(|mut values| values.remove(0).into_bytes()),
+        )
+    }
+
+    fn get_home_bridge_signatures(
+        &self,
+        message_hash: Vec<u8>,
+        count: u64,
+    ) -> Box<dyn Future<Item = Vec<Vec<u8>>, Error = Error>> {
+        let signatures = (0..count)
+            .map(|index| {
+                self.call_view_values(
+                    Chain::Xdai,
+                    self.xdai_home_bridge_address,
+                    "signature(bytes32,uint256)",
+                    &[Token::Bytes(message_hash.clone()), index.into()],
+                    &[AbiType::Bytes],
+                )
+                .and_then(|mut values| values.remove(0).into_bytes())
+            })
+            .collect::<Vec<_>>();
+        Box::new(join_all(signatures))
+    }
+}
+
+/// Checks whether the withdrawal was relayed only once `signatures` are collected, right before
+/// executing them, so that a relay by someone else during the wait doesn't make us send an
+/// `executeSignatures` that reverts
+fn execute_unless_relayed<S, R, E>(
+    signatures: S,
+    is_relayed: R,
+    execute: E,
+) -> Box<dyn Future<Item = Option<Uint256>, Error = Error>>
+where
+    S: Future<Item = CollectedSignatures, Error = Error> + 'static,
+    R: FnOnce() -> Box<dyn Future<Item = bool, Error = Error>> + 'static,
+    E: FnOnce(CollectedSignatures) -> Box<dyn Future<Item = Uint256, Error = Error>> + 'static,
+{
+    Box::new(
+        signatures
+            .and_then(move |collected| is_relayed().map(move |relayed| (collected, relayed)))
+            .and_then(move |(collected, relayed)| {
+                if relayed {
+                    info!("Withdrawal already relayed, nothing to do");
+                    Box::new(futures::future::ok(None)) as Box<dyn Future<Item = _, Error = Error>>
+                } else {
+                    Box::new(execute(collected).map(Some))
+                }
+            }),
+    )
+}
+
+/// `value` as a 32 byte big endian word
+fn to_word(value: &Uint256) -> Vec<u8> {
+    let bytes = value.to_bytes_be();
+    let mut word = vec![0u8; 32 - bytes.len().min(32)];
+    word.extend_from_slice(&bytes);
+    word
+}
+
+/// The message hash and signature count from a `CollectedSignatures` log, none of its fields
+/// are indexed
+fn collected_signatures_event(log: &Log) -> Option<(Vec<u8>, u64)> {
+    let message_hash = log.data.get(32..64)?.to_vec();
+    let count = Uint256::from_bytes_be(log.data.get(64..96)?).to_u64()?;
+    Some((message_hash, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_parts() {
+        let mut message = vec![0u8; 104];
+        message[52..84].copy_from_slice(&[7u8; 32]);
+        assert_eq!(message_tx_hash(&message), Some(&[7u8; 32][..]));
+        assert_eq!(message_tx_hash(&message[0..60]), None);
+
+        let mut signature = vec![1u8; 32];
+        signature.extend_from_slice(&[2u8; 32]);
+        signature.push(27);
+        assert_eq!(
+            split_signature(&signature).unwrap(),
+            (27, vec![1u8; 32], vec![2u8; 32])
+        );
+        assert!(split_signature(&signature[1..]).is_err());
+    }
+
+    #[test]
+    fn test_relayed_during_wait() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let collected = CollectedSignatures {
+            message: vec![1u8; 104],
+            signatures: Vec::new(),
+        };
+        let run = |relayed_during_wait: bool| {
+            let relayed = Arc::new(AtomicBool::new(false));
+            let waited = relayed.clone();
+            let collected = collected.clone();
+            // someone else relays the withdrawal while we wait for the signatures
+            let signatures = futures::future::lazy(move || {
+                waited.store(relayed_during_wait, Ordering::SeqCst);
+                Ok(collected)
+            });
+            execute_unless_relayed(
+                signatures,
+                move || Box::new(futures::future::ok(relayed.load(Ordering::SeqCst))),
+                |_| Box::new(futures::future::ok(7u32.into())),
+            )
+            .wait()
+            .unwrap()
+        };
+        assert_eq!(run(true), None);
+        assert_eq!(run(false), Some(7u32.into()));
+    }
+}
@@ -0,0 +1,75 @@
+//! `LogSubscriber` over a WebSocket connection to the full node using `eth_subscribe`
+
+use crate::subscription::LogSubscriber;
+use failure::format_err;
+use failure::Error;
+use futures::{Future, Sink, Stream};
+use serde_json::{json, Value};
+use web30::types::{Log, NewFilter};
+use websocket::{ClientBuilder, OwnedMessage};
+
+/// Opens a new WebSocket connection to `url` for every subscription
+#[derive(Debug, Clone)]
+pub struct WsLogSubscriber {
+    pub url: String,
+}
+
+impl WsLogSubscriber {
+    pub fn new(url: &str) -> WsLogSubscriber {
+        WsLogSubscriber {
+            url: url.to_string(),
+        }
+    }
+}
+
+/// The log carried by an `eth_subscription` notification, `None` for any other message
+fn notification_log(message: &str) -> Result<Option<Log>, Error> {
+    let value: Value = serde_json::from_str(message)?;
+    if let Some(error) = value.get("error") {
+        return Err(format_err!("eth_subscribe failed {}", error));
+    }
+    if value.get("method").and_then(Value::as_str) != Some("eth_subscription") {
+        return Ok(None);
+    }
+    match value.get("params").and_then(|params| params.get("result")) {
+        Some(result) => Ok(Some(serde_json::from_value(result.clone())?)),
+        None => Err(format_err!(
+            "Malformed eth_subscription notification {}",
+            message
+        )),
+    }
+}
+
+impl LogSubscriber for WsLogSubscriber {
+    fn subscribe_logs(&self, filter: NewFilter) -> Box<dyn Stream<Item = Log, Error = Error>> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_subscribe",
+            "params": ["logs", {"address": filter.address, "topics": filter.topics}],
+        });
+        let builder = match ClientBuilder::new(&self.url) {
+            Ok(builder) => builder,
+            Err(e) => return Box::new(futures::stream::once(Err(e.into()))),
+        };
+
+        Box::new(
+            builder
+                .async_connect(None)
+                .map_err(Error::from)
+                .and_then(move |(client, _headers)| {
+                    client
+                        .send(OwnedMessage::Text(request.to_string()))
+                        .map_err(Error::from)
+                })
+                .map(|client| client.map_err(Error::from))
+                .flatten_stream()
+                .filter_map(|message| match message {
+                    OwnedMessage::Text(text) => Some(text),
+                    _ => None,
+                })
+                .and_then(|text| notification_log(&text))
+                .filter_map(|log| log),
+        )
+    }
+}
@@ -0,0 +1,164 @@
+//! Tests against Eth mainnet and the xDai chain that spend real funds from a funded key. They
+//! only build with `cargo test --features live-tests`, see `local_chain.rs` for the same flows
+//! against a local node.
+#![cfg(feature = "live-tests")]
+
+use auto_bridge::amounts::eth_to_wei;
+use auto_bridge::contracts::{
+    MAINNET_SAI, MAINNET_UNISWAP_SAI_EXCHANGE, MAINNET_XDAI_FOREIGN_BRIDGE,
+    MAINNET_XDAI_HOME_BRIDGE,
+};
+use auto_bridge::units::{Dai, XDai};
+use auto_bridge::TokenBridge;
+use clarity::{Address, PrivateKey};
+use futures::Future;
+use std::str::FromStr;
+use std::time::Duration;
+
+fn new_token_bridge() -> TokenBridge {
+    let pk = PrivateKey::from_str(&format!(
+        "FE1FC0A7A29503BAF72274A{}601D67309E8F3{}D22",
+        "AA3ECDE6DB3E20", "29F7AB4BA52"
+    ))
+    .unwrap();
+
+    TokenBridge::new(
+        Address::from_str(MAINNET_UNISWAP_SAI_EXCHANGE).unwrap(),
+        Address::from_str(MAINNET_XDAI_HOME_BRIDGE).unwrap(),
+        Address::from_str(MAINNET_XDAI_FOREIGN_BRIDGE).unwrap(),
+        Address::from_str(MAINNET_SAI).unwrap(),
+        Address::from_str("0x79AE13432950bF5CDC3499f8d4Cf5963c3F0d42c").unwrap(),
+        pk,
+        "https://eth.althea.org".into(),
+        "https://dai.althea.org".into(),
+    )
+}
+
+#[test]
+fn test_is_approved() {
+    let pk = PrivateKey::from_str(&format!(
+        "FE1FC0A7A29503BAF72274A{}601D67309E8F3{}D22",
+        "AA3ECDE6DB3E20", "29F7AB4BA52"
+    ))
+    .unwrap();
+
+    let system = actix::System::new("test");
+
+    let token_bridge = new_token_bridge();
+
+    let unapproved_token_bridge = TokenBridge::new(
+        Address::from_str(MAINNET_UNISWAP_SAI_EXCHANGE).unwrap(),
+        Address::from_str(MAINNET_XDAI_HOME_BRIDGE).unwrap(),
+        Address::from_str(MAINNET_XDAI_FOREIGN_BRIDGE).unwrap(),
+        Address::from_str(MAINNET_SAI).unwrap(),
+        Address::from_str("0x6d943740746934b2f5D9c9E6Cb1908758A42452f").unwrap(),
+        pk,
+        "https://eth.althea.org".into(),
+        "https://dai.althea.org".into(),
+    );
+
+    actix::spawn(
+        token_bridge
+            .check_if_uniswap_dai_approved()
+            .and_then(move |is_approved| {
+                assert!(is_approved);
+                unapproved_token_bridge
+                    .check_if_uniswap_dai_approved()
+                    .and_then(move |is_approved| {
+                        assert!(!is_approved);
+                        Ok(())
+                    })
+            })
+            .then(|res| {
+                res.unwrap();
+                actix::System::current().stop();
+                Box::new(futures::future::ok(()))
+            }),
+    );
+    system.run();
+}
+
+#[test]
+fn test_eth_to_dai_swap() {
+    let system = actix::System::new("test");
+
+    let token_bridge = new_token_bridge();
+
+    actix::spawn(
+        token_bridge
+            .dai_to_eth_price(Dai::from_wei(eth_to_wei("0.01").unwrap()))
+            .and_then(move |one_cent_in_eth| {
+                token_bridge.eth_to_dai_swap(one_cent_in_eth.clone(), 600)
+            })
+            .then(|res| {
+                res.unwrap();
+                actix::System::current().stop();
+                Box::new(futures::future::ok(()))
+            }),
+    );
+
+    system.run();
+}
+
+#[test]
+fn test_dai_to_eth_swap() {
+    let system = actix::System::new("test");
+    let token_bridge = new_token_bridge();
+
+    actix::spawn(
+        token_bridge
+            .approve_uniswap_dai_transfers(Duration::from_secs(600))
+            .and_then(move |_| {
+                token_bridge.dai_to_eth_swap(Dai::from_wei(eth_to_wei("0.01").unwrap()), 600)
+            })
+            .then(|res| {
+                res.unwrap();
+                actix::System::current().stop();
+                Box::new(futures::future::ok(()))
+            }),
+    );
+
+    system.run();
+}
+
+#[test]
+fn test_dai_to_xdai_bridge() {
+    let system = actix::System::new("test");
+
+    let token_bridge = new_token_bridge();
+
+    actix::spawn(
+        token_bridge
+            // All we can really do here is test that it doesn't throw. Check your balances in
+            // 5-10 minutes to see if the money got transferred.
+            .dai_to_xdai_bridge(Dai::from_wei(eth_to_wei("0.01").unwrap()), 600)
+            .then(|res| {
+                res.unwrap();
+                actix::System::current().stop();
+                Box::new(futures::future::ok(()))
+            }),
+    );
+
+    system.run();
+}
+
+#[test]
+fn test_xdai_to_dai_bridge() {
+    let system = actix::System::new("test");
+
+    let token_bridge = new_token_bridge();
+
+    actix::spawn(
+        token_bridge
+            // All we can really do here is test that it doesn't throw. Check your balances in
+            // 5-10 minutes to see if the money got transferred.
+            .xdai_to_dai_bridge(XDai::from_wei(eth_to_wei("0.01").unwrap()), 600)
+            .then(|res| {
+                res.unwrap();
+                actix::System::current().stop();
+                Box::new(futures::future::ok(()))
+            }),
+    );
+
+    system.run();
+}
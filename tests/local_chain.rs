@@ -0,0 +1,107 @@
+//! End to end tests against the mock contracts of `testing::Devnet` on two local anvil or
+//! ganache nodes, one standing in for Eth and one for xDai.
+//!
+//! Start them with `anvil` and `anvil --port 8546`, then run
+//! `cargo test --features local-chain-tests`. `DEVNET_ETH_URL` and `DEVNET_XDAI_URL` point
+//! the tests at other nodes.
+#![cfg(feature = "local-chain-tests")]
+
+use auto_bridge::testing::{Devnet, DevnetConfig};
+use auto_bridge::units::{Dai, Eth, XDai};
+use auto_bridge::{BlockTag, Chain, TokenBridge};
+use clarity::Address;
+use futures::Future;
+use num256::Uint256;
+use std::env;
+
+fn ether(amount: u64) -> Uint256 {
+    Uint256::from(amount) * 1_000_000_000_000_000_000u64.into()
+}
+
+fn devnet() -> (Devnet, TokenBridge) {
+    let mut config = DevnetConfig::default();
+    if let Ok(url) = env::var("DEVNET_ETH_URL") {
+        config.eth_url = url;
+    }
+    if let Ok(url) = env::var("DEVNET_XDAI_URL") {
+        config.xdai_url = url;
+    }
+    let devnet = Devnet::deploy(config).unwrap();
+    let bridge = devnet.token_bridge().unwrap();
+    (devnet, bridge)
+}
+
+fn run<F: Future>(future: F) -> Result<F::Item, F::Error> {
+    actix::System::new("test").block_on(future)
+}
+
+#[test]
+fn test_devnet_eth_payment() {
+    let (devnet, bridge) = devnet();
+    let recipient = Address::from_slice(&[0xb1; 20]).unwrap();
+    let amount = Eth::from_wei(1_000u32.into());
+
+    let before = devnet.get_balance(Chain::Eth, recipient).unwrap();
+    run(bridge.send_eth(recipient, amount.clone(), Vec::new())).unwrap();
+    assert_eq!(
+        run(bridge.get_eth_balance_at(recipient, BlockTag::Latest)).unwrap(),
+        Eth::from_wei(before) + amount
+    );
+}
+
+#[test]
+fn test_devnet_quotes() {
+    let (_, bridge) = devnet();
+    assert_eq!(
+        run(bridge.eth_to_dai_price(Eth::from_wei(ether(1)))).unwrap(),
+        Dai::from_wei(ether(200))
+    );
+    assert_eq!(
+        run(bridge.dai_to_eth_price(Dai::from_wei(ether(200)))).unwrap(),
+        Eth::from_wei(ether(1))
+    );
+}
+
+#[test]
+fn test_devnet_eth_to_dai_swap() {
+    let (_, bridge) = devnet();
+    let bought = run(bridge.eth_to_dai_swap(Eth::from_wei(ether(1)), 600)).unwrap();
+    assert_eq!(bought, Dai::from_wei(ether(200)));
+}
+
+#[test]
+fn test_devnet_dai_to_xdai() {
+    let (devnet, bridge) = devnet();
+    let own_address = bridge.own_address;
+    devnet
+        .set_dai_balance(own_address, Dai::from_wei(ether(10)))
+        .unwrap();
+
+    let transfer = run(bridge.dai_to_xdai_bridge(Dai::from_wei(ether(1)), 600)).unwrap();
+    assert_eq!(transfer.amount, ether(1));
+
+    let before = devnet.get_balance(Chain::Xdai, own_address).unwrap();
+    devnet
+        .relay_dai_to_xdai(own_address, XDai::from_wei(transfer.amount))
+        .unwrap();
+    assert_eq!(
+        devnet.get_balance(Chain::Xdai, own_address).unwrap(),
+        before + ether(1)
+    );
+}
+
+#[test]
+fn test_devnet_xdai_to_dai() {
+    let (devnet, bridge) = devnet();
+    let own_address = bridge.own_address;
+    devnet
+        .set_dai_balance(own_address, Dai::from_wei(0u32.into()))
+        .unwrap();
+
+    let transfer = run(bridge.xdai_to_dai_bridge(XDai::from_wei(ether(1)), 600)).unwrap();
+    run(devnet.relay_xdai_to_dai(&bridge, own_address, Dai::from_wei(transfer.amount))).unwrap();
+    assert_eq!(
+        run(bridge.get_dai_balance(own_address)).unwrap(),
+        Dai::from_wei(ether(1))
+    );
+}